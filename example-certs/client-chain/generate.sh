@@ -0,0 +1,45 @@
+#!/bin/sh
+# Regenerate the client certificate chain fixtures: a root CA, an intermediate CA issued by it, client certificates
+# issued by the intermediate, and the CRLs of both CAs where the intermediate revokes the second client certificate.
+set -e
+cd "$(dirname "$0")"
+
+cat > ca.cnf <<'CNF'
+[ca]
+default_ca = ca_default
+[ca_default]
+database = index.txt
+default_md = sha256
+default_crl_days = 36500
+crlnumber = crlnumber.txt
+CNF
+
+openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out root.key
+openssl req -x509 -new -key root.key -subj "/CN=rpxy example client root CA" -days 36500 -out root.crt \
+  -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign" \
+  -addext "subjectKeyIdentifier=hash"
+
+openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out intermediate.key
+openssl req -new -key intermediate.key -subj "/CN=rpxy example client intermediate CA" -out intermediate.csr
+openssl x509 -req -in intermediate.csr -CA root.crt -CAkey root.key -set_serial 0x2001 -days 36500 \
+  -extfile intermediate.ext -out intermediate.crt
+cat intermediate.crt root.crt > ca-chain.crt
+
+for name in client revoked; do
+  openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out "$name.key"
+  openssl req -new -key "$name.key" -subj "/CN=rpxy example $name" -out "$name.csr"
+done
+openssl x509 -req -in client.csr -CA intermediate.crt -CAkey intermediate.key -set_serial 0x3001 -days 36500 \
+  -extfile client.ext -out client.crt
+openssl x509 -req -in revoked.csr -CA intermediate.crt -CAkey intermediate.key -set_serial 0x3002 -days 36500 \
+  -extfile client.ext -out revoked.crt
+
+: > index.txt
+echo 1000 > crlnumber.txt
+openssl ca -config ca.cnf -gencrl -keyfile root.key -cert root.crt -out root.crl.pem
+openssl ca -config ca.cnf -revoke revoked.crt -keyfile intermediate.key -cert intermediate.crt
+openssl ca -config ca.cnf -gencrl -keyfile intermediate.key -cert intermediate.crt -out intermediate.crl.pem
+cat intermediate.crl.pem root.crl.pem > crl.pem
+
+rm -f ca.cnf index.txt* crlnumber.txt* root.key intermediate.key intermediate.csr client.csr revoked.csr \
+  root.crl.pem intermediate.crl.pem
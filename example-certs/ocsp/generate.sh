@@ -0,0 +1,26 @@
+#!/bin/sh
+# Regenerate the OCSP fixtures: a CA, a server certificate issued by it with an OCSP responder URL, a delegated
+# OCSP responder, and the responses of the CA and the responder for the server certificate.
+set -e
+cd "$(dirname "$0")"
+
+openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out ca.key
+openssl req -x509 -new -key ca.key -subj "/CN=rpxy example OCSP CA" -days 36500 -out ca.crt \
+  -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign,digitalSignature"
+
+openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out server.key
+openssl req -new -key server.key -subj "/CN=localhost" -out server.csr
+openssl x509 -req -in server.csr -CA ca.crt -CAkey ca.key -set_serial 0x1001 -days 36500 -extfile server.ext -out server.leaf.crt
+cat server.leaf.crt ca.crt > server.crt
+
+openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out responder.key
+openssl req -new -key responder.key -subj "/CN=rpxy example OCSP responder" -out responder.csr
+openssl x509 -req -in responder.csr -CA ca.crt -CAkey ca.key -set_serial 0x2001 -days 36500 -extfile responder.ext -out responder.crt
+
+printf 'V\t21250101000000Z\t\t1001\tunknown\t/CN=localhost\n' > index.good.txt
+printf 'R\t21250101000000Z\t240101000000Z\t1001\tunknown\t/CN=localhost\n' > index.revoked.txt
+openssl ocsp -issuer ca.crt -cert server.leaf.crt -no_nonce -reqout request.der
+openssl ocsp -index index.good.txt -rsigner ca.crt -rkey ca.key -CA ca.crt -reqin request.der -ndays 36500 -respout good.der
+openssl ocsp -index index.revoked.txt -rsigner ca.crt -rkey ca.key -CA ca.crt -reqin request.der -ndays 36500 -respout revoked.der
+openssl ocsp -index index.good.txt -rsigner responder.crt -rkey responder.key -CA ca.crt -reqin request.der -ndays 36500 -respout delegated.der
+rm -f ca.key responder.key responder.crt server.csr responder.csr server.leaf.crt index.good.txt index.revoked.txt request.der
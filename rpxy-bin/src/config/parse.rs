@@ -3,17 +3,17 @@ use crate::error::{anyhow, ensure};
 use ahash::HashMap;
 use clap::Arg;
 use hot_reload::{ReloaderReceiver, ReloaderService};
-use rpxy_certs::{CryptoFileSourceBuilder, CryptoReloader, ServerCryptoBase, build_cert_reloader};
+use rpxy_certs::{CryptoFileSourceBuilder, CryptoReloader, ServerCryptoBase, TlsPolicy, build_cert_reloader};
 #[cfg(feature = "sticky-cookie")]
 use rpxy_lib::StickyCookieSecret;
 use rpxy_lib::{AppConfigList, ProxyConfig};
 #[cfg(feature = "sticky-cookie")]
 use std::sync::Arc;
 
-#[cfg(feature = "acme")]
-use rpxy_acme::{ACME_DIR_URL, ACME_REGISTRY_PATH, AcmeManager};
 #[cfg(feature = "acme")]
 use super::toml::validate_server_name;
+#[cfg(feature = "acme")]
+use rpxy_acme::{ACME_DIR_URL, ACME_REGISTRY_PATH, AcmeManager};
 
 /// Parsed options from CLI
 /// Options for configuring the application.
@@ -113,7 +113,14 @@ pub async fn build_cert_manager(
   anyhow::Error,
 > {
   let apps = config.apps.as_ref().ok_or(anyhow!("No apps"))?;
-  if config.listen_port_tls.is_none() {
+  // Streams terminating TLS load their certificates through the reloader as well
+  let stream_tls_options = config
+    .streams
+    .iter()
+    .flat_map(|streams| streams.0.values())
+    .filter_map(|stream| stream.tls.as_ref())
+    .collect::<Vec<_>>();
+  if config.listen_port_tls.is_none() && stream_tls_options.is_empty() {
     return Ok(None);
   }
 
@@ -132,7 +139,8 @@ pub async fn build_cert_manager(
 
   let mut crypto_source_map = HashMap::default();
   for app in apps.0.values() {
-    if let Some(tls) = app.tls.as_ref() {
+    // Apps passing TLS through hold no certificate
+    if let Some(tls) = app.tls.as_ref().filter(|tls| !tls.passthrough.unwrap_or(false)) {
       let server_name = app.server_name.as_ref().ok_or(anyhow!("No server name"))?;
 
       #[cfg(not(feature = "acme"))]
@@ -147,10 +155,42 @@ pub async fn build_cert_manager(
         .tls_cert_path(tls.tls_cert_path.as_ref().unwrap())
         .tls_cert_key_path(tls.tls_cert_key_path.as_ref().unwrap())
         .client_ca_cert_path(tls.client_ca_cert_path.as_deref())
+        .client_crl_path(tls.client_crl_path.as_deref())
+        .ocsp_response_path(tls.ocsp_response_path.as_deref())
+        .ocsp_fetch(tls.ocsp_fetch.unwrap_or(false))
+        .tls_policy(TlsPolicy::new(
+          tls.min_version.as_deref(),
+          tls.cipher_suites.as_deref(),
+          tls.kx_groups.as_deref(),
+        )?)
         .build()?;
-      crypto_source_map.insert(server_name.to_owned(), crypto_file_source);
+      crypto_source_map.insert(server_name.to_ascii_lowercase(), crypto_file_source);
     }
   }
+  for tls in stream_tls_options {
+    let crypto_file_source = CryptoFileSourceBuilder::default()
+      .tls_cert_path(&tls.tls_cert_path)
+      .tls_cert_key_path(&tls.tls_cert_key_path)
+      .client_ca_cert_path(tls.client_ca_cert_path.as_deref())
+      .client_crl_path(tls.client_crl_path.as_deref())
+      .ocsp_response_path(tls.ocsp_response_path.as_deref())
+      .ocsp_fetch(tls.ocsp_fetch.unwrap_or(false))
+      .tls_policy(TlsPolicy::new(
+        tls.min_version.as_deref(),
+        tls.cipher_suites.as_deref(),
+        tls.kx_groups.as_deref(),
+      )?)
+      .build()?;
+    // Apps and streams share the certificate map keyed by server name, so a stream must not shadow the
+    // certificate of an app. This is validated on loading settings, and enforced here as well.
+    ensure!(
+      crypto_source_map
+        .insert(tls.server_name.to_ascii_lowercase(), crypto_file_source)
+        .is_none(),
+      "tls.server_name {} of a stream is used by another app or stream",
+      tls.server_name
+    );
+  }
   let res = build_cert_reloader(&crypto_source_map, None).await?;
   Ok(Some(res))
 }
@@ -214,11 +254,35 @@ pub async fn build_acme_manager(
   Ok(Some(acme_manager))
 }
 
-#[cfg(all(test, feature = "acme"))]
+#[cfg(test)]
 mod tests {
-  use super::*;
+  #[cfg(feature = "acme")]
   use super::super::toml::{AcmeOption, TlsOption};
+  use super::*;
 
+  #[tokio::test]
+  async fn build_cert_manager_rejects_stream_shadowing_app_certificate() {
+    let config: ConfigToml = toml::from_str(
+      r#"
+listen_port_tls = 8443
+[apps.app1]
+server_name = "App.Example.com"
+reverse_proxy = [{ upstream = [{ location = "127.0.0.1:3000" }] }]
+tls = { tls_cert_path = "app.crt", tls_cert_key_path = "app.key" }
+[streams.s]
+listen = "0.0.0.0:5432"
+upstream = [{ location = "10.0.0.1:5432" }]
+tls = { server_name = "app.example.com", tls_cert_path = "stream.crt", tls_cert_key_path = "stream.key" }
+"#,
+    )
+    .unwrap();
+    let Err(err) = build_cert_manager(&config).await else {
+      panic!("stream sharing the server name of an app must be rejected");
+    };
+    assert!(err.to_string().contains("used by another app or stream"), "{err}");
+  }
+
+  #[cfg(feature = "acme")]
   #[test]
   fn build_tls_for_app_acme_rejects_traversal_server_name() {
     let mut tls = TlsOption {
@@ -236,6 +300,7 @@ mod tests {
     assert!(tls.tls_cert_key_path.is_none());
   }
 
+  #[cfg(feature = "acme")]
   #[tokio::test]
   async fn build_acme_manager_rejects_traversal_server_name_before_fs() {
     // Asserting the error is the validation error (not a write/IO error) proves the check
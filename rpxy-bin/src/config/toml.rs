@@ -4,20 +4,25 @@ use crate::{
   log::warn,
 };
 use ahash::HashMap;
+use rpxy_certs::TlsPolicy;
 use rpxy_lib::{
-  AppConfig, AppConfigList, ProxyConfig, ReverseProxyConfig, TlsConfig, UpstreamUri,
-  reexports::{IpNet, Uri},
+  AdminConfig, AdminListen, AppConfig, AppConfigList, ProxyConfig, ReverseProxyConfig, StreamConfig, StreamProtocol, TlsConfig,
+  UpstreamResolveConfig, UpstreamTlsConfig, UpstreamTlsVersion, UpstreamUri, reexports::IpNet,
 };
 use rpxy_trusted_proxies::resolve_trusted_proxy_entries;
 use serde::Deserialize;
 use std::{
   fs,
   net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  path::PathBuf,
 };
 use tokio::time::Duration;
 
 #[cfg(feature = "proxy-protocol")]
-use rpxy_lib::TcpRecvProxyProtocolConfig;
+use rpxy_lib::{ProxyProtocolVersion, TcpRecvProxyProtocolConfig};
+
+#[cfg(feature = "cache")]
+use rpxy_lib::CacheRuleConfig;
 
 #[cfg(feature = "health-check")]
 use rpxy_lib::{HealthCheckConfig, HealthCheckType, LOAD_BALANCE_PRIMARY_BACKUP};
@@ -132,6 +137,8 @@ fn parse_body_size_string(s: &str, key_name: &str) -> Result<Option<usize>, anyh
 /// - `request_max_body_size`: Optional maximum inbound request body size (h1/h2/h3 by default). Defaults to 256 MiB. Accepts an integer (bytes) or a string with a suffix (`"256k"`, `"10m"`, `"1g"`); set `0` or `"unlimited"` for no limit.
 /// - `apps`: Optional application definitions.
 /// - `default_app`: Optional default application name.
+/// - `streams`: Optional TCP and UDP stream proxy definitions.
+/// - `admin`: Optional local admin API for runtime inspection.
 /// - `experimental`: Optional experimental features.
 pub struct ConfigToml {
   pub listen_port: Option<u16>,
@@ -160,6 +167,8 @@ pub struct ConfigToml {
   pub request_max_body_size: Option<BodySizeValue>,
   pub apps: Option<Apps>,
   pub default_app: Option<String>,
+  pub streams: Option<Streams>,
+  pub admin: Option<AdminOption>,
   pub experimental: Option<Experimental>,
 }
 
@@ -216,9 +225,19 @@ impl ConfigTomlExt for ConfigToml {
       let app_config = app.build_app_config(&registered_app_name)?;
       app_config_list_inner.push(app_config);
     }
+
+    // Build stream configs
+    let mut streams = Vec::<StreamConfig>::new();
+    for (stream_name, stream) in self.streams.iter().flat_map(|s| s.0.iter()) {
+      let stream_config = stream.build_stream_config(stream_name)?;
+      validate_stream(&stream_config, &proxy_config, &app_config_list_inner, &streams)?;
+      streams.push(stream_config);
+    }
+
     let app_config_list = AppConfigList {
       inner: app_config_list_inner,
       default_app: self.default_app.clone().map(|v| v.to_ascii_lowercase()),
+      streams,
     };
 
     Ok((proxy_config, app_config_list))
@@ -285,6 +304,42 @@ pub struct CacheOption {
   pub max_cache_entry: Option<usize>,
   pub max_cache_each_size: Option<usize>,
   pub max_cache_each_size_on_memory: Option<usize>,
+  pub max_cache_memory_size: Option<u64>,
+  pub max_cache_disk_size: Option<u64>,
+  pub max_cache_variants: Option<usize>,
+  pub stale_if_error_grace: Option<u64>,
+  pub coalescing_timeout: Option<u64>,
+  pub cache_status: Option<bool>,
+  pub cache_status_key: Option<bool>,
+}
+
+#[cfg(feature = "cache")]
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+/// Cache rule of an app or a path. The TTLs are in seconds.
+pub struct CacheRuleOption {
+  pub enabled: Option<bool>,
+  pub default_ttl: Option<u64>,
+  pub max_ttl: Option<u64>,
+  pub ignore_query_params: Option<Vec<String>>,
+  pub sort_query: Option<bool>,
+  pub key_headers: Option<Vec<String>>,
+  pub bypass_cookies: Option<Vec<String>>,
+}
+
+#[cfg(feature = "cache")]
+impl CacheRuleOption {
+  /// Fill the options not given with the ones of `base`
+  fn or(&self, base: &Self) -> Self {
+    Self {
+      enabled: self.enabled.or(base.enabled),
+      default_ttl: self.default_ttl.or(base.default_ttl),
+      max_ttl: self.max_ttl.or(base.max_ttl),
+      ignore_query_params: self.ignore_query_params.clone().or_else(|| base.ignore_query_params.clone()),
+      sort_query: self.sort_query.or(base.sort_query),
+      key_headers: self.key_headers.clone().or_else(|| base.key_headers.clone()),
+      bypass_cookies: self.bypass_cookies.clone().or_else(|| base.bypass_cookies.clone()),
+    }
+  }
 }
 
 #[cfg(feature = "acme")]
@@ -320,6 +375,14 @@ pub struct Experimental {
   pub tcp_recv_proxy_protocol: Option<TcpRecvProxyProtocolOption>,
 }
 
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct AdminOption {
+  /// Loopback `address:port`, or `unix:<absolute path>` of a Unix domain socket
+  pub listen: String,
+  /// Bearer token required in the `Authorization` header of admin requests
+  pub token: String,
+}
+
 #[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
 pub struct Apps(pub HashMap<String, Application>);
 
@@ -328,6 +391,9 @@ pub struct Application {
   pub server_name: Option<String>,
   pub reverse_proxy: Option<Vec<ReverseProxyOption>>,
   pub tls: Option<TlsOption>,
+  #[cfg(feature = "cache")]
+  /// Cache rule of all the paths, overridden option by option by the one of each path
+  pub cache: Option<CacheRuleOption>,
 }
 
 #[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
@@ -336,8 +402,22 @@ pub struct TlsOption {
   pub tls_cert_key_path: Option<String>,
   pub https_redirection: Option<bool>,
   pub client_ca_cert_path: Option<String>,
+  /// CRLs of the client CAs to reject revoked client certificates
+  pub client_crl_path: Option<String>,
+  /// OCSP response in DER stapled to handshakes, also where the fetched one is saved with `ocsp_fetch`
+  pub ocsp_response_path: Option<String>,
+  /// Fetch and refresh the OCSP response from the responder given in the certificate
+  pub ocsp_fetch: Option<bool>,
+  /// Minimum TLS protocol version, "1.2" or "1.3"
+  pub min_version: Option<String>,
+  /// Cipher suites allowed in the order of preference, e.g., "TLS_AES_256_GCM_SHA384"
+  pub cipher_suites: Option<Vec<String>>,
+  /// Key exchange groups allowed in the order of preference, e.g., "X25519"
+  pub kx_groups: Option<Vec<String>>,
   #[cfg(feature = "acme")]
   pub acme: Option<bool>,
+  /// Pipe TLS connections to the upstream as they are, routed by SNI without termination
+  pub passthrough: Option<bool>,
 }
 
 #[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
@@ -346,9 +426,36 @@ pub struct ReverseProxyOption {
   pub replace_path: Option<String>,
   pub upstream: Vec<UpstreamParams>,
   pub upstream_options: Option<Vec<String>>,
+  /// Origins allowed to send gRPC-Web requests from browsers, e.g., "https://web.example.com", or "*" for any origin
+  pub grpc_web_allowed_origins: Option<Vec<String>>,
   pub load_balance: Option<String>,
   #[cfg(feature = "health-check")]
   pub health_check: Option<HealthCheckOption>,
+  /// `"static"` (default) or `"dynamic"`
+  pub resolve: Option<String>,
+  /// Re-resolution interval in seconds for `resolve = "dynamic"`
+  pub resolve_interval: Option<u64>,
+  /// JSON file listing the upstreams, watched and applied without restart
+  pub upstream_file: Option<String>,
+  /// TLS settings for connections to the upstreams
+  pub upstream_tls: Option<UpstreamTlsOption>,
+  #[cfg(feature = "proxy-protocol")]
+  /// `"v1"` or `"v2"`: PROXY protocol header sent on each new upstream connection
+  pub send_proxy_protocol: Option<String>,
+  #[cfg(feature = "cache")]
+  /// Cache rule of the path
+  pub cache: Option<CacheRuleOption>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct UpstreamTlsOption {
+  pub ca_cert_path: Option<String>,
+  pub client_cert_path: Option<String>,
+  pub client_key_path: Option<String>,
+  pub sni: Option<String>,
+  /// `"1.2"` (default) or `"1.3"`
+  pub min_version: Option<String>,
+  pub insecure_skip_verify: Option<bool>,
 }
 
 #[cfg(feature = "health-check")]
@@ -382,6 +489,43 @@ pub struct UpstreamParams {
   pub tls: Option<bool>,
 }
 
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct Streams(pub HashMap<String, StreamOption>);
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct StreamOption {
+  /// Listen socket address like `0.0.0.0:5432`
+  pub listen: String,
+  /// `"tcp"` (default) or `"udp"`
+  pub protocol: Option<String>,
+  pub upstream: Vec<UpstreamParams>,
+  pub load_balance: Option<String>,
+  #[cfg(feature = "health-check")]
+  pub health_check: Option<HealthCheckOption>,
+  /// Idle timeout in seconds of connections (TCP) or client sessions (UDP)
+  pub idle_timeout: Option<u64>,
+  #[cfg(feature = "proxy-protocol")]
+  /// Inbound PROXY protocol receive settings of the stream, only for TCP
+  pub recv_proxy_protocol: Option<TcpRecvProxyProtocolOption>,
+  /// TLS termination settings, only for TCP
+  pub tls: Option<StreamTlsOption>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct StreamTlsOption {
+  /// Server name of the certificate, also used to look it up in the certificate reloader
+  pub server_name: String,
+  pub tls_cert_path: String,
+  pub tls_cert_key_path: String,
+  pub client_ca_cert_path: Option<String>,
+  pub client_crl_path: Option<String>,
+  pub ocsp_response_path: Option<String>,
+  pub ocsp_fetch: Option<bool>,
+  pub min_version: Option<String>,
+  pub cipher_suites: Option<Vec<String>>,
+  pub kx_groups: Option<Vec<String>>,
+}
+
 impl TryInto<ProxyConfig> for &ConfigToml {
   type Error = anyhow::Error;
 
@@ -458,6 +602,10 @@ impl TryInto<ProxyConfig> for &ConfigToml {
       }
     }
 
+    if let Some(admin_option) = &self.admin {
+      proxy_config.admin = Some(build_admin_config(admin_option, &proxy_config)?);
+    }
+
     // experimental
     if let Some(exp) = &self.experimental {
       #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
@@ -523,31 +671,32 @@ impl TryInto<ProxyConfig> for &ConfigToml {
         if let Some(num) = cache_option.max_cache_each_size_on_memory {
           proxy_config.cache_max_each_size_on_memory = num;
         }
+        if let Some(num) = cache_option.max_cache_memory_size {
+          proxy_config.cache_max_memory_size = num;
+        }
+        if let Some(num) = cache_option.max_cache_disk_size {
+          proxy_config.cache_max_disk_size = num;
+        }
+        if let Some(num) = cache_option.max_cache_variants {
+          proxy_config.cache_max_variants = num;
+        }
+        if let Some(sec) = cache_option.stale_if_error_grace {
+          proxy_config.cache_stale_if_error_grace = Duration::from_secs(sec);
+        }
+        if let Some(sec) = cache_option.coalescing_timeout {
+          proxy_config.cache_coalescing_timeout = Duration::from_secs(sec);
+        }
+        if let Some(cache_status) = cache_option.cache_status {
+          proxy_config.cache_status_header = cache_status;
+        }
+        if let Some(cache_status_key) = cache_option.cache_status_key {
+          proxy_config.cache_status_key = cache_status_key;
+        }
       }
 
       #[cfg(feature = "proxy-protocol")]
       if let Some(pp_option) = &exp.tcp_recv_proxy_protocol {
-        ensure!(
-          !pp_option.trusted_proxies.is_empty(),
-          "tcp_recv_proxy_protocol.trusted_proxies must not be empty"
-        );
-        let trusted_proxies = pp_option
-          .trusted_proxies
-          .iter()
-          .map(|s| {
-            s.parse::<IpNet>()
-              .map_err(|e| anyhow!("Invalid CIDR in trusted_proxies: {s}: {e}"))
-          })
-          .collect::<Result<Vec<_>, _>>()?;
-        let timeout = match pp_option.timeout {
-          None => Duration::from_millis(rpxy_lib::proxy_protocol_defaults::TIMEOUT_MSEC),
-          Some(0) => Duration::ZERO,
-          Some(ms) => Duration::from_millis(ms),
-        };
-        proxy_config.tcp_recv_proxy_protocol = Some(std::sync::Arc::new(TcpRecvProxyProtocolConfig {
-          trusted_proxies,
-          timeout,
-        }));
+        proxy_config.tcp_recv_proxy_protocol = Some(build_recv_proxy_protocol_config(pp_option, "tcp_recv_proxy_protocol")?);
       }
     }
 
@@ -555,6 +704,83 @@ impl TryInto<ProxyConfig> for &ConfigToml {
   }
 }
 
+#[cfg(feature = "proxy-protocol")]
+/// Convert TOML inbound PROXY protocol option to internal config, with validation
+fn build_recv_proxy_protocol_config(
+  pp_option: &TcpRecvProxyProtocolOption,
+  key_name: &str,
+) -> Result<std::sync::Arc<TcpRecvProxyProtocolConfig>, anyhow::Error> {
+  ensure!(
+    !pp_option.trusted_proxies.is_empty(),
+    "{key_name}.trusted_proxies must not be empty"
+  );
+  let trusted_proxies = pp_option
+    .trusted_proxies
+    .iter()
+    .map(|s| {
+      s.parse::<IpNet>()
+        .map_err(|e| anyhow!("Invalid CIDR in trusted_proxies: {s}: {e}"))
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+  let timeout = match pp_option.timeout {
+    None => Duration::from_millis(rpxy_lib::proxy_protocol_defaults::TIMEOUT_MSEC),
+    Some(0) => Duration::ZERO,
+    Some(ms) => Duration::from_millis(ms),
+  };
+  Ok(std::sync::Arc::new(TcpRecvProxyProtocolConfig {
+    trusted_proxies,
+    timeout,
+  }))
+}
+
+/// Convert TOML admin API option to internal config, with validation.
+/// The admin API exposes the runtime state, so it only listens on a loopback address or a Unix domain socket.
+fn build_admin_config(option: &AdminOption, proxy_config: &ProxyConfig) -> Result<AdminConfig, anyhow::Error> {
+  ensure!(
+    option.token.len() >= ADMIN_TOKEN_MIN_LENGTH,
+    "admin.token must be at least {ADMIN_TOKEN_MIN_LENGTH} characters"
+  );
+  ensure!(
+    option.token.bytes().all(|b| b.is_ascii_graphic()),
+    "admin.token must consist of printable ASCII characters without spaces"
+  );
+
+  let token = option.token.clone();
+
+  if let Some(path) = option.listen.strip_prefix(ADMIN_LISTEN_UNIX_PREFIX) {
+    #[cfg(not(unix))]
+    return Err(anyhow!(
+      "admin.listen on a Unix domain socket is not supported on this platform: {path}"
+    ));
+    #[cfg(unix)]
+    {
+      let path = PathBuf::from(path);
+      ensure!(
+        path.is_absolute(),
+        "admin.listen must be an absolute Unix domain socket path: {path:?}"
+      );
+      return Ok(AdminConfig {
+        listen: AdminListen::Unix(path),
+        token,
+      });
+    }
+  }
+
+  let addr = option
+    .listen
+    .parse::<SocketAddr>()
+    .map_err(|e| anyhow!("admin.listen must be address:port or unix:<path>: {}: {e}", option.listen))?;
+  ensure!(addr.ip().is_loopback(), "admin.listen must be a loopback address: {addr}");
+  ensure!(
+    Some(addr.port()) != proxy_config.http_port && Some(addr.port()) != proxy_config.https_port,
+    "admin.listen port must differ from listen_port and listen_port_tls: {addr}"
+  );
+  Ok(AdminConfig {
+    listen: AdminListen::Tcp(addr),
+    token,
+  })
+}
+
 /// Validate and normalize listen address strings, then combine with ports to build socket addresses.
 /// Each field accepts one or more addresses. Accepts both bracketed (`[::1]`) and bare (`::1`) forms for IPv6.
 fn build_listen_sockets(
@@ -677,19 +903,34 @@ impl Application {
     // tls settings
     let tls_config = if self.tls.is_some() {
       let tls = self.tls.as_ref().unwrap();
+      let passthrough = tls.passthrough.unwrap_or(false);
 
+      if passthrough {
+        validate_tls_passthrough(server_name_string, tls, self.reverse_proxy.as_deref().unwrap_or_default())?;
+      }
       #[cfg(not(feature = "acme"))]
-      ensure!(tls.tls_cert_key_path.is_some() && tls.tls_cert_path.is_some());
+      ensure!(passthrough || (tls.tls_cert_key_path.is_some() && tls.tls_cert_path.is_some()));
 
       #[cfg(feature = "acme")]
       {
         if tls.acme.unwrap_or(false) {
           ensure!(tls.tls_cert_key_path.is_none() && tls.tls_cert_path.is_none());
-        } else {
+        } else if !passthrough {
           ensure!(tls.tls_cert_key_path.is_some() && tls.tls_cert_path.is_some());
         }
       }
 
+      ensure!(
+        tls.client_crl_path.is_none() || tls.client_ca_cert_path.is_some(),
+        "[{server_name_string}] client_crl_path requires client_ca_cert_path"
+      );
+      let tls_policy = TlsPolicy::new(
+        tls.min_version.as_deref(),
+        tls.cipher_suites.as_deref(),
+        tls.kx_groups.as_deref(),
+      )
+      .map_err(|e| anyhow!("[{server_name_string}] {e}"))?;
+
       let https_redirection = if tls.https_redirection.is_none() {
         true // Default true
       } else {
@@ -698,7 +939,9 @@ impl Application {
 
       Some(TlsConfig {
         mutual_tls: tls.client_ca_cert_path.is_some(),
+        restricted_crypto: tls_policy.is_crypto_restricted(),
         https_redirection,
+        passthrough,
         #[cfg(feature = "acme")]
         acme: tls.acme.unwrap_or(false),
       })
@@ -715,6 +958,120 @@ impl Application {
   }
 }
 
+impl StreamOption {
+  pub fn build_stream_config(&self, stream_name: &str) -> std::result::Result<StreamConfig, anyhow::Error> {
+    let listen = self
+      .listen
+      .parse::<SocketAddr>()
+      .map_err(|e| anyhow!("[{stream_name}] Invalid listen address \"{}\": {e}", self.listen))?;
+    let protocol = match self.protocol.as_deref().unwrap_or(STREAM_PROTOCOL_TCP) {
+      STREAM_PROTOCOL_TCP => StreamProtocol::Tcp,
+      STREAM_PROTOCOL_UDP => StreamProtocol::Udp,
+      other => return Err(anyhow!("[{stream_name}] Unknown stream protocol: \"{other}\"")),
+    };
+    let is_tcp = protocol == StreamProtocol::Tcp;
+
+    // Upstreams are plain TCP or UDP endpoints given with their ports
+    ensure!(
+      !self.upstream.is_empty(),
+      "[{stream_name}] At least one upstream must be specified"
+    );
+    let upstream = self
+      .upstream
+      .iter()
+      .map(|u| {
+        ensure!(u.tls.is_none(), "[{stream_name}] tls cannot be set for upstreams of streams");
+        let uri = UpstreamUri::from_location(&u.location, false).map_err(|e| anyhow!("[{stream_name}] {e}"))?;
+        let is_unix = uri.is_unix_socket();
+        ensure!(
+          (is_unix && is_tcp) || (!is_unix && uri.inner.port_u16().is_some()),
+          "[{stream_name}] Upstream location must be host:port (or unix:<path> for tcp): {}",
+          u.location
+        );
+        Ok(uri)
+      })
+      .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    #[cfg(feature = "sticky-cookie")]
+    ensure!(
+      self.load_balance.as_deref() != Some(LOAD_BALANCE_STICKY_ROUND_ROBIN),
+      "[{stream_name}] load_balance = \"{LOAD_BALANCE_STICKY_ROUND_ROBIN}\" cannot be used for streams"
+    );
+
+    #[cfg(feature = "health-check")]
+    let health_check = self
+      .health_check
+      .as_ref()
+      .map(|hc| build_health_check_config(hc, stream_name))
+      .transpose()?
+      .flatten();
+    #[cfg(feature = "health-check")]
+    {
+      ensure!(
+        health_check.is_none() || is_tcp,
+        "[{stream_name}] health_check is not supported for udp streams"
+      );
+      ensure!(
+        health_check.as_ref().is_none_or(|hc| hc.check_type == HealthCheckType::Tcp),
+        "[{stream_name}] Only TCP health checks are supported for streams"
+      );
+      validate_lb_health_check(stream_name, self.load_balance.as_deref(), &health_check)?;
+    }
+
+    let idle_timeout = match self.idle_timeout {
+      Some(0) => return Err(anyhow!("[{stream_name}] idle_timeout must be >= 1")),
+      Some(secs) => secs,
+      None if is_tcp => rpxy_lib::stream_defaults::DEFAULT_TCP_IDLE_TIMEOUT_SEC,
+      None => rpxy_lib::stream_defaults::DEFAULT_UDP_IDLE_TIMEOUT_SEC,
+    };
+
+    #[cfg(feature = "proxy-protocol")]
+    let recv_proxy_protocol = self
+      .recv_proxy_protocol
+      .as_ref()
+      .map(|pp| build_recv_proxy_protocol_config(pp, &format!("streams.{stream_name}.recv_proxy_protocol")))
+      .transpose()?;
+    #[cfg(feature = "proxy-protocol")]
+    ensure!(
+      recv_proxy_protocol.is_none() || is_tcp,
+      "[{stream_name}] recv_proxy_protocol is not supported for udp streams"
+    );
+
+    let tls_server_name = match &self.tls {
+      Some(tls) => {
+        ensure!(is_tcp, "[{stream_name}] tls is not supported for udp streams");
+        validate_server_name(&tls.server_name)?;
+        ensure!(
+          tls.client_crl_path.is_none() || tls.client_ca_cert_path.is_some(),
+          "[{stream_name}] client_crl_path requires client_ca_cert_path"
+        );
+        TlsPolicy::new(
+          tls.min_version.as_deref(),
+          tls.cipher_suites.as_deref(),
+          tls.kx_groups.as_deref(),
+        )
+        .map_err(|e| anyhow!("[{stream_name}] {e}"))?;
+        Some(tls.server_name.to_ascii_lowercase())
+      }
+      None => None,
+    };
+
+    Ok(StreamConfig {
+      name: stream_name.to_owned(),
+      listen,
+      protocol,
+      upstream,
+      load_balance: self.load_balance.clone(),
+      #[cfg(feature = "health-check")]
+      health_check,
+      idle_timeout: Duration::from_secs(idle_timeout),
+      #[cfg(feature = "proxy-protocol")]
+      recv_proxy_protocol,
+      tls_server_name,
+    })
+  }
+}
+
 impl TryInto<Vec<ReverseProxyConfig>> for &Application {
   type Error = anyhow::Error;
 
@@ -725,7 +1082,8 @@ impl TryInto<Vec<ReverseProxyConfig>> for &Application {
     let mut reverse_proxies: Vec<ReverseProxyConfig> = Vec::new();
 
     for rpo in rp_settings.iter() {
-      if rpo.upstream.is_empty() {
+      // With an upstream file, `upstream` only gives the initial set and may be empty.
+      if rpo.upstream.is_empty() && rpo.upstream_file.is_none() {
         return Err(anyhow!("[{}] At least one upstream must be specified", &_server_name_string));
       }
       let upstream_res: Vec<Option<UpstreamUri>> = rpo.upstream.iter().map(|v| v.try_into().ok()).collect();
@@ -742,14 +1100,48 @@ impl TryInto<Vec<ReverseProxyConfig>> for &Application {
         .transpose()?
         .flatten();
 
+      let upstream_tls = rpo
+        .upstream_tls
+        .as_ref()
+        .map(|tls| build_upstream_tls_config(tls, _server_name_string))
+        .transpose()?;
+      let resolve = build_upstream_resolve_config(rpo, _server_name_string)?;
+      #[cfg(feature = "proxy-protocol")]
+      let send_proxy_protocol = build_send_proxy_protocol(rpo, _server_name_string)?;
+      #[cfg(all(feature = "proxy-protocol", feature = "health-check"))]
+      ensure!(
+        send_proxy_protocol.is_none()
+          || !health_check
+            .as_ref()
+            .is_some_and(|hc| matches!(hc.check_type, HealthCheckType::Http { .. })),
+        "[{}] HTTP health checks cannot be combined with send_proxy_protocol, use type = \"tcp\"",
+        &_server_name_string
+      );
+      ensure!(
+        resolve.is_none() || rpo.upstream_file.is_none(),
+        "[{}] upstream_file cannot be combined with resolve = \"{UPSTREAM_RESOLVE_DYNAMIC}\"",
+        &_server_name_string
+      );
+      let grpc_web_allowed_origins = build_grpc_web_allowed_origins(rpo, _server_name_string)?;
+      #[cfg(feature = "cache")]
+      let cache = build_cache_rule_config(rpo.cache.as_ref(), self.cache.as_ref(), _server_name_string)?;
+
       reverse_proxies.push(ReverseProxyConfig {
         path: rpo.path.clone(),
         replace_path: rpo.replace_path.clone(),
         upstream,
         upstream_options: rpo.upstream_options.clone(),
+        grpc_web_allowed_origins,
         load_balance: rpo.load_balance.clone(),
         #[cfg(feature = "health-check")]
         health_check,
+        resolve,
+        upstream_file: rpo.upstream_file.as_ref().map(PathBuf::from),
+        upstream_tls,
+        #[cfg(feature = "proxy-protocol")]
+        send_proxy_protocol,
+        #[cfg(feature = "cache")]
+        cache,
       })
     }
 
@@ -761,15 +1153,176 @@ impl TryInto<UpstreamUri> for &UpstreamParams {
   type Error = anyhow::Error;
 
   fn try_into(self) -> std::result::Result<UpstreamUri, Self::Error> {
-    let scheme = match self.tls {
-      Some(true) => "https",
-      _ => "http",
-    };
-    let location = format!("{}://{}", scheme, self.location);
-    Ok(UpstreamUri {
-      inner: location.parse::<Uri>().map_err(|e| anyhow!("{}", e))?,
-    })
+    UpstreamUri::from_location(&self.location, self.tls == Some(true)).map_err(|e| anyhow!("{}", e))
+  }
+}
+
+/// Convert TOML upstream resolution options to internal config, with validation
+fn build_upstream_resolve_config(
+  rpo: &ReverseProxyOption,
+  server_name: &str,
+) -> Result<Option<UpstreamResolveConfig>, anyhow::Error> {
+  match rpo.resolve.as_deref().unwrap_or(UPSTREAM_RESOLVE_STATIC) {
+    UPSTREAM_RESOLVE_STATIC => {
+      ensure!(
+        rpo.resolve_interval.is_none(),
+        "[{server_name}] resolve_interval requires resolve = \"{UPSTREAM_RESOLVE_DYNAMIC}\""
+      );
+      Ok(None)
+    }
+    UPSTREAM_RESOLVE_DYNAMIC => {
+      // Upstreams are dialed by address after resolution, so the server name for TLS would be lost
+      // unless it is given explicitly.
+      let has_sni = rpo.upstream_tls.as_ref().is_some_and(|tls| tls.sni.is_some());
+      ensure!(
+        has_sni || rpo.upstream.iter().all(|u| u.tls != Some(true)),
+        "[{server_name}] resolve = \"{UPSTREAM_RESOLVE_DYNAMIC}\" is not supported for TLS upstreams without upstream_tls.sni"
+      );
+      let interval = rpo
+        .resolve_interval
+        .unwrap_or(rpxy_lib::upstream_resolve_defaults::DEFAULT_INTERVAL_SEC);
+      ensure!(interval >= 1, "[{server_name}] resolve_interval must be >= 1");
+      Ok(Some(UpstreamResolveConfig {
+        interval: Duration::from_secs(interval),
+      }))
+    }
+    other => Err(anyhow!("[{server_name}] Unknown resolve mode: \"{other}\"")),
+  }
+}
+
+/// Validate the origins allowed for gRPC-Web, each of which must be `*` or `scheme://host[:port]` of http or https
+fn build_grpc_web_allowed_origins(rpo: &ReverseProxyOption, server_name: &str) -> Result<Option<Vec<String>>, anyhow::Error> {
+  let Some(origins) = rpo.grpc_web_allowed_origins.as_ref() else {
+    return Ok(None);
+  };
+  ensure!(
+    rpo
+      .upstream_options
+      .as_ref()
+      .is_some_and(|opts| opts.iter().any(|opt| opt == "grpc_web")),
+    "[{server_name}] grpc_web_allowed_origins requires the grpc_web upstream option"
+  );
+  for origin in origins.iter() {
+    let valid = origin == "*"
+      || ["http://", "https://"].iter().any(|scheme| {
+        origin.len() > scheme.len()
+          && origin[..scheme.len()].eq_ignore_ascii_case(scheme)
+          && !origin[scheme.len()..].contains(['/', '?', '#', '@', ' '])
+      });
+    ensure!(
+      valid,
+      "[{server_name}] Invalid grpc_web_allowed_origins entry: \"{origin}\", expected \"*\" or \"scheme://host[:port]\""
+    );
+  }
+  Ok(Some(origins.clone()))
+}
+
+#[cfg(feature = "proxy-protocol")]
+/// Convert TOML `send_proxy_protocol` option to the PROXY protocol version sent to upstreams
+fn build_send_proxy_protocol(rpo: &ReverseProxyOption, server_name: &str) -> Result<Option<ProxyProtocolVersion>, anyhow::Error> {
+  match rpo.send_proxy_protocol.as_deref() {
+    None => Ok(None),
+    Some(SEND_PROXY_PROTOCOL_V1) => Ok(Some(ProxyProtocolVersion::V1)),
+    Some(SEND_PROXY_PROTOCOL_V2) => Ok(Some(ProxyProtocolVersion::V2)),
+    Some(other) => Err(anyhow!("[{server_name}] Unknown send_proxy_protocol version: \"{other}\"")),
+  }
+}
+
+#[cfg(feature = "cache")]
+/// Convert TOML cache rule options of a path and its app to internal config, with validation. None if neither
+/// is given.
+fn build_cache_rule_config(
+  path_option: Option<&CacheRuleOption>,
+  app_option: Option<&CacheRuleOption>,
+  server_name: &str,
+) -> Result<Option<CacheRuleConfig>, anyhow::Error> {
+  let option = match (path_option, app_option) {
+    (None, None) => return Ok(None),
+    (Some(option), None) | (None, Some(option)) => option.clone(),
+    (Some(path_option), Some(app_option)) => path_option.or(app_option),
+  };
+  let default_ttl = option.default_ttl.map(Duration::from_secs);
+  let max_ttl = option.max_ttl.map(Duration::from_secs);
+  if let (Some(default_ttl), Some(max_ttl)) = (default_ttl, max_ttl) {
+    ensure!(
+      default_ttl <= max_ttl,
+      "[{server_name}] cache.default_ttl must not exceed cache.max_ttl"
+    );
+  }
+  // Tokens of RFC 9110, of which header names and cookie names consist
+  let is_token = |s: &str| {
+    !s.is_empty()
+      && s
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+  };
+
+  let ignore_query_params = option.ignore_query_params.unwrap_or_default();
+  for pattern in ignore_query_params.iter() {
+    let name = pattern.strip_suffix('*').unwrap_or(pattern);
+    ensure!(
+      !pattern.is_empty() && !name.contains(['*', '&', '=']),
+      "[{server_name}] Invalid query parameter in cache.ignore_query_params: \"{pattern}\""
+    );
+  }
+  let key_headers = option.key_headers.unwrap_or_default();
+  for name in key_headers.iter() {
+    ensure!(
+      is_token(name),
+      "[{server_name}] Invalid header name in cache.key_headers: \"{name}\""
+    );
+  }
+  let bypass_cookies = option.bypass_cookies.unwrap_or_default();
+  for name in bypass_cookies.iter() {
+    ensure!(
+      is_token(name),
+      "[{server_name}] Invalid cookie name in cache.bypass_cookies: \"{name}\""
+    );
+  }
+
+  Ok(Some(CacheRuleConfig {
+    enabled: option.enabled.unwrap_or(true),
+    default_ttl,
+    max_ttl,
+    ignore_query_params,
+    sort_query: option.sort_query.unwrap_or(false),
+    key_headers: key_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+    bypass_cookies,
+  }))
+}
+
+/// Convert TOML upstream TLS option to internal config, with validation. The files are read when
+/// the forwarder is built.
+fn build_upstream_tls_config(option: &UpstreamTlsOption, server_name: &str) -> Result<UpstreamTlsConfig, anyhow::Error> {
+  let min_version = match option.min_version.as_deref().unwrap_or(UPSTREAM_TLS_VERSION_1_2) {
+    UPSTREAM_TLS_VERSION_1_2 => UpstreamTlsVersion::Tls12,
+    UPSTREAM_TLS_VERSION_1_3 => UpstreamTlsVersion::Tls13,
+    other => return Err(anyhow!("[{server_name}] Unknown upstream_tls.min_version: \"{other}\"")),
+  };
+  ensure!(
+    option.client_cert_path.is_some() == option.client_key_path.is_some(),
+    "[{server_name}] upstream_tls.client_cert_path and upstream_tls.client_key_path must be given together"
+  );
+  let insecure_skip_verify = option.insecure_skip_verify.unwrap_or(false);
+  ensure!(
+    !(insecure_skip_verify && option.ca_cert_path.is_some()),
+    "[{server_name}] upstream_tls.ca_cert_path cannot be combined with insecure_skip_verify"
+  );
+  if insecure_skip_verify {
+    warn!("[{server_name}] upstream_tls.insecure_skip_verify is enabled: upstream server certificates are not verified");
+  }
+  if let Some(sni) = &option.sni {
+    ensure!(!sni.is_empty(), "[{server_name}] upstream_tls.sni must not be empty");
   }
+
+  Ok(UpstreamTlsConfig {
+    ca_cert_path: option.ca_cert_path.as_ref().map(PathBuf::from),
+    client_cert_path: option.client_cert_path.as_ref().map(PathBuf::from),
+    client_key_path: option.client_key_path.as_ref().map(PathBuf::from),
+    sni: option.sni.clone(),
+    min_version,
+    insecure_skip_verify,
+  })
 }
 
 #[cfg(feature = "health-check")]
@@ -844,6 +1397,89 @@ fn build_health_check_config(option: &HealthCheckOption, server_name: &str) -> R
   }
 }
 
+/// Validate an app passing TLS through: rpxy holds no certificate for it, and connections are routed by SNI only,
+/// so HTTP-level settings cannot apply.
+fn validate_tls_passthrough(
+  server_name: &str,
+  tls: &TlsOption,
+  reverse_proxy: &[ReverseProxyOption],
+) -> Result<(), anyhow::Error> {
+  #[cfg(feature = "acme")]
+  let acme = tls.acme.unwrap_or(false);
+  #[cfg(not(feature = "acme"))]
+  let acme = false;
+  ensure!(
+    tls.tls_cert_path.is_none()
+      && tls.tls_cert_key_path.is_none()
+      && tls.client_ca_cert_path.is_none()
+      && tls.ocsp_response_path.is_none()
+      && !tls.ocsp_fetch.unwrap_or(false)
+      && tls.min_version.is_none()
+      && tls.cipher_suites.is_none()
+      && tls.kx_groups.is_none()
+      && !acme,
+    "[{server_name}] tls.passthrough cannot be combined with certificates, client_ca_cert_path, OCSP stapling, TLS versions and ciphers or acme"
+  );
+  let [rpo] = reverse_proxy else {
+    return Err(anyhow!("[{server_name}] tls.passthrough requires exactly one reverse_proxy"));
+  };
+  ensure!(
+    rpo.path.is_none() && rpo.replace_path.is_none(),
+    "[{server_name}] path and replace_path cannot be used with tls.passthrough"
+  );
+  ensure!(
+    rpo.upstream_options.as_ref().is_none_or(|opts| opts.is_empty()) && rpo.upstream_tls.is_none(),
+    "[{server_name}] upstream_options and upstream_tls cannot be used with tls.passthrough"
+  );
+  #[cfg(feature = "sticky-cookie")]
+  ensure!(
+    rpo.load_balance.as_deref() != Some(LOAD_BALANCE_STICKY_ROUND_ROBIN),
+    "[{server_name}] load_balance = \"{LOAD_BALANCE_STICKY_ROUND_ROBIN}\" cannot be used with tls.passthrough"
+  );
+  Ok(())
+}
+
+/// Validate a stream against the HTTP listeners, the apps and the streams validated so far: listen sockets must not
+/// collide, and the certificate of a TLS-terminating stream is looked up by a server name of its own.
+fn validate_stream(
+  stream: &StreamConfig,
+  proxy_config: &ProxyConfig,
+  apps: &[AppConfig],
+  streams: &[StreamConfig],
+) -> Result<(), anyhow::Error> {
+  let name = &stream.name;
+  let port = stream.listen.port();
+  #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
+  let http3 = proxy_config.http3;
+  #[cfg(not(any(feature = "http3-quinn", feature = "http3-s2n")))]
+  let http3 = false;
+  match stream.protocol {
+    StreamProtocol::Tcp => ensure!(
+      proxy_config.http_port != Some(port) && proxy_config.https_port != Some(port),
+      "[{name}] listen port {port} is used by the HTTP(S) listener"
+    ),
+    StreamProtocol::Udp => ensure!(
+      !(http3 && proxy_config.https_port == Some(port)),
+      "[{name}] listen port {port} is used by the HTTP/3 listener"
+    ),
+  }
+  ensure!(
+    !streams
+      .iter()
+      .any(|s| s.protocol == stream.protocol && s.listen == stream.listen),
+    "[{name}] listen address {} is used by another stream",
+    stream.listen
+  );
+  if let Some(server_name) = &stream.tls_server_name {
+    ensure!(
+      !apps.iter().any(|app| app.server_name.eq_ignore_ascii_case(server_name))
+        && !streams.iter().any(|s| s.tls_server_name.as_ref() == Some(server_name)),
+      "[{name}] tls.server_name {server_name} is used by another app or stream"
+    );
+  }
+  Ok(())
+}
+
 #[cfg(feature = "health-check")]
 /// Validate load balance + health check combinations
 /// Currently only "primary_backup" requires health check, and other load balance strategies don't have specific requirements for health checks.
@@ -923,11 +1559,22 @@ mod tests {
             tls: None,
           }],
           upstream_options: None,
+          grpc_web_allowed_origins: None,
           load_balance: load_balance.map(str::to_string),
           #[cfg(feature = "health-check")]
           health_check: None,
+          resolve: None,
+          resolve_interval: None,
+          upstream_file: None,
+          upstream_tls: None,
+          #[cfg(feature = "proxy-protocol")]
+          send_proxy_protocol: None,
+          #[cfg(feature = "cache")]
+          cache: None,
         }]),
         tls: None,
+        #[cfg(feature = "cache")]
+        cache: None,
       },
     );
 
@@ -1163,6 +1810,37 @@ mod tests {
     assert!(result.err().unwrap().to_string().contains("public_https_port"));
   }
 
+  #[test]
+  fn admin_option() {
+    let build = |admin: &str| {
+      let toml_str = format!("listen_port = 8080\nlisten_port_tls = 8443\n[admin]\n{admin}");
+      let config: ConfigToml = toml::from_str(&toml_str).unwrap();
+      let result: Result<ProxyConfig, _> = (&config).try_into();
+      result.map(|proxy_config| proxy_config.admin.unwrap())
+    };
+
+    let admin = build("listen = '127.0.0.1:9901'\ntoken = '0123456789abcdef'").unwrap();
+    assert_eq!(admin.listen, AdminListen::Tcp("127.0.0.1:9901".parse().unwrap()));
+    assert_eq!(admin.token, "0123456789abcdef");
+    #[cfg(unix)]
+    assert_eq!(
+      build("listen = 'unix:/run/rpxy/admin.sock'\ntoken = '0123456789abcdef'")
+        .unwrap()
+        .listen,
+      AdminListen::Unix(PathBuf::from("/run/rpxy/admin.sock"))
+    );
+
+    // Not loopback
+    assert!(build("listen = '0.0.0.0:9901'\ntoken = '0123456789abcdef'").is_err());
+    // Same port as the proxy listener
+    assert!(build("listen = '127.0.0.1:8443'\ntoken = '0123456789abcdef'").is_err());
+    // Relative socket path
+    assert!(build("listen = 'unix:admin.sock'\ntoken = '0123456789abcdef'").is_err());
+    // Too short or containing spaces
+    assert!(build("listen = '127.0.0.1:9901'\ntoken = 'short'").is_err());
+    assert!(build("listen = '127.0.0.1:9901'\ntoken = '0123456789 abcdef'").is_err());
+  }
+
   #[test]
   fn old_https_redirection_port_is_rejected() {
     let toml_str = r#"
@@ -1376,11 +2054,22 @@ mod tests {
         replace_path: None,
         upstream: vec![],
         upstream_options: None,
+        grpc_web_allowed_origins: None,
         load_balance: None,
         #[cfg(feature = "health-check")]
         health_check: None,
+        resolve: None,
+        resolve_interval: None,
+        upstream_file: None,
+        upstream_tls: None,
+        #[cfg(feature = "proxy-protocol")]
+        send_proxy_protocol: None,
+        #[cfg(feature = "cache")]
+        cache: None,
       }]),
       tls: None,
+      #[cfg(feature = "cache")]
+      cache: None,
     };
     let result: Result<Vec<ReverseProxyConfig>, _> = (&app).try_into();
     assert!(result.is_err());
@@ -1388,6 +2077,509 @@ mod tests {
     assert!(err.to_string().contains("At least one upstream must be specified"));
   }
 
+  #[cfg(unix)]
+  #[test]
+  fn unix_socket_upstream_location() {
+    let params = UpstreamParams {
+      location: "unix:/run/app.sock".to_string(),
+      tls: None,
+    };
+    let upstream: UpstreamUri = (&params).try_into().unwrap();
+    assert_eq!(upstream.inner.scheme_str(), Some("unix"));
+    assert_eq!(
+      upstream.inner,
+      UpstreamUri::from_unix_socket_path("/run/app.sock").unwrap().inner
+    );
+
+    let relative = UpstreamParams {
+      location: "unix:run/app.sock".to_string(),
+      tls: None,
+    };
+    assert!(TryInto::<UpstreamUri>::try_into(&relative).is_err());
+
+    let with_tls = UpstreamParams {
+      location: "unix:/run/app.sock".to_string(),
+      tls: Some(true),
+    };
+    let Err(err) = TryInto::<UpstreamUri>::try_into(&with_tls) else {
+      panic!("tls must be rejected for a unix socket upstream");
+    };
+    assert!(err.to_string().contains("TLS is not supported"));
+  }
+
+  #[test]
+  fn upstream_file_option() {
+    let mut app = Application {
+      server_name: Some("example.com".into()),
+      reverse_proxy: Some(vec![ReverseProxyOption {
+        upstream_file: Some("/var/run/rpxy/api.json".to_string()),
+        ..Default::default()
+      }]),
+      tls: None,
+      #[cfg(feature = "cache")]
+      cache: None,
+    };
+    // The upstream list may be empty when an upstream file is given.
+    let rpc: Vec<ReverseProxyConfig> = (&app).try_into().unwrap();
+    assert!(rpc[0].upstream.is_empty());
+    assert_eq!(
+      rpc[0].upstream_file.as_deref(),
+      Some(std::path::Path::new("/var/run/rpxy/api.json"))
+    );
+
+    app.reverse_proxy.as_mut().unwrap()[0].resolve = Some("dynamic".to_string());
+    let Err(err) = TryInto::<Vec<ReverseProxyConfig>>::try_into(&app) else {
+      panic!("upstream_file must be rejected with resolve = \"dynamic\"");
+    };
+    assert!(err.to_string().contains("upstream_file cannot be combined"));
+  }
+
+  #[cfg(feature = "cache")]
+  #[test]
+  fn cache_rule_option() {
+    let app: Application = toml::from_str(
+      r#"
+      server_name = "example.com"
+      cache = { default_ttl = 60, ignore_query_params = ["utm_*"], key_headers = ["Accept-Language"], bypass_cookies = ["session"] }
+      [[reverse_proxy]]
+      upstream = [{ location = "backend.local:8080" }]
+      [[reverse_proxy]]
+      path = "/api"
+      upstream = [{ location = "backend.local:8081" }]
+      cache = { enabled = false }
+      "#,
+    )
+    .unwrap();
+    let rpc: Vec<ReverseProxyConfig> = (&app).try_into().unwrap();
+    let config = rpc[0].cache.as_ref().unwrap();
+    assert!(config.enabled);
+    assert_eq!(config.default_ttl, Some(Duration::from_secs(60)));
+    assert_eq!(config.max_ttl, None);
+    assert_eq!(config.ignore_query_params, vec!["utm_*".to_string()]);
+    assert!(!config.sort_query);
+    assert_eq!(config.key_headers, vec!["accept-language".to_string()]);
+    assert_eq!(config.bypass_cookies, vec!["session".to_string()]);
+    // The rule of the path overrides the one of the app option by option
+    let config = rpc[1].cache.as_ref().unwrap();
+    assert!(!config.enabled);
+    assert_eq!(config.default_ttl, Some(Duration::from_secs(60)));
+
+    assert!(build_cache_rule_config(None, None, "example.com").unwrap().is_none());
+    for invalid in [
+      CacheRuleOption {
+        default_ttl: Some(600),
+        max_ttl: Some(60),
+        ..Default::default()
+      },
+      CacheRuleOption {
+        ignore_query_params: Some(vec!["utm_*_id".to_string()]),
+        ..Default::default()
+      },
+      CacheRuleOption {
+        ignore_query_params: Some(vec![String::new()]),
+        ..Default::default()
+      },
+      CacheRuleOption {
+        key_headers: Some(vec!["x header".to_string()]),
+        ..Default::default()
+      },
+      CacheRuleOption {
+        bypass_cookies: Some(vec!["session=1".to_string()]),
+        ..Default::default()
+      },
+    ] {
+      assert!(build_cache_rule_config(Some(&invalid), None, "example.com").is_err());
+    }
+  }
+
+  #[cfg(feature = "proxy-protocol")]
+  #[test]
+  fn send_proxy_protocol_option() {
+    let mut app = Application {
+      server_name: Some("example.com".into()),
+      reverse_proxy: Some(vec![ReverseProxyOption {
+        upstream: vec![UpstreamParams {
+          location: "10.0.0.1:8080".to_string(),
+          tls: None,
+        }],
+        send_proxy_protocol: Some("v2".to_string()),
+        ..Default::default()
+      }]),
+      tls: None,
+      #[cfg(feature = "cache")]
+      cache: None,
+    };
+    let rpc: Vec<ReverseProxyConfig> = (&app).try_into().unwrap();
+    assert_eq!(rpc[0].send_proxy_protocol, Some(ProxyProtocolVersion::V2));
+
+    app.reverse_proxy.as_mut().unwrap()[0].send_proxy_protocol = Some("v3".to_string());
+    assert!(TryInto::<Vec<ReverseProxyConfig>>::try_into(&app).is_err());
+
+    // HTTP health check probes would not carry the PROXY protocol header.
+    #[cfg(feature = "health-check")]
+    {
+      let rpo = &mut app.reverse_proxy.as_mut().unwrap()[0];
+      rpo.send_proxy_protocol = Some("v1".to_string());
+      rpo.health_check = Some(http_health_check_option(
+        Some("/healthz"),
+        Some(10),
+        Some(5),
+        Some(2),
+        Some(2),
+      ));
+      let Err(err) = TryInto::<Vec<ReverseProxyConfig>>::try_into(&app) else {
+        panic!("HTTP health check must be rejected with send_proxy_protocol");
+      };
+      assert!(err.to_string().contains("cannot be combined with send_proxy_protocol"));
+      app.reverse_proxy.as_mut().unwrap()[0].health_check = Some(HealthCheckOption::Enabled(true));
+      assert!(TryInto::<Vec<ReverseProxyConfig>>::try_into(&app).is_ok());
+    }
+  }
+
+  #[test]
+  fn tls_passthrough_option() {
+    let mut app = Application {
+      server_name: Some("pass.example.com".into()),
+      reverse_proxy: Some(vec![ReverseProxyOption {
+        upstream: vec![UpstreamParams {
+          location: "10.0.0.1:8443".to_string(),
+          tls: None,
+        }],
+        ..Default::default()
+      }]),
+      tls: Some(TlsOption {
+        passthrough: Some(true),
+        ..Default::default()
+      }),
+      #[cfg(feature = "cache")]
+      cache: None,
+    };
+    let app_config = app.build_app_config("pass").unwrap();
+    assert!(app_config.tls.unwrap().passthrough);
+
+    // rpxy holds no certificate for the app
+    app.tls.as_mut().unwrap().tls_cert_path = Some("/etc/rpxy/cert.pem".to_string());
+    app.tls.as_mut().unwrap().tls_cert_key_path = Some("/etc/rpxy/key.pem".to_string());
+    let Err(err) = app.build_app_config("pass") else {
+      panic!("certificates must be rejected with tls.passthrough");
+    };
+    assert!(err.to_string().contains("cannot be combined with certificates"));
+    app.tls.as_mut().unwrap().tls_cert_path = None;
+    app.tls.as_mut().unwrap().tls_cert_key_path = None;
+
+    // Routing by path is not possible without termination
+    app.reverse_proxy.as_mut().unwrap()[0].path = Some("/api".to_string());
+    let Err(err) = app.build_app_config("pass") else {
+      panic!("path must be rejected with tls.passthrough");
+    };
+    assert!(err.to_string().contains("path and replace_path cannot be used"));
+    app.reverse_proxy.as_mut().unwrap()[0].path = None;
+
+    app.reverse_proxy.as_mut().unwrap()[0].upstream_options = Some(vec!["keep_original_host".to_string()]);
+    assert!(app.build_app_config("pass").is_err());
+    app.reverse_proxy.as_mut().unwrap()[0].upstream_options = None;
+
+    // Nor an OCSP response to staple
+    app.tls.as_mut().unwrap().ocsp_fetch = Some(true);
+    let Err(err) = app.build_app_config("pass") else {
+      panic!("OCSP stapling must be rejected with tls.passthrough");
+    };
+    assert!(err.to_string().contains("OCSP stapling"));
+    app.tls.as_mut().unwrap().ocsp_fetch = None;
+
+    // Nor a TLS policy
+    app.tls.as_mut().unwrap().min_version = Some("1.3".to_string());
+    assert!(app.build_app_config("pass").is_err());
+  }
+
+  #[test]
+  fn app_tls_policy_option() {
+    let build = |tls: &str| {
+      let config: ConfigToml = toml::from_str(&format!(
+        r#"
+        listen_port = 8080
+        listen_port_tls = 8443
+        [apps.app]
+        server_name = "app.example.com"
+        reverse_proxy = [{{ upstream = [{{ location = "127.0.0.1:3000" }}] }}]
+        tls = {{ tls_cert_path = "/etc/rpxy/cert.pem", tls_cert_key_path = "/etc/rpxy/key.pem", {tls} }}
+      "#
+      ))
+      .unwrap();
+      config
+        .validate_and_build_settings()
+        .map(|(_, app_config_list)| app_config_list.inner[0].tls.clone().unwrap())
+    };
+
+    let tls = build(r#"min_version = "1.3""#).unwrap();
+    assert!(!tls.restricted_crypto);
+    let tls =
+      build(r#"min_version = "1.2", cipher_suites = ["TLS_AES_256_GCM_SHA384", "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"]"#)
+        .unwrap();
+    assert!(tls.restricted_crypto);
+    let tls = build(r#"kx_groups = ["X25519", "secp256r1"]"#).unwrap();
+    assert!(tls.restricted_crypto);
+
+    // Rejected at config load
+    assert!(build(r#"min_version = "1.1""#).is_err());
+    assert!(build(r#"cipher_suites = ["TLS_RSA_WITH_RC4_128_SHA"]"#).is_err());
+    assert!(build(r#"kx_groups = []"#).is_err());
+    assert!(build(r#"min_version = "1.3", cipher_suites = ["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"]"#).is_err());
+  }
+
+  #[test]
+  fn app_client_crl_path_requires_client_ca() {
+    let mut app = Application {
+      server_name: Some("mtls.example.com".into()),
+      reverse_proxy: Some(vec![ReverseProxyOption {
+        upstream: vec![UpstreamParams {
+          location: "10.0.0.1:8080".to_string(),
+          tls: None,
+        }],
+        ..Default::default()
+      }]),
+      tls: Some(TlsOption {
+        tls_cert_path: Some("/etc/rpxy/cert.pem".to_string()),
+        tls_cert_key_path: Some("/etc/rpxy/key.pem".to_string()),
+        client_crl_path: Some("/etc/rpxy/client.crl.pem".to_string()),
+        ..Default::default()
+      }),
+      #[cfg(feature = "cache")]
+      cache: None,
+    };
+    let Err(err) = app.build_app_config("mtls") else {
+      panic!("client_crl_path must be rejected without client_ca_cert_path");
+    };
+    assert!(err.to_string().contains("client_crl_path requires client_ca_cert_path"));
+
+    app.tls.as_mut().unwrap().client_ca_cert_path = Some("/etc/rpxy/client.ca.pem".to_string());
+    assert!(app.build_app_config("mtls").unwrap().tls.unwrap().mutual_tls);
+  }
+
+  #[test]
+  fn stream_option() {
+    let config: ConfigToml = toml::from_str(
+      r#"
+      listen_port = 8080
+      [apps.app]
+      server_name = "app.example.com"
+      reverse_proxy = [{ upstream = [{ location = "127.0.0.1:3000" }] }]
+      [streams.db]
+      listen = "0.0.0.0:5432"
+      upstream = [{ location = "10.0.0.1:5432" }, { location = "10.0.0.2:5432" }]
+      load_balance = "round_robin"
+      tls = { server_name = "db.example.com", tls_cert_path = "/etc/rpxy/db.pem", tls_cert_key_path = "/etc/rpxy/db.key" }
+      [streams.dns]
+      listen = "[::]:53"
+      protocol = "udp"
+      upstream = [{ location = "10.0.0.53:53" }]
+    "#,
+    )
+    .unwrap();
+    let (_, app_config_list) = config.validate_and_build_settings().unwrap();
+    let mut streams = app_config_list.streams;
+    streams.sort_by(|a, b| a.name.cmp(&b.name));
+    let [db, dns] = streams.as_slice() else {
+      panic!("two streams must be built");
+    };
+    assert_eq!(db.protocol, StreamProtocol::Tcp);
+    assert_eq!(db.listen, "0.0.0.0:5432".parse::<SocketAddr>().unwrap());
+    assert_eq!(db.upstream.len(), 2);
+    assert_eq!(db.tls_server_name.as_deref(), Some("db.example.com"));
+    assert_eq!(
+      db.idle_timeout,
+      Duration::from_secs(rpxy_lib::stream_defaults::DEFAULT_TCP_IDLE_TIMEOUT_SEC)
+    );
+    assert_eq!(dns.protocol, StreamProtocol::Udp);
+    assert_eq!(
+      dns.idle_timeout,
+      Duration::from_secs(rpxy_lib::stream_defaults::DEFAULT_UDP_IDLE_TIMEOUT_SEC)
+    );
+  }
+
+  #[test]
+  fn stream_option_validation() {
+    let build = |stream: &str| {
+      let config: ConfigToml = toml::from_str(&format!(
+        r#"
+        listen_port = 8080
+        [apps.app]
+        server_name = "app.example.com"
+        reverse_proxy = [{{ upstream = [{{ location = "127.0.0.1:3000" }}] }}]
+        [streams.s]
+        {stream}
+      "#
+      ))
+      .unwrap();
+      config.validate_and_build_settings().map(|_| ())
+    };
+    let upstream = r#"upstream = [{ location = "10.0.0.1:5432" }]"#;
+    assert!(build(&format!("listen = \"0.0.0.0:5432\"\n{upstream}")).is_ok());
+
+    let cases = [
+      // Ports of upstreams are mandatory
+      (
+        "listen = \"0.0.0.0:5432\"\nupstream = [{ location = \"10.0.0.1\" }]".to_string(),
+        "host:port",
+      ),
+      // The port of the HTTP listener
+      (
+        format!("listen = \"0.0.0.0:8080\"\n{upstream}"),
+        "used by the HTTP(S) listener",
+      ),
+      (
+        format!("listen = \"0.0.0.0:5432\"\nprotocol = \"sctp\"\n{upstream}"),
+        "Unknown stream protocol",
+      ),
+      (
+        format!(
+          "listen = \"0.0.0.0:5432\"\nprotocol = \"udp\"\n{upstream}\ntls = {{ server_name = \"db.example.com\", tls_cert_path = \"c\", tls_cert_key_path = \"k\" }}"
+        ),
+        "tls is not supported for udp",
+      ),
+      // The certificate of an app cannot be shared by a stream
+      (
+        format!(
+          "listen = \"0.0.0.0:5432\"\n{upstream}\ntls = {{ server_name = \"app.example.com\", tls_cert_path = \"c\", tls_cert_key_path = \"k\" }}"
+        ),
+        "used by another app or stream",
+      ),
+      (
+        format!("listen = \"0.0.0.0:5432\"\nidle_timeout = 0\n{upstream}"),
+        "idle_timeout must be >= 1",
+      ),
+    ];
+    for (stream, expected) in cases {
+      let Err(err) = build(&stream) else {
+        panic!("stream must be rejected: {stream}");
+      };
+      assert!(err.to_string().contains(expected), "{err}");
+    }
+  }
+
+  #[test]
+  fn upstream_tls_option() {
+    let option = UpstreamTlsOption {
+      ca_cert_path: Some("/etc/rpxy/upstream-ca.pem".to_string()),
+      client_cert_path: Some("/etc/rpxy/client.pem".to_string()),
+      client_key_path: Some("/etc/rpxy/client.key".to_string()),
+      sni: Some("api.internal".to_string()),
+      min_version: Some("1.3".to_string()),
+      insecure_skip_verify: None,
+    };
+    let config = build_upstream_tls_config(&option, "example.com").unwrap();
+    assert_eq!(
+      config.ca_cert_path.as_deref(),
+      Some(std::path::Path::new("/etc/rpxy/upstream-ca.pem"))
+    );
+    assert_eq!(config.sni.as_deref(), Some("api.internal"));
+    assert_eq!(config.min_version, UpstreamTlsVersion::Tls13);
+    assert!(!config.insecure_skip_verify);
+
+    let default = build_upstream_tls_config(&UpstreamTlsOption::default(), "example.com").unwrap();
+    assert_eq!(default, UpstreamTlsConfig::default());
+
+    let invalid = [
+      UpstreamTlsOption {
+        min_version: Some("1.1".to_string()),
+        ..Default::default()
+      },
+      UpstreamTlsOption {
+        client_cert_path: Some("/etc/rpxy/client.pem".to_string()),
+        ..Default::default()
+      },
+      UpstreamTlsOption {
+        ca_cert_path: Some("/etc/rpxy/upstream-ca.pem".to_string()),
+        insecure_skip_verify: Some(true),
+        ..Default::default()
+      },
+      UpstreamTlsOption {
+        sni: Some(String::new()),
+        ..Default::default()
+      },
+    ];
+    for option in invalid.iter() {
+      assert!(build_upstream_tls_config(option, "example.com").is_err(), "{option:?}");
+    }
+  }
+
+  #[test]
+  fn grpc_web_allowed_origins_option() {
+    let mut rpo = ReverseProxyOption {
+      upstream_options: Some(vec!["grpc_web".to_string()]),
+      ..Default::default()
+    };
+    assert!(build_grpc_web_allowed_origins(&rpo, "example.com").unwrap().is_none());
+
+    let origins = vec!["https://web.example.com".to_string(), "http://localhost:8080".to_string()];
+    rpo.grpc_web_allowed_origins = Some(origins.clone());
+    assert_eq!(build_grpc_web_allowed_origins(&rpo, "example.com").unwrap(), Some(origins));
+    rpo.grpc_web_allowed_origins = Some(vec!["*".to_string()]);
+    assert!(build_grpc_web_allowed_origins(&rpo, "example.com").is_ok());
+
+    for invalid in [
+      "web.example.com",
+      "https://",
+      "https://web.example.com/",
+      "ftp://web.example.com",
+      "null",
+    ] {
+      rpo.grpc_web_allowed_origins = Some(vec![invalid.to_string()]);
+      assert!(build_grpc_web_allowed_origins(&rpo, "example.com").is_err(), "{invalid}");
+    }
+
+    // Only for gRPC-Web routes
+    rpo.grpc_web_allowed_origins = Some(vec!["*".to_string()]);
+    rpo.upstream_options = None;
+    let err = build_grpc_web_allowed_origins(&rpo, "example.com").unwrap_err();
+    assert!(err.to_string().contains("requires the grpc_web upstream option"));
+  }
+
+  #[test]
+  fn upstream_resolve_option() {
+    let mut rpo = ReverseProxyOption {
+      upstream: vec![UpstreamParams {
+        location: "backend.local:8080".to_string(),
+        tls: None,
+      }],
+      ..Default::default()
+    };
+    assert!(build_upstream_resolve_config(&rpo, "example.com").unwrap().is_none());
+
+    rpo.resolve = Some("dynamic".to_string());
+    let config = build_upstream_resolve_config(&rpo, "example.com").unwrap().unwrap();
+    assert_eq!(
+      config.interval,
+      Duration::from_secs(rpxy_lib::upstream_resolve_defaults::DEFAULT_INTERVAL_SEC)
+    );
+    rpo.resolve_interval = Some(5);
+    let config = build_upstream_resolve_config(&rpo, "example.com").unwrap().unwrap();
+    assert_eq!(config.interval, Duration::from_secs(5));
+
+    rpo.resolve_interval = Some(0);
+    assert!(build_upstream_resolve_config(&rpo, "example.com").is_err());
+
+    rpo.resolve_interval = None;
+    rpo.upstream[0].tls = Some(true);
+    let err = build_upstream_resolve_config(&rpo, "example.com").unwrap_err();
+    assert!(err.to_string().contains("not supported for TLS upstreams"));
+    // The server name survives re-resolution when it is given explicitly.
+    rpo.upstream_tls = Some(UpstreamTlsOption {
+      sni: Some("backend.local".to_string()),
+      ..Default::default()
+    });
+    assert!(build_upstream_resolve_config(&rpo, "example.com").unwrap().is_some());
+    rpo.upstream_tls = None;
+
+    rpo.upstream[0].tls = None;
+    rpo.resolve = Some("dns".to_string());
+    assert!(build_upstream_resolve_config(&rpo, "example.com").is_err());
+
+    rpo.resolve = None;
+    rpo.resolve_interval = Some(5);
+    assert!(build_upstream_resolve_config(&rpo, "example.com").is_err());
+  }
+
   #[cfg(feature = "sticky-cookie")]
   #[test]
   fn sticky_cookie_secret_not_required_without_sticky_routes() {
@@ -4,6 +4,25 @@ pub const DEFAULT_LISTEN_ADDRESS_V4: &str = "0.0.0.0";
 pub const DEFAULT_LISTEN_ADDRESS_V6: &str = "::";
 /// Delay in seconds before reloading the configuration after changes.
 pub const CONFIG_WATCH_DELAY_SECS: u32 = 15;
+/// `resolve` value for upstreams whose host names are resolved per connection only (default).
+pub const UPSTREAM_RESOLVE_STATIC: &str = "static";
+/// `resolve` value for upstreams whose host names are periodically re-resolved into one upstream per address.
+pub const UPSTREAM_RESOLVE_DYNAMIC: &str = "dynamic";
+/// `send_proxy_protocol` values
+#[cfg(feature = "proxy-protocol")]
+pub const SEND_PROXY_PROTOCOL_V1: &str = "v1";
+#[cfg(feature = "proxy-protocol")]
+pub const SEND_PROXY_PROTOCOL_V2: &str = "v2";
+/// `protocol` values of streams
+pub const STREAM_PROTOCOL_TCP: &str = "tcp";
+pub const STREAM_PROTOCOL_UDP: &str = "udp";
+/// Prefix of an `admin.listen` value pointing at a Unix domain socket
+pub const ADMIN_LISTEN_UNIX_PREFIX: &str = "unix:";
+/// Minimum length of `admin.token`
+pub const ADMIN_TOKEN_MIN_LENGTH: usize = 16;
+/// `upstream_tls.min_version` values
+pub const UPSTREAM_TLS_VERSION_1_2: &str = "1.2";
+pub const UPSTREAM_TLS_VERSION_1_3: &str = "1.3";
 
 #[cfg(feature = "cache")]
 /// Directory path for cache storage (enabled with "cache" feature).
@@ -29,7 +29,7 @@ use crate::{
 use hot_reload::{ReloaderConfig, ReloaderReceiver, ReloaderService};
 #[cfg(feature = "sticky-cookie")]
 use rpxy_lib::StickyCookieSecret;
-use rpxy_lib::{RpxyOptions, RpxyOptionsBuilder, entrypoint};
+use rpxy_lib::{RpxyOptions, RpxyOptionsBuilder, UpstreamOverrides, entrypoint};
 use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
@@ -129,6 +129,8 @@ struct RpxyService {
   /// Whether the installed logger emits access-log lines, determined once at
   /// startup from the log mode and `RUST_LOG` (see `log::access_log_enabled`).
   access_log_enabled: bool,
+  /// Upstream overrides set via the admin API, shared by the services rebuilt on config reload.
+  upstream_overrides: UpstreamOverrides,
   #[cfg(feature = "sticky-cookie")]
   sticky_cookie_secret: Option<Arc<StickyCookieSecret>>,
   #[cfg(feature = "acme")]
@@ -142,6 +144,7 @@ impl RpxyService {
     runtime_handle: tokio::runtime::Handle,
     unsafe_debug_headers: bool,
     access_log_enabled: bool,
+    upstream_overrides: UpstreamOverrides,
   ) -> Result<Self, anyhow::Error> {
     let (proxy_conf, app_conf) = build_settings(config_toml).map_err(|e| anyhow!("Invalid configuration: {e}"))?;
     #[cfg(feature = "sticky-cookie")]
@@ -162,6 +165,7 @@ impl RpxyService {
       cert_rx,
       unsafe_debug_headers,
       access_log_enabled,
+      upstream_overrides,
       #[cfg(feature = "sticky-cookie")]
       sticky_cookie_secret,
       #[cfg(feature = "acme")]
@@ -178,6 +182,7 @@ impl RpxyService {
       cert_rx,
       unsafe_debug_headers,
       access_log_enabled,
+      upstream_overrides,
       #[cfg(feature = "sticky-cookie")]
       sticky_cookie_secret,
       #[cfg(feature = "acme")]
@@ -198,6 +203,7 @@ impl RpxyService {
         .runtime_handle(runtime_handle.clone())
         .unsafe_debug_headers(*unsafe_debug_headers)
         .access_log_enabled(*access_log_enabled)
+        .upstream_overrides(upstream_overrides.clone())
         .server_configs_acme_challenge(Arc::new(server_config_acme_challenge));
       #[cfg(feature = "sticky-cookie")]
       builder.sticky_cookie_secret(sticky_cookie_secret.clone());
@@ -217,7 +223,8 @@ impl RpxyService {
         .cert_rx(cert_rx.clone())
         .runtime_handle(runtime_handle.clone())
         .unsafe_debug_headers(*unsafe_debug_headers)
-        .access_log_enabled(*access_log_enabled);
+        .access_log_enabled(*access_log_enabled)
+        .upstream_overrides(upstream_overrides.clone());
       #[cfg(feature = "sticky-cookie")]
       builder.sticky_cookie_secret(sticky_cookie_secret.clone());
       let rpxy_opts = builder.build()?;
@@ -330,7 +337,16 @@ async fn rpxy_service(
     .borrow()
     .clone()
     .ok_or(anyhow!("Something wrong in config reloader receiver"))?;
-  let mut service = RpxyService::new(&config_toml, runtime_handle.clone(), unsafe_debug_headers, access_log_enabled).await?;
+  // Created once so that overrides set via the admin API survive config reloads.
+  let upstream_overrides = UpstreamOverrides::default();
+  let mut service = RpxyService::new(
+    &config_toml,
+    runtime_handle.clone(),
+    unsafe_debug_headers,
+    access_log_enabled,
+    upstream_overrides.clone(),
+  )
+  .await?;
 
   // Continuous monitoring
   loop {
@@ -353,7 +369,15 @@ async fn rpxy_service(
           error!("Something wrong in config reloader receiver");
           return Err(anyhow!("Something wrong in config reloader receiver"));
         };
-        match RpxyService::new(&new_config_toml, runtime_handle.clone(), unsafe_debug_headers, access_log_enabled).await {
+        match RpxyService::new(
+          &new_config_toml,
+          runtime_handle.clone(),
+          unsafe_debug_headers,
+          access_log_enabled,
+          upstream_overrides.clone(),
+        )
+        .await
+        {
           Ok(new_service) => {
             info!("Configuration updated.");
             service = new_service;
@@ -1,4 +1,8 @@
-use crate::error::*;
+use crate::{
+  error::*,
+  ocsp::{self, OcspValidity},
+  tls_policy::TlsPolicy,
+};
 use ahash::HashMap;
 use rustls::{crypto::aws_lc_rs::sign::any_supported_type, pki_types, sign::CertifiedKey};
 use std::sync::Arc;
@@ -9,6 +13,8 @@ use x509_parser::prelude::*;
 type Certificate = rustls::pki_types::CertificateDer<'static>;
 /// Raw private key in rustls format
 type PrivateKey = pki_types::PrivateKeyDer<'static>;
+/// Raw certificate revocation list in rustls format
+type CertificateRevocationList = pki_types::CertificateRevocationListDer<'static>;
 /// Subject Key ID in bytes
 type SubjectKeyIdentifier = Vec<u8>;
 /// Client CA trust anchors subject to the subject key identifier
@@ -21,6 +27,12 @@ pub struct SingleServerCertsKeys {
   certs: Vec<Certificate>,
   cert_keys: Arc<Vec<PrivateKey>>,
   client_ca_certs: Option<Vec<Certificate>>,
+  /// CRLs of the client CAs, with which revoked client certificates are rejected
+  client_crls: Option<Vec<CertificateRevocationList>>,
+  /// OCSP response in DER verified for the leaf certificate, stapled to handshakes
+  ocsp_response: Option<Vec<u8>>,
+  /// TLS protocol versions, cipher suites and key exchange groups allowed for the server name
+  tls_policy: TlsPolicy,
 }
 
 impl SingleServerCertsKeys {
@@ -30,8 +42,27 @@ impl SingleServerCertsKeys {
       certs: certs.to_owned(),
       cert_keys: cert_keys.clone(),
       client_ca_certs: client_ca_certs.clone(),
+      client_crls: None,
+      ocsp_response: None,
+      tls_policy: TlsPolicy::default(),
     }
   }
+  /// Set the CRLs of the client CAs to check the revocation of client certificates
+  pub(crate) fn set_client_crls(&mut self, client_crls: Option<Vec<CertificateRevocationList>>) {
+    self.client_crls = client_crls;
+  }
+  /// Set the OCSP response to be stapled, which must be verified for the leaf certificate
+  pub(crate) fn set_ocsp_response(&mut self, ocsp_response: Option<Vec<u8>>) {
+    self.ocsp_response = ocsp_response;
+  }
+  /// Set the TLS policy applied to the server config
+  pub(crate) fn set_tls_policy(&mut self, tls_policy: TlsPolicy) {
+    self.tls_policy = tls_policy;
+  }
+  /// TLS policy applied to the server config
+  pub fn tls_policy(&self) -> &TlsPolicy {
+    &self.tls_policy
+  }
   /// Check if mutual tls is enabled
   pub fn is_mutual_tls(&self) -> bool {
     self.client_ca_certs.is_some()
@@ -58,6 +89,16 @@ impl SingleServerCertsKeys {
   pub fn client_ca_certs_bytes(&self) -> Option<Vec<Vec<u8>>> {
     self.client_ca_certs.as_ref().map(|v| v.iter().map(|c| c.to_vec()).collect())
   }
+  /// CRLs of the client CAs
+  pub fn client_crls(&self) -> Option<&[CertificateRevocationList]> {
+    self.client_crls.as_deref()
+  }
+  /// Expiry (notAfter) of the leaf certificate in seconds since the Unix epoch. None if it cannot be parsed.
+  pub fn not_after(&self) -> Option<i64> {
+    let leaf = self.certs.first()?;
+    let (_, x509_cert) = parse_x509_certificate(leaf).ok()?;
+    Some(x509_cert.validity().not_after.timestamp())
+  }
   /* ------------------------------------------------ */
   /// Parse the certificates and private keys for a single server and return a rustls CertifiedKey
   pub fn rustls_certified_key(&self) -> Result<CertifiedKey, RpxyCertError> {
@@ -69,17 +110,35 @@ impl SingleServerCertsKeys {
       .ok_or_else(|| RpxyCertError::InvalidCertificateAndKey)?;
 
     let cert = self.certs.iter().map(|c| Certificate::from(c.to_vec())).collect::<Vec<_>>();
-    Ok(CertifiedKey::new(cert, signing_key))
+    let mut certified_key = CertifiedKey::new(cert, signing_key);
+    certified_key.ocsp = self.ocsp_response.clone();
+    Ok(certified_key)
+  }
+
+  /* ------------------------------------------------ */
+  /// Verify the OCSP response in DER for the leaf certificate at the given time in seconds since the Unix epoch,
+  /// returning its validity period
+  pub(crate) fn verify_ocsp_response(&self, ocsp_response: &[u8], now: i64) -> Result<OcspValidity, RpxyCertError> {
+    ocsp::verify_ocsp_response(ocsp_response, &self.certs, now)
+  }
+  /// URL of the OCSP responder of the leaf certificate and an OCSP request for it
+  pub(crate) fn ocsp_request(&self) -> Result<(String, Vec<u8>), RpxyCertError> {
+    let url = self
+      .certs
+      .first()
+      .and_then(ocsp::ocsp_responder_url)
+      .ok_or_else(|| RpxyCertError::OcspFetchError("no OCSP responder URL in the certificate".to_string()))?;
+    Ok((url, ocsp::build_ocsp_request(&self.certs)?))
   }
 
   /* ------------------------------------------------ */
-  /// Parse the client CA certificates and return a hashmap of pairs of a subject key identifier (key) and a trust anchor (value)
+  /// Parse the client CA certificates and return a hashmap of pairs of a subject key identifier (key) and a trust anchor (value).
+  /// Intermediate CA certificates, i.e., those issued by another certificate in the list, are not trust anchors.
   pub fn rustls_client_certs_trust_anchors(&self) -> Result<TrustAnchors, RpxyCertError> {
-    let certs = self.client_ca_certs.as_ref().ok_or(RpxyCertError::NoClientCert)?;
-    let certs = certs.iter().map(|c| Certificate::from(c.to_vec())).collect::<Vec<_>>();
+    let (roots, _) = self.client_ca_certs_roots_and_intermediates()?;
 
-    let trust_anchors = certs
-      .iter()
+    let trust_anchors = roots
+      .into_iter()
       .filter_map(|v| {
         // retrieve trust anchor
         let trust_anchor = webpki::anchor_from_trusted_cert(v).ok()?;
@@ -98,12 +157,44 @@ impl SingleServerCertsKeys {
 
     Ok(trust_anchors)
   }
+
+  /// Intermediate CA certificates in the client CA certificates, used to build paths from client certificates to the
+  /// trust anchors in addition to the ones sent by clients
+  pub fn rustls_client_certs_intermediates(&self) -> Result<Vec<Certificate>, RpxyCertError> {
+    let (_, intermediates) = self.client_ca_certs_roots_and_intermediates()?;
+    Ok(intermediates.into_iter().cloned().collect())
+  }
+
+  /// Split the client CA certificates into roots and intermediates. A certificate is an intermediate if it is not
+  /// self-issued and its issuer is another certificate in the list, so that a full chain can be given while a list of
+  /// only intermediates is trusted as it is.
+  fn client_ca_certs_roots_and_intermediates(&self) -> Result<(Vec<&Certificate>, Vec<&Certificate>), RpxyCertError> {
+    let certs = self.client_ca_certs.as_ref().ok_or(RpxyCertError::NoClientCert)?;
+    let names = certs
+      .iter()
+      .map(|cert| {
+        let (_, x509_cert) = parse_x509_certificate(cert).ok()?;
+        Some((x509_cert.subject().as_raw().to_vec(), x509_cert.issuer().as_raw().to_vec()))
+      })
+      .collect::<Vec<_>>();
+
+    let (intermediates, roots): (Vec<_>, Vec<_>) = certs.iter().zip(names.iter()).partition(|(_, name)| {
+      name.as_ref().is_some_and(|(subject, issuer)| {
+        subject != issuer && names.iter().flatten().any(|(other_subject, _)| other_subject == issuer)
+      })
+    });
+    Ok((
+      roots.into_iter().map(|(cert, _)| cert).collect(),
+      intermediates.into_iter().map(|(cert, _)| cert).collect(),
+    ))
+  }
 }
 
 /* ------------------------------------------------ */
 #[cfg(test)]
 mod tests {
   use super::super::*;
+  use rustls::pki_types::pem::PemObject;
 
   #[tokio::test]
   async fn read_server_crt_key_files() {
@@ -122,6 +213,9 @@ mod tests {
     let crypto_elem = crypto_elem.unwrap();
     let certificed_key = crypto_elem.rustls_certified_key();
     assert!(certificed_key.is_ok());
+
+    // notAfter of the example certificate: 4096-01-01T00:00:00Z
+    assert_eq!(crypto_elem.not_after(), Some(67_090_118_400));
   }
 
   #[tokio::test]
@@ -152,4 +246,90 @@ mod tests {
     let trust_anchors = trust_anchors.unwrap();
     assert_eq!(trust_anchors.len(), 1);
   }
+
+  #[tokio::test]
+  async fn read_client_ca_chain_and_crls() {
+    let read_with_client_ca = |client_ca_cert_path: &'static str, client_crl_path: Option<&'static str>| async move {
+      CryptoFileSourceBuilder::default()
+        .tls_cert_key_path("../example-certs/server.key")
+        .tls_cert_path("../example-certs/server.crt")
+        .client_ca_cert_path(Some(client_ca_cert_path))
+        .client_crl_path(client_crl_path)
+        .build()
+        .unwrap()
+        .read()
+        .await
+    };
+
+    // The intermediate in the full chain is not a trust anchor
+    let crypto_elem = read_with_client_ca("../example-certs/client-chain/ca-chain.crt", None)
+      .await
+      .unwrap();
+    assert_eq!(crypto_elem.rustls_client_certs_trust_anchors().unwrap().len(), 1);
+    let intermediates = crypto_elem.rustls_client_certs_intermediates().unwrap();
+    assert_eq!(intermediates.len(), 1);
+    assert_eq!(
+      Some(intermediates[0].clone()),
+      rustls::pki_types::CertificateDer::pem_file_iter("../example-certs/client-chain/intermediate.crt")
+        .unwrap()
+        .next()
+        .transpose()
+        .unwrap()
+    );
+    assert!(crypto_elem.client_crls().is_none());
+
+    // The intermediate alone is trusted as it is
+    let crypto_elem = read_with_client_ca("../example-certs/client-chain/intermediate.crt", None)
+      .await
+      .unwrap();
+    assert_eq!(crypto_elem.rustls_client_certs_trust_anchors().unwrap().len(), 1);
+    assert!(crypto_elem.rustls_client_certs_intermediates().unwrap().is_empty());
+
+    // CRLs of both CAs
+    let crypto_elem = read_with_client_ca(
+      "../example-certs/client-chain/ca-chain.crt",
+      Some("../example-certs/client-chain/crl.pem"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(crypto_elem.client_crls().unwrap().len(), 2);
+
+    // Files other than CRLs are rejected
+    assert!(
+      read_with_client_ca(
+        "../example-certs/client-chain/ca-chain.crt",
+        Some("../example-certs/client-chain/root.crt"),
+      )
+      .await
+      .is_err()
+    );
+  }
+
+  #[tokio::test]
+  async fn read_server_crt_key_files_with_ocsp_response() {
+    let read_with_ocsp_response = |ocsp_response_path: &'static str| async move {
+      CryptoFileSourceBuilder::default()
+        .tls_cert_key_path("../example-certs/ocsp/server.key")
+        .tls_cert_path("../example-certs/ocsp/server.crt")
+        .ocsp_response_path(Some(ocsp_response_path))
+        .build()
+        .unwrap()
+        .read()
+        .await
+    };
+
+    // Stapled if verified for the certificate
+    let crypto_elem = read_with_ocsp_response("../example-certs/ocsp/good.der").await.unwrap();
+    let certified_key = crypto_elem.rustls_certified_key().unwrap();
+    assert_eq!(
+      certified_key.ocsp,
+      Some(std::fs::read("../example-certs/ocsp/good.der").unwrap())
+    );
+
+    // The certificate is still served without stapling otherwise
+    for ocsp_response_path in ["../example-certs/ocsp/revoked.der", "../example-certs/ocsp/not-found.der"] {
+      let crypto_elem = read_with_ocsp_response(ocsp_response_path).await.unwrap();
+      assert!(crypto_elem.rustls_certified_key().unwrap().ocsp.is_none());
+    }
+  }
 }
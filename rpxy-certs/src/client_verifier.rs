@@ -0,0 +1,187 @@
+use crate::{certs::SingleServerCertsKeys, error::*};
+use rustls::{
+  DigitallySignedStruct, DistinguishedName, Error, RootCertStore, SignatureScheme,
+  client::danger::HandshakeSignatureValid,
+  crypto::CryptoProvider,
+  pki_types::{CertificateDer, UnixTime},
+  server::{
+    WebPkiClientVerifier,
+    danger::{ClientCertVerified, ClientCertVerifier},
+  },
+};
+use std::sync::Arc;
+use x509_parser::prelude::*;
+
+/* ------------------------------------------------ */
+/// Build the verifier of client certificates for mutual TLS from the client CA certificates and CRLs of the server name
+pub(crate) fn build_client_cert_verifier(
+  certs_keys: &SingleServerCertsKeys,
+  provider: &Arc<CryptoProvider>,
+) -> Result<Arc<dyn ClientCertVerifier>, RpxyCertError> {
+  let mut client_ca_roots = RootCertStore::empty();
+  let trust_anchors = certs_keys.rustls_client_certs_trust_anchors()?;
+  client_ca_roots.extend(trust_anchors.into_values());
+  let intermediates = certs_keys.rustls_client_certs_intermediates()?;
+
+  // Intermediates are also hinted so that clients pick certificates issued by them
+  let intermediate_subjects = intermediates.iter().filter_map(|cert| {
+    let (_, x509_cert) = parse_x509_certificate(cert).ok()?;
+    Some(DistinguishedName::from(x509_cert.subject().as_raw().to_vec()))
+  });
+  let mut builder = WebPkiClientVerifier::builder_with_provider(Arc::new(client_ca_roots), provider.clone())
+    .add_root_hint_subjects(intermediate_subjects.collect::<Vec<_>>());
+  if let Some(client_crls) = certs_keys.client_crls() {
+    // Certificates issued by CAs without a given CRL are accepted
+    builder = builder
+      .with_crls(client_crls.iter().cloned())
+      .allow_unknown_revocation_status();
+  }
+  let verifier = builder.build()?;
+
+  if intermediates.is_empty() {
+    return Ok(verifier);
+  }
+  Ok(Arc::new(IntermediatesClientCertVerifier {
+    inner: verifier,
+    intermediates,
+  }))
+}
+
+/* ------------------------------------------------ */
+#[derive(Debug)]
+/// Client certificate verifier building paths also with the intermediate CA certificates given on the server side,
+/// for clients sending no or incomplete chains
+struct IntermediatesClientCertVerifier {
+  inner: Arc<dyn ClientCertVerifier>,
+  intermediates: Vec<CertificateDer<'static>>,
+}
+
+impl ClientCertVerifier for IntermediatesClientCertVerifier {
+  fn offer_client_auth(&self) -> bool {
+    self.inner.offer_client_auth()
+  }
+
+  fn client_auth_mandatory(&self) -> bool {
+    self.inner.client_auth_mandatory()
+  }
+
+  fn root_hint_subjects(&self) -> &[DistinguishedName] {
+    self.inner.root_hint_subjects()
+  }
+
+  fn verify_client_cert(
+    &self,
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    now: UnixTime,
+  ) -> Result<ClientCertVerified, Error> {
+    // The ones sent by the client come first as they are in the order of the chain
+    let intermediates = intermediates
+      .iter()
+      .cloned()
+      .chain(
+        self
+          .intermediates
+          .iter()
+          .filter(|cert| !intermediates.contains(cert))
+          .cloned(),
+      )
+      .collect::<Vec<_>>();
+    self.inner.verify_client_cert(end_entity, &intermediates, now)
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, Error> {
+    self.inner.verify_tls12_signature(message, cert, dss)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, Error> {
+    self.inner.verify_tls13_signature(message, cert, dss)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.inner.supported_verify_schemes()
+  }
+}
+
+/* ------------------------------------------------ */
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{CryptoFileSourceBuilder, CryptoSource};
+  use rustls::{CertificateError, pki_types::pem::PemObject};
+
+  async fn build_verifier(client_ca_cert_path: &str, client_crl_path: Option<&str>) -> Arc<dyn ClientCertVerifier> {
+    let certs_keys = CryptoFileSourceBuilder::default()
+      .tls_cert_key_path("../example-certs/server.key")
+      .tls_cert_path("../example-certs/server.crt")
+      .client_ca_cert_path(Some(client_ca_cert_path))
+      .client_crl_path(client_crl_path)
+      .build()
+      .unwrap()
+      .read()
+      .await
+      .unwrap();
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    build_client_cert_verifier(&certs_keys, &provider).unwrap()
+  }
+
+  fn read_cert(path: &str) -> CertificateDer<'static> {
+    CertificateDer::from_pem_file(path).unwrap()
+  }
+
+  #[tokio::test]
+  async fn verify_client_certs_issued_by_intermediate() {
+    let client = read_cert("../example-certs/client-chain/client.crt");
+    let intermediate = read_cert("../example-certs/client-chain/intermediate.crt");
+
+    // With the full chain, the intermediate is used even if the client sends only its certificate
+    let verifier = build_verifier("../example-certs/client-chain/ca-chain.crt", None).await;
+    assert!(verifier.verify_client_cert(&client, &[], UnixTime::now()).is_ok());
+    assert!(
+      verifier
+        .verify_client_cert(&client, std::slice::from_ref(&intermediate), UnixTime::now())
+        .is_ok()
+    );
+    assert_eq!(verifier.root_hint_subjects().len(), 2);
+
+    // With the root only, the client must send the intermediate
+    let verifier = build_verifier("../example-certs/client-chain/root.crt", None).await;
+    assert!(verifier.verify_client_cert(&client, &[], UnixTime::now()).is_err());
+    assert!(verifier.verify_client_cert(&client, &[intermediate], UnixTime::now()).is_ok());
+
+    // Certificates of other CAs are rejected
+    let other = read_cert("../example-certs/client.crt");
+    let verifier = build_verifier("../example-certs/client-chain/ca-chain.crt", None).await;
+    assert!(verifier.verify_client_cert(&other, &[], UnixTime::now()).is_err());
+  }
+
+  #[tokio::test]
+  async fn reject_revoked_client_certs() {
+    let client = read_cert("../example-certs/client-chain/client.crt");
+    let revoked = read_cert("../example-certs/client-chain/revoked.crt");
+
+    let verifier = build_verifier("../example-certs/client-chain/ca-chain.crt", None).await;
+    assert!(verifier.verify_client_cert(&revoked, &[], UnixTime::now()).is_ok());
+
+    let verifier = build_verifier(
+      "../example-certs/client-chain/ca-chain.crt",
+      Some("../example-certs/client-chain/crl.pem"),
+    )
+    .await;
+    assert!(verifier.verify_client_cert(&client, &[], UnixTime::now()).is_ok());
+    assert!(matches!(
+      verifier.verify_client_cert(&revoked, &[], UnixTime::now()),
+      Err(Error::InvalidCertificate(CertificateError::Revoked))
+    ));
+  }
+}
@@ -1,4 +1,4 @@
-use crate::{certs::SingleServerCertsKeys, error::*, log::*};
+use crate::{certs::SingleServerCertsKeys, error::*, log::*, tls_policy::TlsPolicy};
 use async_trait::async_trait;
 use derive_builder::Builder;
 use rustls::pki_types::{self, pem::PemObject};
@@ -6,9 +6,15 @@ use std::{
   fs::File,
   io::{self, BufReader, Cursor, Read},
   path::{Path, PathBuf},
-  sync::Arc,
+  sync::{Arc, Mutex},
+  time::{SystemTime, UNIX_EPOCH},
 };
 
+/* ------------------------------------------------ */
+// Constants
+/// Interval in seconds between attempts to fetch an OCSP response after a failure
+const OCSP_FETCH_RETRY_INTERVAL_SECS: i64 = 300;
+
 /* ------------------------------------------------ */
 #[async_trait]
 // Trait to read certs and keys anywhere from KVS, file, sqlite, etc.
@@ -37,6 +43,33 @@ pub struct CryptoFileSource {
   #[builder(setter(custom), default)]
   /// This may not exist
   pub client_ca_cert_path: Option<PathBuf>,
+
+  #[builder(setter(custom), default)]
+  /// CRLs of the client CAs in PEM or DER, read again on every reload. This may not exist
+  pub client_crl_path: Option<PathBuf>,
+
+  #[builder(setter(custom), default)]
+  /// OCSP response in DER for the server certificate, stapled to handshakes. This may not exist
+  pub ocsp_response_path: Option<PathBuf>,
+
+  #[builder(default)]
+  /// Fetch and refresh the OCSP response from the responder of the server certificate, saved to `ocsp_response_path` if given
+  pub ocsp_fetch: bool,
+
+  #[builder(default)]
+  /// TLS protocol versions, cipher suites and key exchange groups allowed for the server name
+  pub tls_policy: TlsPolicy,
+
+  #[builder(setter(skip))]
+  /// State of fetching the OCSP response, kept across reads so that it is fetched again only when due
+  ocsp_fetch_state: Arc<Mutex<OcspFetchState>>,
+}
+
+#[derive(Debug, Default)]
+/// OCSP response fetched last and the time of the last attempt to fetch it
+struct OcspFetchState {
+  response: Option<Vec<u8>>,
+  last_attempt: Option<i64>,
 }
 
 impl CryptoFileSourceBuilder {
@@ -52,6 +85,14 @@ impl CryptoFileSourceBuilder {
     self.client_ca_cert_path = Some(v.map(|p| p.as_ref().to_path_buf()));
     self
   }
+  pub fn client_crl_path<T: AsRef<Path>>(&mut self, v: Option<T>) -> &mut Self {
+    self.client_crl_path = Some(v.map(|p| p.as_ref().to_path_buf()));
+    self
+  }
+  pub fn ocsp_response_path<T: AsRef<Path>>(&mut self, v: Option<T>) -> &mut Self {
+    self.ocsp_response_path = Some(v.map(|p| p.as_ref().to_path_buf()));
+    self
+  }
 }
 
 /* ------------------------------------------------ */
@@ -60,11 +101,20 @@ impl CryptoSource for CryptoFileSource {
   type Error = RpxyCertError;
   /// read crypto materials from source
   async fn read(&self) -> Result<SingleServerCertsKeys, Self::Error> {
-    read_certs_and_keys(
+    let mut certs_keys = read_certs_and_keys(
       &self.tls_cert_path,
       &self.tls_cert_key_path,
       self.client_ca_cert_path.as_ref(),
-    )
+    )?;
+    if let Some(client_crl_path) = self.client_crl_path.as_ref() {
+      certs_keys.set_client_crls(Some(read_client_crls(client_crl_path)?));
+    }
+    if self.ocsp_response_path.is_some() || self.ocsp_fetch {
+      let ocsp_response = self.read_ocsp_response(&certs_keys).await;
+      certs_keys.set_ocsp_response(ocsp_response);
+    }
+    certs_keys.set_tls_policy(self.tls_policy.clone());
+    Ok(certs_keys)
   }
   /// Returns true when mutual tls is enabled
   fn is_mutual_tls(&self) -> bool {
@@ -72,6 +122,94 @@ impl CryptoSource for CryptoFileSource {
   }
 }
 
+impl CryptoFileSource {
+  /// Read the OCSP response verified for the certificates, from the last fetched one or the file. If fetching is
+  /// enabled, a new one is fetched from the responder when the current one is missing or due to be refreshed.
+  /// Failures leave the certificate served without stapling rather than failing the read.
+  async fn read_ocsp_response(&self, certs_keys: &SingleServerCertsKeys) -> Option<Vec<u8>> {
+    let now = unix_now();
+    let fetched = lock_ocsp_fetch_state(&self.ocsp_fetch_state).response.clone();
+    // The last fetched one is no longer valid if the certificates have been renewed
+    let current = fetched
+      .and_then(|response| {
+        let validity = certs_keys.verify_ocsp_response(&response, now).ok()?;
+        Some((response, validity))
+      })
+      .or_else(|| {
+        let path = self.ocsp_response_path.as_ref()?;
+        let response = match std::fs::read(path) {
+          Ok(response) => response,
+          Err(e) if e.kind() == io::ErrorKind::NotFound && self.ocsp_fetch => return None,
+          Err(e) => {
+            warn!("Unable to load the OCSP response [{}]: {e}", path.display());
+            return None;
+          }
+        };
+        match certs_keys.verify_ocsp_response(&response, now) {
+          Ok(validity) => Some((response, validity)),
+          Err(e) => {
+            warn!("Ignore the OCSP response [{}]: {e}", path.display());
+            None
+          }
+        }
+      });
+
+    if !self.ocsp_fetch || current.as_ref().is_some_and(|(_, validity)| !validity.needs_refresh(now)) {
+      return current.map(|(response, _)| response);
+    }
+    {
+      let mut state = lock_ocsp_fetch_state(&self.ocsp_fetch_state);
+      if state
+        .last_attempt
+        .is_some_and(|last_attempt| now < last_attempt + OCSP_FETCH_RETRY_INTERVAL_SECS)
+      {
+        return current.map(|(response, _)| response);
+      }
+      state.last_attempt = Some(now);
+    }
+
+    match self.fetch_ocsp_response(certs_keys, now).await {
+      Ok(response) => Some(response),
+      Err(e) => {
+        warn!(
+          "Failed to fetch the OCSP response for [{}]: {e}",
+          self.tls_cert_path.display()
+        );
+        current.map(|(response, _)| response)
+      }
+    }
+  }
+
+  /// Fetch an OCSP response from the responder and verify it, keeping it for later reads and saving it to the file if given
+  async fn fetch_ocsp_response(&self, certs_keys: &SingleServerCertsKeys, now: i64) -> Result<Vec<u8>, RpxyCertError> {
+    let (url, request) = certs_keys.ocsp_request()?;
+    let response = crate::ocsp::fetch_ocsp_response(&url, request).await?;
+    certs_keys.verify_ocsp_response(&response, now)?;
+    debug!("Fetched the OCSP response from {url}");
+
+    lock_ocsp_fetch_state(&self.ocsp_fetch_state).response = Some(response.clone());
+    if let Some(path) = self.ocsp_response_path.as_ref()
+      && let Err(e) = std::fs::write(path, &response)
+    {
+      warn!("Unable to save the OCSP response [{}]: {e}", path.display());
+    }
+    Ok(response)
+  }
+}
+
+/// Locks the OCSP fetch state, recovering the guard if the mutex was poisoned
+fn lock_ocsp_fetch_state(state: &Mutex<OcspFetchState>) -> std::sync::MutexGuard<'_, OcspFetchState> {
+  state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Current time in seconds since the Unix epoch
+fn unix_now() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or_default()
+}
+
 /* ------------------------------------------------ */
 /// Emit a `warn!` if the private key file at `path` has any group or other
 /// permission bits set. This is a Unix-only observability helper: it does not
@@ -195,6 +333,30 @@ fn read_certs_and_keys(
   ))
 }
 
+/* ------------------------------------------------ */
+/// Read the CRLs of client CAs from a file in PEM, or a single CRL in DER
+fn read_client_crls(path: &Path) -> Result<Vec<pki_types::CertificateRevocationListDer<'static>>, RpxyCertError> {
+  debug!("Read CRLs for client authentication");
+  let encoded = std::fs::read(path)
+    .map_err(|e| io::Error::new(e.kind(), format!("Unable to load the client CRLs [{}]: {e}", path.display())))?;
+  let mut crls = pki_types::CertificateRevocationListDer::pem_slice_iter(&encoded)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Unable to parse the client CRLs"))?;
+  if crls.is_empty() {
+    crls.push(pki_types::CertificateRevocationListDer::from(encoded));
+  }
+  // Reject malformed ones here to keep serving the previously loaded ones on reload
+  for crl in crls.iter() {
+    webpki::BorrowedCertRevocationList::from_der(crl).map_err(|e| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid client CRL [{}]: {e}", path.display()),
+      )
+    })?;
+  }
+  Ok(crls)
+}
+
 #[cfg(all(test, unix))]
 mod tests {
   use super::warn_if_key_perm_loose;
@@ -21,6 +21,18 @@ pub enum RpxyCertError {
   /// Rustls error
   #[error("Rustls error: {0}")]
   RustlsError(#[from] rustls::Error),
+  /// Rustls client certificate verifier error
+  #[error("Failed to build client certificate verifier: {0}")]
+  ClientCertVerifierError(#[from] rustls::server::VerifierBuilderError),
+  /// Error when verifying an OCSP response for the server certificate
+  #[error("Invalid OCSP response: {0}")]
+  InvalidOcspResponse(String),
+  /// Error when fetching an OCSP response from the responder of the server certificate
+  #[error("Failed to fetch OCSP response: {0}")]
+  OcspFetchError(String),
+  /// Error when the TLS policy of a server name is invalid or unsupported by the crypto provider
+  #[error("Invalid TLS policy: {0}")]
+  InvalidTlsPolicy(String),
   /// Rustls CryptoProvider error
   #[error("Rustls No default CryptoProvider error")]
   NoDefaultCryptoProvider,
@@ -1,8 +1,11 @@
 mod certs;
+mod client_verifier;
 mod crypto_source;
 mod error;
+mod ocsp;
 mod reloader_service;
 mod server_crypto;
+mod tls_policy;
 
 #[allow(unused_imports)]
 mod log {
@@ -21,6 +24,7 @@ pub use crate::{
   crypto_source::{CryptoFileSource, CryptoFileSourceBuilder, CryptoFileSourceBuilderError, CryptoSource},
   reloader_service::CryptoReloader,
   server_crypto::{ServerCrypto, ServerCryptoBase, ServerCryptoForSni},
+  tls_policy::TlsPolicy,
 };
 
 /* ------------------------------------------------ */
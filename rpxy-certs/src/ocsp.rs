@@ -0,0 +1,529 @@
+use crate::error::*;
+use aws_lc_rs::digest;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{StatusCode, body::Bytes, header};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use rustls::{crypto::CryptoProvider, pki_types::CertificateDer};
+use std::time::Duration;
+use x509_parser::{oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP, prelude::*};
+
+/* ------------------------------------------------ */
+// Constants
+/// Allowed clock skew in seconds between the responder and rpxy for `thisUpdate` of OCSP responses
+const OCSP_CLOCK_SKEW_SECS: i64 = 300;
+/// Period in seconds after which an OCSP response without `nextUpdate` is refreshed
+const OCSP_REFRESH_WITHOUT_NEXT_UPDATE_SECS: i64 = 3600;
+/// Timeout of fetching an OCSP response from the responder
+const OCSP_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Max size of an OCSP response fetched from the responder
+const OCSP_RESPONSE_MAX_SIZE: usize = 64 * 1024;
+
+/// DER tags used in OCSP requests and responses
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_CONTEXT_0: u8 = 0xa0;
+/// `CertStatus` of OCSP single responses
+const TAG_CERT_STATUS_GOOD: u8 = 0x80;
+const TAG_CERT_STATUS_REVOKED: u8 = 0xa1;
+
+/// OID contents of the hash algorithms of `CertID`
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// OID contents of id-pkix-ocsp-basic
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// Raw certificates in rustls format
+type Certificate = CertificateDer<'static>;
+
+/* ------------------------------------------------ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Validity period of a verified OCSP response, in seconds since the Unix epoch
+pub(crate) struct OcspValidity {
+  pub(crate) this_update: i64,
+  pub(crate) next_update: Option<i64>,
+}
+
+impl OcspValidity {
+  /// Whether the response is due to be refreshed, i.e., half of its validity period has passed
+  pub(crate) fn needs_refresh(&self, now: i64) -> bool {
+    let refresh_at = match self.next_update {
+      Some(next_update) => self.this_update + (next_update - self.this_update) / 2,
+      None => self.this_update + OCSP_REFRESH_WITHOUT_NEXT_UPDATE_SECS,
+    };
+    now >= refresh_at
+  }
+}
+
+/* ------------------------------------------------ */
+/// Verify the OCSP response in DER for the leaf certificate of the chain, returning its validity period.
+/// The response must be signed by the issuer of the leaf, or by a responder delegated by the issuer, and say that
+/// the leaf is good at the given time.
+pub(crate) fn verify_ocsp_response(response: &[u8], certs: &[Certificate], now: i64) -> Result<OcspValidity, RpxyCertError> {
+  let (leaf, issuer_der, issuer) = leaf_and_issuer(certs)?;
+
+  // OCSPResponse
+  let (ocsp_response, _) = expect_der(response, TAG_SEQUENCE)?;
+  let (status, rest) = expect_der(ocsp_response.contents, TAG_ENUMERATED)?;
+  if status.contents != [0] {
+    return Err(invalid_ocsp(format!(
+      "response status is not successful ({:?})",
+      status.contents
+    )));
+  }
+  let (response_bytes, _) = expect_der(rest, TAG_CONTEXT_0)?;
+  let (response_bytes, _) = expect_der(response_bytes.contents, TAG_SEQUENCE)?;
+  let (response_type, rest) = expect_der(response_bytes.contents, TAG_OID)?;
+  if response_type.contents != OID_OCSP_BASIC {
+    return Err(invalid_ocsp("response type is not basic".to_string()));
+  }
+  let (basic_response, _) = expect_der(rest, TAG_OCTET_STRING)?;
+
+  // BasicOCSPResponse
+  let (basic_response, _) = expect_der(basic_response.contents, TAG_SEQUENCE)?;
+  let (signed_response_data, rest) = read_signed(basic_response.contents)?;
+  let responder_certs = match rest {
+    [] => vec![],
+    _ => {
+      let (certs, _) = expect_der(rest, TAG_CONTEXT_0)?;
+      let (certs, _) = expect_der(certs.contents, TAG_SEQUENCE)?;
+      read_der_sequence_of(certs.contents)?
+    }
+  };
+  verify_responder_signature(issuer_der, &issuer, &responder_certs, now, &signed_response_data)?;
+
+  // ResponseData
+  let mut rest = signed_response_data.data.contents;
+  if rest.first() == Some(&TAG_CONTEXT_0) {
+    // version, always v1
+    rest = read_der(rest)?.1;
+  }
+  let (_responder_id, rest) = read_der(rest)?;
+  let (_produced_at, rest) = expect_der(rest, TAG_GENERALIZED_TIME)?;
+  let (responses, _) = expect_der(rest, TAG_SEQUENCE)?;
+
+  for single_response in read_der_sequence_of(responses.contents)? {
+    // SingleResponse
+    let (single_response, _) = expect_der(single_response.raw, TAG_SEQUENCE)?;
+    let (cert_id, rest) = expect_der(single_response.contents, TAG_SEQUENCE)?;
+    if !matches_cert_id(cert_id.contents, &leaf, &issuer)? {
+      continue;
+    }
+    let (cert_status, rest) = read_der(rest)?;
+    match cert_status.tag {
+      TAG_CERT_STATUS_GOOD => {}
+      TAG_CERT_STATUS_REVOKED => return Err(invalid_ocsp("certificate is revoked".to_string())),
+      _ => return Err(invalid_ocsp("certificate status is unknown".to_string())),
+    }
+    let (this_update, rest) = expect_der(rest, TAG_GENERALIZED_TIME)?;
+    let this_update = parse_time(this_update.raw)?;
+    let next_update = match rest.first() {
+      Some(&TAG_CONTEXT_0) => Some(parse_time(read_der(rest)?.0.contents)?),
+      _ => None,
+    };
+    if this_update > now + OCSP_CLOCK_SKEW_SECS {
+      return Err(invalid_ocsp("response is not yet valid".to_string()));
+    }
+    if next_update.is_some_and(|next_update| next_update <= now) {
+      return Err(invalid_ocsp("response has expired".to_string()));
+    }
+    return Ok(OcspValidity {
+      this_update,
+      next_update,
+    });
+  }
+  Err(invalid_ocsp("no response for the certificate".to_string()))
+}
+
+/// Build an OCSP request in DER for the leaf certificate of the chain, identified with SHA-1 as most responders expect
+pub(crate) fn build_ocsp_request(certs: &[Certificate]) -> Result<Vec<u8>, RpxyCertError> {
+  let (leaf, _, issuer) = leaf_and_issuer(certs)?;
+  let algorithm = &digest::SHA1_FOR_LEGACY_USE_ONLY;
+
+  let hash_algorithm = der(TAG_SEQUENCE, &[der(TAG_OID, OID_SHA1), der(TAG_NULL, &[])].concat());
+  let issuer_name_hash = digest::digest(algorithm, leaf.issuer().as_raw());
+  let issuer_key_hash = digest::digest(algorithm, subject_public_key(&issuer)?);
+  let cert_id = der(
+    TAG_SEQUENCE,
+    &[
+      hash_algorithm,
+      der(TAG_OCTET_STRING, issuer_name_hash.as_ref()),
+      der(TAG_OCTET_STRING, issuer_key_hash.as_ref()),
+      der(TAG_INTEGER, leaf.raw_serial()),
+    ]
+    .concat(),
+  );
+  // OCSPRequest { TBSRequest { requestList { Request { CertID } } } }
+  let request = der(TAG_SEQUENCE, &cert_id);
+  let request_list = der(TAG_SEQUENCE, &request);
+  let tbs_request = der(TAG_SEQUENCE, &request_list);
+  Ok(der(TAG_SEQUENCE, &tbs_request))
+}
+
+/// URL of the OCSP responder given in the Authority Information Access extension of the certificate, if any
+pub(crate) fn ocsp_responder_url(cert: &Certificate) -> Option<String> {
+  let (_, x509_cert) = parse_x509_certificate(cert).ok()?;
+  x509_cert.iter_extensions().find_map(|ext| match ext.parsed_extension() {
+    ParsedExtension::AuthorityInfoAccess(aia) => aia.iter().find_map(|desc| match desc.access_location {
+      GeneralName::URI(uri) if desc.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP => Some(uri.to_string()),
+      _ => None,
+    }),
+    _ => None,
+  })
+}
+
+/// Fetch an OCSP response in DER from the responder by HTTP POST
+pub(crate) async fn fetch_ocsp_response(url: &str, request: Vec<u8>) -> Result<Vec<u8>, RpxyCertError> {
+  let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+  let request = hyper::Request::post(url)
+    .header(header::CONTENT_TYPE, "application/ocsp-request")
+    .body(Full::from(request))
+    .map_err(|e| RpxyCertError::OcspFetchError(e.to_string()))?;
+
+  let fetch = async {
+    let response = client
+      .request(request)
+      .await
+      .map_err(|e| RpxyCertError::OcspFetchError(e.to_string()))?;
+    if response.status() != StatusCode::OK {
+      return Err(RpxyCertError::OcspFetchError(format!(
+        "responder returned {}",
+        response.status()
+      )));
+    }
+    let body = Limited::new(response.into_body(), OCSP_RESPONSE_MAX_SIZE)
+      .collect()
+      .await
+      .map_err(|e| RpxyCertError::OcspFetchError(e.to_string()))?;
+    Ok(body.to_bytes().to_vec())
+  };
+  tokio::time::timeout(OCSP_FETCH_TIMEOUT, fetch)
+    .await
+    .map_err(|_| RpxyCertError::OcspFetchError("timed out".to_string()))?
+}
+
+/* ------------------------------------------------ */
+/// Parse the leaf certificate of the chain and find its issuer among the rest
+fn leaf_and_issuer(certs: &[Certificate]) -> Result<(X509Certificate<'_>, &Certificate, X509Certificate<'_>), RpxyCertError> {
+  let (leaf_der, rest) = certs
+    .split_first()
+    .ok_or_else(|| invalid_ocsp("no certificate".to_string()))?;
+  let (_, leaf) = parse_x509_certificate(leaf_der).map_err(|e| invalid_ocsp(e.to_string()))?;
+  rest
+    .iter()
+    .find_map(|issuer_der| {
+      let (_, issuer) = parse_x509_certificate(issuer_der).ok()?;
+      (issuer.subject().as_raw() == leaf.issuer().as_raw()).then_some((issuer_der, issuer))
+    })
+    .map(|(issuer_der, issuer)| (leaf, issuer_der, issuer))
+    .ok_or_else(|| invalid_ocsp("issuer certificate is not found in the chain".to_string()))
+}
+
+/// Whether the contents of `CertID` identify the leaf certificate issued by the issuer
+fn matches_cert_id(cert_id: &[u8], leaf: &X509Certificate<'_>, issuer: &X509Certificate<'_>) -> Result<bool, RpxyCertError> {
+  let (hash_algorithm, rest) = expect_der(cert_id, TAG_SEQUENCE)?;
+  let (hash_algorithm, _) = expect_der(hash_algorithm.contents, TAG_OID)?;
+  let (issuer_name_hash, rest) = expect_der(rest, TAG_OCTET_STRING)?;
+  let (issuer_key_hash, rest) = expect_der(rest, TAG_OCTET_STRING)?;
+  let (serial_number, _) = expect_der(rest, TAG_INTEGER)?;
+
+  let algorithm = match hash_algorithm.contents {
+    OID_SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+    OID_SHA256 => &digest::SHA256,
+    _ => return Ok(false),
+  };
+  Ok(
+    serial_number.contents == leaf.raw_serial()
+      && issuer_name_hash.contents == digest::digest(algorithm, leaf.issuer().as_raw()).as_ref()
+      && issuer_key_hash.contents == digest::digest(algorithm, subject_public_key(issuer)?).as_ref(),
+  )
+}
+
+/// Verify the signature of the response data by the issuer itself, or by a responder certificate included in the
+/// response that is issued by the issuer for OCSP signing and valid at the given time
+fn verify_responder_signature(
+  issuer_der: &Certificate,
+  issuer: &X509Certificate<'_>,
+  responder_certs: &[Der<'_>],
+  now: i64,
+  signed_response_data: &Signed<'_>,
+) -> Result<(), RpxyCertError> {
+  if verify_signature(issuer_der, signed_response_data) {
+    return Ok(());
+  }
+  let now = ASN1Time::from_timestamp(now).map_err(|e| invalid_ocsp(e.to_string()))?;
+  let delegated = responder_certs.iter().any(|responder_der| {
+    let Ok((_, responder)) = parse_x509_certificate(responder_der.raw) else {
+      return false;
+    };
+    let for_ocsp_signing = responder
+      .extended_key_usage()
+      .ok()
+      .flatten()
+      .is_some_and(|eku| eku.value.ocsp_signing);
+    if responder.issuer().as_raw() != issuer.subject().as_raw() || !for_ocsp_signing || !responder.validity().is_valid_at(now) {
+      return false;
+    }
+    let Ok((signed_certificate, _)) =
+      expect_der(responder_der.raw, TAG_SEQUENCE).and_then(|(cert, _)| read_signed(cert.contents))
+    else {
+      return false;
+    };
+    verify_signature(issuer_der, &signed_certificate)
+      && verify_signature(&Certificate::from(responder_der.raw.to_vec()), signed_response_data)
+  });
+  if !delegated {
+    return Err(invalid_ocsp(
+      "signature is not made by the issuer or a responder delegated by it".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+/// Verify the signature of the signed data with the key of the signer certificate
+fn verify_signature(signer: &Certificate, signed: &Signed<'_>) -> bool {
+  let Ok(signer) = webpki::EndEntityCert::try_from(signer) else {
+    return false;
+  };
+  let supported = CryptoProvider::get_default()
+    .map(|provider| provider.signature_verification_algorithms)
+    .unwrap_or_else(|| rustls::crypto::aws_lc_rs::default_provider().signature_verification_algorithms);
+  supported
+    .all
+    .iter()
+    .filter(|algorithm| algorithm.signature_alg_id().as_ref() == signed.signature_algorithm)
+    .any(|algorithm| signer.verify_signature(*algorithm, signed.data.raw, signed.signature).is_ok())
+}
+
+/// Bytes of the subject public key of the certificate, hashed in `CertID`
+fn subject_public_key<'a>(cert: &'a X509Certificate<'_>) -> Result<&'a [u8], RpxyCertError> {
+  let (spki, _) = expect_der(cert.public_key().raw, TAG_SEQUENCE)?;
+  let (_algorithm, rest) = expect_der(spki.contents, TAG_SEQUENCE)?;
+  let (public_key, _) = expect_der(rest, TAG_BIT_STRING)?;
+  bit_string_bytes(&public_key)
+}
+
+/// Parse the time in DER to seconds since the Unix epoch
+fn parse_time(time: &[u8]) -> Result<i64, RpxyCertError> {
+  let (_, time) = ASN1Time::from_der(time).map_err(|e| invalid_ocsp(e.to_string()))?;
+  Ok(time.timestamp())
+}
+
+fn invalid_ocsp(reason: String) -> RpxyCertError {
+  RpxyCertError::InvalidOcspResponse(reason)
+}
+
+/* ------------------------------------------------ */
+/// DER element, i.e., its tag, its contents and its whole encoding
+struct Der<'a> {
+  tag: u8,
+  contents: &'a [u8],
+  raw: &'a [u8],
+}
+
+/// Read the DER element at the head of the input, returning it and the rest of the input
+fn read_der(input: &[u8]) -> Result<(Der<'_>, &[u8]), RpxyCertError> {
+  let malformed = || invalid_ocsp("malformed DER".to_string());
+  let [tag, length, rest @ ..] = input else {
+    return Err(malformed());
+  };
+  let (length, rest) = match *length {
+    length if length < 0x80 => (length as usize, rest),
+    0x81..=0x84 => {
+      let length_size = (length & 0x7f) as usize;
+      if rest.len() < length_size {
+        return Err(malformed());
+      }
+      let length = rest[..length_size]
+        .iter()
+        .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+      (length, &rest[length_size..])
+    }
+    _ => return Err(malformed()),
+  };
+  if rest.len() < length {
+    return Err(malformed());
+  }
+  let header_size = input.len() - rest.len();
+  let der = Der {
+    tag: *tag,
+    contents: &rest[..length],
+    raw: &input[..header_size + length],
+  };
+  Ok((der, &rest[length..]))
+}
+
+/// Read the DER element at the head of the input, which must have the tag
+fn expect_der(input: &[u8], tag: u8) -> Result<(Der<'_>, &[u8]), RpxyCertError> {
+  let (der, rest) = read_der(input)?;
+  if der.tag != tag {
+    return Err(invalid_ocsp(format!(
+      "unexpected DER tag {:#04x}, expected {tag:#04x}",
+      der.tag
+    )));
+  }
+  Ok((der, rest))
+}
+
+/// Read all the DER elements of the contents of `SEQUENCE OF`
+fn read_der_sequence_of(mut contents: &[u8]) -> Result<Vec<Der<'_>>, RpxyCertError> {
+  let mut elements = vec![];
+  while !contents.is_empty() {
+    let (element, rest) = read_der(contents)?;
+    elements.push(element);
+    contents = rest;
+  }
+  Ok(elements)
+}
+
+/// Signed data in a signed object, with the contents of its signature algorithm and its signature
+struct Signed<'a> {
+  data: Der<'a>,
+  signature_algorithm: &'a [u8],
+  signature: &'a [u8],
+}
+
+/// Read the signed data at the head of the contents of a signed object, returning it and the rest of the contents
+fn read_signed(contents: &[u8]) -> Result<(Signed<'_>, &[u8]), RpxyCertError> {
+  let (data, rest) = expect_der(contents, TAG_SEQUENCE)?;
+  let (signature_algorithm, rest) = expect_der(rest, TAG_SEQUENCE)?;
+  let (signature, rest) = expect_der(rest, TAG_BIT_STRING)?;
+  let signed = Signed {
+    data,
+    signature_algorithm: signature_algorithm.contents,
+    signature: bit_string_bytes(&signature)?,
+  };
+  Ok((signed, rest))
+}
+
+/// Bytes of the bit string without unused bits
+fn bit_string_bytes<'a>(bit_string: &Der<'a>) -> Result<&'a [u8], RpxyCertError> {
+  match bit_string.contents {
+    [0, bytes @ ..] => Ok(bytes),
+    _ => Err(invalid_ocsp("bit string with unused bits".to_string())),
+  }
+}
+
+/// Encode a DER element of the tag and the contents
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+  let mut encoded = vec![tag];
+  match contents.len() {
+    length if length < 0x80 => encoded.push(length as u8),
+    length => {
+      let length_bytes = length.to_be_bytes();
+      let length_bytes = &length_bytes[length_bytes.iter().take_while(|byte| **byte == 0).count()..];
+      encoded.push(0x80 | length_bytes.len() as u8);
+      encoded.extend_from_slice(length_bytes);
+    }
+  }
+  encoded.extend_from_slice(contents);
+  encoded
+}
+
+/* ------------------------------------------------ */
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rustls::pki_types::pem::PemObject;
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+  };
+
+  fn read_chain(path: &str) -> Vec<Certificate> {
+    Certificate::pem_file_iter(path)
+      .unwrap()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap()
+  }
+
+  fn read_response(name: &str) -> Vec<u8> {
+    std::fs::read(format!("../example-certs/ocsp/{name}")).unwrap()
+  }
+
+  #[test]
+  fn verify_ocsp_responses_for_the_leaf() {
+    let certs = read_chain("../example-certs/ocsp/server.crt");
+    let now = 1_900_000_000;
+
+    // Signed by the issuer itself
+    let validity = verify_ocsp_response(&read_response("good.der"), &certs, now).unwrap();
+    assert!(validity.this_update < now);
+    assert!(validity.next_update.is_some_and(|next_update| next_update > now));
+    assert!(!validity.needs_refresh(now));
+    assert!(validity.needs_refresh(validity.next_update.unwrap() - 1));
+
+    // Signed by a responder delegated by the issuer
+    assert!(verify_ocsp_response(&read_response("delegated.der"), &certs, now).is_ok());
+
+    // Revoked, expired, or without the issuer to verify with
+    assert!(verify_ocsp_response(&read_response("revoked.der"), &certs, now).is_err());
+    let expired = validity.next_update.unwrap() + 1;
+    assert!(verify_ocsp_response(&read_response("good.der"), &certs, expired).is_err());
+    assert!(verify_ocsp_response(&read_response("good.der"), &certs[..1], now).is_err());
+    let other_certs = read_chain("../example-certs/server.crt");
+    assert!(verify_ocsp_response(&read_response("good.der"), &other_certs, now).is_err());
+
+    // Tampered in the signed data, where the first GeneralizedTime is producedAt
+    let mut tampered = read_response("good.der");
+    let produced_at = tampered.windows(2).position(|w| w == [TAG_GENERALIZED_TIME, 15]).unwrap();
+    tampered[produced_at + 2] ^= 0x01;
+    assert!(verify_ocsp_response(&tampered, &certs, now).is_err());
+    assert!(verify_ocsp_response(b"not an ocsp response", &certs, now).is_err());
+  }
+
+  #[tokio::test]
+  async fn fetch_ocsp_response_from_responder() {
+    let certs = read_chain("../example-certs/ocsp/server.crt");
+    assert_eq!(ocsp_responder_url(&certs[0]).as_deref(), Some("http://127.0.0.1:8888/"));
+    assert_eq!(ocsp_responder_url(&certs[1]), None);
+
+    // Responder answering the request for the leaf with the fixture
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let responder = tokio::spawn(async move {
+      let (mut stream, _) = listener.accept().await.unwrap();
+      let mut received = vec![];
+      let mut buf = [0u8; 4096];
+      // Read until the whole DER of the request is received as the body
+      let request = loop {
+        let read = stream.read(&mut buf).await.unwrap();
+        assert!(read > 0, "connection closed before the request is received");
+        received.extend_from_slice(&buf[..read]);
+        let Some(head_end) = received.windows(4).position(|w| w == b"\r\n\r\n") else {
+          continue;
+        };
+        if let Ok((request, _)) = read_der(&received[head_end + 4..]) {
+          break request.raw.to_vec();
+        }
+      };
+      let body = read_response("good.der");
+      let head = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/ocsp-response\r\ncontent-length: {}\r\n\r\n",
+        body.len()
+      );
+      stream.write_all(&[head.as_bytes(), &body].concat()).await.unwrap();
+      request
+    });
+
+    let request = build_ocsp_request(&certs).unwrap();
+    let response = fetch_ocsp_response(&url, request.clone()).await.unwrap();
+    assert_eq!(response, read_response("good.der"));
+    assert_eq!(responder.await.unwrap(), request);
+
+    // The request identifies the leaf issued by the issuer
+    let (ocsp_request, _) = expect_der(&request, TAG_SEQUENCE).unwrap();
+    let (tbs_request, _) = expect_der(ocsp_request.contents, TAG_SEQUENCE).unwrap();
+    let (request_list, _) = expect_der(tbs_request.contents, TAG_SEQUENCE).unwrap();
+    let (single_request, _) = expect_der(request_list.contents, TAG_SEQUENCE).unwrap();
+    let (cert_id, _) = expect_der(single_request.contents, TAG_SEQUENCE).unwrap();
+    let (_, leaf) = parse_x509_certificate(&certs[0]).unwrap();
+    let (_, issuer) = parse_x509_certificate(&certs[1]).unwrap();
+    assert!(matches_cert_id(cert_id.contents, &leaf, &issuer).unwrap());
+  }
+}
@@ -203,4 +203,39 @@ mod tests {
     let new_value = updated.inner.get(b"localhost".as_slice()).unwrap();
     assert_ne!(new_value, &original, "a successful read must replace the retained value");
   }
+
+  /// CRLs of client CAs are read again on every reload, so that newly revoked client certificates
+  /// are rejected without a restart, while a malformed CRL keeps the previously loaded ones.
+  #[tokio::test]
+  async fn reload_picks_up_updated_client_crls() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::write(dir.join("crl.pem"), "").unwrap();
+
+    let mut crypto_reloader = CryptoReloader::new(&HashMap::default()).await.unwrap();
+    let crypto_source = CryptoFileSourceBuilder::default()
+      .tls_cert_path("../example-certs/server.crt")
+      .tls_cert_key_path("../example-certs/server.key")
+      .client_ca_cert_path(Some("../example-certs/client-chain/ca-chain.crt"))
+      .client_crl_path(Some(dir.join("crl.pem")))
+      .build()
+      .unwrap();
+    crypto_reloader.extend(vec![(b"localhost".to_vec(), crypto_source)]);
+
+    // An empty file is not a valid CRL, so the domain is skipped until it is given
+    assert!(crypto_reloader.reload().await.unwrap().unwrap().inner.is_empty());
+
+    std::fs::copy("../example-certs/client-chain/crl.pem", dir.join("crl.pem")).unwrap();
+    let loaded = crypto_reloader.reload().await.unwrap().unwrap();
+    let certs_keys = loaded.inner.get(b"localhost".as_slice()).unwrap();
+    assert_eq!(certs_keys.client_crls().unwrap().len(), 2);
+
+    std::fs::write(
+      dir.join("crl.pem"),
+      "-----BEGIN X509 CRL-----\nAAAA\n-----END X509 CRL-----\n",
+    )
+    .unwrap();
+    let retained = crypto_reloader.reload().await.unwrap().unwrap();
+    assert_eq!(retained.inner.get(b"localhost".as_slice()).unwrap(), certs_keys);
+  }
 }
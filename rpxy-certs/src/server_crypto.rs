@@ -1,9 +1,10 @@
-use crate::{certs::SingleServerCertsKeys, error::*, log::*};
+use crate::{certs::SingleServerCertsKeys, client_verifier::build_client_cert_verifier, error::*, log::*};
 use ahash::HashMap;
 use rustls::{
-  RootCertStore, ServerConfig,
+  ServerConfig,
   crypto::CryptoProvider,
-  server::{NoServerSessionStorage, ProducesTickets, ResolvesServerCertUsingSni, WebPkiClientVerifier},
+  server::{NoServerSessionStorage, ProducesTickets, ResolvesServerCertUsingSni},
+  sign::SingleCertAndKey,
 };
 use std::sync::{Arc, OnceLock};
 
@@ -50,7 +51,7 @@ pub type ServerNameCryptoMap = HashMap<ServerNameBytes, ServerCryptoForSni>;
 
 /// ServerName (SNI) to ServerConfig map
 pub struct ServerCrypto {
-  // For Quic/HTTP3, only servers with no client authentication, aggregated server config
+  // For Quic/HTTP3, only servers with no client authentication nor restricted crypto, aggregated server config
   pub aggregated_config_no_client_auth: Arc<ServerConfig>,
   // For TLS over TCP/HTTP2 and 1.1, map of SNI to server_crypto for all given servers
   pub individual_config_map: Arc<ServerNameCryptoMap>,
@@ -78,6 +79,14 @@ impl TryInto<Arc<ServerCrypto>> for &ServerCryptoBase {
 }
 
 impl ServerCryptoBase {
+  /// Iterate over the server names and their loaded certificates and keys
+  pub fn iter(&self) -> impl Iterator<Item = (String, &SingleServerCertsKeys)> {
+    self
+      .inner
+      .iter()
+      .map(|(server_name_bytes, certs_keys)| (String::from_utf8_lossy(server_name_bytes).to_string(), certs_keys))
+  }
+
   /// Build individual server crypto inner object
   fn build_individual_server_crypto_map(&self) -> Result<ServerNameCryptoMap, RpxyCertError> {
     let mut server_crypto_map: ServerNameCryptoMap = HashMap::default();
@@ -99,10 +108,18 @@ impl ServerCryptoBase {
         error!("{server_name}: Failed to read some certificates and keys {e}");
       };
 
+      // Protocol versions, cipher suites and key exchange groups restricted by the TLS policy
+      let config_builder = match certs_keys.tls_policy().server_config_builder(provider) {
+        Ok(config_builder) => config_builder,
+        Err(e) => {
+          warn!("Failed to apply TLS policy for {server_name}: {e}");
+          continue;
+        }
+      };
+
       // With no client authentication case
       if !certs_keys.is_mutual_tls() {
-        let mut server_crypto_local = ServerConfig::builder_with_provider(provider.clone())
-          .with_safe_default_protocol_versions()?
+        let mut server_crypto_local = config_builder
           .with_no_client_auth()
           .with_cert_resolver(Arc::new(resolver_local));
 
@@ -131,22 +148,14 @@ impl ServerCryptoBase {
       }
 
       // With client authentication case, enable only http2 and http1.1
-      let mut client_ca_roots_local = RootCertStore::empty();
-      let Ok(trust_anchors) = certs_keys.rustls_client_certs_trust_anchors() else {
-        warn!("Failed to add client CA certificate for {server_name}");
-        continue;
-      };
-      let trust_anchors_without_skid = trust_anchors.values().map(|ta| ta.to_owned());
-      client_ca_roots_local.extend(trust_anchors_without_skid);
-
-      let Ok(client_cert_verifier) =
-        WebPkiClientVerifier::builder_with_provider(Arc::new(client_ca_roots_local), provider.clone()).build()
-      else {
-        warn!("Failed to build client CA certificate verifier for {server_name}");
-        continue;
+      let client_cert_verifier = match build_client_cert_verifier(certs_keys, provider) {
+        Ok(client_cert_verifier) => client_cert_verifier,
+        Err(e) => {
+          warn!("Failed to build client CA certificate verifier for {server_name}: {e}");
+          continue;
+        }
       };
-      let mut server_crypto_local = ServerConfig::builder_with_provider(provider.clone())
-        .with_safe_default_protocol_versions()?
+      let mut server_crypto_local = config_builder
         .with_client_cert_verifier(client_cert_verifier)
         .with_cert_resolver(Arc::new(resolver_local));
       server_crypto_local.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
@@ -167,6 +176,35 @@ impl ServerCryptoBase {
     Ok(server_crypto_map)
   }
 
+  /* ------------------------------------------------ */
+  /// Build a server config for a TCP stream proxy terminating TLS with the certificate of the given server name.
+  /// Unlike the configs for HTTP, the certificate is served even if the client sends no SNI, and no ALPN protocol is offered.
+  /// Returns None if no certificate is loaded for the server name.
+  pub fn build_stream_server_config(&self, server_name: &str) -> Result<Option<Arc<ServerConfig>>, RpxyCertError> {
+    let Some(certs_keys) = self.inner.get(server_name.to_ascii_lowercase().as_bytes()) else {
+      return Ok(None);
+    };
+    let provider = CryptoProvider::get_default().ok_or(RpxyCertError::NoDefaultCryptoProvider)?;
+    let resolver = Arc::new(SingleCertAndKey::from(certs_keys.rustls_certified_key()?));
+    let config_builder = certs_keys.tls_policy().server_config_builder(provider)?;
+
+    if !certs_keys.is_mutual_tls() {
+      let mut server_crypto = config_builder.with_no_client_auth().with_cert_resolver(resolver);
+      // Same stateless-tickets-only resumption policy as the per-SNI non-mTLS configs for HTTP
+      server_crypto.ticketer = shared_ticketer()?;
+      server_crypto.session_storage = Arc::new(NoServerSessionStorage {});
+      return Ok(Some(Arc::new(server_crypto)));
+    }
+
+    let client_cert_verifier = build_client_cert_verifier(certs_keys, provider)?;
+    let mut server_crypto = config_builder
+      .with_client_cert_verifier(client_cert_verifier)
+      .with_cert_resolver(resolver);
+    // No session resumption with mutual TLS, as with the per-SNI mTLS configs for HTTP
+    server_crypto.session_storage = Arc::new(NoServerSessionStorage {});
+    Ok(Some(Arc::new(server_crypto)))
+  }
+
   /* ------------------------------------------------ */
   /// Build aggregated server crypto inner object for no client auth server especially for http3
   fn build_aggregated_server_crypto(&self) -> Result<ServerConfig, RpxyCertError> {
@@ -183,8 +221,10 @@ impl ServerCryptoBase {
         warn!("Failed to add certificate for {server_name}");
         continue;
       };
-      // Add server certificates and private keys to resolver only if client CA certs are not present
-      if !certs_keys.is_mutual_tls() {
+      // Add server certificates and private keys to resolver only if client CA certs are not present and the cipher
+      // suites and key exchange groups are not restricted, which cannot be applied per server name in the shared config.
+      // A minimum version needs no exclusion as QUIC always runs on TLS 1.3.
+      if !certs_keys.is_mutual_tls() && !certs_keys.tls_policy().is_crypto_restricted() {
         // aggregated server config for no client auth server for http3
         if let Err(e) = resolver_global.add(&server_name, certified_key) {
           error!("{server_name}: Failed to read some certificates and keys {e}");
@@ -219,7 +259,7 @@ impl ServerCryptoBase {
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::{CryptoFileSourceBuilder, CryptoSource};
+  use crate::{CryptoFileSourceBuilder, CryptoSource, tls_policy::tests::rejects_tls12_client};
   use std::convert::TryInto;
 
   async fn read_file_source() -> SingleServerCertsKeys {
@@ -291,6 +331,78 @@ mod tests {
     }
   }
 
+  #[tokio::test]
+  async fn test_tls_policy_applied_to_per_sni_configs() {
+    let _ = CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider());
+
+    let tls_policy = crate::TlsPolicy::new(Some("1.3"), Some(&["TLS_AES_256_GCM_SHA384"]), None).unwrap();
+    let crypto_file_source = CryptoFileSourceBuilder::default()
+      .tls_cert_key_path("../example-certs/server.key")
+      .tls_cert_path("../example-certs/server.crt")
+      .tls_policy(tls_policy)
+      .build()
+      .unwrap();
+    let mut server_crypto_base = ServerCryptoBase::default();
+    server_crypto_base
+      .inner
+      .insert(b"example.com".to_vec(), crypto_file_source.read().await.unwrap());
+    server_crypto_base
+      .inner
+      .insert(b"localhost".to_vec(), read_file_source().await);
+    let server_crypto: Arc<ServerCrypto> = (&server_crypto_base).try_into().unwrap();
+
+    let restricted = &server_crypto
+      .individual_config_map
+      .get(b"example.com".as_slice())
+      .unwrap()
+      .server_config;
+    assert!(rejects_tls12_client(restricted.clone()));
+    let suites = restricted
+      .crypto_provider()
+      .cipher_suites
+      .iter()
+      .map(|s| s.suite())
+      .collect::<Vec<_>>();
+    assert_eq!(suites, vec![rustls::CipherSuite::TLS13_AES_256_GCM_SHA384]);
+
+    let default = &server_crypto
+      .individual_config_map
+      .get(b"localhost".as_slice())
+      .unwrap()
+      .server_config;
+    assert!(!rejects_tls12_client(default.clone()));
+    assert!(default.crypto_provider().cipher_suites.len() > 1);
+
+    let stream = server_crypto_base.build_stream_server_config("example.com").unwrap().unwrap();
+    assert!(rejects_tls12_client(stream.clone()));
+  }
+
+  #[tokio::test]
+  async fn test_stream_server_config_serves_cert_without_alpn() {
+    let _ = CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider());
+
+    let mut server_crypto_base = ServerCryptoBase::default();
+    server_crypto_base
+      .inner
+      .insert(b"localhost".to_vec(), read_file_source().await);
+    server_crypto_base
+      .inner
+      .insert(b"example.com".to_vec(), read_file_source_without_client_ca().await);
+
+    let non_mtls = server_crypto_base.build_stream_server_config("Example.com").unwrap().unwrap();
+    assert!(non_mtls.alpn_protocols.is_empty());
+    assert!(non_mtls.ticketer.enabled());
+    let mtls = server_crypto_base.build_stream_server_config("localhost").unwrap().unwrap();
+    assert!(mtls.alpn_protocols.is_empty());
+    assert!(!mtls.ticketer.enabled());
+    assert!(
+      server_crypto_base
+        .build_stream_server_config("unknown.example.com")
+        .unwrap()
+        .is_none()
+    );
+  }
+
   #[tokio::test]
   async fn test_non_mtls_configs_use_stateless_tickets_without_session_cache() {
     let _ = CryptoProvider::install_default(rustls::crypto::aws_lc_rs::default_provider());
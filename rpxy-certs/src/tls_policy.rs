@@ -0,0 +1,270 @@
+use crate::error::*;
+use rustls::{
+  CipherSuite, ConfigBuilder, NamedGroup, ProtocolVersion, ServerConfig, SupportedProtocolVersion, WantsVerifier,
+  crypto::CryptoProvider,
+};
+use std::sync::Arc;
+
+/* ------------------------------------------------ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// TLS protocol versions, cipher suites and key exchange groups allowed for a server name, the defaults of the crypto
+/// provider unless given
+pub struct TlsPolicy {
+  /// Minimum TLS protocol version
+  min_version: Option<ProtocolVersion>,
+  /// Cipher suites in the order of preference
+  cipher_suites: Option<Vec<CipherSuite>>,
+  /// Key exchange groups in the order of preference
+  kx_groups: Option<Vec<NamedGroup>>,
+}
+
+impl TlsPolicy {
+  /// Build a policy from the names, validating them against the crypto provider.
+  /// - `min_version`: `"1.2"` or `"1.3"`
+  /// - `cipher_suites`: IANA names, e.g., `TLS_AES_128_GCM_SHA256` or `TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256`
+  /// - `kx_groups`: e.g., `X25519`, `secp256r1`, `secp384r1` or `X25519MLKEM768`
+  pub fn new<S: AsRef<str>>(
+    min_version: Option<&str>,
+    cipher_suites: Option<&[S]>,
+    kx_groups: Option<&[S]>,
+  ) -> Result<Self, RpxyCertError> {
+    let provider = crypto_provider();
+    let invalid = |reason: String| RpxyCertError::InvalidTlsPolicy(reason);
+
+    let min_version = min_version
+      .map(|version| match version {
+        "1.2" => Ok(ProtocolVersion::TLSv1_2),
+        "1.3" => Ok(ProtocolVersion::TLSv1_3),
+        _ => Err(invalid(format!(
+          "unsupported min_version `{version}`, expected \"1.2\" or \"1.3\""
+        ))),
+      })
+      .transpose()?;
+
+    let cipher_suites = cipher_suites
+      .map(|names| {
+        let supported = provider.cipher_suites.iter().map(|suite| suite.suite()).collect::<Vec<_>>();
+        parse_names(names, "cipher suite", |name| {
+          supported
+            .iter()
+            .find(|suite| {
+              suite
+                .as_str()
+                .is_some_and(|suite_name| cipher_suite_name_matches(suite_name, name))
+            })
+            .copied()
+        })
+      })
+      .transpose()?;
+
+    let kx_groups = kx_groups
+      .map(|names| {
+        let supported = provider.kx_groups.iter().map(|group| group.name()).collect::<Vec<_>>();
+        parse_names(names, "key exchange group", |name| {
+          supported
+            .iter()
+            .find(|group| group.as_str().is_some_and(|group_name| group_name.eq_ignore_ascii_case(name)))
+            .copied()
+        })
+      })
+      .transpose()?;
+
+    let policy = Self {
+      min_version,
+      cipher_suites,
+      kx_groups,
+    };
+    // e.g., no cipher suite for the versions, or no key exchange group for the cipher suites
+    policy
+      .server_config_builder(&provider)
+      .map_err(|e| invalid(format!("inconsistent settings: {e}")))?;
+    Ok(policy)
+  }
+
+  /// Whether cipher suites or key exchange groups are restricted, which cannot be applied to the server config shared
+  /// among server names for HTTP/3
+  pub fn is_crypto_restricted(&self) -> bool {
+    self.cipher_suites.is_some() || self.kx_groups.is_some()
+  }
+
+  /// Start building a server config with the policy applied to the crypto provider
+  pub(crate) fn server_config_builder(
+    &self,
+    provider: &Arc<CryptoProvider>,
+  ) -> Result<ConfigBuilder<ServerConfig, WantsVerifier>, RpxyCertError> {
+    let mut provider = provider.as_ref().clone();
+    if let Some(cipher_suites) = &self.cipher_suites {
+      let supported = std::mem::take(&mut provider.cipher_suites);
+      provider.cipher_suites = cipher_suites
+        .iter()
+        .filter_map(|suite| supported.iter().find(|supported| supported.suite() == *suite).copied())
+        .collect();
+    }
+    if let Some(kx_groups) = &self.kx_groups {
+      let supported = std::mem::take(&mut provider.kx_groups);
+      provider.kx_groups = kx_groups
+        .iter()
+        .filter_map(|group| supported.iter().find(|supported| supported.name() == *group).copied())
+        .collect();
+    }
+    let versions: &[&'static SupportedProtocolVersion] = match self.min_version {
+      Some(ProtocolVersion::TLSv1_3) => &[&rustls::version::TLS13],
+      _ => rustls::DEFAULT_VERSIONS,
+    };
+    let builder = ServerConfig::builder_with_provider(Arc::new(provider)).with_protocol_versions(versions)?;
+    Ok(builder)
+  }
+}
+
+/// Crypto provider to which policies are applied, aws_lc_rs unless another one is installed as the default
+fn crypto_provider() -> Arc<CryptoProvider> {
+  CryptoProvider::get_default()
+    .cloned()
+    .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+}
+
+/// Parse the non-empty list of names, rejecting unsupported ones and dropping duplicates
+fn parse_names<S: AsRef<str>, T: PartialEq>(
+  names: &[S],
+  kind: &str,
+  find: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, RpxyCertError> {
+  if names.is_empty() {
+    return Err(RpxyCertError::InvalidTlsPolicy(format!("empty list of {kind}s")));
+  }
+  let mut parsed = Vec::with_capacity(names.len());
+  for name in names.iter().map(|name| name.as_ref()) {
+    let value = find(name).ok_or_else(|| RpxyCertError::InvalidTlsPolicy(format!("unsupported {kind} `{name}`")))?;
+    if !parsed.contains(&value) {
+      parsed.push(value);
+    }
+  }
+  Ok(parsed)
+}
+
+/// Whether the name matches the cipher suite named by rustls, where TLS 1.3 suites are prefixed by `TLS13_` instead of
+/// `TLS_` of their IANA names
+fn cipher_suite_name_matches(suite_name: &str, name: &str) -> bool {
+  if suite_name.eq_ignore_ascii_case(name) {
+    return true;
+  }
+  match suite_name.strip_prefix("TLS13_") {
+    Some(suffix) => match (name.get(..4), name.get(4..)) {
+      (Some(prefix), Some(rest)) => prefix.eq_ignore_ascii_case("TLS_") && rest.eq_ignore_ascii_case(suffix),
+      _ => false,
+    },
+    None => false,
+  }
+}
+
+/* ------------------------------------------------ */
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  #[test]
+  fn build_tls_policy_from_names() {
+    let policy = TlsPolicy::new::<&str>(None, None, None).unwrap();
+    assert_eq!(policy, TlsPolicy::default());
+    assert!(!policy.is_crypto_restricted());
+
+    let policy = TlsPolicy::new(
+      Some("1.3"),
+      Some(&["TLS_AES_256_GCM_SHA384", "tls13_aes_128_gcm_sha256", "TLS_AES_256_GCM_SHA384"]),
+      Some(&["x25519", "secp384r1"]),
+    )
+    .unwrap();
+    assert_eq!(policy.min_version, Some(ProtocolVersion::TLSv1_3));
+    assert_eq!(
+      policy.cipher_suites,
+      Some(vec![
+        CipherSuite::TLS13_AES_256_GCM_SHA384,
+        CipherSuite::TLS13_AES_128_GCM_SHA256
+      ])
+    );
+    assert_eq!(policy.kx_groups, Some(vec![NamedGroup::X25519, NamedGroup::secp384r1]));
+    assert!(policy.is_crypto_restricted());
+
+    let policy = TlsPolicy::new(Some("1.2"), Some(&["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"]), None).unwrap();
+    assert_eq!(
+      policy.cipher_suites,
+      Some(vec![CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256])
+    );
+  }
+
+  #[test]
+  fn reject_invalid_tls_policy() {
+    for (min_version, cipher_suites, kx_groups) in [
+      // Unsupported versions
+      (Some("1.1"), None, None),
+      (Some("TLSv1.3"), None, None),
+      // Unknown or empty names
+      (None, Some(vec!["TLS_RSA_WITH_AES_128_CBC_SHA"]), None),
+      (None, Some(vec!["TLSé_AES_128_GCM_SHA256"]), None),
+      (None, Some(vec!["TLS"]), None),
+      (None, Some(vec![]), None),
+      (None, None, Some(vec!["ffdhe2048"])),
+      (None, None, Some(vec![])),
+      // No TLS 1.3 cipher suite for TLS 1.3 only
+      (Some("1.3"), Some(vec!["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"]), None),
+    ] {
+      let result = TlsPolicy::new(min_version, cipher_suites.as_deref(), kx_groups.as_deref());
+      assert!(
+        matches!(result, Err(RpxyCertError::InvalidTlsPolicy(_))),
+        "{min_version:?} {cipher_suites:?} {kx_groups:?} must be rejected"
+      );
+    }
+  }
+
+  #[test]
+  fn apply_tls_policy_to_server_config() {
+    let provider = crypto_provider();
+    let policy = TlsPolicy::new(Some("1.3"), Some(&["TLS_CHACHA20_POLY1305_SHA256"]), Some(&["secp256r1"])).unwrap();
+    let config = policy
+      .server_config_builder(&provider)
+      .unwrap()
+      .with_no_client_auth()
+      .with_cert_resolver(Arc::new(rustls::server::ResolvesServerCertUsingSni::new()));
+    let suites = config
+      .crypto_provider()
+      .cipher_suites
+      .iter()
+      .map(|s| s.suite())
+      .collect::<Vec<_>>();
+    assert_eq!(suites, vec![CipherSuite::TLS13_CHACHA20_POLY1305_SHA256]);
+    let groups = config
+      .crypto_provider()
+      .kx_groups
+      .iter()
+      .map(|g| g.name())
+      .collect::<Vec<_>>();
+    assert_eq!(groups, vec![NamedGroup::secp256r1]);
+    assert!(rejects_tls12_client(Arc::new(config)));
+
+    let config = TlsPolicy::default()
+      .server_config_builder(&provider)
+      .unwrap()
+      .with_no_client_auth()
+      .with_cert_resolver(Arc::new(rustls::server::ResolvesServerCertUsingSni::new()));
+    assert_eq!(config.crypto_provider().cipher_suites.len(), provider.cipher_suites.len());
+    assert!(!rejects_tls12_client(Arc::new(config)));
+  }
+
+  /// Whether the server rejects the ClientHello of a TLS 1.2 only client for its protocol version
+  pub(crate) fn rejects_tls12_client(server_config: Arc<ServerConfig>) -> bool {
+    let client_config = rustls::ClientConfig::builder_with_protocol_versions(&[&rustls::version::TLS12])
+      .with_root_certificates(rustls::RootCertStore::empty())
+      .with_no_client_auth();
+    let mut client = rustls::ClientConnection::new(Arc::new(client_config), "localhost".try_into().unwrap()).unwrap();
+    let mut server = rustls::ServerConnection::new(server_config).unwrap();
+    let mut client_hello = Vec::new();
+    client.write_tls(&mut client_hello).unwrap();
+    server.read_tls(&mut client_hello.as_slice()).unwrap();
+    matches!(
+      server.process_new_packets(),
+      Err(rustls::Error::PeerIncompatible(
+        rustls::PeerIncompatible::Tls12NotOfferedOrEnabled
+      ))
+    )
+  }
+}
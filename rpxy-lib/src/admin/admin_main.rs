@@ -0,0 +1,432 @@
+use super::admin_status::*;
+use crate::{
+  backend::{BackendAppManager, UpstreamGroup, UpstreamOverride, UpstreamOverrides},
+  constants::admin::{HEADER_READ_TIMEOUT_SEC, LISTEN_BACKLOG, MAX_REQUEST_BODY_SIZE},
+  error::*,
+  globals::{AdminConfig, AdminListen, Globals},
+  hyper_ext::{
+    body::{BoxBody, full},
+    rt::TokioTimer,
+  },
+  log::*,
+  proxy::bind_tcp_socket,
+};
+use bytes::Bytes;
+use http::{HeaderMap, Method, Request, Response, StatusCode, header};
+use http_body_util::{BodyExt, Limited};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "cache")]
+use crate::forwarder::{PurgeTarget, RpxyCache};
+
+/// Local admin API serving the runtime state as JSON. It listens on its own socket and shares no
+/// connection limits with the proxy listeners, and every request must carry the bearer token.
+#[derive(Clone)]
+pub(crate) struct AdminService {
+  pub config: Arc<AdminConfig>,
+  pub globals: Arc<Globals>,
+  pub app_manager: Arc<BackendAppManager>,
+  pub upstream_overrides: UpstreamOverrides,
+  #[cfg(feature = "cache")]
+  pub cache: Option<RpxyCache>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Body of `PUT` and `DELETE` on `/upstreams/overrides`, addressing an upstream of either an app or a stream
+struct OverrideRequest {
+  app: Option<String>,
+  stream: Option<String>,
+  /// Upstream location as shown at `/upstreams`
+  upstream: String,
+  /// Override to set, required for `PUT`
+  state: Option<String>,
+}
+
+#[cfg(feature = "cache")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Body of `POST /cache/purge`, giving either `url`, `host` with optional `path_prefix`, `path_prefix`,
+/// or `surrogate_key`
+struct PurgeRequest {
+  url: Option<String>,
+  host: Option<String>,
+  path_prefix: Option<String>,
+  surrogate_key: Option<String>,
+}
+
+#[cfg(feature = "cache")]
+impl TryFrom<PurgeRequest> for PurgeTarget {
+  type Error = &'static str;
+  fn try_from(req: PurgeRequest) -> Result<Self, Self::Error> {
+    if req.path_prefix.as_ref().is_some_and(|p| !p.starts_with('/')) {
+      return Err("path_prefix must start with '/'");
+    }
+    match req {
+      PurgeRequest {
+        url: Some(url),
+        host: None,
+        path_prefix: None,
+        surrogate_key: None,
+      } => match url.parse::<http::Uri>() {
+        Ok(uri) if uri.scheme().is_some() && uri.authority().is_some() => Ok(PurgeTarget::Url(uri)),
+        _ => Err("url must be an absolute URL"),
+      },
+      PurgeRequest {
+        url: None,
+        host: Some(host),
+        path_prefix,
+        surrogate_key: None,
+      } => Ok(PurgeTarget::Host { host, path_prefix }),
+      PurgeRequest {
+        url: None,
+        host: None,
+        path_prefix: Some(path_prefix),
+        surrogate_key: None,
+      } => Ok(PurgeTarget::PathPrefix(path_prefix)),
+      PurgeRequest {
+        url: None,
+        host: None,
+        path_prefix: None,
+        surrogate_key: Some(key),
+      } => Ok(PurgeTarget::SurrogateKey(key)),
+      _ => Err("exactly one of url, host (with optional path_prefix), path_prefix or surrogate_key must be given"),
+    }
+  }
+}
+
+impl AdminService {
+  /// Start serving the admin API
+  pub(crate) async fn start(&self) -> RpxyResult<()> {
+    match &self.config.listen {
+      AdminListen::Tcp(listening_on) => {
+        let tcp_listener = bind_tcp_socket(listening_on)?.listen(LISTEN_BACKLOG)?;
+        info!("Start admin API service on {listening_on}");
+        loop {
+          match tcp_listener.accept().await {
+            Ok((stream, _)) => self.serve_connection(stream),
+            Err(e) => warn!("Failed to accept admin API connection: {e}"),
+          }
+        }
+      }
+      #[cfg(unix)]
+      AdminListen::Unix(path) => {
+        let unix_listener = bind_unix_listener(path)?;
+        info!("Start admin API service on {}", path.display());
+        loop {
+          match unix_listener.accept().await {
+            Ok((stream, _)) => self.serve_connection(stream),
+            Err(e) => warn!("Failed to accept admin API connection: {e}"),
+          }
+        }
+      }
+    }
+  }
+
+  /// Serve HTTP/1.1 requests over an accepted admin connection
+  fn serve_connection<S>(&self, stream: S)
+  where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+  {
+    let service = self.clone();
+    self.globals.runtime_handle.spawn(async move {
+      let res = http1::Builder::new()
+        .timer(TokioTimer)
+        .header_read_timeout(Duration::from_secs(HEADER_READ_TIMEOUT_SEC))
+        .keep_alive(false)
+        .serve_connection(
+          TokioIo::new(stream),
+          service_fn(move |req| {
+            let service = service.clone();
+            async move { Ok::<_, Infallible>(service.handle(req).await) }
+          }),
+        )
+        .await;
+      if let Err(e) = res {
+        debug!("Admin API connection closed with error: {e}");
+      }
+    });
+  }
+
+  /// Authorize and route an admin request
+  async fn handle(&self, req: Request<Incoming>) -> Response<BoxBody> {
+    if !is_authorized(req.headers(), &self.config.token) {
+      warn!("Unauthorized admin API request: {} {}", req.method(), req.uri().path());
+      let mut res = error_response(StatusCode::UNAUTHORIZED, "missing or invalid bearer token");
+      res
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+      return res;
+    }
+    debug!("Admin API request: {} {}", req.method(), req.uri().path());
+    if req.uri().path() == "/upstreams/overrides" {
+      return match *req.method() {
+        Method::GET => json_response(&overrides_status(&self.upstream_overrides)),
+        Method::PUT | Method::DELETE => self.update_override(req).await,
+        _ => error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+      };
+    }
+    if req.uri().path() == "/cache/purge" {
+      return match *req.method() {
+        Method::POST => self.purge_cache(req).await,
+        _ => error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+      };
+    }
+    if req.method() != Method::GET {
+      return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    match req.uri().path() {
+      "/status" => json_response(&Status {
+        apps: apps_status(&self.app_manager),
+        streams: streams_status(&self.app_manager),
+        upstreams: upstreams_status(&self.app_manager),
+        overrides: overrides_status(&self.upstream_overrides),
+        cache: self.cache_status().await,
+        certs: self.certs_status(),
+        connections: connections_status(&self.globals),
+      }),
+      "/apps" => json_response(&apps_status(&self.app_manager)),
+      "/streams" => json_response(&streams_status(&self.app_manager)),
+      "/upstreams" => json_response(&upstreams_status(&self.app_manager)),
+      "/cache" => json_response(&self.cache_status().await),
+      "/certs" => json_response(&self.certs_status()),
+      "/connections" => json_response(&connections_status(&self.globals)),
+      _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    }
+  }
+
+  /// Set (`PUT`) or clear (`DELETE`) the override of an upstream, and respond with the overrides in effect.
+  /// An override can be set only for an upstream currently loaded, while it can be cleared anytime.
+  async fn update_override(&self, req: Request<Incoming>) -> Response<BoxBody> {
+    let is_put = req.method() == Method::PUT;
+    let request = match read_json_body::<OverrideRequest>(req).await {
+      Ok(request) => request,
+      Err(res) => return res,
+    };
+    let group = match (request.app, request.stream) {
+      (Some(app), None) => UpstreamGroup::App(app),
+      (None, Some(stream)) => UpstreamGroup::Stream(stream),
+      _ => return error_response(StatusCode::BAD_REQUEST, "exactly one of app or stream must be given"),
+    };
+
+    if is_put {
+      let Some(Ok(state)) = request.state.as_deref().map(UpstreamOverride::try_from) else {
+        return error_response(
+          StatusCode::BAD_REQUEST,
+          "state must be one of drained, disabled or forced_healthy",
+        );
+      };
+      if !has_upstream(&self.app_manager, &group, &request.upstream) {
+        return error_response(StatusCode::NOT_FOUND, "no such upstream");
+      }
+      info!(
+        "Upstream override set via admin API: {group} {} -> {}",
+        request.upstream,
+        state.as_str()
+      );
+      self.upstream_overrides.set(group, request.upstream, Some(state));
+    } else {
+      if request.state.is_some() {
+        return error_response(StatusCode::BAD_REQUEST, "state must not be given to clear an override");
+      }
+      let previous = self.upstream_overrides.set(group.clone(), request.upstream.clone(), None);
+      if previous.is_none() {
+        return error_response(StatusCode::NOT_FOUND, "no override is set for the upstream");
+      }
+      info!("Upstream override cleared via admin API: {group} {}", request.upstream);
+    }
+    json_response(&overrides_status(&self.upstream_overrides))
+  }
+
+  /// Purge the cache entries matching the request, and respond with the number of purged entries
+  #[cfg(feature = "cache")]
+  async fn purge_cache(&self, req: Request<Incoming>) -> Response<BoxBody> {
+    let target = match read_json_body::<PurgeRequest>(req).await.map(PurgeTarget::try_from) {
+      Ok(Ok(target)) => target,
+      Ok(Err(message)) => return error_response(StatusCode::BAD_REQUEST, message),
+      Err(res) => return res,
+    };
+    let Some(cache) = self.cache.as_ref() else {
+      return error_response(StatusCode::NOT_FOUND, "cache is disabled");
+    };
+    match cache.purge(&target).await {
+      Ok(purged) => json_response(&serde_json::json!({ "purged": purged })),
+      Err(e) => {
+        error!("Failed to purge cache entries: {e}");
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to purge cache entries")
+      }
+    }
+  }
+
+  /// Purge the cache entries matching the request, always unavailable without the cache feature
+  #[cfg(not(feature = "cache"))]
+  async fn purge_cache(&self, _req: Request<Incoming>) -> Response<BoxBody> {
+    error_response(StatusCode::NOT_FOUND, "cache is disabled")
+  }
+
+  async fn cache_status(&self) -> CacheStatus {
+    #[cfg(feature = "cache")]
+    return cache_status(self.cache.as_ref()).await;
+    #[cfg(not(feature = "cache"))]
+    return cache_status();
+  }
+
+  fn certs_status(&self) -> Vec<CertStatus> {
+    let server_crypto_base = self.globals.cert_reloader_rx.as_ref().and_then(|rx| rx.get());
+    certs_status(server_crypto_base.as_ref())
+  }
+}
+
+#[cfg(unix)]
+/// Bind the Unix domain socket of the admin API, accessible only by the owner.
+/// A socket left at the path, e.g., by the instance before a config reload, is replaced.
+fn bind_unix_listener(path: &std::path::Path) -> RpxyResult<tokio::net::UnixListener> {
+  use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+  if let Ok(metadata) = std::fs::symlink_metadata(path) {
+    if !metadata.file_type().is_socket() {
+      error!("Admin API socket path exists and is not a socket: {}", path.display());
+      return Err(RpxyError::Io(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+    }
+    std::fs::remove_file(path)?;
+  }
+  let unix_listener = tokio::net::UnixListener::bind(path)?;
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+  Ok(unix_listener)
+}
+
+/// Check the `Authorization: Bearer <token>` header in constant time with respect to the token
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+  let Some(given) = headers
+    .get(header::AUTHORIZATION)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.strip_prefix("Bearer "))
+  else {
+    return false;
+  };
+  let (given, token) = (given.trim().as_bytes(), token.as_bytes());
+  given.len() == token.len() && given.iter().zip(token).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Read the request body of JSON up to `MAX_REQUEST_BODY_SIZE`, or build the error response
+async fn read_json_body<T: DeserializeOwned>(req: Request<Incoming>) -> Result<T, Response<BoxBody>> {
+  let body = match Limited::new(req.into_body(), MAX_REQUEST_BODY_SIZE).collect().await {
+    Ok(body) => body.to_bytes(),
+    Err(e) => {
+      return Err(error_response(
+        StatusCode::BAD_REQUEST,
+        &format!("failed to read request body: {e}"),
+      ));
+    }
+  };
+  serde_json::from_slice::<T>(&body).map_err(|e| error_response(StatusCode::BAD_REQUEST, &format!("invalid request body: {e}")))
+}
+
+fn json_response<T: Serialize>(body: &T) -> Response<BoxBody> {
+  match serde_json::to_vec(body) {
+    Ok(body) => Response::builder()
+      .status(StatusCode::OK)
+      .header(header::CONTENT_TYPE, "application/json")
+      .header(header::CACHE_CONTROL, "no-store")
+      .body(full(Bytes::from(body)))
+      .unwrap(),
+    Err(e) => {
+      error!("Failed to serialize admin API response: {e}");
+      error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to serialize response")
+    }
+  }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<BoxBody> {
+  let body = serde_json::json!({ "error": message }).to_string();
+  Response::builder()
+    .status(status)
+    .header(header::CONTENT_TYPE, "application/json")
+    .header(header::CACHE_CONTROL, "no-store")
+    .body(full(Bytes::from(body)))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn headers(authorization: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+    headers
+  }
+
+  #[cfg(feature = "cache")]
+  #[test]
+  fn purge_request_gives_one_target() {
+    let target = |body: &str| serde_json::from_str::<PurgeRequest>(body).map(PurgeTarget::try_from);
+
+    assert_eq!(
+      target(r#"{"url": "https://example.com/a?b=c"}"#).unwrap(),
+      Ok(PurgeTarget::Url("https://example.com/a?b=c".parse().unwrap()))
+    );
+    assert_eq!(
+      target(r#"{"host": "example.com", "path_prefix": "/img/"}"#).unwrap(),
+      Ok(PurgeTarget::Host {
+        host: "example.com".to_string(),
+        path_prefix: Some("/img/".to_string())
+      })
+    );
+    assert_eq!(
+      target(r#"{"path_prefix": "/img/"}"#).unwrap(),
+      Ok(PurgeTarget::PathPrefix("/img/".to_string()))
+    );
+    assert_eq!(
+      target(r#"{"surrogate_key": "products"}"#).unwrap(),
+      Ok(PurgeTarget::SurrogateKey("products".to_string()))
+    );
+    assert!(target(r#"{"url": "/a"}"#).unwrap().is_err());
+    assert!(target(r#"{"path_prefix": "img/"}"#).unwrap().is_err());
+    assert!(
+      target(r#"{"url": "https://example.com/", "surrogate_key": "products"}"#)
+        .unwrap()
+        .is_err()
+    );
+    assert!(target(r#"{}"#).unwrap().is_err());
+    assert!(target(r#"{"tag": "products"}"#).is_err());
+  }
+
+  #[test]
+  fn bearer_token_is_required() {
+    let token = "s3cr3t-admin-token";
+    assert!(is_authorized(&headers("Bearer s3cr3t-admin-token"), token));
+    assert!(!is_authorized(&headers("Bearer s3cr3t-admin-tokem"), token));
+    assert!(!is_authorized(&headers("Bearer s3cr3t"), token));
+    assert!(!is_authorized(&headers("Basic s3cr3t-admin-token"), token));
+    assert!(!is_authorized(&HeaderMap::new(), token));
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn unix_listener_replaces_stale_socket_only() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("rpxy-admin-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("admin.sock");
+
+    let first = bind_unix_listener(&path).unwrap();
+    drop(first);
+    let _second = bind_unix_listener(&path).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let file = dir.join("not-a-socket");
+    std::fs::write(&file, b"keep").unwrap();
+    assert!(bind_unix_listener(&file).is_err());
+    assert_eq!(std::fs::read(&file).unwrap(), b"keep");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,422 @@
+use crate::{
+  backend::{BackendAppManager, UpstreamCandidates, UpstreamGroup, UpstreamOverrides},
+  globals::{Globals, StreamProtocol},
+  name_exp::PathName,
+};
+use rpxy_certs::ServerCryptoBase;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "cache")]
+use crate::forwarder::RpxyCache;
+
+/* ------------------------------------------------ */
+/// Runtime state of the whole proxy, served at `/status`
+#[derive(Serialize)]
+pub(super) struct Status {
+  pub(super) apps: Vec<AppStatus>,
+  pub(super) streams: Vec<StreamStatus>,
+  pub(super) upstreams: Vec<UpstreamStatus>,
+  pub(super) overrides: Vec<OverrideStatus>,
+  pub(super) cache: CacheStatus,
+  pub(super) certs: Vec<CertStatus>,
+  pub(super) connections: ConnectionStatus,
+}
+
+#[derive(Serialize)]
+/// Loaded application and its routes
+pub(super) struct AppStatus {
+  app_name: String,
+  server_name: String,
+  tls_passthrough: bool,
+  https_redirection: Option<bool>,
+  mutual_tls: Option<bool>,
+  routes: Vec<RouteStatus>,
+}
+
+#[derive(Serialize)]
+/// Route of an application, i.e., an upstream group served at a path
+pub(super) struct RouteStatus {
+  path: String,
+  replace_path: Option<String>,
+  load_balance: &'static str,
+  upstreams: Vec<String>,
+}
+
+#[derive(Serialize)]
+/// Loaded TCP or UDP stream proxy
+pub(super) struct StreamStatus {
+  name: String,
+  listen: String,
+  protocol: &'static str,
+  tls_server_name: Option<String>,
+  load_balance: &'static str,
+  upstreams: Vec<String>,
+}
+
+#[derive(Serialize)]
+/// Health of a single upstream. Upstreams of groups without health check are always healthy.
+/// `available` tells whether the upstream serves new requests, taking its override into account.
+pub(super) struct UpstreamStatus {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  app: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  path: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  stream: Option<String>,
+  upstream: String,
+  health_checked: bool,
+  healthy: bool,
+  #[serde(rename = "override", skip_serializing_if = "Option::is_none")]
+  override_state: Option<&'static str>,
+  available: bool,
+}
+
+#[derive(Serialize)]
+/// Override of an upstream set via the admin API, kept across config reloads until cleared
+pub(super) struct OverrideStatus {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  app: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  stream: Option<String>,
+  upstream: String,
+  state: &'static str,
+}
+
+#[derive(Serialize, Default)]
+/// Occupancy of the response cache
+pub(super) struct CacheStatus {
+  enabled: bool,
+  entries: usize,
+  on_memory: usize,
+  on_file: usize,
+  /// Bytes of the objects on memory and of the cache files
+  memory_size: u64,
+  disk_size: u64,
+}
+
+#[derive(Serialize)]
+/// Expiry of a loaded server certificate
+pub(super) struct CertStatus {
+  server_name: String,
+  mutual_tls: bool,
+  /// notAfter of the leaf certificate in seconds since the Unix epoch
+  not_after: Option<i64>,
+  /// Seconds until notAfter, negative once expired
+  expires_in_secs: Option<i64>,
+}
+
+#[derive(Serialize)]
+/// Client connections currently served by the proxy listeners
+pub(super) struct ConnectionStatus {
+  active: usize,
+  max_clients: usize,
+}
+
+/* ------------------------------------------------ */
+/// Applications and their routes
+pub(super) fn apps_status(app_manager: &BackendAppManager) -> Vec<AppStatus> {
+  let mut apps = app_manager
+    .apps
+    .values()
+    .map(|app| {
+      let mut routes = app
+        .path_manager
+        .iter_candidates()
+        .map(|(path, candidates)| RouteStatus {
+          path: path_string(path),
+          replace_path: candidates.replace_path.as_ref().map(path_string),
+          load_balance: candidates.pool.load().load_balance.name(),
+          upstreams: upstream_locations(candidates),
+        })
+        .collect::<Vec<_>>();
+      routes.sort_by(|a, b| a.path.cmp(&b.path));
+      AppStatus {
+        app_name: app.app_name.clone(),
+        server_name: app.server_name.to_string(),
+        tls_passthrough: app.tls_passthrough,
+        https_redirection: app.https_redirection,
+        mutual_tls: app.mutual_tls,
+        routes,
+      }
+    })
+    .collect::<Vec<_>>();
+  apps.sort_by(|a, b| a.app_name.cmp(&b.app_name));
+  apps
+}
+
+/// TCP and UDP stream proxies
+pub(super) fn streams_status(app_manager: &BackendAppManager) -> Vec<StreamStatus> {
+  app_manager
+    .streams
+    .iter()
+    .map(|stream| StreamStatus {
+      name: stream.config.name.clone(),
+      listen: stream.config.listen.to_string(),
+      protocol: match stream.config.protocol {
+        StreamProtocol::Tcp => "tcp",
+        StreamProtocol::Udp => "udp",
+      },
+      tls_server_name: stream.config.tls_server_name.clone(),
+      load_balance: stream.upstream_candidates.pool.load().load_balance.name(),
+      upstreams: upstream_locations(&stream.upstream_candidates),
+    })
+    .collect()
+}
+
+/// Health of every upstream currently in the pools of apps and streams
+pub(super) fn upstreams_status(app_manager: &BackendAppManager) -> Vec<UpstreamStatus> {
+  let mut app_groups = app_manager
+    .apps
+    .values()
+    .flat_map(|app| {
+      app
+        .path_manager
+        .iter_candidates()
+        .map(move |(path, candidates)| (app.app_name.clone(), path_string(path), candidates))
+    })
+    .collect::<Vec<_>>();
+  app_groups.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+  let from_apps = app_groups.into_iter().flat_map(|(app, path, candidates)| {
+    group_status(candidates).into_iter().map(move |status| UpstreamStatus {
+      app: Some(app.clone()),
+      path: Some(path.clone()),
+      ..status
+    })
+  });
+  let from_streams = app_manager.streams.iter().flat_map(|stream| {
+    group_status(&stream.upstream_candidates)
+      .into_iter()
+      .map(|status| UpstreamStatus {
+        stream: Some(stream.config.name.clone()),
+        ..status
+      })
+  });
+  from_apps.chain(from_streams).collect()
+}
+
+/// Overrides currently set, including those for upstreams not in the loaded config
+pub(super) fn overrides_status(upstream_overrides: &UpstreamOverrides) -> Vec<OverrideStatus> {
+  upstream_overrides
+    .list()
+    .into_iter()
+    .map(|(group, upstream, v)| {
+      let (app, stream) = match group {
+        UpstreamGroup::App(name) => (Some(name), None),
+        UpstreamGroup::Stream(name) => (None, Some(name)),
+      };
+      OverrideStatus {
+        app,
+        stream,
+        upstream,
+        state: v.as_str(),
+      }
+    })
+    .collect()
+}
+
+/// Occupancy of the response cache
+#[cfg(feature = "cache")]
+pub(super) async fn cache_status(cache: Option<&RpxyCache>) -> CacheStatus {
+  let Some(cache) = cache else {
+    return CacheStatus::default();
+  };
+  let (entries, on_memory, on_file) = cache.count().await;
+  let (memory_size, disk_size) = cache.size();
+  CacheStatus {
+    enabled: true,
+    entries,
+    on_memory,
+    on_file,
+    memory_size,
+    disk_size,
+  }
+}
+
+/// Occupancy of the response cache, always disabled without the cache feature
+#[cfg(not(feature = "cache"))]
+pub(super) fn cache_status() -> CacheStatus {
+  CacheStatus::default()
+}
+
+/// Expiry of the certificates currently loaded by the certificate reloader
+pub(super) fn certs_status(server_crypto_base: Option<&ServerCryptoBase>) -> Vec<CertStatus> {
+  let Some(server_crypto_base) = server_crypto_base else {
+    return Vec::new();
+  };
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or_default();
+  let mut certs = server_crypto_base
+    .iter()
+    .map(|(server_name, certs_keys)| {
+      let not_after = certs_keys.not_after();
+      CertStatus {
+        server_name,
+        mutual_tls: certs_keys.is_mutual_tls(),
+        not_after,
+        expires_in_secs: not_after.map(|t| t - now),
+      }
+    })
+    .collect::<Vec<_>>();
+  certs.sort_by(|a, b| a.server_name.cmp(&b.server_name));
+  certs
+}
+
+/// Client connections currently served by the proxy listeners
+pub(super) fn connections_status(globals: &Globals) -> ConnectionStatus {
+  ConnectionStatus {
+    active: globals.request_count.current(),
+    max_clients: globals.proxy_config.max_clients,
+  }
+}
+
+/* ------------------------------------------------ */
+/// Health of the upstreams of a group, without the group name
+fn group_status(candidates: &UpstreamCandidates) -> Vec<UpstreamStatus> {
+  candidates
+    .pool
+    .load()
+    .inner
+    .iter()
+    .map(|upstream| UpstreamStatus {
+      app: None,
+      path: None,
+      stream: None,
+      upstream: upstream.location(),
+      #[cfg(feature = "health-check")]
+      health_checked: upstream.has_health_state(),
+      #[cfg(not(feature = "health-check"))]
+      health_checked: false,
+      healthy: upstream.is_healthy(),
+      override_state: upstream.override_kind().map(|v| v.as_str()),
+      available: upstream.is_available(),
+    })
+    .collect()
+}
+
+/// Upstreams currently in the pool of a group
+fn upstream_locations(candidates: &UpstreamCandidates) -> Vec<String> {
+  candidates.pool.load().inner.iter().map(|u| u.location()).collect()
+}
+
+/// Whether the upstream at the location is currently in a pool of the group
+pub(super) fn has_upstream(app_manager: &BackendAppManager, group: &UpstreamGroup, location: &str) -> bool {
+  let in_group = |candidates: &UpstreamCandidates| candidates.pool.load().inner.iter().any(|u| u.location() == location);
+  match group {
+    UpstreamGroup::App(name) => app_manager
+      .apps
+      .values()
+      .filter(|app| &app.app_name == name)
+      .any(|app| app.path_manager.iter_candidates().any(|(_, candidates)| in_group(candidates))),
+    UpstreamGroup::Stream(name) => app_manager
+      .streams
+      .iter()
+      .filter(|stream| &stream.config.name == name)
+      .any(|stream| in_group(&stream.upstream_candidates)),
+  }
+}
+
+fn path_string(path: &PathName) -> String {
+  path.try_into().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    backend::{Upstream, UpstreamOverride},
+    globals::{AppConfig, AppConfigList, ReverseProxyConfig, UpstreamUri},
+  };
+
+  fn app_config_list() -> AppConfigList {
+    let upstream = ["127.0.0.1:8080", "127.0.0.1:8081"]
+      .iter()
+      .map(|loc| UpstreamUri::from_location(loc, false).unwrap())
+      .collect();
+    AppConfigList {
+      inner: vec![AppConfig {
+        app_name: "app1".to_string(),
+        server_name: "app1.example.com".to_string(),
+        reverse_proxy: vec![ReverseProxyConfig {
+          path: None,
+          replace_path: None,
+          upstream,
+          upstream_options: None,
+          grpc_web_allowed_origins: None,
+          load_balance: Some("round_robin".to_string()),
+          #[cfg(feature = "health-check")]
+          health_check: None,
+          resolve: None,
+          upstream_file: None,
+          upstream_tls: None,
+          #[cfg(feature = "proxy-protocol")]
+          send_proxy_protocol: None,
+          #[cfg(feature = "cache")]
+          cache: None,
+        }],
+        tls: None,
+      }],
+      default_app: None,
+      streams: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn apps_and_upstreams_status_are_serialized() {
+    let app_manager = BackendAppManager::try_from(&app_config_list()).unwrap();
+
+    let apps = serde_json::to_value(apps_status(&app_manager)).unwrap();
+    assert_eq!(apps[0]["app_name"], "app1");
+    assert_eq!(apps[0]["server_name"], "app1.example.com");
+    assert_eq!(apps[0]["routes"][0]["path"], "/");
+    assert_eq!(apps[0]["routes"][0]["load_balance"], "round_robin");
+    assert_eq!(apps[0]["routes"][0]["upstreams"][1], "http://127.0.0.1:8081/");
+
+    let upstreams = serde_json::to_value(upstreams_status(&app_manager)).unwrap();
+    assert_eq!(upstreams.as_array().unwrap().len(), 2);
+    assert_eq!(upstreams[0]["app"], "app1");
+    assert_eq!(upstreams[0]["health_checked"], false);
+    assert_eq!(upstreams[0]["healthy"], true);
+    assert!(upstreams[0].get("stream").is_none());
+    assert!(upstreams[0].get("override").is_none());
+  }
+
+  #[test]
+  fn overrides_are_shown_with_upstreams() {
+    let app_manager = BackendAppManager::try_from(&app_config_list()).unwrap();
+    let upstream_overrides = UpstreamOverrides::default();
+    upstream_overrides.bind(&app_manager);
+
+    let app1 = UpstreamGroup::App("app1".to_string());
+    assert!(has_upstream(&app_manager, &app1, "http://127.0.0.1:8081/"));
+    assert!(!has_upstream(&app_manager, &app1, "http://127.0.0.1:8082/"));
+    assert!(!has_upstream(
+      &app_manager,
+      &UpstreamGroup::Stream("app1".to_string()),
+      "http://127.0.0.1:8081/"
+    ));
+
+    upstream_overrides.set(app1, "http://127.0.0.1:8081/".to_string(), Some(UpstreamOverride::Drained));
+    let upstreams = serde_json::to_value(upstreams_status(&app_manager)).unwrap();
+    assert!(upstreams[0].get("override").is_none());
+    assert_eq!(upstreams[0]["available"], true);
+    assert_eq!(upstreams[1]["override"], "drained");
+    assert_eq!(upstreams[1]["healthy"], true);
+    assert_eq!(upstreams[1]["available"], false);
+
+    let overrides = serde_json::to_value(overrides_status(&upstream_overrides)).unwrap();
+    assert_eq!(overrides[0]["app"], "app1");
+    assert_eq!(overrides[0]["upstream"], "http://127.0.0.1:8081/");
+    assert_eq!(overrides[0]["state"], "drained");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_socket_upstream_is_shown_as_location() {
+    let uri = UpstreamUri::from_location("unix:/run/app.sock", false).unwrap();
+    assert_eq!(Upstream::from(&uri).location(), "unix:/run/app.sock");
+  }
+}
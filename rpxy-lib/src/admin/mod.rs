@@ -0,0 +1,4 @@
+mod admin_main;
+mod admin_status;
+
+pub(crate) use admin_main::AdminService;
@@ -1,14 +1,15 @@
 use crate::{
   AppConfig, AppConfigList,
   error::*,
+  globals::UpstreamTlsConfig,
   log::*,
   name_exp::{ByteName, ServerName},
 };
 use ahash::HashMap;
 use derive_builder::Builder;
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc};
 
-use super::upstream::PathManager;
+use super::{backend_stream::StreamBackend, upstream::PathManager};
 
 /// Struct serving information to route incoming connections, like server name to be handled and tls certs/keys settings.
 #[derive(Builder)]
@@ -28,6 +29,13 @@ pub struct BackendApp {
   #[builder(default)]
   #[allow(unused)]
   pub mutual_tls: Option<bool>,
+  /// tls settings: TLS is passed through to the upstream without termination
+  #[builder(default)]
+  pub tls_passthrough: bool,
+  /// tls settings: cipher suites or key exchange groups are restricted, excluding the app from HTTP/3
+  #[builder(default)]
+  #[allow(unused)]
+  pub tls_restricted_crypto: bool,
 }
 impl<'a> BackendAppBuilder {
   pub fn server_name(&mut self, server_name: impl Into<Cow<'a, str>>) -> &mut Self {
@@ -43,6 +51,34 @@ pub struct BackendAppManager {
   pub apps: HashMap<ServerName, BackendApp>,
   /// for plaintext http
   pub default_server_name: Option<ServerName>,
+  /// whether any app passes TLS through, i.e., ClientHello needs to be peeked at the TLS listener
+  pub tls_passthrough: bool,
+  /// TCP and UDP stream proxies, each served on its own listen socket
+  pub streams: Vec<StreamBackend>,
+}
+
+impl BackendAppManager {
+  /// Distinct TLS settings for upstream connections over all upstream groups. Groups with the same
+  /// settings share one entry, i.e., one set of connections in the forwarder.
+  pub(crate) fn upstream_tls_configs(&self) -> Vec<Arc<UpstreamTlsConfig>> {
+    let mut configs: Vec<Arc<UpstreamTlsConfig>> = Vec::new();
+    let all = self
+      .apps
+      .values()
+      .flat_map(|app| app.path_manager.iter_candidates())
+      .filter_map(|(_path, candidates)| candidates.upstream_tls.as_ref());
+    for config in all {
+      if !configs.iter().any(|c| c == config) {
+        configs.push(config.clone());
+      }
+    }
+    configs
+  }
+
+  /// Get the app passing TLS through for the given server name in SNI, if any
+  pub(crate) fn tls_passthrough_app(&self, server_name: &ServerName) -> Option<&BackendApp> {
+    self.apps.get(server_name).filter(|app| app.tls_passthrough)
+  }
 }
 
 impl TryFrom<&AppConfig> for BackendApp {
@@ -60,9 +96,19 @@ impl TryFrom<&AppConfig> for BackendApp {
       backend_builder.build()?
     } else {
       let tls = app_config.tls.as_ref().unwrap();
+      // Connections passed through are routed by SNI only, i.e., to the upstreams of the default path.
+      if tls.passthrough && backend_builder.path_manager.as_ref().and_then(|pm| pm.get("/")).is_none() {
+        error!(
+          "TLS passthrough requires a reverse proxy without path: {}",
+          app_config.server_name
+        );
+        return Err(RpxyError::InvalidReverseProxyConfig);
+      }
       backend_builder
         .https_redirection(Some(tls.https_redirection))
         .mutual_tls(Some(tls.mutual_tls))
+        .tls_passthrough(tls.passthrough)
+        .tls_restricted_crypto(tls.restricted_crypto)
         .build()?
     };
     Ok(backend)
@@ -76,6 +122,7 @@ impl TryFrom<&AppConfigList> for BackendAppManager {
     let mut manager = Self::default();
     for app_config in config_list.inner.iter() {
       let backend: BackendApp = BackendApp::try_from(app_config)?;
+      manager.tls_passthrough |= backend.tls_passthrough;
       manager.apps.insert(app_config.server_name.clone().to_server_name(), backend);
 
       info!(
@@ -84,6 +131,15 @@ impl TryFrom<&AppConfigList> for BackendAppManager {
       );
     }
 
+    for stream_config in config_list.streams.iter() {
+      let stream = StreamBackend::try_from(stream_config)?;
+      info!(
+        "Registering stream {} ({:?} on {})",
+        &stream_config.name, stream_config.protocol, stream_config.listen
+      );
+      manager.streams.push(stream);
+    }
+
     // default backend application for plaintext http requests
     let Some(default_app_name) = &config_list.default_app else {
       return Ok(manager);
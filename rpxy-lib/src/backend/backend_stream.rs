@@ -0,0 +1,128 @@
+use super::upstream::{Upstream, UpstreamCandidates, UpstreamCandidatesBuilder};
+use crate::{
+  error::*,
+  globals::{StreamConfig, StreamProtocol},
+  hyper_ext::unix::is_unix_socket_uri,
+  log::*,
+};
+#[cfg(feature = "health-check")]
+use std::sync::Arc;
+
+#[derive(Clone)]
+/// Stream proxy served on its own listen socket, relaying TCP connections or UDP datagrams to its upstream server(s).
+pub struct StreamBackend {
+  /// Stream configuration, e.g., listen address and protocol
+  pub config: StreamConfig,
+  /// Upstream server(s) and the load balancer over them, sharing the health state with the health checker
+  pub upstream_candidates: UpstreamCandidates,
+}
+
+impl TryFrom<&StreamConfig> for StreamBackend {
+  type Error = RpxyError;
+
+  fn try_from(config: &StreamConfig) -> Result<Self, Self::Error> {
+    // Streams carry no protocol implying a default port, and UDP is never relayed over Unix domain sockets.
+    for upstream in config.upstream.iter() {
+      let uri = &upstream.inner;
+      let valid = match (is_unix_socket_uri(uri), config.protocol) {
+        (true, StreamProtocol::Tcp) => true,
+        (true, StreamProtocol::Udp) => false,
+        (false, _) => uri.port_u16().is_some(),
+      };
+      if !valid {
+        error!("Invalid upstream of stream {}: {uri}", config.name);
+        return Err(RpxyError::InvalidUpstreamUri(uri.to_string()));
+      }
+    }
+
+    #[cfg(not(feature = "health-check"))]
+    let upstream_vec: Vec<Upstream> = config.upstream.iter().map(Upstream::from).collect();
+    #[cfg(feature = "health-check")]
+    let upstream_vec: Vec<Upstream> = config
+      .upstream
+      .iter()
+      .map(|upstream_uri| {
+        let mut upstream = Upstream::from(upstream_uri);
+        upstream.health = config
+          .health_check
+          .as_ref()
+          .map(|_| Arc::new(super::health_check::UpstreamHealth::new()));
+        upstream
+      })
+      .collect();
+
+    // A stream is routed as a whole, i.e., its upstream group is served at the default path.
+    let mut builder = UpstreamCandidatesBuilder::default();
+    builder.path(&None).replace_path(&None);
+    builder.upstream_pool(upstream_vec, &config.load_balance, &config.name, &None)?;
+    builder.options(&None).resolve(&None).upstream_file(&None).upstream_tls(&None);
+    #[cfg(feature = "health-check")]
+    builder.health_check_config(&config.health_check);
+    #[cfg(feature = "proxy-protocol")]
+    builder.send_proxy_protocol(None);
+
+    let upstream_candidates = builder.build().map_err(|e| {
+      error!("Failed to build upstream candidates of stream {}: {e}", config.name);
+      RpxyError::InvalidReverseProxyConfig
+    })?;
+
+    Ok(Self {
+      config: config.clone(),
+      upstream_candidates,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::globals::UpstreamUri;
+  use std::time::Duration;
+
+  fn stream_config(protocol: StreamProtocol, locations: &[&str]) -> StreamConfig {
+    StreamConfig {
+      name: "db".to_string(),
+      listen: "127.0.0.1:15432".parse().unwrap(),
+      protocol,
+      upstream: locations
+        .iter()
+        .map(|loc| UpstreamUri::from_location(loc, false).unwrap())
+        .collect(),
+      load_balance: Some("round_robin".to_string()),
+      #[cfg(feature = "health-check")]
+      health_check: None,
+      idle_timeout: Duration::from_secs(60),
+      #[cfg(feature = "proxy-protocol")]
+      recv_proxy_protocol: None,
+      tls_server_name: None,
+    }
+  }
+
+  #[test]
+  fn stream_backend_balances_over_upstreams() {
+    let config = stream_config(StreamProtocol::Tcp, &["127.0.0.1:5432", "127.0.0.2:5432"]);
+    let stream = StreamBackend::try_from(&config).unwrap();
+    let pool = stream.upstream_candidates.pool.load();
+    let first = pool.get(&None).0.unwrap().uri.clone();
+    let second = pool.get(&None).0.unwrap().uri.clone();
+    assert_ne!(first, second);
+  }
+
+  #[test]
+  fn stream_backend_requires_upstream_ports() {
+    let config = stream_config(StreamProtocol::Tcp, &["127.0.0.1"]);
+    assert!(matches!(
+      StreamBackend::try_from(&config),
+      Err(RpxyError::InvalidUpstreamUri(_))
+    ));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn stream_backend_rejects_unix_socket_for_udp() {
+    let config = stream_config(StreamProtocol::Tcp, &["unix:/run/db.sock"]);
+    assert!(StreamBackend::try_from(&config).is_ok());
+    let config = stream_config(StreamProtocol::Udp, &["unix:/run/db.sock"]);
+    assert!(StreamBackend::try_from(&config).is_err());
+  }
+}
@@ -1,15 +1,28 @@
-use crate::{error::RpxyResult, hyper_ext::rt::LocalExecutor, log::*};
+use crate::{
+  error::RpxyResult,
+  globals::UpstreamTlsConfig,
+  hyper_ext::{
+    rt::LocalExecutor,
+    unix::{UNIX_SOCKET_HOST, is_unix_socket_uri},
+  },
+  log::*,
+};
 use http_body_util::Empty;
 use hyper::body::Bytes;
 use hyper_util::client::legacy::{Client, connect::HttpConnector};
 use std::time::Duration;
 
+#[cfg(unix)]
+use crate::hyper_ext::unix::UnixConnector;
+
 /// Lightweight HTTP client for health check probes.
 /// Shares the same TLS backend and ALPN configuration as the main Forwarder,
 /// but omits connection tuning (keepalive, reuse_address) since health checks
 /// are infrequent, short-lived probes.
 pub(super) struct HealthCheckHttpClient {
   inner: InnerClient,
+  #[cfg(unix)]
+  inner_unix: Client<UnixConnector, Empty<Bytes>>,
 }
 
 // Type aliases for each TLS backend variant
@@ -24,20 +37,33 @@ type InnerClient = Client<HttpConnector, Empty<Bytes>>;
 
 impl HealthCheckHttpClient {
   /// Build the health check HTTP client with the same TLS backend and ALPN as the Forwarder.
-  pub fn try_new(runtime_handle: &tokio::runtime::Handle) -> RpxyResult<Self> {
+  /// `upstream_tls` gives the TLS settings of the upstream group, if it has its own ones.
+  pub fn try_new(runtime_handle: &tokio::runtime::Handle, upstream_tls: Option<&UpstreamTlsConfig>) -> RpxyResult<Self> {
     let executor = LocalExecutor::new(runtime_handle.clone());
 
+    #[cfg(not(feature = "rustls-backend"))]
+    if upstream_tls.is_some() {
+      return Err(crate::error::RpxyError::FailedToBuildHealthCheckClient(
+        "Upstream TLS settings (upstream_tls) require the 'rustls-backend' feature".to_string(),
+      ));
+    }
+
     #[cfg(feature = "rustls-backend")]
     let inner = {
       let mut http = HttpConnector::new();
       http.enforce_http(false);
 
-      #[cfg(feature = "webpki-roots")]
-      let builder = hyper_rustls::HttpsConnectorBuilder::new().with_webpki_roots();
-      #[cfg(not(feature = "webpki-roots"))]
-      let builder = hyper_rustls::HttpsConnectorBuilder::new().with_platform_verifier();
+      let connector = match upstream_tls {
+        Some(config) => crate::forwarder::build_https_connector(config, http, false)?,
+        None => {
+          #[cfg(feature = "webpki-roots")]
+          let builder = hyper_rustls::HttpsConnectorBuilder::new().with_webpki_roots();
+          #[cfg(not(feature = "webpki-roots"))]
+          let builder = hyper_rustls::HttpsConnectorBuilder::new().with_platform_verifier();
 
-      let connector = builder.https_or_http().enable_all_versions().wrap_connector(http);
+          builder.https_or_http().enable_all_versions().wrap_connector(http)
+        }
+      };
       Client::builder(executor)
         .pool_max_idle_per_host(1)
         .build::<_, Empty<Bytes>>(connector)
@@ -67,8 +93,17 @@ impl HealthCheckHttpClient {
         .build::<_, Empty<Bytes>>(http)
     };
 
+    #[cfg(unix)]
+    let inner_unix = Client::builder(LocalExecutor::new(runtime_handle.clone()))
+      .pool_max_idle_per_host(1)
+      .build::<_, Empty<Bytes>>(UnixConnector);
+
     debug!("Health check HTTP client built");
-    Ok(Self { inner })
+    Ok(Self {
+      inner,
+      #[cfg(unix)]
+      inner_unix,
+    })
   }
 
   /// Perform an HTTP health check: GET `uri + path`, check response status.
@@ -81,7 +116,11 @@ impl HealthCheckHttpClient {
       }
     };
 
-    let host = target_uri.authority().map(|a| a.as_str()).unwrap_or_default();
+    let host = if is_unix_socket_uri(&target_uri) {
+      UNIX_SOCKET_HOST
+    } else {
+      target_uri.authority().map(|a| a.as_str()).unwrap_or_default()
+    };
     let req = match http::Request::builder()
       .method(http::Method::GET)
       .uri(&target_uri)
@@ -95,7 +134,16 @@ impl HealthCheckHttpClient {
       }
     };
 
-    match tokio::time::timeout(timeout, self.inner.request(req)).await {
+    #[cfg(unix)]
+    let response = if is_unix_socket_uri(&target_uri) {
+      self.inner_unix.request(req)
+    } else {
+      self.inner.request(req)
+    };
+    #[cfg(not(unix))]
+    let response = self.inner.request(req);
+
+    match tokio::time::timeout(timeout, response).await {
       Ok(Ok(resp)) => {
         let status = resp.status().as_u16();
         trace!("[{server_name}] Health check HTTP response for {target_uri}: {status}");
@@ -184,4 +232,16 @@ mod tests {
       Some("http://[::1]:8080/healthz".to_string())
     );
   }
+
+  #[cfg(unix)]
+  #[test]
+  fn unix_socket_authority_preserved() {
+    let base = crate::hyper_ext::unix::unix_socket_uri(std::path::Path::new("/run/app.sock")).unwrap();
+    let target = build_health_check_uri(&base, "/healthz").unwrap();
+    assert_eq!(target.path(), "/healthz");
+    assert_eq!(
+      crate::hyper_ext::unix::unix_socket_path(&target).unwrap(),
+      std::path::Path::new("/run/app.sock")
+    );
+  }
 }
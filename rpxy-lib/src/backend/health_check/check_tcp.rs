@@ -3,12 +3,31 @@ use hyper::Uri;
 use std::time::Duration;
 use tokio::net::TcpStream;
 
+#[cfg(unix)]
+use crate::hyper_ext::unix::unix_socket_path;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
 /// Perform a TCP health check by attempting to connect to the upstream's host:port.
 ///
 /// - DNS resolution is handled internally by `TcpStream::connect` (uses host:port string).
 /// - For HTTPS upstreams, only TCP connectivity is verified (no TLS handshake).
 /// - Returns `true` if TCP 3-way handshake completes within `timeout`.
+/// - For Unix domain socket upstreams, a connection to the socket path is attempted instead.
 pub(super) async fn check_tcp(server_name: &str, uri: &Uri, timeout: Duration) -> bool {
+  #[cfg(unix)]
+  if let Some(path) = unix_socket_path(uri) {
+    let res = tokio::time::timeout(timeout, UnixStream::connect(&path))
+      .await
+      .is_ok_and(|r| r.is_ok());
+    trace!(
+      "[{server_name}] Unix socket health check for {}: {}",
+      path.display(),
+      if res { "healthy" } else { "unhealthy" }
+    );
+    return res;
+  }
+
   let Some(authority) = uri.authority() else {
     return false;
   };
@@ -104,4 +123,17 @@ mod tests {
     let uri: Uri = "http://127.0.0.1:1".parse().unwrap();
     assert!(!check_tcp("test", &uri, Duration::from_millis(200)).await);
   }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn check_tcp_connects_to_unix_socket() {
+    let path = std::env::temp_dir().join(format!("rpxy-check-tcp-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let uri = crate::hyper_ext::unix::unix_socket_uri(&path).unwrap();
+    assert!(!check_tcp("test", &uri, Duration::from_millis(200)).await);
+
+    let _listener = tokio::net::UnixListener::bind(&path).unwrap();
+    assert!(check_tcp("test", &uri, Duration::from_millis(200)).await);
+    let _ = std::fs::remove_file(&path);
+  }
 }
@@ -1,9 +1,10 @@
 use super::{UpstreamHealth, check_http::HealthCheckHttpClient, check_tcp::check_tcp, counter::ConsecutiveCounter};
 use crate::{
-  backend::BackendAppManager,
+  backend::{BackendAppManager, UpstreamCandidates, UpstreamPool},
   error::RpxyResult,
-  globals::{HealthCheckConfig, HealthCheckType},
+  globals::{HealthCheckConfig, HealthCheckType, UpstreamTlsConfig},
   log::*,
+  name_exp::PathName,
 };
 use futures::future::join_all;
 use std::sync::Arc;
@@ -12,16 +13,30 @@ use tokio_util::sync::CancellationToken;
 
 /// Check if any configured health check uses HTTP type.
 fn has_http_health_check(app_manager: &BackendAppManager) -> bool {
-  app_manager.apps.iter().any(|(_name, backend_app)| {
-    backend_app.path_manager.iter_candidates().any(|(_path, candidates)| {
-      candidates
-        .health_check_config
-        .as_ref()
-        .is_some_and(|c| matches!(&c.check_type, HealthCheckType::Http { .. }))
-    })
+  upstream_groups(app_manager).any(|(_name, _path, candidates)| {
+    candidates
+      .health_check_config
+      .as_ref()
+      .is_some_and(|c| matches!(&c.check_type, HealthCheckType::Http { .. }))
   })
 }
 
+/// Iterate over all upstream groups, of apps and streams, with the app's server name or the stream name.
+fn upstream_groups(app_manager: &BackendAppManager) -> impl Iterator<Item = (String, &PathName, &UpstreamCandidates)> {
+  let app_groups = app_manager.apps.values().flat_map(|backend_app| {
+    let server_name: String = (&backend_app.server_name).try_into().unwrap_or_else(|_| "<none>".to_string());
+    backend_app
+      .path_manager
+      .iter_candidates()
+      .map(move |(path, candidates)| (server_name.clone(), path, candidates))
+  });
+  let stream_groups = app_manager.streams.iter().map(|stream| {
+    let candidates = &stream.upstream_candidates;
+    (stream.config.name.clone(), &candidates.path, candidates)
+  });
+  app_groups.chain(stream_groups)
+}
+
 /// Spawn health checker tasks for all upstream candidates that have health check enabled.
 /// Returns join handles for the spawned tasks.
 /// Fails if HTTP health checks are configured but the HTTP client cannot be built.
@@ -33,73 +48,105 @@ pub(crate) fn spawn_health_checkers(
   // Only build the HTTP client if at least one health check uses HTTP type.
   // Fail hard if HTTP health checks are configured but the client cannot be built.
   let http_client = if has_http_health_check(app_manager) {
-    let client = HealthCheckHttpClient::try_new(runtime_handle)?;
+    let client = HealthCheckHttpClient::try_new(runtime_handle, None)?;
     Some(Arc::new(client))
   } else {
     None
   };
+  // Upstream groups with their own TLS settings are probed over clients built for them.
+  let mut tls_http_clients: Vec<(Arc<UpstreamTlsConfig>, Arc<HealthCheckHttpClient>)> = Vec::new();
+  if http_client.is_some() {
+    for config in app_manager.upstream_tls_configs() {
+      let client = HealthCheckHttpClient::try_new(runtime_handle, Some(&config))?;
+      tls_http_clients.push((config, Arc::new(client)));
+    }
+  }
 
-  let mut handles = Vec::new();
-
-  app_manager.apps.iter().for_each(|(_app_name, backend_app)| {
-    let server_name = (&backend_app.server_name).try_into().unwrap_or_else(|_| "<none>".to_string());
-    let sub_handles = backend_app.path_manager.iter_candidates().filter_map(|(path, candidates)| {
-      // Collect upstreams that have health check enabled (i.e., have UpstreamHealth)
-      let health_upstreams: Vec<_> = candidates
-        .inner
-        .iter()
-        .filter_map(|upstream| upstream.health.as_ref().map(|h| (upstream.uri.clone(), Arc::clone(h))))
-        .collect();
+  let handles = upstream_groups(app_manager).filter_map(|(server_name, path, candidates)| {
+    // Collect upstreams that have health check enabled (i.e., have UpstreamHealth)
+    let health_upstreams = health_check_targets(&candidates.pool);
 
-      if health_upstreams.is_empty() {
-        return None;
-      }
+    // Upstreams of a dynamic group may appear later, e.g., once the upstream file is read.
+    if health_upstreams.is_empty() && !candidates.is_dynamic() {
+      return None;
+    }
 
-      let Some(ref config) = candidates.health_check_config else {
-        return None;
-      };
+    let Some(ref config) = candidates.health_check_config else {
+      return None;
+    };
 
-      let path_str: String = path.try_into().unwrap_or_else(|_| "<none>".to_string());
-      let num_upstreams = health_upstreams.len();
+    let path_str: String = path.try_into().unwrap_or_else(|_| "<none>".to_string());
+    let num_upstreams = health_upstreams.len();
+    let pool = candidates.pool.clone();
 
-      info!(
-        "[{server_name}] Health checker started for path \"{path_str}\" ({num_upstreams} upstreams, {:?}, interval={:?}, timeout={:?}, healthy_threshold={}, unhealthy_threshold={})",
-        config.check_type, config.interval, config.timeout, config.healthy_threshold, config.unhealthy_threshold
-      );
+    info!(
+      "[{server_name}] Health checker started for path \"{path_str}\" ({num_upstreams} upstreams, {:?}, interval={:?}, timeout={:?}, healthy_threshold={}, unhealthy_threshold={})",
+      config.check_type, config.interval, config.timeout, config.healthy_threshold, config.unhealthy_threshold
+    );
 
-      let config = config.clone();
-      let cancel = cancel_token.clone();
-      let server_name = server_name.clone();
-      let task_http_client = match config.check_type {
-        HealthCheckType::Http { .. } => http_client.clone(),
-        _ => None,
-      };
-      let handle = runtime_handle.spawn(async move {
-        run_health_checker(server_name, path_str, health_upstreams, config, cancel, task_http_client).await
-      });
-
-      Some(handle)
+    let config = config.clone();
+    let cancel = cancel_token.clone();
+    let task_http_client = match (&config.check_type, &candidates.upstream_tls) {
+      (HealthCheckType::Http { .. }, Some(upstream_tls)) => tls_http_clients
+        .iter()
+        .find(|(tls, _)| tls == upstream_tls)
+        .map(|(_, client)| client.clone()),
+      (HealthCheckType::Http { .. }, None) => http_client.clone(),
+      _ => None,
+    };
+    let handle = runtime_handle.spawn(async move {
+      run_health_checker(server_name, path_str, pool, config, cancel, task_http_client).await
     });
-    handles.extend(sub_handles);
+
+    Some(handle)
   });
 
-  Ok(handles)
+  Ok(handles.collect())
+}
+
+/// Collect the upstreams having health state in the current set of the pool.
+fn health_check_targets(pool: &UpstreamPool) -> Vec<(hyper::Uri, Arc<UpstreamHealth>)> {
+  pool
+    .load()
+    .inner
+    .iter()
+    .filter_map(|upstream| upstream.health.as_ref().map(|h| (upstream.uri.clone(), Arc::clone(h))))
+    .collect()
+}
+
+/// Align the consecutive counters with the current targets: counters of upstreams still present
+/// (same health state) are carried over, new upstreams start from scratch, and removed ones are dropped.
+fn align_counters(
+  counters: Vec<(Arc<UpstreamHealth>, ConsecutiveCounter)>,
+  upstreams: &[(hyper::Uri, Arc<UpstreamHealth>)],
+  config: &HealthCheckConfig,
+) -> Vec<(Arc<UpstreamHealth>, ConsecutiveCounter)> {
+  let mut prev = counters;
+  upstreams
+    .iter()
+    .map(|(_, health)| {
+      let counter = match prev.iter().position(|(h, _)| Arc::ptr_eq(h, health)) {
+        Some(i) => prev.swap_remove(i).1,
+        None => ConsecutiveCounter::new(config.unhealthy_threshold, config.healthy_threshold),
+      };
+      (Arc::clone(health), counter)
+    })
+    .collect()
 }
 
 /// Run a single health checker task for a group of upstreams.
 /// Runs an immediate first probe, then schedules subsequent probes with a fixed interval.
+/// The targets are taken from the pool on every tick, so upstreams added or removed by
+/// re-resolution are followed.
 async fn run_health_checker(
   server_name: String,
   path_str: String,
-  upstreams: Vec<(hyper::Uri, Arc<UpstreamHealth>)>,
+  pool: UpstreamPool,
   config: HealthCheckConfig,
   cancel: CancellationToken,
   http_client: Option<Arc<HealthCheckHttpClient>>,
 ) -> RpxyResult<()> {
-  let mut counters: Vec<ConsecutiveCounter> = upstreams
-    .iter()
-    .map(|_| ConsecutiveCounter::new(config.unhealthy_threshold, config.healthy_threshold))
-    .collect();
+  let mut counters: Vec<(Arc<UpstreamHealth>, ConsecutiveCounter)> = Vec::new();
   let mut ticker = tokio::time::interval(config.interval);
   ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
@@ -110,6 +157,8 @@ async fn run_health_checker(
         return Ok(());
       }
       _ = ticker.tick() => {
+        let upstreams = health_check_targets(&pool);
+        counters = align_counters(counters, &upstreams, &config);
         let server_name = &server_name;
         let config = &config;
         let http_client = http_client.as_deref();
@@ -134,7 +183,7 @@ async fn run_health_checker(
           if !ok {
             debug!("[{server_name}:{path_str}] Health check failed for {uri}");
           }
-          if let Some(new_state) = counters[i].record(ok) {
+          if let Some(new_state) = counters[i].1.record(ok) {
             if new_state {
               info!("[{server_name}:{path_str}] Upstream {uri} is now healthy ({} consecutive successes)", config.healthy_threshold);
             } else {
@@ -145,7 +194,7 @@ async fn run_health_checker(
         });
 
         // Warn if all upstreams are unhealthy
-        if upstreams.iter().all(|(_, h)| !h.is_healthy()) {
+        if !upstreams.is_empty() && upstreams.iter().all(|(_, h)| !h.is_healthy()) {
           warn!("[{server_name}:{path_str}] All upstreams are unhealthy, serving best-effort");
         }
       }
@@ -37,32 +37,28 @@ pub(super) trait LoadBalanceWithPointer {
   fn get_ptr(&self, req_info: Option<&LoadBalanceContext>, upstreams: &[Upstream]) -> PointerToUpstream;
 }
 
-#[cfg(feature = "health-check")]
-fn first_healthy_index(upstreams: &[Upstream]) -> Option<usize> {
-  upstreams.iter().position(Upstream::is_healthy)
+fn available_index_count(upstreams: &[Upstream]) -> usize {
+  upstreams.iter().filter(|u| u.is_available()).count()
 }
 
-#[cfg(feature = "health-check")]
-fn healthy_index_count(upstreams: &[Upstream]) -> usize {
-  upstreams.iter().filter(|u| u.is_healthy()).count()
-}
-
-/// Pick the nth healthy upstream without allocating an intermediate index list.
-/// Falls back to all upstreams if every upstream is unhealthy (best-effort).
-#[cfg(not(feature = "health-check"))]
-pub(super) fn pick_nth_available_index(upstreams: &[Upstream], nth: usize) -> usize {
-  let len = upstreams.len();
-  if len == 0 {
-    error!("Upstream list is empty when picking nth available index");
-    return 0;
+/// Pick the nth upstream matching `pred` (modulo their count) without allocating an intermediate index list.
+fn nth_index_where(upstreams: &[Upstream], nth: usize, pred: impl Fn(&Upstream) -> bool) -> Option<usize> {
+  let count = upstreams.iter().filter(|u| pred(u)).count();
+  if count == 0 {
+    return None;
   }
-  // O(1): no health state to check when health-check feature is disabled.
-  nth % len
-}
-
-/// Pick the nth healthy upstream without allocating an intermediate index list.
-/// Falls back to all upstreams if every upstream is unhealthy (best-effort).
-#[cfg(feature = "health-check")]
+  // The state can change concurrently between the count and this scan, so the index may be gone.
+  upstreams
+    .iter()
+    .enumerate()
+    .filter(|(_, u)| pred(u))
+    .nth(nth % count)
+    .map(|(i, _)| i)
+}
+
+/// Pick the nth available upstream, i.e., healthy or forced healthy, and neither drained nor disabled.
+/// Falls back to all upstreams but disabled ones if no upstream is available (best-effort).
+/// Returns the length of the list, i.e., no upstream, if every upstream is disabled.
 pub(super) fn pick_nth_available_index(upstreams: &[Upstream], nth: usize) -> usize {
   let len = upstreams.len();
   if len == 0 {
@@ -70,49 +66,26 @@ pub(super) fn pick_nth_available_index(upstreams: &[Upstream], nth: usize) -> us
     return 0;
   }
 
-  // O(1) fast path: if no upstream in this group has health tracking, all are always healthy.
-  if !upstreams.iter().any(|u| u.has_health_state()) {
-    return nth % len;
-  }
-
-  let healthy_count = healthy_index_count(upstreams);
-
-  // Fast path: all upstreams are healthy
-  if healthy_count == len {
+  // O(1) fast path: if no upstream in this group has health tracking or override, all are always available.
+  if !upstreams.iter().any(Upstream::tracks_availability) {
     return nth % len;
   }
 
-  if healthy_count == 0 {
-    // When all upstreams are unhealthy, fall back to round robin among all upstreams (best-effort).
-    // Log at debug level to avoid flooding; the health checker already warns on state transitions.
-    debug!("No healthy upstreams available, picking among all upstreams as a fallback");
-    nth % len
-  } else {
-    let target = nth % healthy_count;
-    // Health state can change concurrently between healthy_count and this scan,
-    // so fall back to best-effort (nth % len) if the expected index is gone.
-    upstreams
-      .iter()
-      .enumerate()
-      .filter(|(_, u)| u.is_healthy())
-      .nth(target)
-      .map(|(i, _)| i)
-      .unwrap_or(nth % len)
-  }
+  nth_index_where(upstreams, nth, Upstream::is_available)
+    .or_else(|| {
+      // Log at debug level to avoid flooding; the health checker already warns on state transitions.
+      debug!("No available upstreams, picking among all upstreams but disabled ones as a fallback");
+      nth_index_where(upstreams, nth, |u| !u.is_disabled())
+    })
+    .unwrap_or_else(|| {
+      warn!("All upstreams are disabled");
+      len
+    })
 }
 
-#[cfg(feature = "health-check")]
-/// Get the index of the first healthy upstream, or 0 if all are unhealthy (best-effort).
+/// Get the index of the first available upstream, falling back in the same manner as [`pick_nth_available_index`].
 pub(super) fn first_available_index(upstreams: &[Upstream]) -> usize {
-  if upstreams.is_empty() {
-    error!("Upstream list is empty when picking first available index");
-    return 0;
-  }
-  // O(1) fast path: no health tracking in this group means all upstreams are always healthy.
-  if !upstreams.iter().any(|u| u.has_health_state()) {
-    return 0;
-  }
-  first_healthy_index(upstreams).unwrap_or(0)
+  pick_nth_available_index(upstreams, 0)
 }
 
 #[derive(Debug, Clone, Builder)]
@@ -154,24 +127,16 @@ impl LoadBalanceWithPointer for LoadBalanceRandom {
     }
     let mut rng = rand::rng();
 
-    #[cfg(not(feature = "health-check"))]
-    let ptr = rng.random_range(0..len);
-
-    #[cfg(feature = "health-check")]
-    let ptr = {
-      // O(1) fast path: no health tracking in this group
-      if !upstreams.iter().any(|u| u.has_health_state()) {
-        rng.random_range(0..len)
-      } else {
-        let healthy_count = healthy_index_count(upstreams);
-        // When all upstreams are healthy or all are unhealthy, pick randomly among all upstreams.
-        // Otherwise, pick randomly among healthy upstreams only.
-        if healthy_count == len || healthy_count == 0 {
-          rng.random_range(0..len)
-        } else {
-          pick_nth_available_index(upstreams, rng.random_range(0..healthy_count))
-        }
-      }
+    let ptr = if !upstreams.iter().any(Upstream::tracks_availability) {
+      // O(1) fast path: no health tracking or override in this group
+      rng.random_range(0..len)
+    } else {
+      // Pick randomly among available upstreams only, or among the fallback ones if none is available.
+      let count = match available_index_count(upstreams) {
+        0 => len,
+        count => count,
+      };
+      pick_nth_available_index(upstreams, rng.random_range(0..count))
     };
 
     PointerToUpstream { ptr, context: None }
@@ -187,8 +152,8 @@ pub struct LoadBalancePrimaryBackup;
 
 #[cfg(feature = "health-check")]
 impl LoadBalanceWithPointer for LoadBalancePrimaryBackup {
-  // Always pick the lowest-indexed healthy upstream (primary preference).
-  // Falls back to index 0 if every upstream is currently unhealthy.
+  // Always pick the lowest-indexed available upstream (primary preference).
+  // Falls back to the lowest-indexed upstream not disabled if no upstream is currently available.
   fn get_ptr(&self, _info: Option<&LoadBalanceContext>, upstreams: &[Upstream]) -> PointerToUpstream {
     let ptr = first_available_index(upstreams);
     PointerToUpstream { ptr, context: None }
@@ -218,25 +183,36 @@ impl Default for LoadBalance {
 }
 
 impl LoadBalance {
+  /// Build the load balancer for a new set of upstreams. Per-upstream state (sticky cookie ids) is
+  /// rebuilt, while shared state (round-robin pointer, sticky cookie config) is kept.
+  pub fn with_upstreams(&self, _upstreams: &[Upstream]) -> Self {
+    match self {
+      #[cfg(feature = "sticky-cookie")]
+      LoadBalance::StickyRoundRobin(lb) => LoadBalance::StickyRoundRobin(lb.with_upstreams(_upstreams)),
+      _ => self.clone(),
+    }
+  }
+
+  /// Name of the load balancing option as given in the config
+  pub fn name(&self) -> &'static str {
+    match self {
+      LoadBalance::FixToFirst => load_balance_options::FIX_TO_FIRST,
+      LoadBalance::Random(_) => load_balance_options::RANDOM,
+      LoadBalance::RoundRobin(_) => load_balance_options::ROUND_ROBIN,
+      #[cfg(feature = "sticky-cookie")]
+      LoadBalance::StickyRoundRobin(_) => load_balance_options::STICKY_ROUND_ROBIN,
+      #[cfg(feature = "health-check")]
+      LoadBalance::PrimaryBackup(_) => load_balance_options::PRIMARY_BACKUP,
+    }
+  }
+
   /// Get the index of the upstream serving the incoming request
   pub fn get_context(&self, _context_to_lb: &Option<LoadBalanceContext>, upstreams: &[Upstream]) -> PointerToUpstream {
     match self {
-      LoadBalance::FixToFirst => {
-        #[cfg(feature = "health-check")]
-        {
-          PointerToUpstream {
-            ptr: first_available_index(upstreams),
-            context: None,
-          }
-        }
-        #[cfg(not(feature = "health-check"))]
-        {
-          PointerToUpstream {
-            ptr: 0usize,
-            context: None,
-          }
-        }
-      }
+      LoadBalance::FixToFirst => PointerToUpstream {
+        ptr: first_available_index(upstreams),
+        context: None,
+      },
       LoadBalance::RoundRobin(ptr) => ptr.get_ptr(None, upstreams),
       LoadBalance::Random(ptr) => ptr.get_ptr(None, upstreams),
       #[cfg(feature = "sticky-cookie")]
@@ -46,6 +46,12 @@ impl LoadBalanceStickyBuilder {
   }
   /// Set the hashmaps: upstream_index_map and upstream_id_map
   pub fn upstream_maps(&mut self, upstream_vec: &[Upstream]) -> &mut Self {
+    self.upstream_maps = Some(UpstreamMap::new(upstream_vec));
+    self
+  }
+}
+impl UpstreamMap {
+  fn new(upstream_vec: &[Upstream]) -> Self {
     let upstream_index_map: Vec<String> = upstream_vec
       .iter()
       .enumerate()
@@ -55,14 +61,23 @@ impl LoadBalanceStickyBuilder {
     for (i, v) in upstream_index_map.iter().enumerate() {
       upstream_id_map.insert(v.to_string(), i);
     }
-    self.upstream_maps = Some(UpstreamMap {
+    UpstreamMap {
       upstream_index_map,
       upstream_id_map,
-    });
-    self
+    }
   }
 }
 impl<'a> LoadBalanceSticky {
+  /// Clone this load balancer for a new set of upstreams: the hashmaps are rebuilt, while the
+  /// round-robin pointer and the cookie config are shared with the original.
+  pub(crate) fn with_upstreams(&self, upstream_vec: &[Upstream]) -> Self {
+    Self {
+      ptr: self.ptr.clone(),
+      sticky_config: self.sticky_config.clone(),
+      upstream_maps: UpstreamMap::new(upstream_vec),
+    }
+  }
+
   /// Atomically increment ptr, reset near overflow.
   fn fetch_and_advance(&self) -> usize {
     let prev = self.ptr.fetch_add(1, Ordering::Relaxed);
@@ -72,7 +87,7 @@ impl<'a> LoadBalanceSticky {
     prev
   }
 
-  /// Round-robin among healthy upstreams, falling back to all upstreams if all are unhealthy.
+  /// Round-robin among available upstreams, falling back to all upstreams but disabled ones if none is available.
   fn rr_next_index(&self, upstreams: &[Upstream]) -> usize {
     let count = self.fetch_and_advance();
     pick_nth_available_index(upstreams, count)
@@ -88,8 +103,12 @@ impl<'a> LoadBalanceSticky {
     self.upstream_maps.upstream_id_map.get(&id_str).map(|v| v.to_owned())
   }
 
-  /// Build a PointerToUpstream with a new cookie context for the given index
+  /// Build a PointerToUpstream with a new cookie context for the given index.
+  /// No cookie is issued if no upstream was chosen, i.e., every upstream is disabled.
   fn build_ptr_with_new_cookie(&self, ptr: usize) -> PointerToUpstream {
+    if ptr >= self.upstream_maps.upstream_index_map.len() {
+      return PointerToUpstream { ptr, context: None };
+    }
     let upstream_id = self.get_server_id_from_index(ptr);
     let new_cookie = self.sticky_config.build_sticky_cookie(upstream_id).unwrap();
     let new_context = Some(LoadBalanceContext {
@@ -113,17 +132,17 @@ impl LoadBalanceWithPointer for LoadBalanceSticky {
       Some(context) => {
         let server_id = &context.sticky_cookie.value.value;
         match self.get_server_index_from_id(server_id) {
-          Some(index) if upstreams.get(index).is_some_and(|u| u.is_healthy()) => {
-            // Valid cookie, target is healthy -> use it, NO re-issue
-            debug!("Valid sticky cookie: index={index}, healthy",);
+          Some(index) if upstreams.get(index).is_some_and(|u| u.is_available()) => {
+            // Valid cookie, target is available -> use it, NO re-issue
+            debug!("Valid sticky cookie: index={index}, available",);
             PointerToUpstream {
               ptr: index,
               context: None,
             }
           }
           Some(index) => {
-            // Valid cookie but target is unhealthy -> fallback + new cookie
-            debug!("Valid sticky cookie: index={index}, unhealthy -> fallback",);
+            // Valid cookie but target is unhealthy, drained or disabled -> re-pin + new cookie
+            debug!("Valid sticky cookie: index={index}, unavailable -> fallback",);
             let ptr = self.rr_next_index(upstreams);
             self.build_ptr_with_new_cookie(ptr)
           }
@@ -145,6 +164,7 @@ mod tests {
   use super::*;
   use crate::{
     backend::load_balance::sticky_cookie::{StickyCookie, StickyCookieValue},
+    backend::upstream_override::{UpstreamOverride, UpstreamOverrideState},
     constants::STICKY_COOKIE_NAME,
     globals::UpstreamUri,
   };
@@ -165,6 +185,12 @@ mod tests {
     upstream
   }
 
+  fn make_upstream_with_override(uri_str: &str, v: Option<UpstreamOverride>) -> Upstream {
+    let mut upstream = make_upstream(uri_str);
+    upstream.override_state = Some(Arc::new(UpstreamOverrideState::new(v)));
+    upstream
+  }
+
   fn build_sticky_lb(upstreams: &[Upstream]) -> LoadBalanceSticky {
     let config = StickyCookieConfig::try_new(STICKY_COOKIE_NAME, "example.com", &Some("/".to_string()), 300).unwrap();
     LoadBalanceStickyBuilder::default()
@@ -303,4 +329,62 @@ mod tests {
     assert!(result.ptr < upstreams.len());
     assert!(result.context.is_some());
   }
+
+  #[test]
+  fn with_upstreams_rebuilds_ids_for_new_set() {
+    let upstreams = vec![make_upstream("http://a.local:80"), make_upstream("http://b.local:80")];
+    let lb = build_sticky_lb(&upstreams);
+    let context_b = make_context_for(&lb, 1);
+
+    // "b" keeps its index in the new set, so its cookie is still honored without re-issue.
+    let new_upstreams = vec![
+      make_upstream("http://a.local:80"),
+      make_upstream("http://b.local:80"),
+      make_upstream("http://c.local:80"),
+    ];
+    let new_lb = lb.with_upstreams(&new_upstreams);
+    let result = new_lb.get_ptr(Some(&context_b), &new_upstreams);
+    assert_eq!(result.ptr, 1);
+    assert!(result.context.is_none());
+
+    // A cookie for an upstream that is not in the set does not resolve.
+    let context_c = make_context_for(&new_lb, 2);
+    let result = lb.get_ptr(Some(&context_c), &upstreams);
+    assert!(result.context.is_some());
+  }
+
+  #[test]
+  fn valid_cookie_drained_target_repins() {
+    let upstreams = vec![
+      make_upstream_with_override("http://a:8080", None),
+      make_upstream_with_override("http://b:8080", Some(UpstreamOverride::Drained)),
+    ];
+    let lb = build_sticky_lb(&upstreams);
+    let ctx = make_context_for(&lb, 1);
+
+    for _ in 0..3 {
+      let result = lb.get_ptr(Some(&ctx), &upstreams);
+      assert_eq!(result.ptr, 0);
+      assert!(result.context.is_some());
+    }
+
+    // Cleared override: the cookie is honored again.
+    upstreams[1].override_state.as_ref().unwrap().set(None);
+    let result = lb.get_ptr(Some(&ctx), &upstreams);
+    assert_eq!(result.ptr, 1);
+    assert!(result.context.is_none());
+  }
+
+  #[test]
+  fn all_disabled_yields_no_upstream() {
+    let upstreams = vec![
+      make_upstream_with_override("http://a:8080", Some(UpstreamOverride::Disabled)),
+      make_upstream_with_override("http://b:8080", Some(UpstreamOverride::Disabled)),
+    ];
+    let lb = build_sticky_lb(&upstreams);
+
+    let result = lb.get_ptr(Some(&make_context_for(&lb, 0)), &upstreams);
+    assert_eq!(result.ptr, upstreams.len());
+    assert!(result.context.is_none());
+  }
 }
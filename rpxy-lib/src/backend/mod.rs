@@ -1,7 +1,12 @@
 mod backend_main;
+mod backend_stream;
 mod load_balance;
 mod upstream;
+mod upstream_file;
 mod upstream_opts;
+mod upstream_override;
+mod upstream_pool;
+mod upstream_resolve;
 
 #[cfg(feature = "health-check")]
 pub(crate) mod health_check;
@@ -12,13 +17,19 @@ pub(crate) use self::load_balance::{
 };
 #[cfg(feature = "sticky-cookie")]
 pub use self::load_balance::{StickyCookieSecret, validate_sticky_cookie_aad_component};
+pub use self::upstream_override::UpstreamOverrides;
 #[allow(unused)]
 pub(crate) use self::{
   load_balance::{LoadBalance, LoadBalanceContext},
   upstream::{PathManager, Upstream, UpstreamCandidates},
   upstream_opts::UpstreamOption,
+  upstream_override::{UpstreamGroup, UpstreamOverride},
+  upstream_pool::{UpstreamPool, UpstreamRef, UpstreamSet},
 };
 pub(crate) use backend_main::{BackendApp, BackendAppBuilderError, BackendAppManager};
+pub(crate) use backend_stream::StreamBackend;
+pub(crate) use upstream_file::spawn_upstream_file_watchers;
+pub(crate) use upstream_resolve::spawn_upstream_resolvers;
 
 #[cfg(feature = "health-check")]
 pub(crate) const LOAD_BALANCE_PRIMARY_BACKUP: &str = self::load_balance::load_balance_options::PRIMARY_BACKUP;
@@ -1,16 +1,24 @@
-use super::load_balance::{
-  LoadBalance, LoadBalanceContext, LoadBalanceRandomBuilder, LoadBalanceRoundRobinBuilder, load_balance_options as lb_opts,
-};
+use super::load_balance::{LoadBalance, LoadBalanceRandomBuilder, LoadBalanceRoundRobinBuilder, load_balance_options as lb_opts};
 #[cfg(feature = "sticky-cookie")]
 use super::load_balance::{LoadBalanceStickyBuilder, StickyCookieConfig};
-use super::upstream_opts::UpstreamOption;
+use super::{
+  upstream_opts::UpstreamOption,
+  upstream_override::{UpstreamOverride, UpstreamOverrideState},
+  upstream_pool::{UpstreamPool, UpstreamSet},
+};
 #[cfg(feature = "sticky-cookie")]
 use crate::constants::{STICKY_COOKIE_DURATION_SECS, STICKY_COOKIE_NAME};
+#[cfg(feature = "cache")]
+use crate::globals::CacheRuleConfig;
 #[cfg(feature = "health-check")]
 use crate::globals::HealthCheckConfig;
+#[cfg(feature = "proxy-protocol")]
+use crate::globals::ProxyProtocolVersion;
 use crate::{
+  constants::UNIX_SOCKET_LOCATION_PREFIX,
   error::RpxyError,
-  globals::{AppConfig, UpstreamUri},
+  globals::{AppConfig, UpstreamResolveConfig, UpstreamTlsConfig, UpstreamUri},
+  hyper_ext::unix::{UNIX_SOCKET_HOST, is_unix_socket_uri},
   log::*,
   name_exp::{ByteName, PathName},
 };
@@ -21,9 +29,7 @@ use derive_builder::Builder;
 use http::HeaderValue;
 #[cfg(feature = "sticky-cookie")]
 use sha2::{Digest, Sha256};
-use std::borrow::Cow;
-#[cfg(feature = "health-check")]
-use std::sync::Arc;
+use std::{borrow::Cow, net::SocketAddr, path::PathBuf, sync::Arc};
 
 #[derive(Debug, Clone)]
 /// Handler for given path to route incoming request to path's corresponding upstream server(s).
@@ -60,15 +66,21 @@ impl TryFrom<&AppConfig> for PathManager {
         .collect();
 
       let mut builder = UpstreamCandidatesBuilder::default();
+      builder.path(&rpc.path).replace_path(&rpc.replace_path);
+      builder.upstream_pool(upstream_vec, &rpc.load_balance, &app_config.server_name, &rpc.path)?;
       builder
-        .upstream(&upstream_vec)
-        .path(&rpc.path)
-        .replace_path(&rpc.replace_path);
-      builder.load_balance(&rpc.load_balance, &upstream_vec, &app_config.server_name, &rpc.path)?;
-      builder.options(&rpc.upstream_options);
+        .options(&rpc.upstream_options)
+        .grpc_web_allowed_origins(&rpc.grpc_web_allowed_origins)
+        .resolve(&rpc.resolve)
+        .upstream_file(&rpc.upstream_file)
+        .upstream_tls(&rpc.upstream_tls);
 
       #[cfg(feature = "health-check")]
       builder.health_check_config(&rpc.health_check);
+      #[cfg(feature = "proxy-protocol")]
+      builder.send_proxy_protocol(rpc.send_proxy_protocol);
+      #[cfg(feature = "cache")]
+      builder.cache_rule(&rpc.cache);
 
       let elem = builder.build().map_err(|e| {
         error!("Failed to build upstream candidates: {e}");
@@ -94,7 +106,6 @@ impl TryFrom<&AppConfig> for PathManager {
 }
 
 impl PathManager {
-  #[cfg(feature = "health-check")]
   pub(crate) fn iter_candidates(&self) -> impl Iterator<Item = (&PathName, &UpstreamCandidates)> {
     self.inner.iter()
   }
@@ -148,24 +159,33 @@ pub struct Upstream {
   /// None if health check is not configured or explicitly disabled for upstream group this upstream belongs to.
   #[cfg(feature = "health-check")]
   pub health: Option<Arc<super::health_check::UpstreamHealth>>,
+  /// Override of the availability set via the admin API, shared with the override registry.
+  /// None until the pool of this upstream is bound to the registry.
+  pub override_state: Option<Arc<UpstreamOverrideState>>,
 }
 impl From<&UpstreamUri> for Upstream {
   fn from(value: &UpstreamUri) -> Self {
     // Render the `Host` value once. None when there is no host, or (practically unreachable for a
     // host taken from a valid `Uri` plus a numeric port) when the value fails HeaderValue
     // validation; the per-request override then yields the existing "No hostname is given" error.
-    let host_header = value.inner.host().and_then(|host| {
-      match value.inner.port_u16() {
-        Some(port) => HeaderValue::from_str(&format!("{host}:{port}")),
-        None => HeaderValue::from_str(host),
-      }
-      .ok()
-    });
+    // The authority of a Unix domain socket upstream is the encoded socket path, not a host name.
+    let host_header = if is_unix_socket_uri(&value.inner) {
+      Some(HeaderValue::from_static(UNIX_SOCKET_HOST))
+    } else {
+      value.inner.host().and_then(|host| {
+        match value.inner.port_u16() {
+          Some(port) => HeaderValue::from_str(&format!("{host}:{port}")),
+          None => HeaderValue::from_str(host),
+        }
+        .ok()
+      })
+    };
     Self {
       uri: value.inner.clone(),
       host_header,
       #[cfg(feature = "health-check")]
       health: None,
+      override_state: None,
     }
   }
 }
@@ -196,6 +216,61 @@ impl Upstream {
   pub fn has_health_state(&self) -> bool {
     self.health.is_some()
   }
+
+  /// Returns the override of the availability set via the admin API, if any.
+  pub fn override_kind(&self) -> Option<UpstreamOverride> {
+    self.override_state.as_ref().and_then(|s| s.get())
+  }
+
+  /// Returns whether this upstream may serve new requests, i.e., it is healthy or forced healthy,
+  /// and neither drained nor disabled.
+  pub fn is_available(&self) -> bool {
+    match self.override_kind() {
+      Some(UpstreamOverride::ForcedHealthy) => true,
+      Some(UpstreamOverride::Drained | UpstreamOverride::Disabled) => false,
+      None => self.is_healthy(),
+    }
+  }
+
+  /// Returns whether this upstream is disabled, i.e., never chosen even as a fallback.
+  pub fn is_disabled(&self) -> bool {
+    self.override_kind() == Some(UpstreamOverride::Disabled)
+  }
+
+  /// Returns whether the availability of this upstream may change at runtime, by health check or
+  /// by an override. Groups without any such upstream skip the availability scan.
+  pub fn tracks_availability(&self) -> bool {
+    #[cfg(feature = "health-check")]
+    if self.health.is_some() {
+      return true;
+    }
+    self.override_kind().is_some()
+  }
+
+  /// Location of this upstream as given in the config, i.e., the socket path with `unix:` prefix for
+  /// Unix domain sockets, otherwise the uri.
+  pub fn location(&self) -> String {
+    #[cfg(unix)]
+    if let Some(path) = crate::hyper_ext::unix::unix_socket_path(&self.uri) {
+      return format!("{UNIX_SOCKET_LOCATION_PREFIX}{}", path.display());
+    }
+    self.uri.to_string()
+  }
+
+  /// Build an upstream dialing one resolved address of this upstream's host name. The `Host` value
+  /// rendered from the configured host name is kept, so `set_upstream_host` still sends the name.
+  /// Health and override states are not carried over; the resolver attaches them per address.
+  pub(super) fn with_resolved_addr(&self, addr: SocketAddr) -> Option<Self> {
+    let scheme = self.uri.scheme_str()?;
+    let uri = format!("{scheme}://{addr}").parse::<hyper::Uri>().ok()?;
+    Some(Self {
+      uri,
+      host_header: self.host_header.clone(),
+      #[cfg(feature = "health-check")]
+      health: None,
+      override_state: None,
+    })
+  }
 }
 impl Upstream {
   #[cfg(feature = "sticky-cookie")]
@@ -212,8 +287,8 @@ impl Upstream {
 /// Struct serving multiple upstream servers for, e.g., load balancing.
 pub struct UpstreamCandidates {
   #[builder(setter(custom))]
-  /// Upstream server(s)
-  pub inner: Vec<Upstream>,
+  /// Upstream server(s) and the load balancer over them, swapped as a whole when upstreams are re-resolved
+  pub pool: UpstreamPool,
 
   #[builder(setter(custom), default)]
   /// Path like "/path" in [[PathName]] associated with the upstream server(s)
@@ -223,26 +298,43 @@ pub struct UpstreamCandidates {
   /// Path in [[PathName]] that will be used to replace the "path" part of incoming url
   pub replace_path: Option<PathName>,
 
-  #[builder(setter(custom), default)]
-  /// Load balancing option
-  pub load_balance: LoadBalance,
-
   #[builder(setter(custom), default)]
   /// Activated upstream options defined in [[UpstreamOption]]
   pub options: HashSet<UpstreamOption>,
 
+  #[builder(setter(custom), default)]
+  /// Origins in lowercase allowed to send gRPC-Web requests cross-origin, where `*` allows any origin
+  pub grpc_web_allowed_origins: Vec<String>,
+
   #[cfg(feature = "health-check")]
   #[builder(setter(custom), default)]
   /// Health check configuration for this upstream group
   pub health_check_config: Option<HealthCheckConfig>,
+
+  #[builder(setter(custom), default)]
+  /// Re-resolution configuration of upstream host names. None if upstreams are static.
+  pub resolve: Option<UpstreamResolveConfig>,
+
+  #[builder(setter(custom), default)]
+  /// JSON file listing the upstream server(s), watched for changes. None if upstreams are static.
+  pub upstream_file: Option<PathBuf>,
+
+  #[builder(setter(custom), default)]
+  /// TLS settings for connections to the upstream server(s). None if the default client is used.
+  pub upstream_tls: Option<Arc<UpstreamTlsConfig>>,
+
+  #[cfg(feature = "proxy-protocol")]
+  #[builder(setter(custom), default)]
+  /// PROXY protocol version sent on new connections to the upstream server(s). None if not sent.
+  pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+
+  #[cfg(feature = "cache")]
+  #[builder(setter(custom), default)]
+  /// Cache rule of the path. None if responses are cached as per the global cache settings only.
+  pub cache_rule: Option<Arc<CacheRuleConfig>>,
 }
 
 impl UpstreamCandidatesBuilder {
-  /// Set the upstream server(s)
-  pub fn upstream(&mut self, upstream_vec: &[Upstream]) -> &mut Self {
-    self.inner = Some(upstream_vec.to_vec());
-    self
-  }
   /// Set the path like "/path" in [[PathName]] associated with the upstream server(s), default is "/"
   pub fn path(&mut self, v: &Option<String>) -> &mut Self {
     let path = match v {
@@ -257,15 +349,13 @@ impl UpstreamCandidatesBuilder {
     self.replace_path = Some(v.to_owned().as_ref().map_or_else(|| None, |v| Some(v.to_path_name())));
     self
   }
-  /// Set the load balancing option. Fallible: building the sticky-cookie config validates its
-  /// AAD components (and precomputes the AAD), so an invalid configuration is rejected here -
-  /// at backend build time - instead of panicking or failing per request.
-  pub fn load_balance(
+  /// Set the upstream server(s) and the load balancing option. Fallible: building the sticky-cookie
+  /// config validates its AAD components (and precomputes the AAD), so an invalid configuration is
+  /// rejected here - at backend build time - instead of panicking or failing per request.
+  pub fn upstream_pool(
     &mut self,
+    upstream_vec: Vec<Upstream>,
     v: &Option<String>,
-    // upstream_num: &usize,
-    #[cfg(feature = "sticky-cookie")] upstream_vec: &[Upstream],
-    #[cfg(not(feature = "sticky-cookie"))] _upstream_vec: &[Upstream],
     #[cfg(feature = "sticky-cookie")] server_name: &str,
     #[cfg(not(feature = "sticky-cookie"))] _server_name: &str,
     #[cfg(feature = "sticky-cookie")] path_opt: &Option<String>,
@@ -283,7 +373,7 @@ impl UpstreamCandidatesBuilder {
           LoadBalance::StickyRoundRobin(
             LoadBalanceStickyBuilder::default()
               .sticky_config(sticky_config)
-              .upstream_maps(upstream_vec)
+              .upstream_maps(&upstream_vec)
               .build()
               .unwrap(),
           )
@@ -298,7 +388,10 @@ impl UpstreamCandidatesBuilder {
     } else {
       LoadBalance::default()
     };
-    self.load_balance = Some(lb);
+    self.pool = Some(UpstreamPool::new(UpstreamSet {
+      inner: upstream_vec,
+      load_balance: lb,
+    }));
     Ok(self)
   }
 
@@ -309,6 +402,45 @@ impl UpstreamCandidatesBuilder {
     self
   }
 
+  /// Set the re-resolution configuration of upstream host names
+  pub fn resolve(&mut self, v: &Option<UpstreamResolveConfig>) -> &mut Self {
+    self.resolve = Some(v.clone());
+    self
+  }
+
+  /// Set the JSON file listing the upstream server(s)
+  pub fn upstream_file(&mut self, v: &Option<PathBuf>) -> &mut Self {
+    self.upstream_file = Some(v.clone());
+    self
+  }
+
+  #[cfg(feature = "proxy-protocol")]
+  /// Set the PROXY protocol version sent on new connections to the upstream server(s)
+  pub fn send_proxy_protocol(&mut self, v: Option<ProxyProtocolVersion>) -> &mut Self {
+    self.send_proxy_protocol = Some(v);
+    self
+  }
+
+  #[cfg(feature = "cache")]
+  /// Set the cache rule of the path
+  pub fn cache_rule(&mut self, v: &Option<CacheRuleConfig>) -> &mut Self {
+    self.cache_rule = Some(v.clone().map(Arc::new));
+    self
+  }
+
+  /// Set the TLS settings for connections to the upstream server(s)
+  pub fn upstream_tls(&mut self, v: &Option<UpstreamTlsConfig>) -> &mut Self {
+    self.upstream_tls = Some(v.clone().map(Arc::new));
+    self
+  }
+
+  /// Set the origins allowed to send gRPC-Web requests cross-origin
+  pub fn grpc_web_allowed_origins(&mut self, v: &Option<Vec<String>>) -> &mut Self {
+    let origins = v.iter().flatten().map(|origin| origin.to_ascii_lowercase()).collect();
+    self.grpc_web_allowed_origins = Some(origins);
+    self
+  }
+
   /// Set the activated upstream options defined in [[UpstreamOption]]
   pub fn options(&mut self, v: &Option<Vec<String>>) -> &mut Self {
     let opts = v.as_ref().map_or_else(
@@ -326,13 +458,23 @@ impl UpstreamCandidatesBuilder {
 }
 
 impl UpstreamCandidates {
-  /// Get an enabled option of load balancing [[LoadBalance]]
-  pub fn get(&self, context_to_lb: &Option<LoadBalanceContext>) -> (Option<&Upstream>, Option<LoadBalanceContext>) {
-    let pointer_to_upstream = self.load_balance.get_context(context_to_lb, &self.inner);
-    trace!("Upstream of index {} is chosen.", pointer_to_upstream.ptr);
-    trace!("Context to LB (Cookie in Request): {:?}", context_to_lb);
-    trace!("Context from LB (Set-Cookie in Response): {:?}", pointer_to_upstream.context);
-    (self.inner.get(pointer_to_upstream.ptr), pointer_to_upstream.context)
+  /// Returns whether the upstream server(s) of this group may be replaced at runtime, by
+  /// re-resolution or from the upstream file.
+  #[cfg(feature = "health-check")]
+  pub(crate) fn is_dynamic(&self) -> bool {
+    self.resolve.is_some() || self.upstream_file.is_some()
+  }
+
+  /// Returns whether upstream server(s) added to this group at runtime need health state.
+  pub(crate) fn with_health(&self) -> bool {
+    #[cfg(feature = "health-check")]
+    {
+      self.health_check_config.is_some()
+    }
+    #[cfg(not(feature = "health-check"))]
+    {
+      false
+    }
   }
 }
 
@@ -353,9 +495,17 @@ mod test {
           inner: "http://127.0.0.1:8080".parse().unwrap(),
         }],
         upstream_options: None,
+        grpc_web_allowed_origins: None,
         load_balance: None,
         #[cfg(feature = "health-check")]
         health_check: None,
+        resolve: None,
+        upstream_file: None,
+        upstream_tls: None,
+        #[cfg(feature = "proxy-protocol")]
+        send_proxy_protocol: None,
+        #[cfg(feature = "cache")]
+        cache: None,
       }
     }
 
@@ -387,6 +537,7 @@ mod test {
       host_header: None,
       #[cfg(feature = "health-check")]
       health: None,
+      override_state: None,
     };
     assert_eq!(
       "eGsjoPbactQ1eUJjafYjPT3ekYZQkaqJnHdA_FMSkgM",
@@ -0,0 +1,252 @@
+use super::{BackendAppManager, Upstream, UpstreamPool, upstream_pool::attach_health};
+use crate::{
+  constants::UPSTREAM_FILE_WATCH_DELAY_SECS,
+  error::{RpxyError, RpxyResult},
+  globals::UpstreamUri,
+  log::*,
+};
+use async_trait::async_trait;
+use hot_reload::{Reload, ReloaderError, ReloaderService};
+use serde::Deserialize;
+use std::{path::PathBuf, sync::Arc};
+use tokio_util::sync::CancellationToken;
+
+/* ------------------------------------------------ */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Single entry of an upstream file, same as an `upstream` entry in the config
+struct UpstreamFileEntry {
+  location: String,
+  #[serde(default)]
+  tls: bool,
+}
+
+#[derive(PartialEq, Eq, Clone)]
+/// Validated upstream list read from an upstream file
+pub(crate) struct UpstreamFileList {
+  inner: Vec<UpstreamUri>,
+}
+
+impl UpstreamFileList {
+  /// Parse and validate the content of an upstream file, a JSON array of `{ "location": "host:port", "tls": false }`.
+  /// Fails as a whole if any entry is invalid or the list is empty.
+  fn parse(content: &str) -> RpxyResult<Self> {
+    let entries: Vec<UpstreamFileEntry> =
+      serde_json::from_str(content).map_err(|e| RpxyError::InvalidUpstreamUri(format!("Failed to parse upstream file: {e}")))?;
+    if entries.is_empty() {
+      return Err(RpxyError::InvalidUpstreamUri("Upstream file lists no upstream".to_string()));
+    }
+    let inner = entries
+      .iter()
+      .map(|entry| UpstreamUri::from_location(&entry.location, entry.tls))
+      .collect::<RpxyResult<Vec<_>>>()?;
+    Ok(Self { inner })
+  }
+}
+
+/* ------------------------------------------------ */
+/// Reloader service for an upstream file
+pub(crate) struct UpstreamFileReloader {
+  path: PathBuf,
+}
+
+#[async_trait]
+impl Reload<UpstreamFileList> for UpstreamFileReloader {
+  type Source = PathBuf;
+
+  async fn new(source: &Self::Source) -> Result<Self, ReloaderError<UpstreamFileList>> {
+    Ok(Self { path: source.clone() })
+  }
+
+  async fn reload(&self) -> Result<Option<UpstreamFileList>, ReloaderError<UpstreamFileList>> {
+    // A missing or invalid file must not drop the upstreams being served, so errors are only
+    // logged and the last good list stays in effect.
+    let content = match tokio::fs::read_to_string(&self.path).await {
+      Ok(content) => content,
+      Err(e) => {
+        warn!(
+          "Failed to read upstream file {}, keeping the current upstreams: {e}",
+          self.path.display()
+        );
+        return Ok(None);
+      }
+    };
+    match UpstreamFileList::parse(&content) {
+      Ok(list) => Ok(Some(list)),
+      Err(e) => {
+        warn!(
+          "Invalid upstream file {}, keeping the current upstreams: {e}",
+          self.path.display()
+        );
+        Ok(None)
+      }
+    }
+  }
+}
+
+/* ------------------------------------------------ */
+/// Spawn watcher tasks for all upstream candidates configured with `upstream_file`.
+/// Each task watches the file and replaces the upstream set of the group, together with its load
+/// balancer state, whenever a valid list is read.
+/// Returns join handles for the spawned tasks.
+pub(crate) fn spawn_upstream_file_watchers(
+  app_manager: &Arc<BackendAppManager>,
+  cancel_token: CancellationToken,
+  runtime_handle: &tokio::runtime::Handle,
+) -> Vec<tokio::task::JoinHandle<RpxyResult<()>>> {
+  let mut handles = Vec::new();
+
+  app_manager.apps.iter().for_each(|(_app_name, backend_app)| {
+    let server_name = (&backend_app.server_name).try_into().unwrap_or_else(|_| "<none>".to_string());
+    let sub_handles = backend_app.path_manager.iter_candidates().filter_map(|(path, candidates)| {
+      let upstream_file = candidates.upstream_file.clone()?;
+      let path_str: String = path.try_into().unwrap_or_else(|_| "<none>".to_string());
+
+      info!(
+        "[{server_name}] Upstream file watcher started for path \"{path_str}\" ({})",
+        upstream_file.display()
+      );
+
+      let pool = candidates.pool.clone();
+      let with_health = candidates.with_health();
+      let cancel = cancel_token.clone();
+      let server_name = server_name.clone();
+      let handle = runtime_handle
+        .spawn(async move { run_upstream_file_watcher(server_name, path_str, upstream_file, pool, with_health, cancel).await });
+      Some(handle)
+    });
+    handles.extend(sub_handles);
+  });
+
+  handles
+}
+
+/// Run a single watcher task for an upstream file.
+async fn run_upstream_file_watcher(
+  server_name: String,
+  path_str: String,
+  upstream_file: PathBuf,
+  pool: UpstreamPool,
+  with_health: bool,
+  cancel: CancellationToken,
+) -> RpxyResult<()> {
+  let (reloader_service, mut reloader_rx) =
+    ReloaderService::<UpstreamFileReloader, UpstreamFileList>::with_delay(&upstream_file, UPSTREAM_FILE_WATCH_DELAY_SECS)
+      .await
+      .map_err(|e| RpxyError::UpstreamFileWatcherError(e.to_string()))?;
+
+  let apply = async {
+    while reloader_rx.changed().await.is_ok() {
+      let Some(list) = reloader_rx.get() else {
+        continue;
+      };
+      let current = pool.load();
+      let upstreams = list
+        .inner
+        .iter()
+        .map(|uri| attach_health(Upstream::from(uri), &current.inner, with_health))
+        .collect::<Vec<_>>();
+      info!(
+        "[{server_name}:{path_str}] Upstreams updated from {}: [{}]",
+        upstream_file.display(),
+        upstreams.iter().map(|u| u.uri.to_string()).collect::<Vec<_>>().join(", ")
+      );
+      pool.replace_upstreams(upstreams);
+    }
+  };
+
+  tokio::select! {
+    _ = cancel.cancelled() => {
+      debug!("[{server_name}:{path_str}] Upstream file watcher terminated");
+      Ok(())
+    }
+    res = reloader_service.start() => {
+      res.map_err(|e| RpxyError::UpstreamFileWatcherError(e.to_string()))
+    }
+    _ = apply => {
+      Err(RpxyError::UpstreamFileWatcherError(format!("Upstream file watcher for {} closed", upstream_file.display())))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::{LoadBalance, UpstreamSet};
+
+  #[test]
+  fn parse_upstream_file() {
+    let list = UpstreamFileList::parse(
+      r#"[
+        { "location": "10.0.0.1:8080" },
+        { "location": "api.internal:8443", "tls": true }
+      ]"#,
+    )
+    .unwrap();
+    assert_eq!(list.inner.len(), 2);
+    assert_eq!(list.inner[0].inner, "http://10.0.0.1:8080");
+    assert_eq!(list.inner[1].inner, "https://api.internal:8443");
+  }
+
+  #[test]
+  fn invalid_upstream_file_is_rejected_as_a_whole() {
+    assert!(UpstreamFileList::parse("[]").is_err());
+    assert!(UpstreamFileList::parse("{ \"location\": \"10.0.0.1:8080\" }").is_err());
+    assert!(UpstreamFileList::parse(r#"[{ "location": "10.0.0.1:8080" }, { "location": "bad host" }]"#).is_err());
+    assert!(UpstreamFileList::parse(r#"[{ "location": "10.0.0.1:8080", "weight": 1 }]"#).is_err());
+  }
+
+  #[tokio::test]
+  async fn reload_keeps_last_good_list_on_error() {
+    let path = std::env::temp_dir().join(format!("rpxy-upstream-file-{}.json", std::process::id()));
+    std::fs::write(&path, r#"[{ "location": "10.0.0.1:8080" }]"#).unwrap();
+    let Ok(reloader) = UpstreamFileReloader::new(&path).await else {
+      panic!("failed to build the reloader");
+    };
+    let Ok(Some(list)) = reloader.reload().await else {
+      panic!("a valid upstream file must be loaded");
+    };
+    assert_eq!(list.inner[0].inner, "http://10.0.0.1:8080");
+
+    // An invalid or missing file yields no update, so the current upstreams stay in effect.
+    std::fs::write(&path, "not json").unwrap();
+    assert!(matches!(reloader.reload().await, Ok(None)));
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(reloader.reload().await, Ok(None)));
+  }
+
+  #[tokio::test]
+  async fn watcher_replaces_upstreams() {
+    let path = std::env::temp_dir().join(format!("rpxy-upstream-file-watch-{}.json", std::process::id()));
+    std::fs::write(&path, r#"[{ "location": "10.0.0.1:8080" }, { "location": "10.0.0.2:8080" }]"#).unwrap();
+
+    let pool = UpstreamPool::new(UpstreamSet {
+      inner: vec![],
+      load_balance: LoadBalance::default(),
+    });
+    let cancel = CancellationToken::new();
+    let task = tokio::spawn(run_upstream_file_watcher(
+      "example.com".to_string(),
+      "/".to_string(),
+      path.clone(),
+      pool.clone(),
+      false,
+      cancel.clone(),
+    ));
+
+    let mut loaded = false;
+    for _ in 0..50 {
+      if pool.load().inner.len() == 2 {
+        loaded = true;
+        break;
+      }
+      tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(loaded);
+    assert_eq!(pool.load().get(&None).0.unwrap().uri, "http://10.0.0.1:8080");
+
+    cancel.cancel();
+    task.await.unwrap().unwrap();
+    let _ = std::fs::remove_file(&path);
+  }
+}
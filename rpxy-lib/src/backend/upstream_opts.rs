@@ -15,6 +15,8 @@ pub enum UpstreamOption {
   ForceHttp2Upstream,
   /// Add RFC 7239 Forwarded header
   ForwardedHeader,
+  /// Translate gRPC-Web requests into native gRPC over HTTP/2, and answer CORS preflight requests
+  GrpcWeb,
   // TODO: Add more request-header override options as configuration needs grow.
 }
 impl TryFrom<&str> for UpstreamOption {
@@ -27,6 +29,7 @@ impl TryFrom<&str> for UpstreamOption {
       "force_http11_upstream" => Ok(Self::ForceHttp11Upstream),
       "force_http2_upstream" => Ok(Self::ForceHttp2Upstream),
       "forwarded_header" => Ok(Self::ForwardedHeader),
+      "grpc_web" => Ok(Self::GrpcWeb),
       _ => Err(RpxyError::UnsupportedUpstreamOption),
     }
   }
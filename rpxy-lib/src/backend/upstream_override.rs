@@ -0,0 +1,245 @@
+use super::{BackendAppManager, Upstream, UpstreamCandidates};
+use crate::{error::*, log::*};
+use ahash::HashMap;
+use std::sync::{
+  Arc, RwLock, Weak,
+  atomic::{AtomicU8, Ordering},
+};
+
+/// Operator override of the availability of an upstream, set at runtime via the admin API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamOverride {
+  /// No new requests while any other upstream of the group is available; in-flight ones finish
+  Drained,
+  /// No new requests at all; in-flight ones finish
+  Disabled,
+  /// Always available, regardless of the health check result
+  ForcedHealthy,
+}
+impl TryFrom<&str> for UpstreamOverride {
+  type Error = RpxyError;
+  fn try_from(val: &str) -> RpxyResult<Self> {
+    match val {
+      "drained" => Ok(Self::Drained),
+      "disabled" => Ok(Self::Disabled),
+      "forced_healthy" => Ok(Self::ForcedHealthy),
+      _ => Err(RpxyError::UnsupportedUpstreamOverride),
+    }
+  }
+}
+impl UpstreamOverride {
+  /// Name of the override as accepted by the admin API
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Drained => "drained",
+      Self::Disabled => "disabled",
+      Self::ForcedHealthy => "forced_healthy",
+    }
+  }
+  fn to_u8(self) -> u8 {
+    match self {
+      Self::Drained => 1,
+      Self::Disabled => 2,
+      Self::ForcedHealthy => 3,
+    }
+  }
+  fn from_u8(v: u8) -> Option<Self> {
+    match v {
+      1 => Some(Self::Drained),
+      2 => Some(Self::Disabled),
+      3 => Some(Self::ForcedHealthy),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug)]
+/// Override state shared by every instance of the same upstream, read per request without locking
+pub struct UpstreamOverrideState(AtomicU8);
+impl UpstreamOverrideState {
+  pub(super) fn new(v: Option<UpstreamOverride>) -> Self {
+    Self(AtomicU8::new(v.map_or(0, UpstreamOverride::to_u8)))
+  }
+  pub fn get(&self) -> Option<UpstreamOverride> {
+    UpstreamOverride::from_u8(self.0.load(Ordering::Relaxed))
+  }
+  pub(super) fn set(&self, v: Option<UpstreamOverride>) {
+    self.0.store(v.map_or(0, UpstreamOverride::to_u8), Ordering::Relaxed);
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Upstream group an override is scoped to, i.e., all routes of an app, or a stream proxy
+pub enum UpstreamGroup {
+  App(String),
+  Stream(String),
+}
+
+impl std::fmt::Display for UpstreamGroup {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::App(name) => write!(f, "app {name}"),
+      Self::Stream(name) => write!(f, "stream {name}"),
+    }
+  }
+}
+
+/// Key of an override: the group and the upstream location as shown by the admin API
+type OverrideKey = (UpstreamGroup, String);
+
+#[derive(Debug, Default)]
+struct UpstreamOverridesInner {
+  /// Overrides set by the operator, kept until cleared
+  overrides: HashMap<OverrideKey, UpstreamOverride>,
+  /// States attached to the upstreams currently built. Weak so that upstreams dropped by a config
+  /// reload or a re-resolution do not accumulate here.
+  states: HashMap<OverrideKey, Weak<UpstreamOverrideState>>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Registry of upstream overrides. It outlives the backends rebuilt on config reload, so an override
+/// persists across reloads until cleared, and is applied to the upstream of the same location in the
+/// same group of the new config.
+pub struct UpstreamOverrides {
+  inner: Arc<RwLock<UpstreamOverridesInner>>,
+}
+
+impl UpstreamOverrides {
+  /// Get the states of the upstreams, shared with the other instances of the same upstream in the group
+  fn states(&self, keys: impl Iterator<Item = OverrideKey>) -> Vec<Arc<UpstreamOverrideState>> {
+    let mut inner = self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    inner.states.retain(|_, s| s.strong_count() > 0);
+    keys
+      .map(|key| {
+        if let Some(state) = inner.states.get(&key).and_then(Weak::upgrade) {
+          return state;
+        }
+        let state = Arc::new(UpstreamOverrideState::new(inner.overrides.get(&key).copied()));
+        inner.states.insert(key, Arc::downgrade(&state));
+        state
+      })
+      .collect()
+  }
+
+  /// Set or clear (`None`) the override of the upstream. Returns the previous override.
+  pub(crate) fn set(&self, group: UpstreamGroup, location: String, v: Option<UpstreamOverride>) -> Option<UpstreamOverride> {
+    let mut inner = self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let key = (group, location);
+    if let Some(state) = inner.states.get(&key).and_then(Weak::upgrade) {
+      state.set(v);
+    }
+    match v {
+      Some(v) => inner.overrides.insert(key, v),
+      None => inner.overrides.remove(&key),
+    }
+  }
+
+  /// Overrides currently set, sorted by group and upstream
+  pub(crate) fn list(&self) -> Vec<(UpstreamGroup, String, UpstreamOverride)> {
+    let inner = self.inner.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut list = inner
+      .overrides
+      .iter()
+      .map(|((group, location), v)| (group.clone(), location.clone(), *v))
+      .collect::<Vec<_>>();
+    list.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    list
+  }
+
+  /// Bind the upstream pools of every app and stream to the overrides of their group
+  pub(crate) fn bind(&self, app_manager: &BackendAppManager) {
+    for app in app_manager.apps.values() {
+      let group = UpstreamGroup::App(app.app_name.clone());
+      for (_, candidates) in app.path_manager.iter_candidates() {
+        self.bind_group(candidates, &group);
+      }
+    }
+    for stream in app_manager.streams.iter() {
+      self.bind_group(
+        &stream.upstream_candidates,
+        &UpstreamGroup::Stream(stream.config.name.clone()),
+      );
+    }
+    let count = self
+      .inner
+      .read()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+      .overrides
+      .len();
+    if count > 0 {
+      info!("{count} upstream override(s) set via the admin API are in effect");
+    }
+  }
+
+  fn bind_group(&self, candidates: &UpstreamCandidates, group: &UpstreamGroup) {
+    candidates.pool.bind_overrides(GroupOverrides::new(self, group));
+  }
+}
+
+#[derive(Debug, Clone)]
+/// Overrides of a single upstream group, held by its pool to attach states to upstreams added at runtime
+pub(crate) struct GroupOverrides {
+  registry: UpstreamOverrides,
+  group: UpstreamGroup,
+}
+
+impl GroupOverrides {
+  pub(super) fn new(registry: &UpstreamOverrides, group: &UpstreamGroup) -> Self {
+    Self {
+      registry: registry.clone(),
+      group: group.clone(),
+    }
+  }
+
+  /// Attach the override states of the same upstreams in the group
+  pub(super) fn attach(&self, upstreams: Vec<Upstream>) -> Vec<Upstream> {
+    let states = self
+      .registry
+      .states(upstreams.iter().map(|u| (self.group.clone(), u.location())));
+    upstreams
+      .into_iter()
+      .zip(states)
+      .map(|(mut upstream, state)| {
+        upstream.override_state = Some(state);
+        upstream
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn override_is_shared_and_persists_until_cleared() {
+    let registry = UpstreamOverrides::default();
+    let group = UpstreamGroup::App("app1".to_string());
+    let location = "http://10.0.0.1:8080/".to_string();
+
+    let state = |group: &UpstreamGroup| registry.states(std::iter::once((group.clone(), location.clone()))).remove(0);
+
+    let before = state(&group);
+    assert_eq!(before.get(), None);
+    assert_eq!(
+      registry.set(group.clone(), location.clone(), Some(UpstreamOverride::Drained)),
+      None
+    );
+    assert_eq!(before.get(), Some(UpstreamOverride::Drained));
+
+    // The state of an upstream built later, e.g., by a config reload, carries the override over.
+    drop(before);
+    let after = state(&group);
+    assert_eq!(after.get(), Some(UpstreamOverride::Drained));
+    // Other groups are not affected.
+    let other = state(&UpstreamGroup::Stream("app1".to_string()));
+    assert_eq!(other.get(), None);
+
+    assert_eq!(
+      registry.set(group.clone(), location.clone(), None),
+      Some(UpstreamOverride::Drained)
+    );
+    assert_eq!(after.get(), None);
+    assert!(registry.list().is_empty());
+  }
+}
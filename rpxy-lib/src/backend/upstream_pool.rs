@@ -0,0 +1,196 @@
+use super::{
+  load_balance::{LoadBalance, LoadBalanceContext},
+  upstream::Upstream,
+  upstream_override::GroupOverrides,
+};
+use crate::log::*;
+use std::{
+  ops::Deref,
+  sync::{Arc, OnceLock, RwLock},
+};
+
+#[derive(Debug, Clone)]
+/// Snapshot of the upstream servers of a group and the load balancer built over them.
+/// The load balancer may hold per-upstream state (e.g. sticky cookie ids), so both are always
+/// replaced together.
+pub struct UpstreamSet {
+  /// Upstream server(s)
+  pub inner: Vec<Upstream>,
+  /// Load balancing option
+  pub load_balance: LoadBalance,
+}
+
+impl UpstreamSet {
+  /// Build a new set of upstreams, rebuilding the per-upstream state of the given load balancer
+  /// while keeping its shared state (e.g. round-robin pointer and sticky cookie config).
+  pub fn new(inner: Vec<Upstream>, load_balance: &LoadBalance) -> Self {
+    let load_balance = load_balance.with_upstreams(&inner);
+    Self { inner, load_balance }
+  }
+
+  /// Get an upstream server chosen by the load balancer, with the context from the load balancer
+  /// (e.g. a new sticky cookie).
+  pub fn get(self: &Arc<Self>, context_to_lb: &Option<LoadBalanceContext>) -> (Option<UpstreamRef>, Option<LoadBalanceContext>) {
+    let pointer_to_upstream = self.load_balance.get_context(context_to_lb, &self.inner);
+    trace!("Upstream of index {} is chosen.", pointer_to_upstream.ptr);
+    trace!("Context to LB (Cookie in Request): {:?}", context_to_lb);
+    trace!("Context from LB (Set-Cookie in Response): {:?}", pointer_to_upstream.context);
+    let upstream = (pointer_to_upstream.ptr < self.inner.len()).then(|| UpstreamRef {
+      set: self.clone(),
+      index: pointer_to_upstream.ptr,
+    });
+    (upstream, pointer_to_upstream.context)
+  }
+}
+
+#[derive(Debug, Clone)]
+/// Upstream chosen for a request. Keeps the snapshot it was taken from alive, so that the set can
+/// be swapped while the request is in flight.
+pub struct UpstreamRef {
+  set: Arc<UpstreamSet>,
+  index: usize,
+}
+
+impl Deref for UpstreamRef {
+  type Target = Upstream;
+  fn deref(&self) -> &Self::Target {
+    &self.set.inner[self.index]
+  }
+}
+
+#[derive(Debug, Clone)]
+/// Shared handle to the current [`UpstreamSet`] of an upstream group.
+/// Readers take a cheap snapshot per request; writers (e.g. the dynamic resolver) replace the whole
+/// set at once, so a request never observes upstreams and load balancer state out of sync.
+pub struct UpstreamPool {
+  current: Arc<RwLock<Arc<UpstreamSet>>>,
+  /// Overrides of the group set via the admin API, attached to every upstream entering the pool
+  overrides: Arc<OnceLock<GroupOverrides>>,
+}
+
+impl UpstreamPool {
+  /// Create a new pool holding the given set
+  pub fn new(set: UpstreamSet) -> Self {
+    Self {
+      current: Arc::new(RwLock::new(Arc::new(set))),
+      overrides: Default::default(),
+    }
+  }
+
+  /// Bind the pool to the overrides of its group, attaching them to the current upstreams and to
+  /// every upstream replacing them later. A pool is bound only once; later calls are ignored.
+  pub(crate) fn bind_overrides(&self, overrides: GroupOverrides) {
+    if self.overrides.set(overrides).is_err() {
+      return;
+    }
+    let upstreams = self.load().inner.clone();
+    self.replace_upstreams(upstreams);
+  }
+
+  /// Get the current snapshot of the set
+  pub fn load(&self) -> Arc<UpstreamSet> {
+    // The lock only guards an `Arc` swap, so a poisoned lock still holds a consistent snapshot.
+    self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+  }
+
+  /// Replace the set with new upstreams, keeping the load balancer configuration of the current one
+  pub fn replace_upstreams(&self, upstreams: Vec<Upstream>) {
+    let upstreams = match self.overrides.get() {
+      Some(overrides) => overrides.attach(upstreams),
+      None => upstreams,
+    };
+    let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let new_set = UpstreamSet::new(upstreams, &current.load_balance);
+    *current = Arc::new(new_set);
+  }
+}
+
+#[cfg(feature = "health-check")]
+/// Attach health state to an upstream replacing the current set: the state of the same upstream
+/// (same uri) in the current set is reused, otherwise a new one is started if `with_health` is set.
+pub(super) fn attach_health(upstream: Upstream, current: &[Upstream], with_health: bool) -> Upstream {
+  if !with_health {
+    return upstream;
+  }
+  let health = current
+    .iter()
+    .find(|u| u.uri == upstream.uri)
+    .and_then(|u| u.health.clone())
+    .unwrap_or_else(|| Arc::new(super::health_check::UpstreamHealth::new()));
+  let mut upstream = upstream;
+  upstream.health = Some(health);
+  upstream
+}
+
+#[cfg(not(feature = "health-check"))]
+pub(super) fn attach_health(upstream: Upstream, _current: &[Upstream], _with_health: bool) -> Upstream {
+  upstream
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{backend::load_balance::LoadBalanceRoundRobinBuilder, globals::UpstreamUri};
+
+  fn make_upstream(uri_str: &str) -> Upstream {
+    Upstream::from(&UpstreamUri {
+      inner: uri_str.parse::<http::Uri>().unwrap(),
+    })
+  }
+
+  #[test]
+  fn replace_upstreams_keeps_in_flight_snapshot() {
+    let lb = LoadBalance::RoundRobin(LoadBalanceRoundRobinBuilder::default().build().unwrap());
+    let pool = UpstreamPool::new(UpstreamSet::new(
+      vec![make_upstream("http://10.0.0.1:8080"), make_upstream("http://10.0.0.2:8080")],
+      &lb,
+    ));
+
+    let (chosen, _) = pool.load().get(&None);
+    let chosen = chosen.unwrap();
+    assert_eq!(chosen.uri, "http://10.0.0.1:8080");
+
+    pool.replace_upstreams(vec![make_upstream("http://10.0.0.3:8080")]);
+    // The upstream taken before the swap still refers to the old snapshot.
+    assert_eq!(chosen.uri, "http://10.0.0.1:8080");
+
+    let current = pool.load();
+    assert_eq!(current.inner.len(), 1);
+    assert!(matches!(current.load_balance, LoadBalance::RoundRobin(_)));
+    for _ in 0..3 {
+      assert_eq!(current.get(&None).0.unwrap().uri, "http://10.0.0.3:8080");
+    }
+  }
+
+  #[test]
+  fn overrides_apply_to_replaced_upstreams() {
+    use crate::backend::upstream_override::{UpstreamGroup, UpstreamOverride, UpstreamOverrides};
+
+    let lb = LoadBalance::RoundRobin(LoadBalanceRoundRobinBuilder::default().build().unwrap());
+    let pool = UpstreamPool::new(UpstreamSet::new(
+      vec![make_upstream("http://10.0.0.1:8080"), make_upstream("http://10.0.0.2:8080")],
+      &lb,
+    ));
+    let registry = UpstreamOverrides::default();
+    let group = UpstreamGroup::Stream("s1".to_string());
+    registry.set(
+      group.clone(),
+      "http://10.0.0.1:8080/".to_string(),
+      Some(UpstreamOverride::Disabled),
+    );
+    pool.bind_overrides(GroupOverrides::new(&registry, &group));
+    for _ in 0..3 {
+      assert_eq!(pool.load().get(&None).0.unwrap().uri, "http://10.0.0.2:8080");
+    }
+
+    // An upstream re-entering the pool, e.g., by re-resolution, keeps its override.
+    pool.replace_upstreams(vec![make_upstream("http://10.0.0.1:8080")]);
+    assert!(pool.load().get(&None).0.is_none());
+  }
+
+  #[test]
+  fn empty_set_yields_no_upstream() {
+    let pool = UpstreamPool::new(UpstreamSet::new(vec![], &LoadBalance::default()));
+    assert!(pool.load().get(&None).0.is_none());
+  }
+}
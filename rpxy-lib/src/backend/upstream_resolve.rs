@@ -0,0 +1,210 @@
+use super::{BackendAppManager, Upstream, UpstreamPool, upstream_pool::attach_health};
+use crate::{error::RpxyResult, globals::UpstreamResolveConfig, hyper_ext::unix::is_unix_socket_uri, log::*};
+use std::{net::IpAddr, sync::Arc};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+
+/// Spawn resolver tasks for all upstream candidates configured with `resolve = "dynamic"`.
+/// Each task re-resolves the configured host names at the given interval, expands every address
+/// into its own upstream, and replaces the upstream set of the group when the addresses change.
+/// Returns join handles for the spawned tasks.
+pub(crate) fn spawn_upstream_resolvers(
+  app_manager: &Arc<BackendAppManager>,
+  cancel_token: CancellationToken,
+  runtime_handle: &tokio::runtime::Handle,
+) -> Vec<tokio::task::JoinHandle<RpxyResult<()>>> {
+  let mut handles = Vec::new();
+
+  app_manager.apps.iter().for_each(|(_app_name, backend_app)| {
+    let server_name = (&backend_app.server_name).try_into().unwrap_or_else(|_| "<none>".to_string());
+    let sub_handles = backend_app.path_manager.iter_candidates().filter_map(|(path, candidates)| {
+      let config = candidates.resolve.as_ref()?;
+      let path_str: String = path.try_into().unwrap_or_else(|_| "<none>".to_string());
+
+      // The upstreams given in the config are the templates of every resolution.
+      let templates = candidates.pool.load().inner.clone();
+      if !templates.iter().any(needs_resolution) {
+        debug!("[{server_name}] No upstream host name to resolve for path \"{path_str}\"");
+        return None;
+      }
+
+      let with_health = candidates.with_health();
+
+      info!(
+        "[{server_name}] Upstream resolver started for path \"{path_str}\" ({} upstreams, interval={:?})",
+        templates.len(),
+        config.interval
+      );
+
+      let config = config.clone();
+      let pool = candidates.pool.clone();
+      let cancel = cancel_token.clone();
+      let server_name = server_name.clone();
+      let handle = runtime_handle
+        .spawn(async move { run_upstream_resolver(server_name, path_str, templates, pool, config, with_health, cancel).await });
+      Some(handle)
+    });
+    handles.extend(sub_handles);
+  });
+
+  handles
+}
+
+/// Run a single resolver task for a group of upstreams.
+/// Resolves immediately, then re-resolves with a fixed interval. TTLs of the records are not
+/// available from the system resolver, so the interval is the only refresh trigger.
+async fn run_upstream_resolver(
+  server_name: String,
+  path_str: String,
+  templates: Vec<Upstream>,
+  pool: UpstreamPool,
+  config: UpstreamResolveConfig,
+  with_health: bool,
+  cancel: CancellationToken,
+) -> RpxyResult<()> {
+  let mut ticker = tokio::time::interval(config.interval);
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+  loop {
+    tokio::select! {
+      _ = cancel.cancelled() => {
+        debug!("[{server_name}:{path_str}] Upstream resolver terminated");
+        return Ok(());
+      }
+      _ = ticker.tick() => {
+        let current = pool.load();
+        let Some(resolved) = resolve_upstreams(&templates, &current.inner, with_health).await else {
+          // Keep serving with the last known addresses.
+          warn!("[{server_name}:{path_str}] Failed to resolve upstreams, keeping the current {} upstreams", current.inner.len());
+          continue;
+        };
+        if resolved.iter().map(|u| &u.uri).eq(current.inner.iter().map(|u| &u.uri)) {
+          trace!("[{server_name}:{path_str}] Upstream addresses unchanged");
+          continue;
+        }
+        info!(
+          "[{server_name}:{path_str}] Upstream addresses updated: [{}]",
+          resolved.iter().map(|u| u.uri.to_string()).collect::<Vec<_>>().join(", ")
+        );
+        pool.replace_upstreams(resolved);
+      }
+    }
+  }
+}
+
+/// Returns whether the upstream is given by a host name, i.e., neither an IP literal nor a Unix domain socket.
+fn needs_resolution(upstream: &Upstream) -> bool {
+  if is_unix_socket_uri(&upstream.uri) {
+    return false;
+  }
+  upstream
+    .uri
+    .host()
+    .is_some_and(|host| host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_err())
+}
+
+/// Expand the templates into one upstream per resolved address, in template order and with
+/// addresses sorted within a template. Health state of an address already in the current set is
+/// carried over so that its health history survives re-resolution.
+/// Returns None if any host name fails to resolve or resolves to no address.
+async fn resolve_upstreams(templates: &[Upstream], current: &[Upstream], with_health: bool) -> Option<Vec<Upstream>> {
+  let mut resolved: Vec<Upstream> = Vec::new();
+  for template in templates {
+    if !needs_resolution(template) {
+      if !resolved.iter().any(|u| u.uri == template.uri) {
+        resolved.push(template.clone());
+      }
+      continue;
+    }
+    let host = template.uri.host()?;
+    let port = template
+      .uri
+      .port_u16()
+      .unwrap_or(if template.uri.scheme_str() == Some("https") { 443 } else { 80 });
+    let mut addrs = match tokio::net::lookup_host((host, port)).await {
+      Ok(addrs) => addrs.collect::<Vec<_>>(),
+      Err(e) => {
+        warn!("Failed to resolve upstream host {host}: {e}");
+        return None;
+      }
+    };
+    if addrs.is_empty() {
+      warn!("Upstream host {host} resolved to no address");
+      return None;
+    }
+    addrs.sort();
+    addrs.dedup();
+    for addr in addrs {
+      let Some(upstream) = template.with_resolved_addr(addr) else {
+        warn!("Failed to build upstream uri for {host} ({addr})");
+        return None;
+      };
+      if resolved.iter().any(|u| u.uri == upstream.uri) {
+        continue;
+      }
+      resolved.push(attach_health(upstream, current, with_health));
+    }
+  }
+  Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::globals::UpstreamUri;
+
+  fn make_upstream(uri_str: &str) -> Upstream {
+    Upstream::from(&UpstreamUri {
+      inner: uri_str.parse::<http::Uri>().unwrap(),
+    })
+  }
+
+  #[test]
+  fn ip_literals_are_not_resolved() {
+    assert!(!needs_resolution(&make_upstream("http://192.168.0.1:8080")));
+    assert!(!needs_resolution(&make_upstream("http://[::1]:8080")));
+    assert!(needs_resolution(&make_upstream("http://backend.local:8080")));
+  }
+
+  #[tokio::test]
+  async fn host_name_expands_to_addresses() {
+    let templates = vec![
+      make_upstream("http://localhost:8080"),
+      make_upstream("http://192.168.0.1:8080"),
+    ];
+    let resolved = resolve_upstreams(&templates, &[], false).await.unwrap();
+    assert!(resolved.len() >= 2);
+    // Every address of `localhost` precedes the IP literal, which is kept as is.
+    let (last, addrs) = resolved.split_last().unwrap();
+    assert_eq!(last.uri, templates[1].uri);
+    for upstream in addrs {
+      let host = upstream.uri.host().unwrap().trim_start_matches('[').trim_end_matches(']');
+      assert!(host.parse::<IpAddr>().unwrap().is_loopback());
+      assert_eq!(upstream.uri.port_u16(), Some(8080));
+      // Host header still carries the configured host name.
+      assert_eq!(upstream.host_header().unwrap(), "localhost:8080");
+    }
+  }
+
+  #[cfg(feature = "health-check")]
+  #[tokio::test]
+  async fn health_state_survives_re_resolution() {
+    let templates = vec![make_upstream("http://localhost:8080")];
+    let first = resolve_upstreams(&templates, &[], true).await.unwrap();
+    first[0].health.as_ref().unwrap().set(false);
+
+    let second = resolve_upstreams(&templates, &first, true).await.unwrap();
+    assert_eq!(first.len(), second.len());
+    assert!(Arc::ptr_eq(
+      first[0].health.as_ref().unwrap(),
+      second[0].health.as_ref().unwrap()
+    ));
+    assert!(!second[0].is_healthy());
+  }
+
+  #[tokio::test]
+  async fn unresolvable_host_fails_resolution() {
+    let templates = vec![make_upstream("http://rpxy-unresolvable.invalid:8080")];
+    assert!(resolve_upstreams(&templates, &[], false).await.is_none());
+  }
+}
@@ -3,6 +3,9 @@ pub const TCP_LISTEN_BACKLOG: u32 = 1024;
 pub const PROXY_IDLE_TIMEOUT_SEC: u64 = 20;
 pub const UPSTREAM_IDLE_TIMEOUT_SEC: u64 = 20;
 pub const TLS_HANDSHAKE_TIMEOUT_SEC: u64 = 15; // default as with firefox browser
+pub const TLS_PASSTHROUGH_CONNECT_TIMEOUT_SEC: u64 = 10; // connecting to upstreams for TLS passthrough
+pub const TLS_PASSTHROUGH_IDLE_TIMEOUT_SEC: u64 = 600; // closing idle connections passed through
+pub const TLS_CLIENT_HELLO_PEEK_INTERVAL_MSEC: u64 = 10; // waiting for the rest of a partially received ClientHello
 pub const MAX_CLIENTS: usize = 512;
 pub const MAX_CLIENTS_PER_IP: usize = 0; // 0 disables the per-IP connection limit
 pub const MAX_CONCURRENT_STREAMS: u32 = 64;
@@ -49,15 +52,36 @@ pub const MAX_CACHE_EACH_SIZE: usize = 65_535;
 // the file tier engages only when an operator raises max_cache_each_size beyond this. Worst-case
 // on-memory footprint at defaults is MAX_CACHE_ENTRY x this value (~64 MB).
 pub const MAX_CACHE_EACH_SIZE_ON_MEMORY: usize = 65_535;
+#[cfg(feature = "cache")]
+// total size in bytes of the objects on memory
+pub const MAX_CACHE_MEMORY_SIZE: u64 = 64 * 1024 * 1024;
+#[cfg(feature = "cache")]
+// total size in bytes of the cache files
+pub const MAX_CACHE_DISK_SIZE: u64 = 1024 * 1024 * 1024;
+#[cfg(feature = "cache")]
+// max # of variants per URI stored for responses with `Vary`
+pub const MAX_CACHE_VARIANTS: usize = 8;
+#[cfg(feature = "cache")]
+// seconds to serve a stale object when the upstream fails, unless the response gives `stale-if-error`
+pub const CACHE_STALE_IF_ERROR_GRACE_SEC: u64 = 0;
+#[cfg(feature = "cache")]
+// max seconds a cache miss waits for the head of the response fetched by a concurrent request for the same entry
+pub const CACHE_COALESCING_TIMEOUT_SEC: u64 = 5;
+
+/// Lifetime in seconds of CORS preflight results for gRPC-Web routes
+pub const GRPC_WEB_CORS_MAX_AGE_SEC: u64 = 86_400;
 
 #[cfg(feature = "proxy-protocol")]
 pub mod proxy_protocol {
   /// Timeout in milliseconds for receiving the PROXY protocol header (enabled with "proxy-protocol" feature).
   pub const TIMEOUT_MSEC: u64 = 50;
+  /// Idle time in seconds after which the upstream clients dedicated to a downstream client, and their
+  /// pooled connections, are dropped when sending the PROXY protocol header to upstreams.
+  pub const UPSTREAM_CLIENT_IDLE_TIMEOUT_SEC: u64 = 90;
+  /// Interval in seconds between two background sweeps of idle upstream clients.
+  pub const UPSTREAM_CLIENT_SWEEP_INTERVAL_SEC: u64 = 10;
 }
 
-// TODO: Add a total cache size ceiling; current cache limits cover entry count and per-entry size only.
-
 #[cfg(feature = "health-check")]
 /// Default health check constants
 pub mod health_check {
@@ -73,6 +97,42 @@ pub mod health_check {
   pub const DEFAULT_EXPECTED_STATUS: u16 = 200;
 }
 
+/// Prefix of an upstream `location` pointing at a Unix domain socket, e.g. `unix:/run/app.sock`.
+pub const UNIX_SOCKET_LOCATION_PREFIX: &str = "unix:";
+
+/// Delay in seconds to watch upstream files given by `upstream_file`
+pub const UPSTREAM_FILE_WATCH_DELAY_SECS: u32 = 5;
+
+/// Default upstream re-resolution constants
+pub mod upstream_resolve {
+  /// Default re-resolution interval in seconds
+  pub const DEFAULT_INTERVAL_SEC: u64 = 30;
+}
+
+/// Default stream proxy constants
+pub mod stream {
+  /// Default idle timeout in seconds of TCP stream connections
+  pub const DEFAULT_TCP_IDLE_TIMEOUT_SEC: u64 = 600;
+  /// Default idle timeout in seconds of UDP stream sessions
+  pub const DEFAULT_UDP_IDLE_TIMEOUT_SEC: u64 = 30;
+  /// Timeout in seconds for connecting to (TCP) or resolving (UDP) stream upstreams
+  pub const CONNECT_TIMEOUT_SEC: u64 = 10;
+  /// Receive buffer size for UDP datagrams, i.e., the maximum UDP payload
+  pub const UDP_MAX_DATAGRAM_SIZE: usize = 65_535;
+  /// Maximum number of datagrams of a client queued while its UDP session is being opened
+  pub const UDP_MAX_QUEUED_DATAGRAMS: usize = 16;
+}
+
+/// Admin API constants
+pub mod admin {
+  /// TCP listen backlog of the admin API
+  pub const LISTEN_BACKLOG: u32 = 16;
+  /// Timeout in seconds for reading the request header of an admin request
+  pub const HEADER_READ_TIMEOUT_SEC: u64 = 10;
+  /// Maximum size in bytes of an admin request body
+  pub const MAX_REQUEST_BODY_SIZE: usize = 4096;
+}
+
 /// Logging event names.
 ///
 /// TODO: Split access, operational, and error logs into separate targets if logging needs diverge.
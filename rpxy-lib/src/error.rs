@@ -26,6 +26,10 @@ pub enum RpxyError {
   FailedToUpdateServerCrypto(String),
   #[error("No server crypto: {0}")]
   NoServerCrypto(String),
+  #[error("No upstream available for TLS passthrough: {0}")]
+  NoTlsPassthroughUpstream(String),
+  #[error("No upstream available for stream: {0}")]
+  NoStreamUpstream(String),
 
   // hyper errors
   #[error("hyper body manipulation error: {0}")]
@@ -36,6 +40,8 @@ pub enum RpxyError {
   HyperNewBodyWriteAborted,
   #[error("Hyper error in serving request or response body type: {0}")]
   HyperBodyError(#[from] hyper::Error),
+  #[error("Invalid gRPC-Web body: {0}")]
+  InvalidGrpcWebBody(String),
 
   // http/3 errors
   #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
@@ -87,6 +93,10 @@ pub enum RpxyError {
   InvalidReverseProxyConfig,
   #[error("Invalid upstream option setting")]
   InvalidUpstreamOptionSetting,
+  #[error("Invalid upstream uri: {0}")]
+  InvalidUpstreamUri(String),
+  #[error("Upstream file watcher error: {0}")]
+  UpstreamFileWatcherError(String),
   #[error("Failed to build backend app: {0}")]
   FailedToBuildBackendApp(#[from] crate::backend::BackendAppBuilderError),
   #[cfg(feature = "sticky-cookie")]
@@ -117,12 +127,16 @@ pub enum RpxyError {
   FailedToBuildForwarder(String),
   #[error("Failed to build health check client: {0}")]
   FailedToBuildHealthCheckClient(String),
+  #[error("Invalid upstream TLS config: {0}")]
+  InvalidUpstreamTlsConfig(String),
   #[error("Failed to fetch from upstream: {0}")]
   FailedToFetchFromUpstream(String),
 
   // Upstream connection setting errors
   #[error("Unsupported upstream option")]
   UnsupportedUpstreamOption,
+  #[error("Unsupported upstream override")]
+  UnsupportedUpstreamOverride,
 
   // Cache error map
   #[cfg(feature = "cache")]
@@ -33,6 +33,24 @@ pub enum CacheError {
   #[error("Failed to remove cache file: {0}")]
   FailedToRemoveCacheFile(String),
 
+  #[error("Unusable cache file: {0}")]
+  UnusableCacheFile(String),
+
   #[error("Hash mismatched in cache file")]
   HashMismatchedInCacheFile,
+
+  #[error("Cache entry purged while being stored")]
+  PurgedWhileStoring,
+
+  #[error("Failed to read cache body: {0}")]
+  FailedToReadCacheBody(String),
+
+  #[error("Cache body not found in the storage")]
+  CacheBodyNotFound,
+
+  #[error("Cache entry deleted or replaced while being updated")]
+  CacheEntryReplaced,
+
+  #[error("Unsupported by the cache storage: {0}")]
+  UnsupportedByCacheStorage(&'static str),
 }
@@ -0,0 +1,1456 @@
+use super::{
+  cache_error::*,
+  cache_main::{
+    CACHE_STREAM_CHANNEL_CAPACITY, CacheFileOrOnMemory, CacheObject, StaleResponse, bound_ttl,
+    derive_cache_key_from_effective_uri, derive_variant_key, surrogate_keys, variant_names, vary_names,
+  },
+  cache_range::BodySegment,
+  cache_rule::{CacheRule, CacheTtl},
+};
+use crate::{hyper_ext::body::BoundedStreamBody, log::*};
+use base64::{Engine as _, engine::general_purpose};
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, channel::mpsc};
+use http::{HeaderMap, Request, Response, header, response};
+use http_body_util::{BodyExt, StreamBody};
+use http_cache_semantics::CachePolicy;
+use hyper::body::Frame;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  io::SeekFrom,
+  path::{Path, PathBuf},
+  sync::{
+    Arc, LazyLock,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+  },
+  time::{Duration, SystemTime},
+};
+use tokio::{
+  fs::{self, File, OpenOptions},
+  io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+/// File-cache read chunk size: large enough that a typical cached object is read in one or a
+/// few iterations (vs the ~64 B that `BytesMut` auto-grows per `read_buf`). Each read fills at
+/// most one chunk-sized buffer, so we never load the whole object into a single `BytesMut`
+/// (matters when `max_each_size` is configured large). This bounds the per-read buffer; how many
+/// such chunks can queue toward a slow downstream is bounded separately by
+/// `CACHE_STREAM_CHANNEL_CAPACITY`.
+const FILE_CACHE_READ_CHUNK: usize = 64 * 1024;
+
+/// Magic bytes at the beginning of a cache file. A cache file consists of the magic, the SHA256 hash
+/// of the body, the length of the metadata (u32, big endian), the metadata in JSON, and the body.
+pub(super) const CACHE_FILE_MAGIC: &[u8; 8] = b"RPXYCF01";
+/// Length of the SHA256 hash of the body in the cache file header
+const CACHE_FILE_HASH_LEN: usize = 32;
+/// Length of the fixed part of the cache file header preceding the metadata
+pub(super) const CACHE_FILE_FIXED_HEADER_LEN: usize = CACHE_FILE_MAGIC.len() + CACHE_FILE_HASH_LEN + 4;
+/// Upper bound of the metadata length, beyond which the cache file is regarded as corrupted
+const CACHE_FILE_MAX_META_LEN: usize = 1024 * 1024;
+
+/* ---------------------------------------------- */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Metadata of a cache object persisted in the header of its cache file, from which the entry and its
+/// `CachePolicy` are rebuilt when the cache is restored
+pub(super) struct CacheFileMeta {
+  /// Client-facing effective request URI, i.e., the cache key
+  pub(super) uri: String,
+  pub(super) method: String,
+  pub(super) request_headers: Vec<(String, Vec<u8>)>,
+  pub(super) status: u16,
+  pub(super) response_headers: Vec<(String, Vec<u8>)>,
+  /// Time the response was received, from which its age is computed
+  pub(super) response_time: SystemTime,
+  pub(super) surrogate_keys: Vec<String>,
+  /// Request headers keying the cache by the cache rule of the route, in addition to `Vary`
+  #[serde(default)]
+  pub(super) key_headers: Vec<String>,
+  /// Bounds of the freshness lifetime by the cache rule of the route
+  #[serde(default)]
+  pub(super) ttl: CacheTtl,
+}
+
+impl CacheFileMeta {
+  pub(super) fn new<R>(req: &Request<R>, res: &response::Parts, response_time: SystemTime) -> Self {
+    let headers = header_pairs;
+    let rule = req.extensions().get::<CacheRule>();
+    Self {
+      uri: derive_cache_key_from_effective_uri(req.uri()),
+      method: req.method().to_string(),
+      request_headers: headers(req.headers()),
+      status: res.status.as_u16(),
+      response_headers: headers(&res.headers),
+      response_time,
+      surrogate_keys: surrogate_keys(&res.headers),
+      key_headers: rule.map(|rule| rule.0.key_headers.clone()).unwrap_or_default(),
+      ttl: rule.map(CacheRule::ttl).unwrap_or_default(),
+    }
+  }
+
+  /// Metadata of the response refreshed by a `304 Not Modified` at `response_time`, with the response headers merged
+  pub(super) fn refreshed(&self, response_headers: &HeaderMap, response_time: SystemTime) -> Self {
+    Self {
+      response_headers: header_pairs(response_headers),
+      response_time,
+      surrogate_keys: surrogate_keys(response_headers),
+      ..self.clone()
+    }
+  }
+
+  /// Rebuild the request and response heads the cache policy was built from
+  fn heads(&self) -> CacheResult<(Request<()>, Response<()>)> {
+    let unusable = |e: http::Error| CacheError::UnusableCacheFile(e.to_string());
+    let req = self
+      .request_headers
+      .iter()
+      .fold(
+        Request::builder().method(self.method.as_str()).uri(self.uri.as_str()),
+        |builder, (name, value)| builder.header(name.as_str(), value.as_slice()),
+      )
+      .body(())
+      .map_err(unusable)?;
+    let res = self
+      .response_headers
+      .iter()
+      .fold(Response::builder().status(self.status), |builder, (name, value)| {
+        builder.header(name.as_str(), value.as_slice())
+      })
+      .body(())
+      .map_err(unusable)?;
+    Ok((req, res))
+  }
+
+  /// Rebuild the cache object of the response, returning it with its cache key
+  pub(super) fn cache_object(
+    &self,
+    target: CacheFileOrOnMemory,
+    hash: Bytes,
+    stale_if_error_grace: Duration,
+  ) -> CacheResult<(String, CacheObject)> {
+    let (req, res) = self.heads()?;
+    let Some(vary) = vary_names(res.headers()) else {
+      return Err(CacheError::UnusableCacheFile("Vary: *".to_string()));
+    };
+    let vary = variant_names(vary, &self.key_headers);
+    let policy = CachePolicy::new_options(&req, &res, self.response_time, Default::default());
+    let (policy, cache_control) = bound_ttl(policy, &req, res.status(), res.headers(), self.response_time, &self.ttl);
+    let stale_response = StaleResponse::new(&policy, res.status(), res.headers(), self.response_time, stale_if_error_grace);
+    let cache_key = derive_variant_key(&self.uri, &vary, req.headers());
+    let cache_object = CacheObject::new(policy, target, hash)
+      .with_surrogate_keys(self.surrogate_keys.clone())
+      .with_vary(vary)
+      .with_ttl(self.ttl, cache_control)
+      .with_stale_response(stale_response);
+    Ok((cache_key, cache_object))
+  }
+
+  /// Stale response head of the response under the cache policy, with the grace period after the upstream fails
+  pub(super) fn stale_response(&self, policy: &CachePolicy, stale_if_error_grace: Duration) -> Option<StaleResponse> {
+    let (_, res) = self.heads().ok()?;
+    StaleResponse::new(policy, res.status(), res.headers(), self.response_time, stale_if_error_grace)
+  }
+
+  /// Encode the cache file header with the hash of the body, zeroed for a temp file to be filled in on commit
+  pub(super) fn to_header(&self, hash: &[u8]) -> CacheResult<Vec<u8>> {
+    if hash.len() != CACHE_FILE_HASH_LEN {
+      return Err(CacheError::UnusableCacheFile("invalid hash".to_string()));
+    }
+    let meta = serde_json::to_vec(self).map_err(|e| CacheError::UnusableCacheFile(e.to_string()))?;
+    let mut header = Vec::with_capacity(CACHE_FILE_FIXED_HEADER_LEN + meta.len());
+    header.extend_from_slice(CACHE_FILE_MAGIC);
+    header.extend_from_slice(hash);
+    header.extend_from_slice(&(meta.len() as u32).to_be_bytes());
+    header.extend_from_slice(&meta);
+    Ok(header)
+  }
+}
+
+/// Header names and values persisted in the cache file metadata
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, Vec<u8>)> {
+  headers
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+    .collect()
+}
+
+/// Read the header of a cache file, leaving the file positioned at the beginning of the body.
+/// Returns the hash of the body and the metadata in JSON.
+async fn read_cache_file_header(file: &mut File) -> CacheResult<(Bytes, Vec<u8>)> {
+  let truncated = |_| CacheError::UnusableCacheFile("truncated header".to_string());
+  let mut fixed = [0u8; CACHE_FILE_FIXED_HEADER_LEN];
+  file.read_exact(&mut fixed).await.map_err(truncated)?;
+  let (hash, meta_len) = parse_fixed_header(&fixed)?;
+  let mut meta = vec![0u8; meta_len];
+  file.read_exact(&mut meta).await.map_err(truncated)?;
+  Ok((hash, meta))
+}
+
+/// Parse the fixed part of the cache file header, returning the hash of the body and the length of the metadata
+fn parse_fixed_header(fixed: &[u8; CACHE_FILE_FIXED_HEADER_LEN]) -> CacheResult<(Bytes, usize)> {
+  let (magic, rest) = fixed.split_at(CACHE_FILE_MAGIC.len());
+  if magic != CACHE_FILE_MAGIC {
+    return Err(CacheError::UnusableCacheFile("unknown format".to_string()));
+  }
+  let (hash, meta_len) = rest.split_at(CACHE_FILE_HASH_LEN);
+  let meta_len = u32::from_be_bytes(meta_len.try_into().unwrap_or_default()) as usize;
+  if meta_len > CACHE_FILE_MAX_META_LEN {
+    return Err(CacheError::UnusableCacheFile("too large metadata".to_string()));
+  }
+  Ok((Bytes::copy_from_slice(hash), meta_len))
+}
+
+/// Decode a cache entry of a custom storage encoded as a cache file header, whose body is held by the storage
+pub(super) fn decode_cache_entry(bytes: &[u8]) -> CacheResult<(CacheFileMeta, CacheObject)> {
+  let truncated = || CacheError::UnusableCacheFile("truncated header".to_string());
+  let (fixed, rest) = bytes
+    .split_first_chunk::<CACHE_FILE_FIXED_HEADER_LEN>()
+    .ok_or_else(truncated)?;
+  let (hash, meta_len) = parse_fixed_header(fixed)?;
+  let meta = rest.get(..meta_len).ok_or_else(truncated)?;
+  let meta = serde_json::from_slice::<CacheFileMeta>(meta).map_err(|e| CacheError::UnusableCacheFile(e.to_string()))?;
+  // The grace period of `stale-if-error` is of the instance serving the entry, applied once got from the storage
+  let (_, cache_object) = meta.cache_object(CacheFileOrOnMemory::External, hash, Duration::ZERO)?;
+  Ok((meta, cache_object))
+}
+
+/// Read the header of a cache file left in the cache directory, verifying its freshness and size, and build its
+/// cache object. Returns the cache key, the object and the time the response was received. The body is not read
+/// here; its hash is verified when the file is served, discarding it on mismatch.
+pub(super) async fn restore_cache_file(
+  path: &Path,
+  max_each_size: usize,
+  stale_if_error_grace: Duration,
+  now: SystemTime,
+) -> CacheResult<(String, CacheObject, SystemTime)> {
+  if path.extension().is_some_and(|ext| ext == "tmp") {
+    return Err(CacheError::UnusableCacheFile("temp file of an interrupted store".to_string()));
+  }
+  let mut file = File::open(path).await.map_err(|_| CacheError::FailedToOpenCacheFile)?;
+  let (hash, meta_bytes) = read_cache_file_header(&mut file).await?;
+  let meta = serde_json::from_slice::<CacheFileMeta>(&meta_bytes).map_err(|e| CacheError::UnusableCacheFile(e.to_string()))?;
+  let target = CacheFileOrOnMemory::File(path.to_path_buf());
+  let (cache_key, cache_object) = meta.cache_object(target, hash.clone(), stale_if_error_grace)?;
+  let still_usable = cache_object.stale_response.as_ref().is_some_and(|stale| stale.is_usable(now))
+    || meta
+      .response_headers
+      .iter()
+      .any(|(name, _)| name == header::ETAG.as_str() || name == header::LAST_MODIFIED.as_str());
+  if !cache_object.policy.is_storable() || (cache_object.policy.is_stale(now) && !still_usable) {
+    return Err(CacheError::UnusableCacheFile("stale response".to_string()));
+  }
+
+  let file_size = file
+    .metadata()
+    .await
+    .map_err(|e| CacheError::UnusableCacheFile(e.to_string()))?
+    .len();
+  let body_size = file_size.saturating_sub((CACHE_FILE_FIXED_HEADER_LEN + meta_bytes.len()) as u64);
+  if body_size > max_each_size as u64 {
+    return Err(CacheError::UnusableCacheFile("exceeds max_cache_each_size".to_string()));
+  }
+  Ok((cache_key, cache_object.with_size(file_size), meta.response_time))
+}
+
+/// Time of the first restore of the cache dir, i.e., the startup of the process before any store.
+pub(super) static CACHE_RESTORE_START: LazyLock<SystemTime> = LazyLock::new(SystemTime::now);
+
+/// Whether a temp file in the cache dir may be of an in-flight store of this process, i.e., named by
+/// `unique_cache_paths` with this process id after the startup. Other temp files are of interrupted stores.
+pub(super) fn is_temp_file_of_this_process(path: &Path) -> bool {
+  let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+    return false;
+  };
+  let mut parts = stem.rsplitn(4, '-');
+  let (Some(_seq), Some(nanos), Some(pid)) = (parts.next(), parts.next(), parts.next()) else {
+    return false;
+  };
+  let (Ok(nanos), Ok(pid)) = (nanos.parse::<u64>(), pid.parse::<u32>()) else {
+    return false;
+  };
+  let created = std::time::UNIX_EPOCH + Duration::from_nanos(nanos);
+  pid == std::process::id() && created >= *CACHE_RESTORE_START
+}
+
+/* ---------------------------------------------- */
+/// Monotonic counter making temp/final cache file names process-unique (see `unique_cache_paths`).
+static CACHE_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Build a `(temp, final)` path pair with a process-unique name in `cache_dir`. The final name is
+/// generation-unique - not merely URI-derived - so concurrent stores of the same URI never collide
+/// or clobber each other's file; each cache entry references its own immutable file. The
+/// URI-derived prefix is kept only for human debuggability.
+pub(super) fn unique_cache_paths(cache_dir: &Path, uri: &str) -> (PathBuf, PathBuf) {
+  let base = derive_filename_from_uri(uri);
+  let nanos = SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|d| d.as_nanos())
+    .unwrap_or(0);
+  let seq = CACHE_FILE_SEQ.fetch_add(1, Ordering::Relaxed);
+  let unique = format!("{base}-{}-{nanos}-{seq}", std::process::id());
+  let final_path = cache_dir.join(&unique);
+  let temp_path = cache_dir.join(format!("{unique}.tmp"));
+  (temp_path, final_path)
+}
+
+/// Unlink a cache file WITHOUT adjusting the file-store count. A missing file is ignored. Used
+/// wherever the count must not change: temp files that never reached commit (never counted), and
+/// the integrity-check (hash mismatch) removal, where the file IS counted but its LRU metadata
+/// still exists - there the count is reconciled later, when that metadata is evicted via the
+/// counted `FileStore::evict`/`remove` (which tolerates the already-missing file). Counted files
+/// whose metadata is already gone go through `FileStore::evict` directly instead.
+pub(super) async fn remove_uncounted_file(path: &Path) {
+  if let Err(e) = fs::remove_file(path).await
+    && e.kind() != std::io::ErrorKind::NotFound
+  {
+    warn!("Failed to remove uncommitted cache file {path:?}: {e}");
+  }
+}
+
+/// An in-progress file-cache write: data is appended to a temp file starting with the cache file
+/// header, whose hash is filled in before the file is atomically renamed to its final path on `commit`. The file-store count is intentionally NOT touched here; it is bumped
+/// by `publish_cache_object` (just before publishing the metadata).
+pub(super) struct SpillFile {
+  file: File,
+  temp_path: PathBuf,
+  final_path: PathBuf,
+}
+
+impl SpillFile {
+  /// Create a fresh temp file with a generation-unique name in `cache_dir`, and write the header of
+  /// `meta` to it. `create_new(true)` refuses to follow or overwrite an existing file/symlink.
+  pub(super) async fn create(cache_dir: &Path, meta: &CacheFileMeta) -> CacheResult<Self> {
+    let header = meta.to_header(&[0u8; CACHE_FILE_HASH_LEN])?;
+    let (temp_path, final_path) = unique_cache_paths(cache_dir, &meta.uri);
+    let file = OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .open(&temp_path)
+      .await
+      .map_err(|e| {
+        error!("Failed to create temp cache file {temp_path:?}: {e}");
+        CacheError::FailedToCreateFileCache
+      })?;
+    let mut spill = Self {
+      file,
+      temp_path,
+      final_path,
+    };
+    if let Err(e) = spill.write(&header).await {
+      spill.abort().await;
+      return Err(e);
+    }
+    Ok(spill)
+  }
+
+  /// Append `data` to the temp file.
+  pub(super) async fn write(&mut self, data: &[u8]) -> CacheResult<()> {
+    self.file.write_all(data).await.map_err(|e| {
+      error!("Failed to write temp cache file {:?}: {e}", self.temp_path);
+      CacheError::FailedToWriteFileCache
+    })
+  }
+
+  /// Fill in the hash of the body, then flush and atomically rename the temp file to its final path,
+  /// returning that path. On any failure the temp file is removed and an error is returned.
+  pub(super) async fn commit(self, hash: &[u8]) -> CacheResult<PathBuf> {
+    let SpillFile {
+      mut file,
+      temp_path,
+      final_path,
+    } = self;
+    let res = async {
+      file.flush().await?;
+      file.seek(SeekFrom::Start(CACHE_FILE_MAGIC.len() as u64)).await?;
+      file.write_all(hash).await?;
+      file.flush().await
+    };
+    if let Err(e) = res.await {
+      error!("Failed to flush temp cache file {temp_path:?}: {e}");
+      drop(file);
+      remove_uncounted_file(&temp_path).await;
+      return Err(CacheError::FailedToWriteFileCache);
+    }
+    drop(file); // close the handle before renaming
+    if let Err(e) = fs::rename(&temp_path, &final_path).await {
+      error!("Failed to rename cache file {temp_path:?} -> {final_path:?}: {e}");
+      remove_uncounted_file(&temp_path).await;
+      return Err(CacheError::FailedToRenameCacheFile);
+    }
+    Ok(final_path)
+  }
+
+  /// Discard the in-progress temp file (close + unlink). Does not touch the file-store count.
+  pub(super) async fn abort(self) {
+    let SpillFile { file, temp_path, .. } = self;
+    drop(file);
+    remove_uncounted_file(&temp_path).await;
+  }
+}
+
+/// Forward `body` downstream frame by frame while attempting to cache it.
+///
+/// Hard invariant: a cache-side failure - too-large body, upstream body error, or any file I/O
+/// failure - must NEVER cut the downstream relay. Every frame is forwarded first; caching is then
+/// attempted as a side effect and silently abandoned (cleaning up any temp file) on failure.
+///
+/// `body_tx` is bounded, so forwarding awaits a free slot when the downstream consumer lags:
+/// backpressure pauses the relay (and, transitively, the upstream read and the store) instead of
+/// queueing frames in memory without bound. Pausing is not cutting - the send fails only when the
+/// receiver is dropped, exactly the case the relay has nothing left to forward to.
+///
+/// Returns `Some((target, hash))` when the object was fully and successfully stored (on memory, or
+/// streamed to a committed file), `None` otherwise. `body_tx` is taken by value and dropped on
+/// return, so `body_rx` reaches a clean EOF as soon as streaming finishes.
+pub(super) async fn spool_and_store<B, E>(
+  mut body: B,
+  mut body_tx: mpsc::Sender<Result<Frame<Bytes>, E>>,
+  max_each_size: usize,
+  max_each_size_on_memory: usize,
+  cache_dir: &Path,
+  meta: &CacheFileMeta,
+) -> Option<(CacheFileOrOnMemory, Bytes)>
+where
+  B: hyper::body::Body<Data = Bytes, Error = E> + Unpin,
+{
+  let mut hasher = Sha256::new();
+  let mut buf = BytesMut::new(); // Phase M: in-memory buffer until the on-memory threshold
+  let mut size: usize = 0;
+  let mut cacheable = true;
+  let mut spill: Option<SpillFile> = None; // Phase F: present once spilled to a temp file
+
+  while let Some(frame) = body.frame().await {
+    // Take the cache-side data handle before the frame is moved into the send. `Bytes` is
+    // reference-counted, so this is a cheap Arc bump, not a body copy; `None` for an error item or
+    // a non-data frame (e.g. trailers).
+    let data = frame.as_ref().ok().and_then(|f| f.data_ref().cloned());
+    let was_err = frame.is_err();
+
+    // Forward downstream first; the relay is never cut by cache work. The bounded send awaits a
+    // free slot when the consumer lags (backpressure) and errs only on a dropped receiver.
+    if body_tx.send(frame).await.is_err() {
+      // Downstream receiver is gone; nothing left to forward or cache.
+      if let Some(s) = spill.take() {
+        s.abort().await;
+      }
+      return None;
+    }
+
+    if !cacheable {
+      continue; // keep draining/forwarding, but no longer caching
+    }
+
+    // Upstream body error: a complete object cannot be cached. The error frame was already
+    // forwarded above so the downstream consumer observes it instead of a silent EOF.
+    if was_err {
+      cacheable = false;
+      if let Some(s) = spill.take() {
+        s.abort().await;
+      }
+      buf = BytesMut::new();
+      continue;
+    }
+
+    let Some(data) = data else {
+      continue; // non-data frame: forward only
+    };
+
+    // `saturating_add` keeps the size check correct even against a pathologically large frame
+    // length, so the limit can never be bypassed by integer overflow.
+    if size.saturating_add(data.len()) > max_each_size {
+      debug!("Response exceeds max_each_size ({max_each_size} bytes); forwarding without caching");
+      cacheable = false;
+      if let Some(s) = spill.take() {
+        s.abort().await;
+      }
+      buf = BytesMut::new();
+      continue;
+    }
+    size = size.saturating_add(data.len());
+    hasher.update(data.as_ref());
+
+    if spill.is_some() {
+      // Phase F: write straight to the temp file.
+      if spill.as_mut().unwrap().write(data.as_ref()).await.is_err() {
+        cacheable = false;
+        spill.take().unwrap().abort().await;
+      }
+    } else if buf.len().saturating_add(data.len()) > max_each_size_on_memory {
+      // Phase M crossing the on-memory threshold: spill to a temp file. Write the already-buffered
+      // bytes and this frame straight to disk rather than first growing `buf` by a potentially
+      // large frame, which would defeat the point of bounding store-path memory.
+      match SpillFile::create(cache_dir, meta).await {
+        Ok(mut s) => {
+          if s.write(buf.as_ref()).await.is_err() || s.write(data.as_ref()).await.is_err() {
+            cacheable = false;
+            s.abort().await;
+          } else {
+            spill = Some(s);
+          }
+        }
+        Err(_) => {
+          // Could not create a temp file; give up caching but keep forwarding.
+          cacheable = false;
+        }
+      }
+      buf = BytesMut::new(); // free the in-memory copy regardless of spill outcome
+    } else {
+      // Phase M still under the on-memory threshold: keep buffering on memory.
+      buf.extend_from_slice(data.as_ref());
+    }
+  }
+
+  if !cacheable {
+    return None; // any temp file was already aborted above
+  }
+
+  let hash = Bytes::copy_from_slice(hasher.finalize().as_ref());
+  match spill {
+    // Phase F: commit the temp file to its final path.
+    Some(s) => match s.commit(&hash).await {
+      Ok(final_path) => Some((CacheFileOrOnMemory::File(final_path), hash)),
+      Err(_) => None, // commit failed and cleaned up its temp; nothing to publish
+    },
+    // Phase M: small enough to stay on memory.
+    None => Some((CacheFileOrOnMemory::OnMemory(buf.freeze()), hash)),
+  }
+}
+
+/* ---------------------------------------------- */
+#[derive(Debug, Clone)]
+/// Cache file manager. Lock-free by design: committed cache files are immutable and live at
+/// generation-unique paths, so the only shared mutable state is the best-effort count of
+/// committed file-cache objects. Keeping that count in an atomic (instead of a lock held across
+/// file I/O) means a store's publish can never queue behind another task's unlink or open -
+/// under sustained store-and-evict churn a single slow unlink previously serialized every
+/// in-flight publish behind one exclusive lock, stalling publication entirely while
+/// committed-but-unpublished files accumulated on disk without bound.
+pub(super) struct FileStore {
+  /// Approximate count of committed file-cache objects (best-effort by design; see `count`).
+  cnt: Arc<AtomicUsize>,
+  /// Async runtime
+  runtime_handle: tokio::runtime::Handle,
+}
+
+impl FileStore {
+  /// Build manager
+  pub(super) async fn new(runtime_handle: &tokio::runtime::Handle) -> Self {
+    Self {
+      cnt: Arc::new(AtomicUsize::new(0)),
+      runtime_handle: runtime_handle.clone(),
+    }
+  }
+
+  /// Count file cache entries
+  pub(super) async fn count(&self) -> usize {
+    self.cnt.load(Ordering::Relaxed)
+  }
+
+  /// Account for a newly committed file-cache object whose file is already renamed into place.
+  /// Must be called BEFORE the corresponding metadata is published into the LRU, so that a visible
+  /// File entry is always already counted (see `publish_cache_object`). That invariant is an
+  /// ordering property, not a mutual-exclusion one, so a plain atomic increment upholds it.
+  pub(super) async fn incr_count(&self) {
+    self.cnt.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Evict a counted file cache object, logs warning if removal fails
+  pub(super) async fn evict(&self, path: impl AsRef<Path>) {
+    if let Err(e) = self.remove(path).await {
+      warn!("Eviction failed during file object removal: {:?}", e);
+    }
+  }
+
+  /// Remove a counted file-cache object.
+  ///
+  /// The count is decremented **regardless of whether the unlink succeeds**: the caller has decided
+  /// to evict this counted file (its LRU metadata is already gone), so it is no longer a live counted
+  /// object even if the file was already removed externally or by the integrity-check path. Only a
+  /// genuine I/O error (not "already gone") is surfaced. Otherwise the file count leaks above the
+  /// number of live entries. No lock is held across the unlink: the file is immutable at a
+  /// generation-unique path, so the I/O needs no exclusion and concurrent removals proceed in
+  /// parallel instead of queueing publishers behind one another.
+  pub(super) async fn remove(&self, path: impl AsRef<Path>) -> CacheResult<()> {
+    // Saturate rather than underflow: the count and the LRU are updated independently, so a
+    // pathological concurrent ordering could otherwise drive a `usize` below zero (wraparound).
+    // `incr_count`-before-publish makes this unreachable in practice.
+    let _ = self
+      .cnt
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(c.saturating_sub(1)));
+    debug!(
+      "Removed a cache file at {:?} (file count: {})",
+      path.as_ref(),
+      self.cnt.load(Ordering::Relaxed)
+    );
+
+    match fs::remove_file(path.as_ref()).await {
+      Ok(()) => {}
+      // Already gone (e.g. removed externally or by the integrity-check path); the count correction
+      // above still stands, so this is not an error.
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+      Err(e) => return Err(CacheError::FailedToRemoveCacheFile(e.to_string())),
+    }
+
+    Ok(())
+  }
+
+  /// Read a stored file-cache object, returns error if the file cannot be opened. The integrity
+  /// hash is verified incrementally by the producer task and acted on at EOF.
+  pub(super) async fn read(
+    &self,
+    path: impl AsRef<Path> + Send + Sync + 'static,
+    hash: &Bytes,
+  ) -> CacheResult<BoundedStreamBody> {
+    let Ok(mut file) = File::open(&path).await else {
+      warn!("Cache file object cannot be opened");
+      return Err(CacheError::FailedToOpenCacheFile);
+    };
+    if let Err(e) = read_cache_file_header(&mut file).await {
+      warn!("Cache file object cannot be read: {e}");
+      return Err(e);
+    }
+    let hash_clone = hash.clone();
+
+    let (mut body_tx, body_rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(CACHE_STREAM_CHANNEL_CAPACITY);
+
+    self.runtime_handle.spawn(async move {
+      let mut hasher = Sha256::new();
+      let mut buf = BytesMut::with_capacity(FILE_CACHE_READ_CHUNK);
+      loop {
+        // Reserve a fresh chunk only when the spare capacity is exhausted. After `split()` the
+        // buffer keeps whatever spare it had, so a small object is read into the initial
+        // capacity and the EOF-confirming read reuses the leftover spare without allocating.
+        if buf.capacity() == buf.len() {
+          buf.reserve(FILE_CACHE_READ_CHUNK);
+        }
+        match file.read_buf(&mut buf).await {
+          Ok(0) => break,
+          Ok(_) => {
+            // Hand the filled bytes off zero-copy; `buf` keeps the remaining spare capacity.
+            // The bounded send awaits a free slot when the consumer lags, so a slow client
+            // paces the file read instead of queueing the whole object in memory. It errs only
+            // when the receiver is dropped; the early return then skips the integrity check
+            // below (an incomplete hash proves nothing) and leaves the file in place.
+            let bytes = buf.split().freeze();
+            hasher.update(bytes.as_ref());
+            body_tx
+              .send(Ok(Frame::data(bytes)))
+              .await
+              .map_err(|e| CacheError::FailedToSendFrameFromCache(e.to_string()))?
+          }
+          Err(_) => break,
+        };
+      }
+      let hash_bytes = Bytes::copy_from_slice(hasher.finalize().as_ref());
+      if hash_bytes != hash_clone {
+        warn!("Hash mismatched. Cache object is corrupted. Force to remove the cache file.");
+        // Unlink WITHOUT touching the count. The LRU entry pointing at this file still exists;
+        // the count is reconciled when that entry is evicted through the metadata path, whose
+        // counted removal tolerates the already-missing file. A counted removal here would
+        // decrement twice for one object. (This matches the previous behavior, where this path
+        // operated on a clone holding a copied counter.)
+        remove_uncounted_file(path.as_ref()).await;
+        return Err(CacheError::HashMismatchedInCacheFile);
+      }
+      Ok(()) as CacheResult<()>
+    });
+
+    let stream_body = StreamBody::new(body_rx);
+
+    Ok(stream_body)
+  }
+
+  /// Read the metadata in the header of a stored file-cache object
+  pub(super) async fn read_meta(&self, path: impl AsRef<Path>) -> CacheResult<CacheFileMeta> {
+    let Ok(mut file) = File::open(&path).await else {
+      warn!("Cache file object cannot be opened");
+      return Err(CacheError::FailedToOpenCacheFile);
+    };
+    let (_, meta) = read_cache_file_header(&mut file).await?;
+    serde_json::from_slice::<CacheFileMeta>(&meta).map_err(|e| CacheError::UnusableCacheFile(e.to_string()))
+  }
+
+  /// Copy the body of a stored file-cache object to a new cache file headed by `meta`, returning the path and
+  /// the size of the new file. The new file is not counted; it takes over the count of the original one, which
+  /// is to be removed uncounted once the new one replaces it in the index.
+  pub(super) async fn rewrite_header(
+    &self,
+    cache_dir: &Path,
+    path: impl AsRef<Path>,
+    meta: &CacheFileMeta,
+    hash: &[u8],
+  ) -> CacheResult<(PathBuf, u64)> {
+    let (file, _, body_len) = self.open_body(path).await?;
+    let mut spill = SpillFile::create(cache_dir, meta).await?;
+    if let Err(e) = tokio::io::copy(&mut file.take(body_len), &mut spill.file).await {
+      error!("Failed to copy the body to temp cache file {:?}: {e}", spill.temp_path);
+      spill.abort().await;
+      return Err(CacheError::FailedToWriteFileCache);
+    }
+    let new_path = spill.commit(hash).await?;
+    let size = fs::metadata(&new_path).await.map(|m| m.len()).unwrap_or_default();
+    Ok((new_path, size))
+  }
+
+  /// Open a stored file-cache object, returning the file with the offset and the length of the body
+  pub(super) async fn open_body(&self, path: impl AsRef<Path>) -> CacheResult<(File, u64, u64)> {
+    let Ok(mut file) = File::open(&path).await else {
+      warn!("Cache file object cannot be opened");
+      return Err(CacheError::FailedToOpenCacheFile);
+    };
+    let (_, meta) = read_cache_file_header(&mut file).await?;
+    let body_start = (CACHE_FILE_FIXED_HEADER_LEN + meta.len()) as u64;
+    let file_len = file
+      .metadata()
+      .await
+      .map_err(|e| CacheError::UnusableCacheFile(e.to_string()))?
+      .len();
+    Ok((file, body_start, file_len.saturating_sub(body_start)))
+  }
+
+  /// Read the segments of the body of a file-cache object opened by `open_body`. The stream ends early if
+  /// the file turns out to be shorter than the ranges.
+  pub(super) fn read_segments(&self, mut file: File, body_start: u64, segments: Vec<BodySegment>) -> BoundedStreamBody {
+    let (mut body_tx, body_rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(CACHE_STREAM_CHANNEL_CAPACITY);
+
+    self.runtime_handle.spawn(async move {
+      let send_err = |e: mpsc::SendError| CacheError::FailedToSendFrameFromCache(e.to_string());
+      let read_err = |e: std::io::Error| CacheError::UnusableCacheFile(e.to_string());
+      for segment in segments {
+        let (first, last) = match segment {
+          BodySegment::Literal(bytes) => {
+            body_tx.send(Ok(Frame::data(bytes))).await.map_err(send_err)?;
+            continue;
+          }
+          BodySegment::Range(first, last) => (first, last),
+        };
+        file.seek(SeekFrom::Start(body_start + first)).await.map_err(read_err)?;
+        let mut remaining = last - first + 1;
+        while remaining > 0 {
+          let mut buf = BytesMut::zeroed(remaining.min(FILE_CACHE_READ_CHUNK as u64) as usize);
+          if let Err(e) = file.read_exact(&mut buf).await {
+            warn!("Cache file object is shorter than the requested range: {e}");
+            return Err(read_err(e));
+          }
+          remaining -= buf.len() as u64;
+          body_tx.send(Ok(Frame::data(buf.freeze()))).await.map_err(send_err)?;
+        }
+      }
+      Ok(()) as CacheResult<()>
+    });
+
+    StreamBody::new(body_rx)
+  }
+}
+
+fn derive_filename_from_uri(uri: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(uri);
+  let digest = hasher.finalize();
+  general_purpose::URL_SAFE_NO_PAD.encode(digest)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+  use super::*;
+  use crate::forwarder::cache::cache_main::get_policy_if_cacheable;
+  use futures::{StreamExt, stream};
+  use http::Uri;
+  use std::{
+    pin::Pin,
+    task::{Context, Poll},
+  };
+
+  /// NOTE: the relay channels are bounded. A test that runs the producer to completion BEFORE
+  /// draining the receiver (the common pattern below) deadlocks once a body has more frames than
+  /// the channel capacity, so these tests create channels with a capacity comfortably above any
+  /// test body (16) - except the backpressure tests, which exercise the bound itself and drain
+  /// concurrently.
+  pub(crate) const TEST_CHANNEL_CAPACITY: usize = 16;
+
+  /// Build an `Ok` data frame from a static byte slice.
+  fn data_frame(bytes: &'static [u8]) -> Result<Frame<Bytes>, hyper::Error> {
+    Ok(Frame::data(Bytes::from_static(bytes)))
+  }
+
+  /// Build a test body from a list of frames. Only `Ok` frames are constructed, so no
+  /// `hyper::Error` needs to be built.
+  fn body_from(
+    frames: Vec<Result<Frame<Bytes>, hyper::Error>>,
+  ) -> impl hyper::body::Body<Data = Bytes, Error = hyper::Error> + Unpin {
+    StreamBody::new(stream::iter(frames))
+  }
+
+  /// Concatenate the data bytes of all forwarded frames in order.
+  fn forwarded_data(frames: Vec<Result<Frame<Bytes>, hyper::Error>>) -> Vec<u8> {
+    frames
+      .into_iter()
+      .filter_map(|f| f.ok())
+      .filter_map(|f| f.into_data().ok())
+      .flat_map(|b| b.to_vec())
+      .collect()
+  }
+
+  /// Total number of data bytes across forwarded frames (works for any error type).
+  fn forwarded_len<E>(frames: Vec<Result<Frame<Bytes>, E>>) -> usize {
+    frames
+      .into_iter()
+      .filter_map(|f| f.ok())
+      .filter_map(|f| f.into_data().ok())
+      .map(|b| b.len())
+      .sum()
+  }
+
+  /// Drive the store path for a body that must stay on memory (threshold = `usize::MAX`, so it
+  /// never spills to a file) and return the stored bytes if cacheable. Mirrors the old
+  /// `spool_body` return shape for the existing on-memory tests.
+  async fn store_on_memory<B, E>(body: B, body_tx: mpsc::Sender<Result<Frame<Bytes>, E>>, max_each_size: usize) -> Option<Bytes>
+  where
+    B: hyper::body::Body<Data = Bytes, Error = E> + Unpin,
+  {
+    let uri: Uri = "http://example.com/onmem".parse().unwrap();
+    spool_and_store(
+      body,
+      body_tx,
+      max_each_size,
+      usize::MAX,
+      &std::env::temp_dir(),
+      &fresh_meta(&uri),
+    )
+    .await
+    .map(|(target, _hash)| match target {
+      CacheFileOrOnMemory::OnMemory(bytes) => bytes,
+      CacheFileOrOnMemory::File(_) | CacheFileOrOnMemory::External => {
+        unreachable!("usize::MAX on-memory threshold never spills")
+      }
+    })
+  }
+
+  /// Unique, freshly created temp directory for file-cache store tests.
+  pub(crate) async fn temp_cache_dir(tag: &str) -> PathBuf {
+    let dir = temp_cache_path(tag);
+    fs::create_dir_all(&dir).await.unwrap();
+    dir
+  }
+
+  /// A fresh, storable cache policy for `uri` (so the freshness gate passes).
+  pub(crate) fn fresh_policy(uri: &Uri) -> CachePolicy {
+    let req = Request::builder().uri(uri.clone()).body(()).unwrap();
+    let res = Response::builder()
+      .header("cache-control", "public, max-age=3600")
+      .body(())
+      .unwrap();
+    get_policy_if_cacheable(Some(&req), Some(&res)).unwrap().unwrap()
+  }
+
+  /// Metadata of a fresh, storable response for `uri`, as persisted in cache files.
+  pub(crate) fn fresh_meta(uri: &Uri) -> CacheFileMeta {
+    let req = Request::builder().uri(uri.clone()).body(()).unwrap();
+    let (res, _) = Response::builder()
+      .header("cache-control", "public, max-age=3600")
+      .body(())
+      .unwrap()
+      .into_parts();
+    CacheFileMeta::new(&req, &res, SystemTime::now())
+  }
+
+  /// Content of a committed cache file of `body` with the metadata.
+  pub(crate) fn cache_file_bytes(meta: &CacheFileMeta, body: &[u8]) -> Vec<u8> {
+    let mut bytes = meta.to_header(&[0u8; CACHE_FILE_HASH_LEN]).unwrap();
+    bytes[CACHE_FILE_MAGIC.len()..CACHE_FILE_MAGIC.len() + CACHE_FILE_HASH_LEN].copy_from_slice(Sha256::digest(body).as_ref());
+    bytes.extend_from_slice(body);
+    bytes
+  }
+
+  /// Write a committed cache file of `body` with the metadata into `dir`.
+  pub(crate) async fn write_cache_file(dir: &Path, meta: &CacheFileMeta, body: &[u8]) -> PathBuf {
+    let (_, path) = unique_cache_paths(dir, &meta.uri);
+    fs::write(&path, cache_file_bytes(meta, body)).await.unwrap();
+    path
+  }
+
+  /// In-memory `FileStore` for store-path tests (no dir cleanup at construction).
+  pub(crate) fn test_file_store() -> FileStore {
+    FileStore {
+      cnt: Arc::new(AtomicUsize::new(0)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    }
+  }
+
+  #[tokio::test]
+  async fn within_limit_caches_and_forwards_all() {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+    let body = body_from(vec![data_frame(b"hello"), data_frame(b"world")]);
+    let cached = store_on_memory(body, tx, 1024).await;
+    assert_eq!(cached.as_deref(), Some(&b"helloworld"[..]));
+    assert_eq!(forwarded_data(rx.collect::<Vec<_>>().await), b"helloworld");
+  }
+
+  /// Regression test for the truncation bug: an over-limit cacheable response must still be
+  /// forwarded to the client in full, just not cached.
+  #[tokio::test]
+  async fn over_limit_forwards_all_but_does_not_cache() {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+    // three 5-byte frames = 15 bytes total, over the 8-byte limit
+    let body = body_from(vec![data_frame(b"aaaaa"), data_frame(b"bbbbb"), data_frame(b"ccccc")]);
+    let cached = store_on_memory(body, tx, 8).await;
+    assert!(cached.is_none(), "over-limit object must not be cached");
+    assert_eq!(
+      forwarded_data(rx.collect::<Vec<_>>().await),
+      b"aaaaabbbbbccccc",
+      "all frames must be forwarded, not truncated"
+    );
+  }
+
+  #[tokio::test]
+  async fn boundary_exactly_max_is_cached() {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+    // 5 + 3 = 8 == limit (matches the original `size > max_each_size` boundary)
+    let body = body_from(vec![data_frame(b"aaaaa"), data_frame(b"bbb")]);
+    let cached = store_on_memory(body, tx, 8).await;
+    assert_eq!(cached.as_deref(), Some(&b"aaaaabbb"[..]));
+    assert_eq!(forwarded_data(rx.collect::<Vec<_>>().await), b"aaaaabbb");
+  }
+
+  /// This only pins down trailer *forwarding* and that `buf` holds data bytes only. It does
+  /// not assert that trailer-bearing responses are cacheable as a spec (a cache hit does not
+  /// reproduce trailers); that is pre-existing behaviour and out of scope (design doc 3/8).
+  #[tokio::test]
+  async fn forwards_trailers_without_buffering_them() {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("x-trailer", http::HeaderValue::from_static("v"));
+    let body = body_from(vec![data_frame(b"data"), Ok(Frame::trailers(trailers))]);
+    let cached = store_on_memory(body, tx, 1024).await;
+    assert_eq!(cached.as_deref(), Some(&b"data"[..]));
+    let forwarded = rx.collect::<Vec<_>>().await;
+    assert_eq!(forwarded.len(), 2);
+    assert!(forwarded[1].as_ref().unwrap().is_trailers());
+  }
+
+  #[tokio::test]
+  async fn returns_none_when_downstream_dropped() {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+    drop(rx);
+    let body = body_from(vec![data_frame(b"a"), data_frame(b"b")]);
+    let cached = store_on_memory(body, tx, 1024).await;
+    assert!(cached.is_none());
+  }
+
+  /// Stand-in body error type. `hyper::Error` has no public constructor, so the error path is
+  /// exercised with a body whose `Error` is this type; `spool_and_store` is generic over the error.
+  #[derive(Debug)]
+  pub(crate) struct TestBodyError;
+
+  /// Regression test for the upstream-error path: an error frame must be propagated downstream
+  /// (not masked as a clean EOF), and the object must not be cached.
+  #[tokio::test]
+  async fn upstream_error_is_propagated_and_not_cached() {
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> =
+      vec![Ok(Frame::data(Bytes::from_static(b"partial"))), Err(TestBodyError)];
+    let body = StreamBody::new(stream::iter(frames));
+    let cached = store_on_memory(body, tx, 1024).await;
+    assert!(cached.is_none(), "an errored upstream body must not be cached");
+    let forwarded = rx.collect::<Vec<_>>().await;
+    assert_eq!(forwarded.len(), 2, "the data frame and the error frame are both forwarded");
+    assert!(forwarded[0].is_ok());
+    assert!(forwarded[1].is_err(), "the upstream error must be propagated downstream");
+  }
+
+  /// Unique temp path for a file-cache test object.
+  pub(crate) fn temp_cache_path(tag: &str) -> PathBuf {
+    let nanos = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+    std::env::temp_dir().join(format!("rpxy-cache-test-{tag}-{}-{nanos}", std::process::id()))
+  }
+
+  /// A cached file larger than `FILE_CACHE_READ_CHUNK` must be streamed back intact, exercising
+  /// the multi-chunk read path. Guards correct reassembly across chunk boundaries.
+  #[tokio::test]
+  async fn file_store_read_streams_object_across_chunks() {
+    let path = temp_cache_path("ok");
+    // ~200 KB so the read spans several FILE_CACHE_READ_CHUNK (64 KiB) iterations.
+    let content: Vec<u8> = (0..200_000usize).map(|i| (i % 251) as u8).collect();
+    fs::write(
+      &path,
+      cache_file_bytes(&fresh_meta(&Uri::from_static("http://example.com/file")), &content),
+    )
+    .await
+    .unwrap();
+    let hash = Bytes::copy_from_slice(Sha256::digest(&content).as_ref());
+
+    let file_store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(0)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    let body = file_store.read(path.clone(), &hash).await.unwrap();
+    let got = BodyExt::collect(body).await.unwrap().to_bytes();
+    assert_eq!(got.as_ref(), content.as_slice());
+    // The happy path leaves the file in place.
+    assert!(fs::metadata(&path).await.is_ok());
+    let _ = fs::remove_file(&path).await;
+  }
+
+  /// On a hash mismatch the file is evicted. `read()` returns the stream immediately and the
+  /// integrity check + removal run at the end of the spawned task, so the stream is drained to
+  /// EOF before asserting the file is gone (draining to EOF implies the task finished, since it
+  /// awaits the removal before dropping the sender that closes the stream).
+  #[tokio::test]
+  async fn file_store_read_evicts_on_hash_mismatch() {
+    let path = temp_cache_path("bad");
+    let content = b"some cached bytes".to_vec();
+    fs::write(
+      &path,
+      cache_file_bytes(&fresh_meta(&Uri::from_static("http://example.com/file")), &content),
+    )
+    .await
+    .unwrap();
+    let wrong_hash = Bytes::from_static(&[0u8; 32]);
+
+    let file_store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(1)), // the entry for this file is still counted
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    let body = file_store.read(path.clone(), &wrong_hash).await.unwrap();
+    let _ = BodyExt::collect(body).await; // drain to EOF; data frames are all Ok, the mismatch is internal
+    assert!(
+      fs::metadata(&path).await.is_err(),
+      "a corrupted cache file must be removed on hash mismatch"
+    );
+    // The mismatch path unlinks WITHOUT decrementing: the LRU entry still exists and the count
+    // is reconciled when that entry is evicted through the metadata path (no double decrement).
+    assert_eq!(
+      file_store.count().await,
+      1,
+      "the integrity-check removal must not touch the count"
+    );
+  }
+
+  /// A body larger than the on-memory threshold spills to a file; the committed file is renamed
+  /// into `cache_dir`, the whole body is forwarded downstream, and reading the file back with the
+  /// returned hash succeeds (proving the incremental hash matches a one-shot hash of the bytes).
+  #[tokio::test]
+  async fn store_spills_large_object_to_file_and_round_trips() {
+    let dir = temp_cache_dir("spill").await;
+    let uri: Uri = "http://example.com/big".parse().unwrap();
+    // 5000 + 5000 = 10000 bytes, well over the 4096 on-memory threshold and under max_each_size.
+    let chunk = vec![7u8; 5000];
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![
+      Ok(Frame::data(Bytes::from(chunk.clone()))),
+      Ok(Frame::data(Bytes::from(chunk.clone()))),
+    ];
+    let body = StreamBody::new(stream::iter(frames));
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+
+    let (target, hash) = spool_and_store(body, tx, 1_000_000, 4096, &dir, &fresh_meta(&uri))
+      .await
+      .expect("a within-limit object must be cacheable");
+    let CacheFileOrOnMemory::File(path) = target else {
+      panic!("an over-threshold object must spill to a file target");
+    };
+    assert!(path.starts_with(&dir), "the committed file must live in cache_dir");
+    assert_eq!(
+      forwarded_len(rx.collect::<Vec<_>>().await),
+      10000,
+      "the whole body is forwarded"
+    );
+
+    // Read the committed file back, verifying integrity against the incrementally computed hash.
+    let file_store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(0)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    let read_body = file_store.read(path.clone(), &hash).await.unwrap();
+    let got = BodyExt::collect(read_body).await.unwrap().to_bytes();
+    assert_eq!(got.len(), 10000);
+    assert!(got.iter().all(|&b| b == 7), "round-tripped bytes must match");
+    assert!(fs::metadata(&path).await.is_ok(), "a verified file is left in place");
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// A body within the on-memory threshold stays on memory and no file is created.
+  #[tokio::test]
+  async fn store_keeps_small_object_on_memory() {
+    let dir = temp_cache_dir("onmem-store").await;
+    let uri: Uri = "http://example.com/small".parse().unwrap();
+    let (tx, _rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+    let body = body_from(vec![data_frame(b"tiny")]);
+
+    let (target, _hash) = spool_and_store(body, tx, 1_000_000, 4096, &dir, &fresh_meta(&uri))
+      .await
+      .expect("cacheable");
+    assert!(
+      matches!(target, CacheFileOrOnMemory::OnMemory(ref b) if b.as_ref() == b"tiny"),
+      "a sub-threshold object must stay on memory"
+    );
+    let mut entries = fs::read_dir(&dir).await.unwrap();
+    assert!(
+      entries.next_entry().await.unwrap().is_none(),
+      "no file must be created for an on-memory object"
+    );
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// A single frame larger than the on-memory threshold spills directly to a file (the buffer is
+  /// not first grown by the whole frame), forwards in full, and round-trips intact. Guards the
+  /// spill-first-on-threshold-crossing path that bounds store-path memory against a large frame.
+  #[tokio::test]
+  async fn store_single_large_frame_spills_directly() {
+    let dir = temp_cache_dir("single-large").await;
+    let uri: Uri = "http://example.com/onebig".parse().unwrap();
+    let data = vec![3u8; 50_000]; // one frame, well over the 4096 threshold
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![Ok(Frame::data(Bytes::from(data)))];
+    let body = StreamBody::new(stream::iter(frames));
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+
+    let (target, hash) = spool_and_store(body, tx, 1_000_000, 4096, &dir, &fresh_meta(&uri))
+      .await
+      .expect("cacheable");
+    let CacheFileOrOnMemory::File(path) = target else {
+      panic!("a single frame over the threshold must spill to a file target");
+    };
+    assert_eq!(
+      forwarded_len(rx.collect::<Vec<_>>().await),
+      50_000,
+      "the whole frame is forwarded"
+    );
+
+    let file_store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(0)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    let read_body = file_store.read(path.clone(), &hash).await.unwrap();
+    let got = BodyExt::collect(read_body).await.unwrap().to_bytes();
+    assert_eq!(got.len(), 50_000);
+    assert!(got.iter().all(|&b| b == 3), "round-tripped bytes must match");
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// Exceeding `max_each_size` *after* a spill keeps forwarding the full body, caches nothing, and
+  /// leaves no temp file behind.
+  #[tokio::test]
+  async fn store_too_large_after_spill_forwards_all_and_leaves_no_file() {
+    let dir = temp_cache_dir("toolarge").await;
+    let uri: Uri = "http://example.com/big".parse().unwrap();
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+    // on-memory 4096, max_each_size 8000: 4000 (M) -> 8000 (spill) -> 12000 (too large).
+    let frame = |n: usize| Ok(Frame::data(Bytes::from(vec![1u8; n])));
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![frame(4000), frame(4000), frame(4000)];
+    let body = StreamBody::new(stream::iter(frames));
+
+    let out = spool_and_store(body, tx, 8000, 4096, &dir, &fresh_meta(&uri)).await;
+    assert!(out.is_none(), "an over-limit object must not be cached");
+    assert_eq!(forwarded_len(rx.collect::<Vec<_>>().await), 12000, "all bytes are forwarded");
+    let mut entries = fs::read_dir(&dir).await.unwrap();
+    assert!(
+      entries.next_entry().await.unwrap().is_none(),
+      "the temp file must be cleaned up on abort"
+    );
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// An upstream error after a spill forwards the error, caches nothing, and cleans up the temp.
+  #[tokio::test]
+  async fn store_upstream_error_after_spill_forwards_and_cleans_temp() {
+    let dir = temp_cache_dir("err-spill").await;
+    let uri: Uri = "http://example.com/big".parse().unwrap();
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> =
+      vec![Ok(Frame::data(Bytes::from(vec![1u8; 5000]))), Err(TestBodyError)];
+    let body = StreamBody::new(stream::iter(frames));
+
+    let out = spool_and_store(body, tx, 1_000_000, 4096, &dir, &fresh_meta(&uri)).await;
+    assert!(out.is_none(), "an errored body must not be cached");
+    let forwarded = rx.collect::<Vec<_>>().await;
+    assert!(
+      forwarded.iter().any(|f| f.is_err()),
+      "the upstream error is forwarded downstream"
+    );
+    let mut entries = fs::read_dir(&dir).await.unwrap();
+    assert!(
+      entries.next_entry().await.unwrap().is_none(),
+      "the temp file must be cleaned up after an upstream error"
+    );
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// A store-side I/O failure (here: a non-existent cache_dir so the spill cannot be created) must
+  /// never cut the downstream relay: the full body is still forwarded, and nothing is cached.
+  #[tokio::test]
+  async fn store_io_failure_keeps_forwarding_without_caching() {
+    let dir = temp_cache_path("missing-dir"); // intentionally NOT created
+    let uri: Uri = "http://example.com/big".parse().unwrap();
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![
+      Ok(Frame::data(Bytes::from(vec![9u8; 5000]))),
+      Ok(Frame::data(Bytes::from(vec![9u8; 1000]))),
+    ];
+    let body = StreamBody::new(stream::iter(frames));
+
+    let out = spool_and_store(body, tx, 1_000_000, 4096, &dir, &fresh_meta(&uri)).await;
+    assert!(out.is_none(), "a failed store must not cache");
+    assert_eq!(
+      forwarded_len(rx.collect::<Vec<_>>().await),
+      6000,
+      "the full body must still reach downstream despite the store I/O failure"
+    );
+  }
+
+  /// Removing a file when the count is already zero must saturate, not underflow/panic. This
+  /// defends the cross-lock count race: the file count and the LRU map are updated under separate
+  /// locks, so a pathological concurrent ordering could otherwise drive the `usize` count below
+  /// zero (panic in debug, wraparound in release).
+  #[tokio::test]
+  async fn file_store_remove_count_saturates_at_zero() {
+    let dir = temp_cache_dir("saturate").await;
+    let path = dir.join("f");
+    fs::write(&path, b"x").await.unwrap();
+    let store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(0)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    store.remove(&path).await.unwrap();
+    assert_eq!(
+      store.count().await,
+      0,
+      "the file count must saturate at zero, not wrap around"
+    );
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// Evicting a counted file entry must restore the count even when the file is already gone (e.g.
+  /// removed externally, or by the integrity-check path on a hash mismatch). Otherwise the file
+  /// count leaks above the number of live entries once the metadata is popped.
+  #[tokio::test]
+  async fn evict_missing_file_still_restores_count() {
+    let file_store = test_file_store();
+    file_store.incr_count().await; // a counted file entry exists
+    assert_eq!(file_store.count().await, 1);
+
+    // The file is already gone (never created here); eviction must still correct the count.
+    let missing = std::env::temp_dir().join("rpxy-cache-test-never-created-file");
+    file_store.evict(&missing).await;
+    assert_eq!(
+      file_store.count().await,
+      0,
+      "the file count must be restored even when the file was already gone"
+    );
+  }
+
+  /// Body wrapper counting the frames pulled from it, to observe how far ahead of a stalled
+  /// consumer the spool producer runs.
+  struct CountingBody<B> {
+    inner: B,
+    pulled: Arc<AtomicUsize>,
+  }
+
+  impl<B> hyper::body::Body for CountingBody<B>
+  where
+    B: hyper::body::Body<Data = Bytes> + Unpin,
+  {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+      let this = self.get_mut();
+      let res = Pin::new(&mut this.inner).poll_frame(cx);
+      if let Poll::Ready(Some(_)) = &res {
+        this.pulled.fetch_add(1, Ordering::Relaxed);
+      }
+      res
+    }
+  }
+
+  /// The store/miss path must not race ahead of a stalled consumer: the producer parks once the
+  /// channel (capacity + the sender's guaranteed slot) is full instead of pulling the whole body.
+  /// Yielding cannot un-park it; only draining the receiver can.
+  #[tokio::test]
+  async fn store_backpressure_limits_producer_readahead() {
+    const TEST_CAPACITY: usize = 2;
+    const TOTAL_FRAMES: usize = 32;
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CAPACITY);
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = (0..TOTAL_FRAMES)
+      .map(|_| Ok(Frame::data(Bytes::from(vec![0u8; 1024]))))
+      .collect();
+    let body = CountingBody {
+      inner: StreamBody::new(stream::iter(frames)),
+      pulled: pulled.clone(),
+    };
+    let uri: Uri = "http://example.com/bp".parse().unwrap();
+    let dir = std::env::temp_dir();
+    let spool = tokio::spawn(async move {
+      // On-memory threshold usize::MAX: no disk involved, the only await point is the bounded send.
+      spool_and_store(body, tx, usize::MAX, usize::MAX, &dir, &fresh_meta(&uri)).await
+    });
+
+    // Let the producer run until it parks on the full channel (single-threaded test runtime).
+    for _ in 0..50 {
+      tokio::task::yield_now().await;
+    }
+    let ahead = pulled.load(Ordering::Relaxed);
+    assert!(
+      ahead < TOTAL_FRAMES,
+      "the producer must be parked by backpressure, not run to EOF (pulled {ahead})"
+    );
+    // Bound: TEST_CAPACITY buffered + the sender's guaranteed slot + the frame parked in the send.
+    assert!(
+      ahead <= TEST_CAPACITY + 2,
+      "the producer read-ahead must be bounded by the channel capacity (pulled {ahead})"
+    );
+
+    // Draining un-parks the producer; the spool completes and the full object is cached.
+    assert_eq!(forwarded_len(rx.collect::<Vec<_>>().await), TOTAL_FRAMES * 1024);
+    let stored = spool.await.unwrap();
+    assert!(
+      matches!(stored, Some((CacheFileOrOnMemory::OnMemory(ref b), _)) if b.len() == TOTAL_FRAMES * 1024),
+      "the drained object must be cached in full"
+    );
+  }
+
+  /// A slow consumer paces the file-read hit path. Mismatch eviction happens only at EOF, and a
+  /// full channel provably keeps the producer from reaching EOF, so with only one frame consumed
+  /// the wrong-hashed file must still exist; draining to EOF must then evict it.
+  #[tokio::test]
+  async fn file_read_backpressure_holds_eof_eviction_until_drained() {
+    let path = temp_cache_path("bp-read");
+    // Enough chunks that the producer cannot reach EOF while the channel is full.
+    let content = vec![5u8; FILE_CACHE_READ_CHUNK * (CACHE_STREAM_CHANNEL_CAPACITY + 4)];
+    fs::write(
+      &path,
+      cache_file_bytes(&fresh_meta(&Uri::from_static("http://example.com/file")), &content),
+    )
+    .await
+    .unwrap();
+    let wrong_hash = Bytes::from_static(&[0u8; 32]);
+    let file_store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(1)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    let mut body = file_store.read(path.clone(), &wrong_hash).await.unwrap();
+
+    // Consume a single frame, then stall.
+    assert!(body.frame().await.is_some(), "the first frame must arrive");
+    for _ in 0..50 {
+      tokio::task::yield_now().await;
+    }
+    assert!(
+      fs::metadata(&path).await.is_ok(),
+      "the producer must be parked before EOF, so the mismatch eviction has not run yet"
+    );
+
+    // Drain to EOF: the integrity check finally runs and evicts the corrupted file.
+    while body.frame().await.is_some() {}
+    assert!(
+      fs::metadata(&path).await.is_err(),
+      "draining to EOF must evict the wrong-hashed file"
+    );
+  }
+
+  /// Dropping the receiver mid-stream after the store has spilled to a temp file must abort the
+  /// store, clean up the temp file, and cache nothing (complements
+  /// `returns_none_when_downstream_dropped`, which covers the receiver being gone from the start).
+  #[tokio::test]
+  async fn store_dropped_receiver_after_spill_cleans_temp() {
+    let dir = temp_cache_dir("drop-spill").await;
+    let uri: Uri = "http://example.com/drop".parse().unwrap();
+    let (tx, mut rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(0);
+    // 6 x 2000 bytes with a 4096 on-memory threshold: the spill starts at the third frame.
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> =
+      (0..6).map(|_| Ok(Frame::data(Bytes::from(vec![2u8; 2000])))).collect();
+    let body = StreamBody::new(stream::iter(frames));
+    let dir_clone = dir.clone();
+    let spool = tokio::spawn(async move { spool_and_store(body, tx, 1_000_000, 4096, &dir_clone, &fresh_meta(&uri)).await });
+
+    // Consume enough frames for the spill to have happened (the third frame was processed once
+    // the fourth has been forwarded), then hang up.
+    for _ in 0..4 {
+      assert!(rx.next().await.is_some());
+    }
+    drop(rx);
+
+    assert!(spool.await.unwrap().is_none(), "an aborted store must not cache");
+    let mut entries = fs::read_dir(&dir).await.unwrap();
+    assert!(
+      entries.next_entry().await.unwrap().is_none(),
+      "the temp file must be cleaned up when the receiver goes away mid-spill"
+    );
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// Concurrent count updates converge without locks. Phase-separated for a deterministic
+  /// expectation (free interleaving with the saturating decrement would make the final count
+  /// scheduling-dependent): phase 1 increments concurrently, phase 2 evicts each task's own
+  /// pre-created file concurrently.
+  #[tokio::test]
+  async fn file_store_count_concurrent_storm_phased() {
+    const N: usize = 64;
+    let dir = temp_cache_dir("storm").await;
+    let store = test_file_store();
+
+    // Phase 1: N concurrent increments (publish-side bookkeeping).
+    let handles: Vec<_> = (0..N)
+      .map(|_| {
+        let s = store.clone();
+        tokio::spawn(async move { s.incr_count().await })
+      })
+      .collect();
+    for h in handles {
+      h.await.unwrap();
+    }
+    assert_eq!(store.count().await, N, "all concurrent increments must be counted");
+
+    // Phase 2: N concurrent evictions, each of its own counted file.
+    let mut handles = Vec::with_capacity(N);
+    for i in 0..N {
+      let p = dir.join(format!("f{i}"));
+      fs::write(&p, b"x").await.unwrap();
+      let s = store.clone();
+      handles.push(tokio::spawn(async move { s.evict(&p).await }));
+    }
+    for h in handles {
+      h.await.unwrap();
+    }
+    assert_eq!(store.count().await, 0, "all concurrent evictions must be counted");
+    let mut entries = fs::read_dir(&dir).await.unwrap();
+    assert!(
+      entries.next_entry().await.unwrap().is_none(),
+      "every evicted file must be unlinked"
+    );
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// Dropping the receiver mid-read must not evict the file. The file has more chunks than the
+  /// channel can hold, so the producer cannot have reached EOF when the drop lands; the stored
+  /// hash is intentionally wrong, so the file surviving proves the aborted read exits without
+  /// acting on the (incomplete) integrity check - a run to EOF would have evicted it.
+  #[tokio::test]
+  async fn file_read_dropped_receiver_does_not_evict() {
+    let path = temp_cache_path("drop-read");
+    let content = vec![6u8; FILE_CACHE_READ_CHUNK * (CACHE_STREAM_CHANNEL_CAPACITY + 4)];
+    fs::write(
+      &path,
+      cache_file_bytes(&fresh_meta(&Uri::from_static("http://example.com/file")), &content),
+    )
+    .await
+    .unwrap();
+    let wrong_hash = Bytes::from_static(&[0u8; 32]);
+    let file_store = FileStore {
+      cnt: Arc::new(AtomicUsize::new(1)),
+      runtime_handle: tokio::runtime::Handle::current(),
+    };
+    let mut body = file_store.read(path.clone(), &wrong_hash).await.unwrap();
+    assert!(body.frame().await.is_some(), "the first frame must arrive");
+    drop(body);
+
+    // Let the producer observe the disconnect and exit.
+    for _ in 0..50 {
+      tokio::task::yield_now().await;
+    }
+    assert!(
+      fs::metadata(&path).await.is_ok(),
+      "an aborted read must leave the file in place (no integrity verdict without EOF)"
+    );
+    let _ = fs::remove_file(&path).await;
+  }
+}
@@ -0,0 +1,357 @@
+use super::{
+  cache_error::*,
+  cache_main::{CacheFileOrOnMemory, CacheObject, PurgeTarget, uri_of_cache_key},
+  tiny_lfu::{Tier, TinyLfu},
+};
+use crate::log::*;
+use http::HeaderName;
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+  },
+};
+
+#[derive(Debug, Default)]
+/// Request header names listed in `Vary` of a URI, and the keys of its stored variants from the oldest
+struct Variants {
+  vary: Vec<HeaderName>,
+  keys: VecDeque<String>,
+}
+
+#[derive(Debug)]
+/// Cache objects evicted by the W-TinyLFU policy within the maximum number of entries and the byte budgets
+/// of the tiers, with the variants stored per URI whose responses have `Vary`. Each variant is keyed on the
+/// URI and the normalized request headers listed in `Vary` (see `derive_variant_key`).
+struct CacheIndex {
+  entries: HashMap<String, CacheObject>,
+  /// Eviction policy over the keys of the entries
+  policy: TinyLfu,
+  /// Variants per URI, kept in sync with the entries
+  variants: HashMap<String, Variants>,
+  /// Maximum number of variants per URI
+  max_variants: usize,
+}
+
+impl CacheIndex {
+  fn new(max_entry: usize, max_variants: usize, max_memory_size: u64, max_disk_size: u64) -> Self {
+    Self {
+      entries: HashMap::new(),
+      policy: TinyLfu::new(max_entry, max_memory_size, max_disk_size),
+      variants: HashMap::new(),
+      max_variants: max_variants.max(1),
+    }
+  }
+
+  /// Get the entry of the key, recording the lookup for the eviction policy even if missing
+  fn get(&mut self, cache_key: &str) -> Option<&CacheObject> {
+    self.policy.access(cache_key);
+    self.entries.get(cache_key)
+  }
+
+  /// Request header names listed in `Vary` of the URI, empty if its response does not vary
+  fn vary(&self, uri_key: &str) -> &[HeaderName] {
+    self.variants.get(uri_key).map(|v| v.vary.as_slice()).unwrap_or_default()
+  }
+
+  /// Pop the entry of the key
+  fn pop(&mut self, cache_key: &str) -> Option<(String, CacheObject)> {
+    let entry = self.entries.remove_entry(cache_key)?;
+    self.policy.remove(cache_key);
+    self.forget_variant(cache_key);
+    Some(entry)
+  }
+
+  /// Remove the key from the variants of its URI
+  fn forget_variant(&mut self, cache_key: &str) {
+    let uri_key = uri_of_cache_key(cache_key);
+    if let Some(variants) = self.variants.get_mut(uri_key) {
+      variants.keys.retain(|k| k != cache_key);
+      if variants.keys.is_empty() {
+        self.variants.remove(uri_key);
+      }
+    }
+  }
+
+  /// Push the object, returning the entries displaced by it: the one of the same key, the ones evicted
+  /// by the policy beyond the limits, which may include the object itself if not admitted, the oldest
+  /// variant beyond the maximum number of variants, and the entries of the same URI stored with another
+  /// `Vary`.
+  fn push(&mut self, cache_key: String, cache_object: CacheObject) -> Vec<(String, CacheObject)> {
+    let uri_key = uri_of_cache_key(&cache_key).to_string();
+    let mut displaced = Vec::new();
+
+    let superseded = match self.variants.get(&uri_key) {
+      Some(variants) if variants.vary != cache_object.vary => variants.keys.iter().cloned().collect(),
+      None if !cache_object.vary.is_empty() => vec![uri_key.clone()],
+      _ => vec![],
+    };
+    displaced.extend(superseded.iter().filter_map(|k| self.pop(k)));
+
+    if !cache_object.vary.is_empty() {
+      let variants = self.variants.entry(uri_key).or_insert_with(|| Variants {
+        vary: cache_object.vary.clone(),
+        keys: VecDeque::new(),
+      });
+      if !variants.keys.contains(&cache_key) {
+        variants.keys.push_back(cache_key.clone());
+      }
+      while variants.keys.len() > self.max_variants {
+        let Some(oldest) = variants.keys.pop_front() else {
+          break;
+        };
+        if let Some(entry) = self.entries.remove_entry(&oldest) {
+          self.policy.remove(&oldest);
+          displaced.push(entry);
+        }
+      }
+    }
+
+    let (tier, size) = (cache_object.tier(), cache_object.size);
+    if let Some(object) = self.entries.insert(cache_key.clone(), cache_object) {
+      self.policy.remove(&cache_key);
+      displaced.push((cache_key.clone(), object));
+    }
+    for key in self.policy.insert(cache_key, tier, size) {
+      displaced.extend(self.pop(&key));
+    }
+    displaced
+  }
+}
+
+/* ---------------------------------------------- */
+#[derive(Debug, Clone)]
+/// Cache manager that is responsible to handle `Mutex` as an outer of `CacheIndex`
+pub(super) struct LruCacheManager {
+  /// Cache index main object
+  inner: Arc<Mutex<CacheIndex>>,
+  /// Counter of current cached object (total)
+  cnt: Arc<AtomicUsize>,
+  /// Number of purges so far, bumped under the lock so that a store overtaken by a purge is never published
+  purge_epoch: Arc<AtomicU64>,
+}
+
+impl LruCacheManager {
+  /// Build the cache index
+  pub(super) fn new(
+    cache_max_entry: usize,
+    cache_max_variants: usize,
+    cache_max_memory_size: u64,
+    cache_max_disk_size: u64,
+  ) -> Self {
+    let index = CacheIndex::new(
+      cache_max_entry,
+      cache_max_variants,
+      cache_max_memory_size,
+      cache_max_disk_size,
+    );
+    Self {
+      inner: Arc::new(Mutex::new(index)),
+      cnt: Default::default(),
+      purge_epoch: Default::default(),
+    }
+  }
+
+  /// Count entries
+  pub(super) fn count(&self) -> usize {
+    self.cnt.load(Ordering::Relaxed)
+  }
+
+  /// Bytes of the entries in the memory and the disk tiers
+  pub(super) fn size(&self) -> (u64, u64) {
+    let Ok(lock) = self.inner.lock() else {
+      error!("Mutex can't be locked for checking cache size");
+      return (0, 0);
+    };
+    (lock.policy.size(Tier::Memory), lock.policy.size(Tier::Disk))
+  }
+
+  /// Current purge epoch
+  pub(super) fn purge_epoch(&self) -> u64 {
+    self.purge_epoch.load(Ordering::Acquire)
+  }
+
+  /// Pop every entry matching the target and start a new purge epoch, returns error if mutex cannot be acquired
+  pub(super) fn purge(&self, target: &PurgeTarget) -> CacheResult<Vec<CacheObject>> {
+    let mut lock = self.inner.lock().map_err(|_| {
+      error!("Mutex can't be locked to purge cache entries");
+      CacheError::FailedToAcquiredMutexLockForCache
+    })?;
+    self.purge_epoch.fetch_add(1, Ordering::AcqRel);
+    let keys = lock
+      .entries
+      .iter()
+      .filter(|(k, v)| target.matches_object(k, v))
+      .map(|(k, _)| k.clone())
+      .collect::<Vec<_>>();
+    let purged = keys.iter().filter_map(|k| lock.pop(k)).map(|(_, v)| v).collect();
+    // This may be inconsistent with the actual number of entries
+    self.cnt.store(lock.entries.len(), Ordering::Relaxed);
+    Ok(purged)
+  }
+
+  /// Evict the entry for `cache_key` only if it is still the `generation` the caller observed.
+  ///
+  /// Eviction is sometimes triggered from a stale snapshot (e.g. a `get()` that cloned the entry,
+  /// then found it stale or failed to read its file). A concurrent re-store may have replaced that
+  /// entry with a newer live one under the same key in the meantime; popping unconditionally would
+  /// delete the live entry (orphaning its file and desyncing the file count). Peeking the current
+  /// generation and only popping on a match prevents that. Returns the popped entry when it matched.
+  pub(super) fn evict_if_generation(&self, cache_key: &str, generation: u64) -> Option<(String, CacheObject)> {
+    let mut lock = match self.inner.lock() {
+      Ok(lock) => lock,
+      Err(_) => {
+        error!("Mutex can't be locked to evict a cache entry");
+        return None;
+      }
+    };
+    // Looked up without recording an access; only pop when the generation still matches.
+    if lock.entries.get(cache_key).map(|o| o.generation) != Some(generation) {
+      return None;
+    }
+    let res = lock.pop(cache_key);
+    // This may be inconsistent with the actual number of entries
+    self.cnt.store(lock.entries.len(), Ordering::Relaxed);
+    res
+  }
+
+  /// Remove the entry of the key regardless of its generation, returning it if any
+  pub(super) fn remove(&self, cache_key: &str) -> Option<CacheObject> {
+    let Ok(mut lock) = self.inner.lock() else {
+      error!("Mutex can't be locked to remove a cache entry");
+      return None;
+    };
+    let res = lock.pop(cache_key).map(|(_, v)| v);
+    // This may be inconsistent with the actual number of entries
+    self.cnt.store(lock.entries.len(), Ordering::Relaxed);
+    res
+  }
+
+  /// Replace the entry with its refreshed object, if it still holds the same body, i.e., the cache file `cached_target`
+  /// or an object on memory with the same hash. Returns whether it was replaced.
+  pub(super) fn replace_if_same_body(
+    &self,
+    cache_key: &str,
+    cached_target: &CacheFileOrOnMemory,
+    cache_object: CacheObject,
+  ) -> bool {
+    let Ok(mut lock) = self.inner.lock() else {
+      error!("Mutex can't be locked to refresh a cache entry");
+      return false;
+    };
+    let same_body = |cached_object: &CacheObject| {
+      cached_object.hash == cache_object.hash
+        && match (&cached_object.target, cached_target) {
+          (CacheFileOrOnMemory::File(cached), CacheFileOrOnMemory::File(refreshed)) => cached == refreshed,
+          (CacheFileOrOnMemory::OnMemory(_), CacheFileOrOnMemory::OnMemory(_)) => true,
+          _ => false,
+        }
+    };
+    let Some(cached_object) = lock.entries.get_mut(cache_key).filter(|o| same_body(o)) else {
+      return false;
+    };
+    // The target is taken over, so the entry keeps its place in the eviction policy
+    *cached_object = cache_object;
+    true
+  }
+
+  /// Push an entry into the LRU cache, returning the displaced entries. Returns error if mutex cannot
+  /// be acquired or a purge has run since `purge_epoch`
+  pub(super) fn push(
+    &self,
+    cache_key: &str,
+    cache_object: &CacheObject,
+    purge_epoch: u64,
+  ) -> CacheResult<Vec<(String, CacheObject)>> {
+    let mut lock = self.inner.lock().map_err(|_| {
+      error!("Failed to acquire mutex lock for writing cache entry");
+      CacheError::FailedToAcquiredMutexLockForCache
+    })?;
+    if self.purge_epoch() != purge_epoch {
+      return Err(CacheError::PurgedWhileStoring);
+    }
+    let res = Ok(lock.push(cache_key.to_string(), cache_object.clone()));
+    // This may be inconsistent with the actual number of entries
+    self.cnt.store(lock.entries.len(), Ordering::Relaxed);
+    res
+  }
+
+  /// Get the request header names listed in `Vary` of the URI, returns error if mutex cannot be acquired
+  pub(super) fn vary(&self, uri_key: &str) -> CacheResult<Vec<HeaderName>> {
+    let lock = self.inner.lock().map_err(|_| {
+      error!("Mutex can't be locked for checking cache entry");
+      CacheError::FailedToAcquiredMutexLockForCheck
+    })?;
+    Ok(lock.vary(uri_key).to_vec())
+  }
+
+  #[cfg(test)]
+  /// Key of the entry to be evicted first regardless of frequency
+  pub(super) fn victim(&self) -> Option<String> {
+    self.inner.lock().ok()?.policy.victim()
+  }
+
+  /// Get an entry from the LRU cache, returns error if mutex cannot be acquired
+  pub(super) fn get(&self, cache_key: &str) -> CacheResult<Option<CacheObject>> {
+    let mut lock = self.inner.lock().map_err(|_| {
+      error!("Mutex can't be locked for checking cache entry");
+      CacheError::FailedToAcquiredMutexLockForCheck
+    })?;
+    let Some(cached_object) = lock.get(cache_key) else {
+      return Ok(None);
+    };
+    Ok(Some(cached_object.clone()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::forwarder::cache::{cache_file::tests::fresh_policy, cache_main::derive_cache_key_from_effective_uri};
+  use bytes::Bytes;
+  use http::Uri;
+
+  /// A stale snapshot must not evict a newer live entry of the same key. Models the race where a
+  /// `get()` cloned generation A, a concurrent re-store published generation B under the same key,
+  /// and the stale `get()` then attempts eviction: B must survive, and only B's own generation can
+  /// evict B.
+  #[tokio::test]
+  async fn evict_if_generation_spares_newer_entry() {
+    let manager = LruCacheManager::new(10, 4, u64::MAX, u64::MAX);
+    let uri: Uri = "http://example.com/x".parse().unwrap();
+    let key = derive_cache_key_from_effective_uri(&uri);
+
+    let obj_a = CacheObject::new(
+      fresh_policy(&uri),
+      CacheFileOrOnMemory::OnMemory(Bytes::from_static(b"A")),
+      Bytes::from_static(&[1u8; 32]),
+    );
+    let gen_a = obj_a.generation;
+    manager.push(&key, &obj_a, manager.purge_epoch()).unwrap();
+
+    // A concurrent re-store replaces the entry under the same key with a newer generation.
+    let obj_b = CacheObject::new(
+      fresh_policy(&uri),
+      CacheFileOrOnMemory::OnMemory(Bytes::from_static(b"B")),
+      Bytes::from_static(&[2u8; 32]),
+    );
+    let gen_b = obj_b.generation;
+    manager.push(&key, &obj_b, manager.purge_epoch()).unwrap();
+
+    // The stale snapshot (generation A) must not evict the live entry B.
+    assert!(
+      manager.evict_if_generation(&key, gen_a).is_none(),
+      "a stale generation must not evict the newer entry"
+    );
+    let current = manager.get(&key).unwrap().expect("the newer entry must survive");
+    assert_eq!(current.generation, gen_b);
+
+    // The current generation can still be evicted.
+    assert!(manager.evict_if_generation(&key, gen_b).is_some());
+    assert!(
+      manager.get(&key).unwrap().is_none(),
+      "evicting the current generation removes it"
+    );
+  }
+}
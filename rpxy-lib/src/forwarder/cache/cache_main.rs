@@ -1,38 +1,40 @@
-use super::cache_error::*;
+use super::{
+  cache_error::*,
+  cache_file::{CacheFileMeta, FileStore, spool_and_store},
+  cache_index::LruCacheManager,
+  cache_range::{
+    self, ByteRange, if_range_matches, is_not_modified, not_modified, parse_range, partial_content, range_not_satisfiable,
+    resolve_ranges, slice_segments,
+  },
+  cache_rule::CacheTtl,
+  cache_status::{CacheForward, CacheStatus},
+  cache_storage::{CacheEntry, CacheStorage, CustomStorage, TieredStorage, publish_cache_object},
+  tiny_lfu::Tier,
+};
 use crate::{
   globals::Globals,
   hyper_ext::body::{BoundedStreamBody, BoxBody, ResponseBody, full},
   log::*,
 };
-use base64::{Engine as _, engine::general_purpose};
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 use futures::{SinkExt, channel::mpsc};
-use http::{Request, Response, Uri};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri, header, response};
 use http_body_util::{BodyExt, StreamBody};
-use http_cache_semantics::CachePolicy;
-use hyper::body::{Frame, Incoming};
-use lru::LruCache;
-use sha2::{Digest, Sha256};
+use http_cache_semantics::{AfterResponse, BeforeRequest, CachePolicy};
+use hyper::body::{Body, Frame, SizeHint};
 use std::{
-  path::{Path, PathBuf},
+  collections::{HashMap, HashSet},
+  future::Future,
+  path::PathBuf,
+  pin::Pin,
   sync::{
     Arc, Mutex,
     atomic::{AtomicU64, AtomicUsize, Ordering},
   },
-  time::SystemTime,
+  task::{Context, Poll},
+  time::{Duration, SystemTime},
 };
-use tokio::{
-  fs::{self, File, OpenOptions},
-  io::{AsyncReadExt, AsyncWriteExt},
-};
-
-/// File-cache read chunk size: large enough that a typical cached object is read in one or a
-/// few iterations (vs the ~64 B that `BytesMut` auto-grows per `read_buf`). Each read fills at
-/// most one chunk-sized buffer, so we never load the whole object into a single `BytesMut`
-/// (matters when `max_each_size` is configured large). This bounds the per-read buffer; how many
-/// such chunks can queue toward a slow downstream is bounded separately by
-/// `CACHE_STREAM_CHANNEL_CAPACITY`.
-const FILE_CACHE_READ_CHUNK: usize = 64 * 1024;
+use tokio::{fs, sync::watch};
 
 /// Capacity of the bounded per-stream channels relaying cache-path bodies downstream (both the
 /// file-read hit path and the store/miss path). The producer awaits when the channel is full, so
@@ -40,72 +42,127 @@ const FILE_CACHE_READ_CHUNK: usize = 64 * 1024;
 /// one slot beyond the buffer) instead of the whole object when the consumer is slower than the
 /// producer. A few frames of slack keep a fast consumer fed without a producer/consumer wakeup
 /// ping-pong on every frame.
-const CACHE_STREAM_CHANNEL_CAPACITY: usize = 4;
+pub(super) const CACHE_STREAM_CHANNEL_CAPACITY: usize = 4;
+
+/// Response headers tagging a cached object with surrogate keys for purge: `Surrogate-Key` lists
+/// space-separated keys, and `Cache-Tag` comma-separated ones.
+const SURROGATE_KEY_HEADERS: [&str; 2] = ["surrogate-key", "cache-tag"];
+
+/// Request headers listed in `Vary` whose values are compared case-insensitively
+const CASE_INSENSITIVE_VARY_HEADERS: [&str; 4] = ["accept", "accept-charset", "accept-encoding", "accept-language"];
 
 /* ---------------------------------------------- */
 #[derive(Clone, Debug)]
 /// Cache main manager
 pub(crate) struct RpxyCache {
-  /// Inner lru cache manager storing http message caching policy
-  inner: LruCacheManager,
-  /// Managing committed cache file objects (lock-free count; files are immutable once committed)
-  file_store: FileStore,
+  /// Storage of the entries and their bodies
+  storage: Storage,
   /// Async runtime
   runtime_handle: tokio::runtime::Handle,
   /// Maximum size of each cache file object
   max_each_size: usize,
-  /// Maximum size of cache object on memory
-  max_each_size_on_memory: usize,
-  /// Cache directory path
-  cache_dir: PathBuf,
+  /// Period a stale object is served when the upstream fails, unless given by `stale-if-error`
+  stale_if_error_grace: Duration,
+  /// Keys of the stale entries being refreshed in background
+  revalidating: Arc<Mutex<HashSet<String>>>,
+  /// Maximum time a request waits for the head of the response fetched by a concurrent request for the same
+  /// entry, zero to disable
+  coalescing_timeout: Duration,
+  /// Keys of the entries being fetched from the upstream, with the receivers notified of their responses
+  filling: Arc<Mutex<HashMap<String, watch::Receiver<FillHead>>>>,
+  /// Whether the `Cache-Status` header is added to the responses
+  status_header: bool,
+  /// Whether `Cache-Status` gives the cache key, i.e., the normalized URI including the query
+  status_key: bool,
 }
 
 impl RpxyCache {
-  #[allow(unused)]
   /// Generate cache storage
   pub(crate) async fn new(globals: &Globals) -> Option<Self> {
     if !globals.proxy_config.cache_enabled {
       return None;
     }
-    let cache_dir = match globals.proxy_config.cache_dir.as_ref() {
-      Some(dir) => dir,
+    let max_each_size = globals.proxy_config.cache_max_each_size;
+    let stale_if_error_grace = globals.proxy_config.cache_stale_if_error_grace;
+
+    let storage = match globals.cache_storage.as_ref() {
+      Some(storage) => Storage::Custom(CustomStorage::new(storage.clone(), stale_if_error_grace)),
       None => {
-        warn!("Cache directory not set in proxy config");
-        return None;
+        let cache_dir = match globals.proxy_config.cache_dir.as_ref() {
+          Some(dir) => dir,
+          None => {
+            warn!("Cache directory not set in proxy config");
+            return None;
+          }
+        };
+        let mut max_each_size_on_memory = globals.proxy_config.cache_max_each_size_on_memory;
+        if max_each_size < max_each_size_on_memory {
+          warn!("Maximum size of on-memory cache per entry must be smaller than or equal to the maximum of each file cache");
+          max_each_size_on_memory = max_each_size;
+        }
+        if let Err(e) = fs::create_dir_all(&cache_dir).await {
+          error!("Failed to create cache dir: {e}");
+          return None;
+        }
+        let tiers = TieredStorage {
+          index: LruCacheManager::new(
+            globals.proxy_config.cache_max_entry,
+            globals.proxy_config.cache_max_variants,
+            globals.proxy_config.cache_max_memory_size,
+            globals.proxy_config.cache_max_disk_size,
+          ),
+          file_store: FileStore::new(&globals.runtime_handle).await,
+          cache_dir: cache_dir.clone(),
+          max_each_size_on_memory,
+        };
+        tiers
+          .restore(
+            globals.proxy_config.cache_max_entry,
+            globals.proxy_config.cache_max_disk_size,
+            max_each_size,
+            stale_if_error_grace,
+          )
+          .await;
+        Storage::Tiered(tiers)
       }
     };
-    let file_store = FileStore::new(&globals.runtime_handle).await;
-    let inner = LruCacheManager::new(globals.proxy_config.cache_max_entry);
-
-    let max_each_size = globals.proxy_config.cache_max_each_size;
-    let mut max_each_size_on_memory = globals.proxy_config.cache_max_each_size_on_memory;
-    if max_each_size < max_each_size_on_memory {
-      warn!("Maximum size of on-memory cache per entry must be smaller than or equal to the maximum of each file cache");
-      max_each_size_on_memory = max_each_size;
-    }
-
-    if let Err(e) = fs::remove_dir_all(cache_dir).await {
-      warn!("Failed to clean up the cache dir: {e}");
-    }
-    if let Err(e) = fs::create_dir_all(&cache_dir).await {
-      error!("Failed to create cache dir: {e}");
-      return None;
-    }
 
     Some(Self {
-      file_store,
-      inner,
+      storage,
       runtime_handle: globals.runtime_handle.clone(),
       max_each_size,
-      max_each_size_on_memory,
-      cache_dir: cache_dir.clone(),
+      stale_if_error_grace,
+      revalidating: Default::default(),
+      coalescing_timeout: globals.proxy_config.cache_coalescing_timeout,
+      filling: Default::default(),
+      status_header: globals.proxy_config.cache_status_header,
+      status_key: globals.proxy_config.cache_status_key,
     })
   }
 
-  /// Count cache entries
+  /// Storage of the entries as a trait object
+  fn storage(&self) -> &dyn CacheStorage {
+    match &self.storage {
+      Storage::Tiered(tiers) => tiers,
+      Storage::Custom(custom) => custom,
+    }
+  }
+
+  /// Memory and file tiers, unless a custom storage is given
+  fn tiers(&self) -> Option<&TieredStorage> {
+    match &self.storage {
+      Storage::Tiered(tiers) => Some(tiers),
+      Storage::Custom(_) => None,
+    }
+  }
+
+  /// Count cache entries, not reported for a custom storage
   pub(crate) async fn count(&self) -> (usize, usize, usize) {
-    let total = self.inner.count();
-    let file = self.file_store.count().await;
+    let Some(tiers) = self.tiers() else {
+      return (0, 0, 0);
+    };
+    let total = tiers.index.count();
+    let file = tiers.file_store.count().await;
     // `total` (LRU) and `file` (file store) are tracked under different locks and updated in
     // separate steps while publishing/evicting, so a concurrent store can transiently make
     // `file > total` (a file counted just before its metadata is published). Saturate instead of
@@ -114,16 +171,69 @@ impl RpxyCache {
     (total, on_memory, file)
   }
 
-  /// Put response into the cache
-  pub(crate) async fn put(&self, uri: &hyper::Uri, body: Incoming, policy: &CachePolicy) -> CacheResult<BoundedStreamBody> {
-    let cache_manager = self.inner.clone();
-    let file_store = self.file_store.clone();
-    let uri = uri.clone();
-    let policy_clone = policy.clone();
+  /// Bytes of the on-memory and the file objects, counted against the budgets of the tiers
+  pub(crate) fn size(&self) -> (u64, u64) {
+    self.tiers().map(|tiers| tiers.index.size()).unwrap_or_default()
+  }
+
+  /// Whether the `Cache-Status` header is added to the responses
+  pub(crate) fn status_header(&self) -> bool {
+    self.status_header
+  }
+
+  /// Whether `Cache-Status` gives the cache key
+  pub(crate) fn status_key(&self) -> bool {
+    self.status_key
+  }
+
+  /// Current purge epoch, to be taken before the response to be stored is requested to the upstream
+  pub(crate) fn purge_epoch(&self) -> u64 {
+    match &self.storage {
+      Storage::Tiered(tiers) => tiers.index.purge_epoch(),
+      Storage::Custom(custom) => custom.purge_epoch(),
+    }
+  }
+
+  /// Put response into the cache. The object is not published if a purge has run since `purge_epoch`
+  /// was taken, since the response may predate the content the purge was meant for. The fill guard, if
+  /// any, is released once the object is published or given up.
+  pub(crate) async fn put<B>(
+    &self,
+    req: &Request<()>,
+    res: &response::Parts,
+    body: B,
+    policy: &CachePolicy,
+    purge_epoch: u64,
+    fill_guard: Option<FillGuard>,
+  ) -> CacheResult<BoundedStreamBody>
+  where
+    B: Body<Data = Bytes, Error = hyper::Error> + Send + Unpin + 'static,
+  {
+    let storage = self.storage.clone();
+    let meta = CacheFileMeta::new(req, res, SystemTime::now());
+    let vary = variant_names(vary_names(&res.headers).unwrap_or_default(), &meta.key_headers);
+    let ttl = meta.ttl;
+    let (policy, cache_control) = bound_ttl(policy.clone(), req, res.status, &res.headers, meta.response_time, &ttl);
+    let stale_response = StaleResponse::new(
+      &policy,
+      res.status,
+      &res.headers,
+      meta.response_time,
+      self.stale_if_error_grace,
+    );
+    let cache_key = derive_variant_key(&meta.uri, &vary, req.headers());
     let max_each_size = self.max_each_size;
-    let max_each_size_on_memory = self.max_each_size_on_memory;
-    let cache_dir = self.cache_dir.clone();
+    // A custom storage takes the whole body at once, so it is never spilled to a file
+    let (max_each_size_on_memory, cache_dir) = match &storage {
+      Storage::Tiered(tiers) => (tiers.max_each_size_on_memory, tiers.cache_dir.clone()),
+      Storage::Custom(_) => (max_each_size, PathBuf::new()),
+    };
 
+    // The body is shared with the requests waiting for the response, if any, as it is received
+    let fill = fill_guard
+      .as_ref()
+      .and_then(|guard| guard.share(res, cache_control.as_deref(), max_each_size));
+    let mut body = FillTee { body, fill };
     let (body_tx, body_rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(CACHE_STREAM_CHANNEL_CAPACITY);
 
     self.runtime_handle.spawn(async move {
@@ -135,16 +245,40 @@ impl RpxyCache {
       // The channel is bounded: when the downstream consumer is slower than the upstream, the
       // relay (and hence the upstream read and the store) pauses instead of queueing frames in
       // memory without bound.
-      let Some((target, hash)) = spool_and_store(body, body_tx, max_each_size, max_each_size_on_memory, &cache_dir, &uri).await
-      else {
+      let stored = spool_and_store(&mut body, body_tx, max_each_size, max_each_size_on_memory, &cache_dir, &meta).await;
+      // The requests waiting for the response are still relayed its body after the client of this one has gone
+      while body.is_shared() && matches!(body.frame().await, Some(Ok(_))) {}
+      let Some((target, hash)) = stored else {
         return;
       };
 
-      let cache_key = derive_cache_key_from_effective_uri(&uri);
-      let cache_object = CacheObject::new(policy_clone, target, hash);
-      // The file (if any) is now fully written and renamed into place, so it is safe to publish
-      // the metadata; this also accounts for the file count and evicts any displaced file.
-      publish_cache_object(&cache_manager, &file_store, &cache_key, cache_object).await;
+      let size = match &target {
+        CacheFileOrOnMemory::File(path) => fs::metadata(path).await.map(|m| m.len()).unwrap_or_default(),
+        CacheFileOrOnMemory::OnMemory(object) => object.len() as u64,
+        CacheFileOrOnMemory::External => 0,
+      };
+      let object_of = |target| {
+        CacheObject::new(policy, target, hash)
+          .with_size(size)
+          .with_surrogate_keys(meta.surrogate_keys.clone())
+          .with_vary(vary)
+          .with_ttl(ttl, cache_control)
+          .with_stale_response(stale_response)
+      };
+      match (storage, target) {
+        (Storage::Tiered(tiers), target) => {
+          // The file (if any) is now fully written and renamed into place, so it is safe to publish
+          // the metadata; this also accounts for the file count and evicts any displaced file.
+          let cache_object = object_of(target);
+          publish_cache_object(&tiers.index, &tiers.file_store, &cache_key, cache_object, purge_epoch).await;
+        }
+        (Storage::Custom(custom), CacheFileOrOnMemory::OnMemory(body)) => {
+          let entry = CacheEntry::new(object_of(CacheFileOrOnMemory::External), Some(Arc::new(meta)));
+          custom.put_if_not_purged(&cache_key, entry, body, purge_epoch).await;
+        }
+        (Storage::Custom(_), _) => {}
+      }
+      drop(fill_guard);
     });
 
     let stream_body = StreamBody::new(body_rx);
@@ -152,18 +286,64 @@ impl RpxyCache {
     Ok(stream_body)
   }
 
-  /// Get cached response
-  pub(crate) async fn get<R>(&self, req: &Request<R>) -> Option<Response<ResponseBody>> {
+  /// Look up the cache for the request. A stale entry is evicted unless it is still to be served as
+  /// allowed by `stale-while-revalidate` or `stale-if-error`.
+  pub(crate) async fn get<R>(&self, req: &Request<R>) -> CacheLookup {
     trace!("Current cache status: (total, on-memory, file) = {:?}", self.count().await);
-    let cache_key = derive_cache_key_from_effective_uri(req.uri());
+    let uri_key = derive_cache_key_from_effective_uri(req.uri());
+    let Ok(vary) = self.storage().vary(&uri_key).await else {
+      return CacheLookup::Miss(CacheForward::Miss);
+    };
+    let cache_key = derive_variant_key(&uri_key, &vary, req.headers());
 
     // First check cache chance
-    let cached_object = self.inner.get(&cache_key).ok()??;
+    let Ok(Some(entry)) = self.storage().get(&cache_key).await else {
+      // The URI has other variants only if it is known to vary
+      let reason = if vary.is_empty() {
+        CacheForward::UriMiss
+      } else {
+        CacheForward::VaryMiss
+      };
+      return CacheLookup::Miss(reason);
+    };
+    let cached_object = &entry.object;
 
-    // Secondly check the cache freshness as an HTTP message
+    // Secondly check the cache freshness as an HTTP message, comparing the request headers listed in
+    // `Vary` in the normalized form as stored
     let now = SystemTime::now();
-    let http_cache_semantics::BeforeRequest::Fresh(res_parts) = cached_object.policy.before_request(req, now) else {
-      // Evict stale cache entry.
+    let before_request = if vary.is_empty() {
+      cached_object.policy.before_request(req, now)
+    } else {
+      let mut normalized = Request::new(());
+      *normalized.method_mut() = req.method().clone();
+      *normalized.uri_mut() = req.uri().clone();
+      *normalized.headers_mut() = req.headers().clone();
+      normalize_headers(normalized.headers_mut(), &vary);
+      cached_object.policy.before_request(&normalized, now)
+    };
+    let (matches, validators) = match before_request {
+      BeforeRequest::Fresh(res_parts) => {
+        // Finally retrieve the file/on-memory object
+        let ttl = cached_object.policy.time_to_live(now).as_secs() as i64;
+        return match self.respond(req, &cache_key, &entry, res_parts).await {
+          Some(res) => CacheLookup::Fresh(with_status(res, CacheStatus::hit(uri_key, ttl))),
+          None => CacheLookup::Miss(CacheForward::Miss),
+        };
+      }
+      BeforeRequest::Stale { request, matches } => (matches, validators(&request.headers)),
+    };
+
+    // The entry is still usable for other requests unless stale, e.g., a request with `no-cache`.
+    if !cached_object.policy.is_stale(now) {
+      let reason = if matches { CacheForward::Request } else { CacheForward::Miss };
+      return CacheLookup::Miss(reason);
+    }
+    let stale_response = cached_object
+      .stale_response
+      .clone()
+      .filter(|stale_response| stale_response.is_usable(now));
+    if stale_response.is_none() && validators.is_empty() {
+      // Evict stale cache entry that can be neither served stale nor revalidated.
       // This might be okay to keep as is since it would be updated later.
       // However, there is no guarantee that newly got objects will be still cacheable.
       // So, we have to evict stale cache entries and cache file objects if found.
@@ -171,470 +351,737 @@ impl RpxyCache {
       // already replaced it with a fresh (live) entry that must not be removed, and whose file the
       // replacing store now owns.
       debug!("Stale cache entry: {cache_key}");
-      if self.inner.evict_if_generation(&cache_key, cached_object.generation).is_some()
-        && let CacheFileOrOnMemory::File(path) = &cached_object.target
-      {
-        self.file_store.evict(&path).await;
+      self.evict_if_generation(&cache_key, &entry).await;
+      return CacheLookup::Miss(CacheForward::Stale);
+    }
+    if !matches {
+      return CacheLookup::Miss(CacheForward::Miss);
+    }
+
+    // Serve the stale object right away if the request allows, and let the first request refresh it
+    if let Some(stale_response) = stale_response
+      && stale_response.staleness(now) <= stale_response.while_revalidate
+      && !requests_no_cache(req.headers())
+    {
+      let res_parts = stale_response.parts(&cached_object.policy, now);
+      let staleness = stale_response.staleness(now).as_secs() as i64;
+      let Some(res) = self.respond(req, &cache_key, &entry, res_parts).await else {
+        return CacheLookup::Miss(CacheForward::Miss);
+      };
+      let res = with_status(res, CacheStatus::hit(uri_key, -staleness));
+      debug!("Serve stale cache entry while revalidating: {cache_key}");
+      let revalidation = self.try_revalidate(&cache_key).map(|guard| {
+        let stale_entry = StaleEntry {
+          cache_key,
+          entry,
+          validators,
+        };
+        (guard, Box::new(stale_entry))
+      });
+      return CacheLookup::Revalidate(res, revalidation);
+    }
+    CacheLookup::Stale(Box::new(StaleEntry {
+      cache_key,
+      entry,
+      validators,
+    }))
+  }
+
+  /// Look up the cache like `get`, collapsing concurrent misses of the same entry. The first request to
+  /// miss gets the guard to fetch it from the upstream, while the others wait for the head of its response,
+  /// up to the coalescing timeout. They are relayed the body as it is received if the response is shared (see
+  /// `FillGuard::share`), and look up again otherwise. Only `GET` requests without `no-cache` are collapsed,
+  /// and a stale entry within `stale-if-error` is regarded as a miss.
+  pub(crate) async fn get_or_join<R>(&self, req: &Request<R>) -> (CacheLookup, Option<FillGuard>) {
+    let lookup = self.get(req).await;
+    if !matches!(lookup, CacheLookup::Miss(_) | CacheLookup::Stale(_))
+      || self.coalescing_timeout.is_zero()
+      || req.method() != Method::GET
+      || requests_no_cache(req.headers())
+    {
+      return (lookup, None);
+    }
+    let uri_key = derive_cache_key_from_effective_uri(req.uri());
+    let Ok(vary) = self.storage().vary(&uri_key).await else {
+      return (lookup, None);
+    };
+    let cache_key = derive_variant_key(&uri_key, &vary, req.headers());
+
+    let mut waiter = {
+      let Ok(mut filling) = self.filling.lock() else {
+        return (lookup, None);
+      };
+      match filling.get(&cache_key) {
+        Some(waiter) => waiter.clone(),
+        None => {
+          let (notifier, waiter) = watch::channel(FillHead::Pending);
+          filling.insert(cache_key.clone(), waiter);
+          let guard = FillGuard {
+            filling: self.filling.clone(),
+            cache_key,
+            notifier,
+          };
+          return (lookup, Some(guard));
+        }
+      }
+    };
+    debug!("Wait for the concurrent request fetching the cache entry: {cache_key}");
+    // Resolved with an error when the notifier is dropped with the guard, e.g., for a response not cacheable
+    let head = waiter.wait_for(|head| !matches!(head, FillHead::Pending));
+    let fill = match tokio::time::timeout(self.coalescing_timeout, head).await {
+      Ok(Ok(head)) => match &*head {
+        FillHead::Shared(fill) => Some(fill.clone()),
+        _ => None,
+      },
+      Ok(Err(_)) => None,
+      Err(_) => {
+        debug!("Timed out waiting for the concurrent request fetching the cache entry: {cache_key}");
+        None
+      }
+    };
+    let Some(fill) = fill else {
+      return (self.get(req).await, None);
+    };
+    let fwd = match lookup {
+      CacheLookup::Miss(fwd) => fwd,
+      _ => CacheForward::Stale,
+    };
+    debug!("Relay the response fetched by the concurrent request: {cache_key}");
+    (self.serve_fill(req, fill, fwd).await, None)
+  }
+
+  /// Serve the response shared by a concurrent request to the request waiting for it, as a cached response is
+  /// served for the conditionals and `Range` of the request
+  async fn serve_fill<R>(&self, req: &Request<R>, fill: Arc<Fill>, fwd: CacheForward) -> CacheLookup {
+    let status = CacheStatus {
+      fwd_status: Some(fill.status),
+      collapsed: true,
+      ..CacheStatus::forward(fwd, Some(derive_cache_key_from_effective_uri(req.uri())))
+    };
+    let res_parts = fill.head();
+    if res_parts.status == StatusCode::OK && is_not_modified(req, &res_parts.headers) {
+      return CacheLookup::Collapsed(with_status(not_modified(res_parts), status));
+    }
+    let res = Response::from_parts(res_parts, fill.relay(&self.runtime_handle));
+    let client_range = [header::RANGE, header::IF_RANGE]
+      .into_iter()
+      .filter_map(|name| req.headers().get(&name).map(|value| (name, value.clone())))
+      .collect::<HeaderMap>();
+    if req.method() != Method::GET || !client_range.contains_key(header::RANGE) {
+      return CacheLookup::Collapsed(with_status(res, status));
+    }
+    match self.serve_removed_range(&client_range, res).await {
+      Ok(res) => CacheLookup::Collapsed(with_status(res, status)),
+      Err(e) => {
+        debug!("Response fetched by the concurrent request cut short: {e}");
+        CacheLookup::Miss(fwd)
       }
+    }
+  }
+
+  /// Serve the stale entry found by `get` in place of a failed or 5xx upstream response, if it is still
+  /// within `stale-if-error`
+  pub(crate) async fn get_stale_if_error<R>(&self, stale_entry: &StaleEntry, req: &Request<R>) -> Option<Response<ResponseBody>> {
+    let StaleEntry { cache_key, entry, .. } = stale_entry;
+    let now = SystemTime::now();
+    let stale_response = entry.object.stale_response.as_ref()?;
+    if stale_response.staleness(now) > stale_response.if_error {
+      return None;
+    }
+    let res = self
+      .respond(req, cache_key, entry, stale_response.parts(&entry.object.policy, now))
+      .await?;
+    debug!("Serve stale cache entry on upstream error: {cache_key}");
+    let status = CacheStatus {
+      ttl: Some(-(stale_response.staleness(now).as_secs() as i64)),
+      ..CacheStatus::forward(CacheForward::Stale, Some(derive_cache_key_from_effective_uri(req.uri())))
+    };
+    Some(with_status(res, status))
+  }
+
+  /// Refresh the stale entry with the `304 Not Modified` response to its revalidation, and serve it with the
+  /// updated head, which is also written to the cache file or the custom storage holding the entry.
+  pub(crate) async fn refresh<B>(
+    &self,
+    stale_entry: &StaleEntry,
+    req: &Request<()>,
+    res: &Response<B>,
+  ) -> Option<Response<ResponseBody>> {
+    let StaleEntry { cache_key, entry, .. } = stale_entry;
+    let cached_object = &entry.object;
+    let mut normalized = Request::new(());
+    *normalized.method_mut() = req.method().clone();
+    *normalized.uri_mut() = req.uri().clone();
+    *normalized.headers_mut() = req.headers().clone();
+    normalize_headers(normalized.headers_mut(), &cached_object.vary);
+
+    let now = SystemTime::now();
+    let AfterResponse::NotModified(policy, mut res_parts) = cached_object.policy.after_response(&normalized, res, now) else {
+      debug!("Not modified response not matching the stale cache entry: {cache_key}");
       return None;
     };
+    // The head merged with the `304` is kept by a custom storage or in the cache file, from which the policy is
+    // rebuilt when got or restored
+    let meta = match (&entry.meta, &cached_object.target, self.tiers()) {
+      (Some(meta), ..) => Some(meta.clone()),
+      (None, CacheFileOrOnMemory::File(path), Some(tiers)) => match tiers.file_store.read_meta(path).await {
+        Ok(meta) => Some(Arc::new(meta)),
+        Err(e) => {
+          debug!("Cache file header of the revalidated stale entry not read: {cache_key}: {e}");
+          None
+        }
+      },
+      _ => None,
+    }
+    .map(|meta| Arc::new(meta.refreshed(&res_parts.headers, now)));
+    // The stored `Cache-Control` is the one rewritten for the TTLs of the route, unless updated by the `304`
+    if let Some(cache_control) = &cached_object.cache_control
+      && !res.headers().contains_key(header::CACHE_CONTROL)
+    {
+      set_cache_control(&mut res_parts.headers, cache_control);
+    }
+    let (policy, cache_control) = bound_ttl(
+      policy,
+      &normalized,
+      res_parts.status,
+      &res_parts.headers,
+      now,
+      &cached_object.ttl,
+    );
+    let stale_response = StaleResponse::new(&policy, res_parts.status, &res_parts.headers, now, self.stale_if_error_grace);
+    let refreshed = CacheObject::new(policy, cached_object.target.clone(), cached_object.hash.clone())
+      .with_size(cached_object.size)
+      .with_surrogate_keys(surrogate_keys(&res_parts.headers))
+      .with_vary(cached_object.vary.clone())
+      .with_ttl(cached_object.ttl, cache_control)
+      .with_stale_response(stale_response);
+    let refreshed = CacheEntry::new(refreshed, meta);
+    // The body is opened before the update, which may move it to another cache file
+    let served = self.respond(req, cache_key, &refreshed, res_parts).await?;
+    let stored = refreshed.object.policy.is_storable()
+      && match self.storage().update_entry(cache_key, refreshed.clone()).await {
+        Ok(()) => true,
+        Err(e) => {
+          debug!("Revalidated stale cache entry not updated: {cache_key}: {e}");
+          false
+        }
+      };
+    if stored {
+      debug!("Revalidated stale cache entry: {cache_key}");
+    }
+    let status = CacheStatus {
+      fwd_status: Some(res.status()),
+      ttl: Some(refreshed.object.policy.time_to_live(now).as_secs() as i64),
+      stored,
+      ..CacheStatus::forward(CacheForward::Stale, Some(derive_cache_key_from_effective_uri(req.uri())))
+    };
+    Some(with_status(served, status))
+  }
+
+  /// Evict the stale entry found by `get` unless it is still to be served stale, when the upstream
+  /// responded other than `304 Not Modified`
+  pub(crate) async fn discard(&self, stale_entry: StaleEntry) {
+    let StaleEntry { cache_key, entry, .. } = stale_entry;
+    let usable = entry
+      .object
+      .stale_response
+      .as_ref()
+      .is_some_and(|stale_response| stale_response.is_usable(SystemTime::now()));
+    if !usable {
+      self.evict_if_generation(&cache_key, &entry).await;
+    }
+  }
+
+  /// Evict the entry and its file if it is still of the generation, or delete the entry from a custom storage
+  async fn evict_if_generation(&self, cache_key: &str, entry: &CacheEntry) {
+    match &self.storage {
+      Storage::Tiered(tiers) => {
+        if tiers.index.evict_if_generation(cache_key, entry.object.generation).is_some()
+          && let CacheFileOrOnMemory::File(path) = &entry.object.target
+        {
+          tiers.file_store.evict(path).await;
+        }
+      }
+      Storage::Custom(custom) => {
+        if let Err(e) = custom.delete(cache_key).await {
+          warn!("Failed to delete cache entry from the storage: {e}");
+        }
+      }
+    }
+  }
+
+  /// Mark the entry as being refreshed, unless another request already does it
+  fn try_revalidate(&self, cache_key: &str) -> Option<RevalidationGuard> {
+    let mut revalidating = self.revalidating.lock().ok()?;
+    if !revalidating.insert(cache_key.to_string()) {
+      return None;
+    }
+    Some(RevalidationGuard {
+      revalidating: self.revalidating.clone(),
+      cache_key: cache_key.to_string(),
+    })
+  }
+
+  /// Run the refresh of a stale entry in background, during which no other refresh of the entry starts
+  pub(crate) fn revalidate<F>(&self, guard: RevalidationGuard, refresh: F)
+  where
+    F: Future<Output = ()> + Send + 'static,
+  {
+    self.runtime_handle.spawn(async move {
+      refresh.await;
+      drop(guard);
+    });
+  }
 
-    // Finally retrieve the file/on-memory object
-    let response_body = match cached_object.target {
-      CacheFileOrOnMemory::File(path) => {
-        let stream_body = match self.file_store.read(path.clone(), &cached_object.hash).await {
-          Ok(s) => s,
+  /// Build the response to the request from the file/on-memory object with the cached head. A conditional
+  /// request matching the object is answered with `304 Not Modified`, and a `Range` request with
+  /// `206 Partial Content`, or `416 Range Not Satisfiable` if none of the ranges is in the object.
+  async fn respond<R>(
+    &self,
+    req: &Request<R>,
+    cache_key: &str,
+    entry: &CacheEntry,
+    mut res_parts: response::Parts,
+  ) -> Option<Response<ResponseBody>> {
+    if let Some(cache_control) = &entry.object.cache_control {
+      set_cache_control(&mut res_parts.headers, cache_control);
+    }
+    if res_parts.status == StatusCode::OK && is_not_modified(req, &res_parts.headers) {
+      debug!("Cache hit (not modified): {cache_key}");
+      return Some(not_modified(res_parts));
+    }
+    if res_parts.status == StatusCode::OK
+      && req.method() == Method::GET
+      && if_range_matches(req.headers(), &res_parts.headers)
+      && let Some(ranges) = req.headers().get(header::RANGE).and_then(parse_range)
+    {
+      return self.read_ranges(cache_key, entry, res_parts, &ranges).await;
+    }
+    let body = self.read_body(cache_key, entry).await?;
+    Some(Response::from_parts(res_parts, body))
+  }
+
+  /// Serve the byte ranges of the file/on-memory object. Unlike a full read, a ranged read of a file object
+  /// cannot verify the hash of the whole body, so a corrupted file is left to be detected by a full read. The
+  /// body of an entry of a custom storage is read as a whole to be sliced.
+  async fn read_ranges(
+    &self,
+    cache_key: &str,
+    entry: &CacheEntry,
+    mut res_parts: response::Parts,
+    ranges: &[ByteRange],
+  ) -> Option<Response<ResponseBody>> {
+    let cached_object = &entry.object;
+    let (file, object) = match (&cached_object.target, self.tiers()) {
+      (CacheFileOrOnMemory::File(path), Some(tiers)) => match tiers.file_store.open_body(path).await {
+        Ok((file, body_start, len)) => (Some((file, body_start, len)), None),
+        Err(e) => {
+          warn!("Failed to read from file cache: {e}");
+          self.evict_if_generation(cache_key, entry).await;
+          return None;
+        }
+      },
+      (CacheFileOrOnMemory::File(_), None) => return None,
+      (CacheFileOrOnMemory::OnMemory(object), _) => (None, Some(object.clone())),
+      (CacheFileOrOnMemory::External, _) => {
+        let body = match self.storage().stream_body(cache_key, entry).await {
+          Ok(body) => body.collect().await,
+          Err(e) => Err(e),
+        };
+        match body {
+          Ok(object) => (None, Some(object)),
           Err(e) => {
-            warn!("Failed to read from file cache: {e}");
-            // Conditional eviction: only drop this entry/file if a concurrent re-store has not
-            // already replaced it under the same key (the replacement owns its own file).
-            if self.inner.evict_if_generation(&cache_key, cached_object.generation).is_some() {
-              self.file_store.evict(path).await;
-            }
+            warn!("Failed to read from cache storage: {e}");
+            self.evict_if_generation(cache_key, entry).await;
             return None;
           }
-        };
-        debug!("Cache hit from file: {cache_key}");
-        ResponseBody::Streamed(stream_body)
+        }
       }
-      CacheFileOrOnMemory::OnMemory(object) => {
-        // No integrity re-check here, unlike the file target. A file-backed object lives on disk
-        // (an external, mutable resource that can be corrupted or overwritten independently), so
-        // `FileStore::read` re-verifies its hash on every read. An on-memory object is an
-        // immutable `Bytes` held inside the same `CacheObject` as its `hash` and is never mutated
-        // after insertion, with no external aliasing. Re-hashing it on every hit only guards
-        // against in-RAM corruption, which the stored `hash` itself equally suffers, so it is not
-        // worth a full SHA-256 per hit.
-        debug!("Cache hit from on memory: {cache_key}");
-        ResponseBody::Boxed(BoxBody::new(full(object)))
+    };
+    let len = match (&file, &object) {
+      (Some((_, _, len)), _) => *len,
+      (None, Some(object)) => object.len() as u64,
+      (None, None) => return None,
+    };
+    let ranges = resolve_ranges(ranges, len);
+    if ranges.is_empty() {
+      debug!("Cache hit (range not satisfiable): {cache_key}");
+      return Some(range_not_satisfiable(res_parts, len));
+    }
+    let boundary = cached_object
+      .hash
+      .iter()
+      .take(12)
+      .map(|b| format!("{b:02x}"))
+      .collect::<String>();
+    let segments = partial_content(&mut res_parts, &ranges, len, &boundary);
+    let body = match (file, object, self.tiers()) {
+      (Some((file, body_start, _)), _, Some(tiers)) => {
+        debug!("Cache hit (partial) from file: {cache_key}");
+        ResponseBody::Streamed(tiers.file_store.read_segments(file, body_start, segments))
+      }
+      (None, Some(object), _) => {
+        debug!("Cache hit (partial) from on memory: {cache_key}");
+        ResponseBody::Boxed(BoxBody::new(full(slice_segments(&object, &segments))))
       }
+      _ => return None,
     };
-    Some(Response::from_parts(res_parts, response_body))
+    Some(Response::from_parts(res_parts, body))
+  }
+
+  /// Serve `Range` of the client, removed from the request to fill the cache with the full response, from the
+  /// response, buffering its body to be sliced only if within the size of a cacheable object
+  pub(crate) async fn serve_removed_range(
+    &self,
+    client_range: &HeaderMap,
+    res: Response<ResponseBody>,
+  ) -> crate::error::RpxyResult<Response<ResponseBody>> {
+    cache_range::serve_removed_range(client_range, res, self.max_each_size).await
+  }
+
+  /// Retrieve the body of the entry from the storage, evicting the entry if unreadable
+  async fn read_body(&self, cache_key: &str, entry: &CacheEntry) -> Option<ResponseBody> {
+    match self.storage().stream_body(cache_key, entry).await {
+      Ok(body) => Some(body.into_response_body(&self.runtime_handle)),
+      Err(e) => {
+        warn!("Failed to read from cache storage: {e}");
+        // Conditional eviction: only drop this entry/file if a concurrent re-store has not
+        // already replaced it under the same key (the replacement owns its own file).
+        self.evict_if_generation(cache_key, entry).await;
+        None
+      }
+    }
   }
-}
 
-/* ---------------------------------------------- */
-/// Monotonic counter making temp/final cache file names process-unique (see `unique_cache_paths`).
-static CACHE_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
-
-/// Build a `(temp, final)` path pair with a process-unique name in `cache_dir`. The final name is
-/// generation-unique - not merely URI-derived - so concurrent stores of the same URI never collide
-/// or clobber each other's file; each cache entry references its own immutable file. The
-/// URI-derived prefix is kept only for human debuggability.
-fn unique_cache_paths(cache_dir: &Path, uri: &Uri) -> (PathBuf, PathBuf) {
-  let base = derive_filename_from_uri(uri);
-  let nanos = SystemTime::now()
-    .duration_since(std::time::UNIX_EPOCH)
-    .map(|d| d.as_nanos())
-    .unwrap_or(0);
-  let seq = CACHE_FILE_SEQ.fetch_add(1, Ordering::Relaxed);
-  let unique = format!("{base}-{}-{nanos}-{seq}", std::process::id());
-  let final_path = cache_dir.join(&unique);
-  let temp_path = cache_dir.join(format!("{unique}.tmp"));
-  (temp_path, final_path)
+  /// Purge the entries matching the target from the storage, returning the number of purged entries. Stores
+  /// in flight are not published afterwards (see `put`).
+  pub(crate) async fn purge(&self, target: &PurgeTarget) -> CacheResult<usize> {
+    let purged = self.storage().purge(target).await?;
+    info!("Purged {purged} cache entries: {target:?}");
+    Ok(purged)
+  }
 }
 
-/// Unlink a cache file WITHOUT adjusting the file-store count. A missing file is ignored. Used
-/// wherever the count must not change: temp files that never reached commit (never counted), and
-/// the integrity-check (hash mismatch) removal, where the file IS counted but its LRU metadata
-/// still exists - there the count is reconciled later, when that metadata is evicted via the
-/// counted `FileStore::evict`/`remove` (which tolerates the already-missing file). Counted files
-/// whose metadata is already gone go through `FileStore::evict` directly instead.
-async fn remove_uncounted_file(path: &Path) {
-  if let Err(e) = fs::remove_file(path).await
-    && e.kind() != std::io::ErrorKind::NotFound
-  {
-    warn!("Failed to remove uncommitted cache file {path:?}: {e}");
-  }
+/* ---------------------------------------------- */
+/// Result of a cache lookup
+pub(crate) enum CacheLookup {
+  /// Fresh cached response
+  Fresh(Response<ResponseBody>),
+  /// Stale cached response within `stale-while-revalidate`, with the guard and the entry if the request is
+  /// to refresh it
+  Revalidate(Response<ResponseBody>, Option<(RevalidationGuard, Box<StaleEntry>)>),
+  /// Stale entry to be revalidated with the upstream, or served if the upstream fails within `stale-if-error`
+  Stale(Box<StaleEntry>),
+  /// No usable cache entry, with the reason
+  Miss(CacheForward),
+  /// Response fetched by a concurrent request for the same entry, relayed as its body is received
+  Collapsed(Response<ResponseBody>),
 }
 
-/// An in-progress file-cache write: data is appended to a temp file that is atomically renamed to
-/// its final path on `commit`. The file-store count is intentionally NOT touched here; it is bumped
-/// by `publish_cache_object` (just before publishing the metadata).
-struct SpillFile {
-  file: File,
-  temp_path: PathBuf,
-  final_path: PathBuf,
+/// Stale cache entry kept by the request until the upstream responds
+pub(crate) struct StaleEntry {
+  cache_key: String,
+  entry: CacheEntry,
+  /// `If-None-Match` and `If-Modified-Since` to revalidate the entry, empty if it has no validator
+  validators: HeaderMap,
 }
 
-impl SpillFile {
-  /// Create a fresh temp file with a generation-unique name in `cache_dir`. `create_new(true)`
-  /// refuses to follow or overwrite an existing file/symlink.
-  async fn create(cache_dir: &Path, uri: &Uri) -> CacheResult<Self> {
-    let (temp_path, final_path) = unique_cache_paths(cache_dir, uri);
-    let file = OpenOptions::new()
-      .write(true)
-      .create_new(true)
-      .open(&temp_path)
-      .await
-      .map_err(|e| {
-        error!("Failed to create temp cache file {temp_path:?}: {e}");
-        CacheError::FailedToCreateFileCache
-      })?;
-    Ok(Self {
-      file,
-      temp_path,
-      final_path,
-    })
+impl StaleEntry {
+  /// Make the request to the upstream conditional to revalidate the entry, unless it has no validator or
+  /// the client made the request conditional by itself. Returns whether the request was made conditional.
+  pub(crate) fn make_conditional<B>(&self, req: &mut Request<B>) -> bool {
+    let conditional_by_client = [
+      header::IF_MATCH,
+      header::IF_NONE_MATCH,
+      header::IF_MODIFIED_SINCE,
+      header::IF_UNMODIFIED_SINCE,
+      header::IF_RANGE,
+    ]
+    .iter()
+    .any(|name| req.headers().contains_key(name));
+    if self.validators.is_empty() || conditional_by_client {
+      return false;
+    }
+    req.headers_mut().extend(self.validators.clone());
+    true
   }
+}
 
-  /// Append `data` to the temp file.
-  async fn write(&mut self, data: &[u8]) -> CacheResult<()> {
-    self.file.write_all(data).await.map_err(|e| {
-      error!("Failed to write temp cache file {:?}: {e}", self.temp_path);
-      CacheError::FailedToWriteFileCache
-    })
-  }
+/// Marks a stale cache entry as being refreshed in background until dropped
+pub(crate) struct RevalidationGuard {
+  revalidating: Arc<Mutex<HashSet<String>>>,
+  cache_key: String,
+}
 
-  /// Flush and atomically rename the temp file to its final path, returning that path. On any
-  /// failure the temp file is removed and an error is returned.
-  async fn commit(self) -> CacheResult<PathBuf> {
-    let SpillFile {
-      mut file,
-      temp_path,
-      final_path,
-    } = self;
-    if let Err(e) = file.flush().await {
-      error!("Failed to flush temp cache file {temp_path:?}: {e}");
-      drop(file);
-      remove_uncounted_file(&temp_path).await;
-      return Err(CacheError::FailedToWriteFileCache);
-    }
-    drop(file); // close the handle before renaming
-    if let Err(e) = fs::rename(&temp_path, &final_path).await {
-      error!("Failed to rename cache file {temp_path:?} -> {final_path:?}: {e}");
-      remove_uncounted_file(&temp_path).await;
-      return Err(CacheError::FailedToRenameCacheFile);
+impl Drop for RevalidationGuard {
+  fn drop(&mut self) {
+    if let Ok(mut revalidating) = self.revalidating.lock() {
+      revalidating.remove(&self.cache_key);
     }
-    Ok(final_path)
   }
+}
 
-  /// Discard the in-progress temp file (close + unlink). Does not touch the file-store count.
-  async fn abort(self) {
-    let SpillFile { file, temp_path, .. } = self;
-    drop(file);
-    remove_uncounted_file(&temp_path).await;
-  }
+/// Marks a cache entry as being fetched from the upstream until dropped, when the requests waiting for
+/// it are woken up
+pub(crate) struct FillGuard {
+  filling: Arc<Mutex<HashMap<String, watch::Receiver<FillHead>>>>,
+  cache_key: String,
+  /// Notifying the waiting requests of the response, and dropped after the entry is removed from `filling`
+  notifier: watch::Sender<FillHead>,
 }
 
-/// Forward `body` downstream frame by frame while attempting to cache it.
-///
-/// Hard invariant: a cache-side failure - too-large body, upstream body error, or any file I/O
-/// failure - must NEVER cut the downstream relay. Every frame is forwarded first; caching is then
-/// attempted as a side effect and silently abandoned (cleaning up any temp file) on failure.
-///
-/// `body_tx` is bounded, so forwarding awaits a free slot when the downstream consumer lags:
-/// backpressure pauses the relay (and, transitively, the upstream read and the store) instead of
-/// queueing frames in memory without bound. Pausing is not cutting - the send fails only when the
-/// receiver is dropped, exactly the case the relay has nothing left to forward to.
-///
-/// Returns `Some((target, hash))` when the object was fully and successfully stored (on memory, or
-/// streamed to a committed file), `None` otherwise. `body_tx` is taken by value and dropped on
-/// return, so `body_rx` reaches a clean EOF as soon as streaming finishes.
-async fn spool_and_store<B, E>(
-  mut body: B,
-  mut body_tx: mpsc::Sender<Result<Frame<Bytes>, E>>,
-  max_each_size: usize,
-  max_each_size_on_memory: usize,
-  cache_dir: &Path,
-  uri: &Uri,
-) -> Option<(CacheFileOrOnMemory, Bytes)>
-where
-  B: hyper::body::Body<Data = Bytes, Error = E> + Unpin,
-{
-  let mut hasher = Sha256::new();
-  let mut buf = BytesMut::new(); // Phase M: in-memory buffer until the on-memory threshold
-  let mut size: usize = 0;
-  let mut cacheable = true;
-  let mut spill: Option<SpillFile> = None; // Phase F: present once spilled to a temp file
-
-  while let Some(frame) = body.frame().await {
-    // Take the cache-side data handle before the frame is moved into the send. `Bytes` is
-    // reference-counted, so this is a cheap Arc bump, not a body copy; `None` for an error item or
-    // a non-data frame (e.g. trailers).
-    let data = frame.as_ref().ok().and_then(|f| f.data_ref().cloned());
-    let was_err = frame.is_err();
-
-    // Forward downstream first; the relay is never cut by cache work. The bounded send awaits a
-    // free slot when the consumer lags (backpressure) and errs only on a dropped receiver.
-    if body_tx.send(frame).await.is_err() {
-      // Downstream receiver is gone; nothing left to forward or cache.
-      if let Some(s) = spill.take() {
-        s.abort().await;
-      }
+impl FillGuard {
+  /// Share the cacheable response with the requests waiting for it, if its length is known and within `max_len`.
+  /// Otherwise, they are woken up to go to the upstream by themselves rather than waiting for the whole body.
+  fn share(&self, res: &response::Parts, cache_control: Option<&[HeaderValue]>, max_len: usize) -> Option<Arc<Fill>> {
+    let Some(len) = content_length(&res.headers).filter(|len| *len <= max_len as u64) else {
+      self.notifier.send_replace(FillHead::Unshared);
       return None;
+    };
+    let mut headers = res.headers.clone();
+    if let Some(cache_control) = cache_control {
+      set_cache_control(&mut headers, cache_control);
     }
+    let fill = Arc::new(Fill {
+      status: res.status,
+      version: res.version,
+      headers,
+      body: Mutex::new(FillBody {
+        len,
+        ..Default::default()
+      }),
+      progress: watch::Sender::new(()),
+      followers: Default::default(),
+    });
+    self.notifier.send_replace(FillHead::Shared(fill.clone()));
+    Some(fill)
+  }
+}
 
-    if !cacheable {
-      continue; // keep draining/forwarding, but no longer caching
+impl Drop for FillGuard {
+  fn drop(&mut self) {
+    if let Ok(mut filling) = self.filling.lock() {
+      filling.remove(&self.cache_key);
     }
+  }
+}
 
-    // Upstream body error: a complete object cannot be cached. The error frame was already
-    // forwarded above so the downstream consumer observes it instead of a silent EOF.
-    if was_err {
-      cacheable = false;
-      if let Some(s) = spill.take() {
-        s.abort().await;
-      }
-      buf = BytesMut::new();
-      continue;
-    }
+#[derive(Clone, Debug, Default)]
+/// Response of a cache entry being fetched, as notified to the requests waiting for it
+enum FillHead {
+  /// The response has not arrived yet
+  #[default]
+  Pending,
+  /// The response is cacheable and shared as its body is received
+  Shared(Arc<Fill>),
+  /// The response is cacheable but not shared, of an unknown length or too large
+  Unshared,
+}
 
-    let Some(data) = data else {
-      continue; // non-data frame: forward only
-    };
+#[derive(Debug)]
+/// Cacheable response being fetched, relayed to the requests waiting for it. The body received so far is kept
+/// on memory until all of them are served, for the requests joining later.
+struct Fill {
+  status: StatusCode,
+  version: http::Version,
+  headers: HeaderMap,
+  body: Mutex<FillBody>,
+  /// Notified of every update of the body
+  progress: watch::Sender<()>,
+  /// Number of requests being relayed the body
+  followers: AtomicUsize,
+}
 
-    // `saturating_add` keeps the size check correct even against a pathologically large frame
-    // length, so the limit can never be bypassed by integer overflow.
-    if size.saturating_add(data.len()) > max_each_size {
-      debug!("Response exceeds max_each_size ({max_each_size} bytes); forwarding without caching");
-      cacheable = false;
-      if let Some(s) = spill.take() {
-        s.abort().await;
-      }
-      buf = BytesMut::new();
-      continue;
-    }
-    size = size.saturating_add(data.len());
-    hasher.update(data.as_ref());
+#[derive(Debug, Default)]
+/// Body of a shared response received so far
+struct FillBody {
+  chunks: Vec<Bytes>,
+  received: u64,
+  /// Length given by `Content-Length`, beyond which the body is cut
+  len: u64,
+  /// Whether the body has ended, completely or not
+  ended: bool,
+}
 
-    if spill.is_some() {
-      // Phase F: write straight to the temp file.
-      if spill.as_mut().unwrap().write(data.as_ref()).await.is_err() {
-        cacheable = false;
-        spill.take().unwrap().abort().await;
+impl Fill {
+  /// Head of the response
+  fn head(&self) -> response::Parts {
+    let (mut res_parts, _) = Response::new(()).into_parts();
+    res_parts.status = self.status;
+    res_parts.version = self.version;
+    res_parts.headers = self.headers.clone();
+    res_parts
+  }
+
+  /// Append the data received, ending the body if it exceeds `Content-Length`
+  fn push(&self, data: &Bytes) {
+    self.update(|body| {
+      if body.received.saturating_add(data.len() as u64) > body.len {
+        body.ended = true;
+      } else if !body.ended {
+        body.received += data.len() as u64;
+        body.chunks.push(data.clone());
       }
-    } else if buf.len().saturating_add(data.len()) > max_each_size_on_memory {
-      // Phase M crossing the on-memory threshold: spill to a temp file. Write the already-buffered
-      // bytes and this frame straight to disk rather than first growing `buf` by a potentially
-      // large frame, which would defeat the point of bounding store-path memory.
-      match SpillFile::create(cache_dir, uri).await {
-        Ok(mut s) => {
-          if s.write(buf.as_ref()).await.is_err() || s.write(data.as_ref()).await.is_err() {
-            cacheable = false;
-            s.abort().await;
-          } else {
-            spill = Some(s);
-          }
-        }
-        Err(_) => {
-          // Could not create a temp file; give up caching but keep forwarding.
-          cacheable = false;
-        }
-      }
-      buf = BytesMut::new(); // free the in-memory copy regardless of spill outcome
-    } else {
-      // Phase M still under the on-memory threshold: keep buffering on memory.
-      buf.extend_from_slice(data.as_ref());
-    }
+    });
   }
 
-  if !cacheable {
-    return None; // any temp file was already aborted above
+  /// End the body. Ended short of `Content-Length`, the relayed bodies are cut short as well.
+  fn end(&self) {
+    self.update(|body| body.ended = true);
   }
 
-  let hash = Bytes::copy_from_slice(hasher.finalize().as_ref());
-  match spill {
-    // Phase F: commit the temp file to its final path.
-    Some(s) => match s.commit().await {
-      Ok(final_path) => Some((CacheFileOrOnMemory::File(final_path), hash)),
-      Err(_) => None, // commit failed and cleaned up its temp; nothing to publish
-    },
-    // Phase M: small enough to stay on memory.
-    None => Some((CacheFileOrOnMemory::OnMemory(buf.freeze()), hash)),
+  fn update(&self, f: impl FnOnce(&mut FillBody)) {
+    if let Ok(mut body) = self.body.lock() {
+      f(&mut body);
+    }
+    self.progress.send_replace(());
   }
-}
 
-/// Publish a freshly stored cache object's metadata into the LRU, accounting for the file-store
-/// count and evicting any displaced entry's file.
-///
-/// Ordering matters for correctness: a file is counted (`incr_count`) BEFORE its metadata is
-/// published, so a `File` entry visible in the LRU is always already counted and a concurrent
-/// eviction can never decrement a not-yet-counted file (the count and the LRU use separate locks).
-/// If `push()` fails (poisoned mutex), the just-counted file is rolled back (unlink + decrement) via
-/// `evict`. The displaced entry's file is evicted after a successful `push()`.
-async fn publish_cache_object(
-  cache_manager: &LruCacheManager,
-  file_store: &FileStore,
-  cache_key: &str,
-  cache_object: CacheObject,
-) {
-  let new_file_path = match &cache_object.target {
-    CacheFileOrOnMemory::File(path) => Some(path.clone()),
-    CacheFileOrOnMemory::OnMemory(_) => None,
-  };
+  /// Whether the body is being relayed to any request and has not ended
+  fn is_relayed(&self) -> bool {
+    self.followers.load(Ordering::Relaxed) > 0 && self.body.lock().is_ok_and(|body| !body.ended)
+  }
 
-  // Count a file-backed object BEFORE publishing its metadata. The file count and the LRU map are
-  // guarded by different locks, so this ordering upholds the invariant "a File entry visible in the
-  // LRU has already been counted": any concurrent eviction that observes the published entry always
-  // finds a count it can safely decrement, instead of decrementing a not-yet-counted file (which
-  // would underflow). The transient `file > total` this opens (counted, not yet in the LRU) is
-  // tolerated by `count()` via `saturating_sub`.
-  if new_file_path.is_some() {
-    file_store.incr_count().await;
-  }
-
-  match cache_manager.push(cache_key, &cache_object) {
-    Err(e) => {
-      // Metadata could not be published; roll back both the count and the committed-but-unpublished
-      // file so neither leaks (the file was already counted just above).
-      warn!("Failed to publish cache entry: {e}");
-      if let Some(path) = &new_file_path {
-        file_store.evict(path).await;
-      }
-    }
-    Ok(displaced) => {
-      // Evict the displaced entry's file (same-key update or capacity eviction), unless it is the
-      // very file just published (only possible without generation-unique paths).
-      if let Some((_, v)) = displaced
-        && let CacheFileOrOnMemory::File(old_path) = v.target
-        && Some(&old_path) != new_file_path.as_ref()
-      {
-        info!("Evicting displaced cache file");
-        file_store.evict(&old_path).await;
+  /// Body relaying the data received so far and the rest as it is received
+  fn relay(self: Arc<Self>, runtime_handle: &tokio::runtime::Handle) -> ResponseBody {
+    let (mut body_tx, body_rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(CACHE_STREAM_CHANNEL_CAPACITY);
+    let mut progress = self.progress.subscribe();
+    self.followers.fetch_add(1, Ordering::Relaxed);
+    runtime_handle.spawn(async move {
+      let mut next = 0;
+      'relay: loop {
+        progress.borrow_and_update();
+        let Ok((chunks, ended)) = self.body.lock().map(|body| (body.chunks[next..].to_vec(), body.ended)) else {
+          break;
+        };
+        next += chunks.len();
+        for chunk in chunks {
+          if body_tx.send(Ok(Frame::data(chunk))).await.is_err() {
+            break 'relay;
+          }
+        }
+        if ended || progress.changed().await.is_err() {
+          break;
+        }
       }
-    }
+      self.followers.fetch_sub(1, Ordering::Relaxed);
+    });
+    ResponseBody::Streamed(StreamBody::new(body_rx))
   }
 }
 
-/* ---------------------------------------------- */
-#[derive(Debug, Clone)]
-/// Cache file manager. Lock-free by design: committed cache files are immutable and live at
-/// generation-unique paths, so the only shared mutable state is the best-effort count of
-/// committed file-cache objects. Keeping that count in an atomic (instead of a lock held across
-/// file I/O) means a store's publish can never queue behind another task's unlink or open -
-/// under sustained store-and-evict churn a single slow unlink previously serialized every
-/// in-flight publish behind one exclusive lock, stalling publication entirely while
-/// committed-but-unpublished files accumulated on disk without bound.
-struct FileStore {
-  /// Approximate count of committed file-cache objects (best-effort by design; see `count`).
-  cnt: Arc<AtomicUsize>,
-  /// Async runtime
-  runtime_handle: tokio::runtime::Handle,
+/// Body of a response being fetched, copying the data to the response shared with the waiting requests if any
+struct FillTee<B> {
+  body: B,
+  fill: Option<Arc<Fill>>,
 }
 
-impl FileStore {
-  #[allow(unused)]
-  /// Build manager
-  async fn new(runtime_handle: &tokio::runtime::Handle) -> Self {
-    Self {
-      cnt: Arc::new(AtomicUsize::new(0)),
-      runtime_handle: runtime_handle.clone(),
-    }
+impl<B> FillTee<B> {
+  /// Whether the body is being relayed to the waiting requests
+  fn is_shared(&self) -> bool {
+    self.fill.as_ref().is_some_and(|fill| fill.is_relayed())
   }
+}
 
-  /// Count file cache entries
-  async fn count(&self) -> usize {
-    self.cnt.load(Ordering::Relaxed)
+impl<B> Body for FillTee<B>
+where
+  B: Body<Data = Bytes> + Unpin,
+{
+  type Data = Bytes;
+  type Error = B::Error;
+
+  fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+    let polled = Pin::new(&mut self.body).poll_frame(cx);
+    if let Some(fill) = &self.fill {
+      match &polled {
+        Poll::Ready(Some(Ok(frame))) => {
+          if let Some(data) = frame.data_ref() {
+            fill.push(data);
+          }
+        }
+        Poll::Ready(_) => fill.end(),
+        Poll::Pending => {}
+      }
+    }
+    polled
   }
 
-  /// Account for a newly committed file-cache object whose file is already renamed into place.
-  /// Must be called BEFORE the corresponding metadata is published into the LRU, so that a visible
-  /// File entry is always already counted (see `publish_cache_object`). That invariant is an
-  /// ordering property, not a mutual-exclusion one, so a plain atomic increment upholds it.
-  async fn incr_count(&self) {
-    self.cnt.fetch_add(1, Ordering::Relaxed);
+  fn is_end_stream(&self) -> bool {
+    self.body.is_end_stream()
   }
 
-  /// Evict a counted file cache object, logs warning if removal fails
-  async fn evict(&self, path: impl AsRef<Path>) {
-    if let Err(e) = self.remove(path).await {
-      warn!("Eviction failed during file object removal: {:?}", e);
-    }
+  fn size_hint(&self) -> SizeHint {
+    self.body.size_hint()
   }
+}
 
-  /// Remove a counted file-cache object.
-  ///
-  /// The count is decremented **regardless of whether the unlink succeeds**: the caller has decided
-  /// to evict this counted file (its LRU metadata is already gone), so it is no longer a live counted
-  /// object even if the file was already removed externally or by the integrity-check path. Only a
-  /// genuine I/O error (not "already gone") is surfaced. Otherwise the file count leaks above the
-  /// number of live entries. No lock is held across the unlink: the file is immutable at a
-  /// generation-unique path, so the I/O needs no exclusion and concurrent removals proceed in
-  /// parallel instead of queueing publishers behind one another.
-  async fn remove(&self, path: impl AsRef<Path>) -> CacheResult<()> {
-    // Saturate rather than underflow: the count and the LRU are updated independently, so a
-    // pathological concurrent ordering could otherwise drive a `usize` below zero (wraparound).
-    // `incr_count`-before-publish makes this unreachable in practice.
-    let _ = self
-      .cnt
-      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(c.saturating_sub(1)));
-    debug!(
-      "Removed a cache file at {:?} (file count: {})",
-      path.as_ref(),
-      self.cnt.load(Ordering::Relaxed)
-    );
-
-    match fs::remove_file(path.as_ref()).await {
-      Ok(()) => {}
-      // Already gone (e.g. removed externally or by the integrity-check path); the count correction
-      // above still stands, so this is not an error.
-      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
-      Err(e) => return Err(CacheError::FailedToRemoveCacheFile(e.to_string())),
+impl<B> Drop for FillTee<B> {
+  fn drop(&mut self) {
+    if let Some(fill) = &self.fill {
+      fill.end();
     }
-
-    Ok(())
   }
+}
 
-  /// Read a stored file-cache object, returns error if the file cannot be opened. The integrity
-  /// hash is verified incrementally by the producer task and acted on at EOF.
-  async fn read(&self, path: impl AsRef<Path> + Send + Sync + 'static, hash: &Bytes) -> CacheResult<BoundedStreamBody> {
-    let Ok(mut file) = File::open(&path).await else {
-      warn!("Cache file object cannot be opened");
-      return Err(CacheError::FailedToOpenCacheFile);
-    };
-    let hash_clone = hash.clone();
-
-    let (mut body_tx, body_rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(CACHE_STREAM_CHANNEL_CAPACITY);
-
-    self.runtime_handle.spawn(async move {
-      let mut hasher = Sha256::new();
-      let mut buf = BytesMut::with_capacity(FILE_CACHE_READ_CHUNK);
-      loop {
-        // Reserve a fresh chunk only when the spare capacity is exhausted. After `split()` the
-        // buffer keeps whatever spare it had, so a small object is read into the initial
-        // capacity and the EOF-confirming read reuses the leftover spare without allocating.
-        if buf.capacity() == buf.len() {
-          buf.reserve(FILE_CACHE_READ_CHUNK);
-        }
-        match file.read_buf(&mut buf).await {
-          Ok(0) => break,
-          Ok(_) => {
-            // Hand the filled bytes off zero-copy; `buf` keeps the remaining spare capacity.
-            // The bounded send awaits a free slot when the consumer lags, so a slow client
-            // paces the file read instead of queueing the whole object in memory. It errs only
-            // when the receiver is dropped; the early return then skips the integrity check
-            // below (an incomplete hash proves nothing) and leaves the file in place.
-            let bytes = buf.split().freeze();
-            hasher.update(bytes.as_ref());
-            body_tx
-              .send(Ok(Frame::data(bytes)))
-              .await
-              .map_err(|e| CacheError::FailedToSendFrameFromCache(e.to_string()))?
-          }
-          Err(_) => break,
-        };
-      }
-      let hash_bytes = Bytes::copy_from_slice(hasher.finalize().as_ref());
-      if hash_bytes != hash_clone {
-        warn!("Hash mismatched. Cache object is corrupted. Force to remove the cache file.");
-        // Unlink WITHOUT touching the count. The LRU entry pointing at this file still exists;
-        // the count is reconciled when that entry is evicted through the metadata path, whose
-        // counted removal tolerates the already-missing file. A counted removal here would
-        // decrement twice for one object. (This matches the previous behavior, where this path
-        // operated on a clone holding a copied counter.)
-        remove_uncounted_file(path.as_ref()).await;
-        return Err(CacheError::HashMismatchedInCacheFile);
-      }
-      Ok(()) as CacheResult<()>
-    });
+/// Length of the body given by `Content-Length`
+pub(super) fn content_length(headers: &HeaderMap) -> Option<u64> {
+  headers
+    .get(header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<u64>().ok())
+}
 
-    let stream_body = StreamBody::new(body_rx);
+/* ---------------------------------------------- */
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Cache entries to be purged
+pub enum PurgeTarget {
+  /// Entry of the exact client-facing URL, e.g., `https://example.com/path?query`
+  Url(Uri),
+  /// Entries of the host (any port), only those whose path starts with the prefix if given
+  Host { host: String, path_prefix: Option<String> },
+  /// Entries whose path starts with the prefix, of any host
+  PathPrefix(String),
+  /// Entries tagged with the key by the `Surrogate-Key` or `Cache-Tag` response header
+  SurrogateKey(String),
+}
 
-    Ok(stream_body)
+impl PurgeTarget {
+  /// Whether the entry stored under the cache key matches this target
+  pub fn matches(&self, cache_key: &str, entry: &CacheEntry) -> bool {
+    self.matches_object(cache_key, &entry.object)
+  }
+
+  /// Whether the object stored under the cache key matches this target
+  pub(super) fn matches_object(&self, cache_key: &str, cache_object: &CacheObject) -> bool {
+    let path_matches = |uri: &Uri, prefix: &str| uri.path().starts_with(prefix);
+    let uri_key = uri_of_cache_key(cache_key);
+    match self {
+      PurgeTarget::Url(uri) => uri_key == derive_cache_key_from_effective_uri(uri),
+      PurgeTarget::Host { host, path_prefix } => uri_key.parse::<Uri>().is_ok_and(|uri| {
+        uri.host().is_some_and(|h| h.eq_ignore_ascii_case(host))
+          && path_prefix.as_deref().is_none_or(|prefix| path_matches(&uri, prefix))
+      }),
+      PurgeTarget::PathPrefix(prefix) => uri_key.parse::<Uri>().is_ok_and(|uri| path_matches(&uri, prefix)),
+      PurgeTarget::SurrogateKey(key) => cache_object.surrogate_keys.iter().any(|k| k == key),
+    }
   }
 }
 
+/// Surrogate keys tagging a response for purge, from the `Surrogate-Key` and `Cache-Tag` headers
+pub(super) fn surrogate_keys(headers: &HeaderMap) -> Vec<String> {
+  let mut keys = SURROGATE_KEY_HEADERS
+    .iter()
+    .flat_map(|name| headers.get_all(*name))
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(|c: char| c == ',' || c.is_ascii_whitespace()))
+    .filter(|k| !k.is_empty())
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+  keys.sort_unstable();
+  keys.dedup();
+  keys
+}
+
 /* ---------------------------------------------- */
 
 #[derive(Clone, Debug)]
@@ -644,6 +1091,8 @@ pub(crate) enum CacheFileOrOnMemory {
   File(PathBuf),
   /// Cached body itself
   OnMemory(Bytes),
+  /// Body held by a custom storage
+  External,
 }
 
 /// Monotonic counter assigning each stored `CacheObject` a unique generation (see `CacheObject`).
@@ -651,111 +1100,192 @@ static CACHE_OBJECT_GEN: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Clone, Debug)]
 /// Cache object definition
-struct CacheObject {
+pub(super) struct CacheObject {
   /// Cache policy to determine if the stored cache can be used as a response to a new incoming request
-  policy: CachePolicy,
+  pub(super) policy: CachePolicy,
   /// Cache target: on-memory object or temporary file
-  target: CacheFileOrOnMemory,
+  pub(super) target: CacheFileOrOnMemory,
   /// SHA256 hash used to verify file-backed cache targets on read; still computed at store time
   /// before the file/on-memory target is selected. Not consulted on on-memory hits (the object is
   /// an immutable in-process `Bytes`, so there is no external mutation to detect).
-  hash: Bytes,
+  pub(super) hash: Bytes,
   /// Process-unique generation id assigned at store time. Lets an eviction triggered from a stale
   /// snapshot (e.g. a concurrent `get()`) pop the entry only if it is still the same generation,
   /// so it cannot delete a newer live entry that a concurrent re-store published under the same key.
-  generation: u64,
+  pub(super) generation: u64,
+  /// Surrogate keys given by the upstream response, to purge the object by tag
+  pub(super) surrogate_keys: Vec<String>,
+  /// Request header names listed in `Vary` of the response, empty if it does not vary
+  pub(super) vary: Vec<HeaderName>,
+  /// Response head to serve the object once stale, if allowed
+  pub(super) stale_response: Option<Arc<StaleResponse>>,
+  /// Bytes counted against the budget of the tier: the length of the body on memory, or of the file
+  pub(super) size: u64,
+  /// Bounds of the freshness lifetime by the cache rule of the route
+  pub(super) ttl: CacheTtl,
+  /// `Cache-Control` fields of the response, served in place of the ones rewritten for the bounded lifetime
+  pub(super) cache_control: Option<Vec<HeaderValue>>,
 }
 
 impl CacheObject {
   /// Build a cache object, assigning it a fresh generation id.
-  fn new(policy: CachePolicy, target: CacheFileOrOnMemory, hash: Bytes) -> Self {
+  pub(super) fn new(policy: CachePolicy, target: CacheFileOrOnMemory, hash: Bytes) -> Self {
+    let size = match &target {
+      CacheFileOrOnMemory::File(_) | CacheFileOrOnMemory::External => 0,
+      CacheFileOrOnMemory::OnMemory(object) => object.len() as u64,
+    };
     Self {
       policy,
       target,
+      size,
       hash,
       generation: CACHE_OBJECT_GEN.fetch_add(1, Ordering::Relaxed),
+      surrogate_keys: Vec::new(),
+      vary: Vec::new(),
+      stale_response: None,
+      ttl: CacheTtl::default(),
+      cache_control: None,
     }
   }
-}
 
-/* ---------------------------------------------- */
-#[derive(Debug, Clone)]
-/// Lru cache manager that is responsible to handle `Mutex` as an outer of `LruCache`
-struct LruCacheManager {
-  /// Inner lru cache manager main object
-  /// TODO: Revisit the string URL key when adding vhost-aware cache keys or
-  /// broader request-attribute checks.
-  inner: Arc<Mutex<LruCache<String, CacheObject>>>,
-  /// Counter of current cached object (total)
-  cnt: Arc<AtomicUsize>,
-}
+  /// Set the length of the cache file of a file object
+  pub(super) fn with_size(self, size: u64) -> Self {
+    Self { size, ..self }
+  }
+
+  /// Tier of the object
+  pub(super) fn tier(&self) -> Tier {
+    match self.target {
+      CacheFileOrOnMemory::File(_) | CacheFileOrOnMemory::External => Tier::Disk,
+      CacheFileOrOnMemory::OnMemory(_) => Tier::Memory,
+    }
+  }
+
+  /// Tag the object with surrogate keys
+  pub(super) fn with_surrogate_keys(self, surrogate_keys: Vec<String>) -> Self {
+    Self { surrogate_keys, ..self }
+  }
+
+  /// Mark the object as a variant selected by the request headers listed in `Vary`
+  pub(super) fn with_vary(self, vary: Vec<HeaderName>) -> Self {
+    Self { vary, ..self }
+  }
 
-impl LruCacheManager {
-  #[allow(unused)]
-  /// Build LruCache
-  fn new(cache_max_entry: usize) -> Self {
+  /// Bound the freshness lifetime by the cache rule of the route, with the original `Cache-Control` if rewritten
+  pub(super) fn with_ttl(self, ttl: CacheTtl, cache_control: Option<Vec<HeaderValue>>) -> Self {
     Self {
-      inner: Arc::new(Mutex::new(LruCache::new(
-        std::num::NonZeroUsize::new(cache_max_entry).unwrap(),
-      ))),
-      cnt: Default::default(),
+      ttl,
+      cache_control,
+      ..self
     }
   }
 
-  /// Count entries
-  fn count(&self) -> usize {
-    self.cnt.load(Ordering::Relaxed)
+  /// Allow the object to be served once stale
+  pub(super) fn with_stale_response(self, stale_response: Option<StaleResponse>) -> Self {
+    Self {
+      stale_response: stale_response.map(Arc::new),
+      ..self
+    }
   }
+}
 
-  /// Evict the entry for `cache_key` only if it is still the `generation` the caller observed.
-  ///
-  /// Eviction is sometimes triggered from a stale snapshot (e.g. a `get()` that cloned the entry,
-  /// then found it stale or failed to read its file). A concurrent re-store may have replaced that
-  /// entry with a newer live one under the same key in the meantime; popping unconditionally would
-  /// delete the live entry (orphaning its file and desyncing the file count). Peeking the current
-  /// generation and only popping on a match prevents that. Returns the popped entry when it matched.
-  fn evict_if_generation(&self, cache_key: &str, generation: u64) -> Option<(String, CacheObject)> {
-    let mut lock = match self.inner.lock() {
-      Ok(lock) => lock,
-      Err(_) => {
-        error!("Mutex can't be locked to evict a cache entry");
-        return None;
-      }
+#[derive(Debug)]
+/// Response head of a cache object, with the periods after the object becomes stale within which it is
+/// still served as allowed by `stale-while-revalidate` and `stale-if-error` (RFC 5861)
+pub(super) struct StaleResponse {
+  status: StatusCode,
+  headers: HeaderMap,
+  /// Time the object becomes stale
+  stale_at: SystemTime,
+  /// Served while being refreshed in background by the request that found it stale
+  while_revalidate: Duration,
+  /// Served when the upstream fails or returns 5xx
+  if_error: Duration,
+}
+
+impl StaleResponse {
+  /// Build the stale response head of a stored response, or `None` if it is never served stale.
+  /// `stale_if_error_grace` applies unless the response gives `stale-if-error` or requires revalidation.
+  pub(super) fn new(
+    policy: &CachePolicy,
+    status: StatusCode,
+    headers: &HeaderMap,
+    response_time: SystemTime,
+    stale_if_error_grace: Duration,
+  ) -> Option<Self> {
+    let requires_revalidation = ["must-revalidate", "proxy-revalidate", "no-cache"]
+      .iter()
+      .any(|directive| cache_control_directive(headers, directive).is_some());
+    let seconds = |directive| {
+      cache_control_directive(headers, directive)
+        .flatten()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Duration::from_secs)
     };
-    // `peek` does not promote the entry; only pop when the generation still matches.
-    if lock.peek(cache_key).map(|o| o.generation) != Some(generation) {
+    let while_revalidate = seconds("stale-while-revalidate").unwrap_or_default();
+    let if_error = seconds("stale-if-error").unwrap_or(if requires_revalidation {
+      Duration::ZERO
+    } else {
+      stale_if_error_grace
+    });
+    if while_revalidate.is_zero() && if_error.is_zero() {
       return None;
     }
-    let res = lock.pop_entry(cache_key);
-    // This may be inconsistent with the actual number of entries
-    self.cnt.store(lock.len(), Ordering::Relaxed);
-    res
+    let ttl = policy.time_to_live(response_time);
+    // Past its lifetime on arrival by `Age`, the object became stale before it was received. The policy no longer
+    // tells the lifetime then, so it is taken from the head without `Age`.
+    let stale_at = if ttl.is_zero() && headers.contains_key(header::AGE) {
+      let mut res = Response::new(());
+      *res.status_mut() = status;
+      *res.headers_mut() = headers.clone();
+      res.headers_mut().remove(header::AGE);
+      let lifetime =
+        CachePolicy::new_options(&Request::new(()), &res, response_time, Default::default()).time_to_live(response_time);
+      let overdue = policy.age(response_time).saturating_sub(lifetime);
+      response_time.checked_sub(overdue).unwrap_or(response_time)
+    } else {
+      response_time + ttl
+    };
+    Some(Self {
+      status,
+      headers: headers.clone(),
+      stale_at,
+      while_revalidate,
+      if_error,
+    })
   }
 
-  /// Push an entry into the LRU cache, returns error if mutex cannot be acquired
-  fn push(&self, cache_key: &str, cache_object: &CacheObject) -> CacheResult<Option<(String, CacheObject)>> {
-    let mut lock = self.inner.lock().map_err(|_| {
-      error!("Failed to acquire mutex lock for writing cache entry");
-      CacheError::FailedToAcquiredMutexLockForCache
-    })?;
-    let res = Ok(lock.push(cache_key.to_string(), cache_object.clone()));
-    // This may be inconsistent with the actual number of entries
-    self.cnt.store(lock.len(), Ordering::Relaxed);
-    res
+  /// How long the object has been stale
+  fn staleness(&self, now: SystemTime) -> Duration {
+    now.duration_since(self.stale_at).unwrap_or_default()
   }
 
-  /// Get an entry from the LRU cache, returns error if mutex cannot be acquired
-  fn get(&self, cache_key: &str) -> CacheResult<Option<CacheObject>> {
-    let mut lock = self.inner.lock().map_err(|_| {
-      error!("Mutex can't be locked for checking cache entry");
-      CacheError::FailedToAcquiredMutexLockForCheck
-    })?;
-    let Some(cached_object) = lock.get(cache_key) else {
-      return Ok(None);
-    };
-    Ok(Some(cached_object.clone()))
+  /// Whether the object is still to be served in either way
+  pub(super) fn is_usable(&self, now: SystemTime) -> bool {
+    self.staleness(now) <= self.while_revalidate.max(self.if_error)
   }
-}
+
+  /// Response head served for the stale object
+  fn parts(&self, policy: &CachePolicy, now: SystemTime) -> response::Parts {
+    let (mut parts, _) = Response::new(()).into_parts();
+    parts.status = self.status;
+    parts.headers = self.headers.clone();
+    parts
+      .headers
+      .insert(header::AGE, HeaderValue::from(policy.age(now).as_secs()));
+    parts
+  }
+}
+
+/* ---------------------------------------------- */
+#[derive(Clone, Debug)]
+/// Storage behind the cache
+enum Storage {
+  /// Memory and file tiers, the default
+  Tiered(TieredStorage),
+  /// Storage given by the embedder
+  Custom(CustomStorage),
+}
 
 /* ---------------------------------------------- */
 /// Generate cache policy if the response is cacheable
@@ -771,6 +1301,14 @@ pub(crate) fn get_policy_if_cacheable<B1, B2>(
     return Err(CacheError::NullRequestOrResponse);
   };
 
+  // `Vary: *` never matches a subsequent request
+  if vary_names(res.headers()).is_none() {
+    return Ok(None);
+  }
+  // Partial content is never stored, since ranges are served from the full response
+  if res.status() == StatusCode::PARTIAL_CONTENT {
+    return Ok(None);
+  }
   let new_policy = CachePolicy::new(req, res);
   if new_policy.is_storable() {
     // debug!("Response is cacheable: {:?}\n{:?}", req, res.headers());
@@ -780,249 +1318,237 @@ pub(crate) fn get_policy_if_cacheable<B1, B2>(
   }
 }
 
-fn derive_filename_from_uri(uri: &hyper::Uri) -> String {
-  let mut hasher = Sha256::new();
-  hasher.update(uri.to_string());
-  let digest = hasher.finalize();
-  general_purpose::URL_SAFE_NO_PAD.encode(digest)
-}
-
 /// Derive the LRU cache key from the client-facing effective request URI. The caller MUST pass
 /// the effective URI (scheme + authority + path/query, as the client addressed it), NOT the
 /// upstream-rewritten request URI - otherwise distinct client-facing vhosts that rewrite to the
 /// same upstream target would collide on one key (cross-vhost cache poisoning).
-fn derive_cache_key_from_effective_uri(uri: &hyper::Uri) -> String {
+pub(super) fn derive_cache_key_from_effective_uri(uri: &hyper::Uri) -> String {
   uri.to_string()
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  use futures::{StreamExt, stream};
-  use std::{
-    pin::Pin,
-    task::{Context, Poll},
-  };
+/// Put the cache status of the response in its extensions
+fn with_status(mut res: Response<ResponseBody>, status: CacheStatus) -> Response<ResponseBody> {
+  res.extensions_mut().insert(status);
+  res
+}
 
-  /// NOTE: the relay channels are bounded. A test that runs the producer to completion BEFORE
-  /// draining the receiver (the common pattern below) deadlocks once a body has more frames than
-  /// the channel capacity, so these tests create channels with a capacity comfortably above any
-  /// test body (16) - except the backpressure tests, which exercise the bound itself and drain
-  /// concurrently.
-  const TEST_CHANNEL_CAPACITY: usize = 16;
+/// Derive the key of the variant selected by the request headers listed in `Vary`, appending their
+/// normalized values to the URI key on separate lines. The URI key itself if the response does not vary.
+pub(super) fn derive_variant_key(uri_key: &str, vary: &[HeaderName], headers: &HeaderMap) -> String {
+  vary.iter().fold(uri_key.to_string(), |key, name| {
+    format!("{key}\n{name}: {}", normalized_header_value(headers, name))
+  })
+}
 
-  /// Build an `Ok` data frame from a static byte slice.
-  fn data_frame(bytes: &'static [u8]) -> Result<Frame<Bytes>, hyper::Error> {
-    Ok(Frame::data(Bytes::from_static(bytes)))
-  }
+/// Request header names selecting the variant: the ones listed in `Vary` and the key headers of the cache rule
+/// of the route, sorted and deduplicated
+pub(super) fn variant_names(mut vary: Vec<HeaderName>, key_headers: &[String]) -> Vec<HeaderName> {
+  vary.extend(
+    key_headers
+      .iter()
+      .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok()),
+  );
+  vary.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+  vary.dedup();
+  vary
+}
 
-  /// Build a test body from a list of frames. Only `Ok` frames are constructed, so no
-  /// `hyper::Error` needs to be built.
-  fn body_from(
-    frames: Vec<Result<Frame<Bytes>, hyper::Error>>,
-  ) -> impl hyper::body::Body<Data = Bytes, Error = hyper::Error> + Unpin {
-    StreamBody::new(stream::iter(frames))
-  }
+/// URI key of the cache key, i.e., without the request headers selecting the variant
+pub(super) fn uri_of_cache_key(cache_key: &str) -> &str {
+  cache_key.split_once('\n').map_or(cache_key, |(uri_key, _)| uri_key)
+}
 
-  /// Concatenate the data bytes of all forwarded frames in order.
-  fn forwarded_data(frames: Vec<Result<Frame<Bytes>, hyper::Error>>) -> Vec<u8> {
-    frames
-      .into_iter()
-      .filter_map(|f| f.ok())
-      .filter_map(|f| f.into_data().ok())
-      .flat_map(|b| b.to_vec())
-      .collect()
-  }
+/// Validators in the revalidation request built by `CachePolicy::before_request`
+fn validators(headers: &HeaderMap) -> HeaderMap {
+  [header::IF_NONE_MATCH, header::IF_MODIFIED_SINCE]
+    .into_iter()
+    .filter_map(|name| headers.get(&name).map(|value| (name, value.clone())))
+    .collect()
+}
 
-  /// Total number of data bytes across forwarded frames (works for any error type).
-  fn forwarded_len<E>(frames: Vec<Result<Frame<Bytes>, E>>) -> usize {
-    frames
-      .into_iter()
-      .filter_map(|f| f.ok())
-      .filter_map(|f| f.into_data().ok())
-      .map(|b| b.len())
-      .sum()
-  }
+/// Bound the freshness lifetime of a response by the TTLs of the cache rule of its route: `default_ttl` replaces
+/// the heuristic lifetime of a response without `max-age`, `s-maxage` or `Expires`, and `max_ttl` caps any. The
+/// policy is then rebuilt from the head with `s-maxage` rewritten to the bounded lifetime, which takes precedence
+/// in a shared cache, and returned with the original `Cache-Control` fields to serve in place of the rewritten.
+/// The policy is kept as it is if not bounded, or if the response is not storable as it is.
+pub(super) fn bound_ttl<R>(
+  policy: CachePolicy,
+  req: &Request<R>,
+  status: StatusCode,
+  headers: &HeaderMap,
+  response_time: SystemTime,
+  ttl: &CacheTtl,
+) -> (CachePolicy, Option<Vec<HeaderValue>>) {
+  if ttl.is_unbounded() {
+    return (policy, None);
+  }
+  let policy_of = |headers: &HeaderMap| {
+    let mut res = Response::new(());
+    *res.status_mut() = status;
+    *res.headers_mut() = headers.clone();
+    CachePolicy::new_options(req, &res, response_time, Default::default())
+  };
+  // Rebuilt from the head as given, since the policy may have been built from a rewritten one
+  let policy = policy_of(headers);
+  if !policy.is_storable() {
+    return (policy, None);
+  }
+  let explicit = headers.contains_key(header::EXPIRES)
+    || cache_control_directive(headers, "max-age").is_some()
+    || cache_control_directive(headers, "s-maxage").is_some();
+  let (lifetime, replaced) = match ttl.default_ttl {
+    Some(default_ttl) if !explicit => (default_ttl, true),
+    // Already stale on arrival, which no upper bound changes
+    _ if policy.is_stale(response_time) => return (policy, None),
+    _ => (policy.time_to_live(response_time) + policy.age(response_time), false),
+  };
+  let bounded = ttl.max_ttl.map_or(lifetime, |max_ttl| lifetime.min(max_ttl));
+  if !replaced && bounded >= lifetime {
+    return (policy, None);
+  }
+
+  let cache_control = headers.get_all(header::CACHE_CONTROL).iter().cloned().collect::<Vec<_>>();
+  let directives = cache_control
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .map(str::trim)
+    .filter(|d| {
+      let name = d.split_once('=').map_or(*d, |(name, _)| name);
+      !d.is_empty() && !name.trim().eq_ignore_ascii_case("s-maxage")
+    })
+    .map(str::to_string)
+    .chain(std::iter::once(format!("s-maxage={}", bounded.as_secs())))
+    .collect::<Vec<_>>()
+    .join(", ");
+  let Ok(directives) = HeaderValue::from_str(&directives) else {
+    return (policy, None);
+  };
+  let mut rewritten = headers.clone();
+  rewritten.insert(header::CACHE_CONTROL, directives);
+  (policy_of(&rewritten), Some(cache_control))
+}
 
-  /// Drive the store path for a body that must stay on memory (threshold = `usize::MAX`, so it
-  /// never spills to a file) and return the stored bytes if cacheable. Mirrors the old
-  /// `spool_body` return shape for the existing on-memory tests.
-  async fn store_on_memory<B, E>(body: B, body_tx: mpsc::Sender<Result<Frame<Bytes>, E>>, max_each_size: usize) -> Option<Bytes>
-  where
-    B: hyper::body::Body<Data = Bytes, Error = E> + Unpin,
-  {
-    let uri: Uri = "http://example.com/onmem".parse().unwrap();
-    spool_and_store(body, body_tx, max_each_size, usize::MAX, &std::env::temp_dir(), &uri)
-      .await
-      .map(|(target, _hash)| match target {
-        CacheFileOrOnMemory::OnMemory(bytes) => bytes,
-        CacheFileOrOnMemory::File(_) => unreachable!("usize::MAX on-memory threshold never spills"),
-      })
+/// Replace the `Cache-Control` fields of the head
+fn set_cache_control(headers: &mut HeaderMap, cache_control: &[HeaderValue]) {
+  headers.remove(header::CACHE_CONTROL);
+  for value in cache_control {
+    headers.append(header::CACHE_CONTROL, value.clone());
   }
+}
 
-  /// Unique, freshly created temp directory for file-cache store tests.
-  async fn temp_cache_dir(tag: &str) -> PathBuf {
-    let dir = temp_cache_path(tag);
-    fs::create_dir_all(&dir).await.unwrap();
-    dir
-  }
+/// Find a directive in `Cache-Control`, returning its value if any
+fn cache_control_directive(headers: &HeaderMap, directive: &str) -> Option<Option<String>> {
+  headers
+    .get_all(header::CACHE_CONTROL)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .find_map(|d| {
+      let (name, value) = d.split_once('=').map_or((d, None), |(name, value)| (name, Some(value)));
+      name
+        .trim()
+        .eq_ignore_ascii_case(directive)
+        .then(|| value.map(|v| v.trim().trim_matches('"').to_string()))
+    })
+}
 
-  /// A fresh, storable cache policy for `uri` (so the freshness gate passes).
-  fn fresh_policy(uri: &Uri) -> CachePolicy {
-    let req = Request::builder().uri(uri.clone()).body(()).unwrap();
-    let res = Response::builder()
-      .header("cache-control", "public, max-age=3600")
-      .body(())
-      .unwrap();
-    get_policy_if_cacheable(Some(&req), Some(&res)).unwrap().unwrap()
-  }
+/// Whether the request does not accept a response from the cache without revalidation
+fn requests_no_cache(headers: &HeaderMap) -> bool {
+  cache_control_directive(headers, "no-cache").is_some()
+    || headers
+      .get_all(header::PRAGMA)
+      .iter()
+      .any(|v| v.to_str().is_ok_and(|v| v.contains("no-cache")))
+}
 
-  /// In-memory `FileStore` for store-path tests (no dir cleanup at construction).
-  fn test_file_store() -> FileStore {
-    FileStore {
-      cnt: Arc::new(AtomicUsize::new(0)),
-      runtime_handle: tokio::runtime::Handle::current(),
+/// Request header names listed in `Vary` of the response, sorted and deduplicated, or `None` for
+/// `Vary: *` that never matches
+pub(super) fn vary_names(headers: &HeaderMap) -> Option<Vec<HeaderName>> {
+  let mut names = Vec::new();
+  for name in headers
+    .get_all(header::VARY)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .map(str::trim)
+    .filter(|n| !n.is_empty())
+  {
+    if name == "*" {
+      return None;
+    }
+    if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+      names.push(name);
     }
   }
+  names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+  names.dedup();
+  Some(names)
+}
 
-  #[tokio::test]
-  async fn within_limit_caches_and_forwards_all() {
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
-    let body = body_from(vec![data_frame(b"hello"), data_frame(b"world")]);
-    let cached = store_on_memory(body, tx, 1024).await;
-    assert_eq!(cached.as_deref(), Some(&b"helloworld"[..]));
-    assert_eq!(forwarded_data(rx.collect::<Vec<_>>().await), b"helloworld");
-  }
-
-  /// Regression test for the truncation bug: an over-limit cacheable response must still be
-  /// forwarded to the client in full, just not cached.
-  #[tokio::test]
-  async fn over_limit_forwards_all_but_does_not_cache() {
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
-    // three 5-byte frames = 15 bytes total, over the 8-byte limit
-    let body = body_from(vec![data_frame(b"aaaaa"), data_frame(b"bbbbb"), data_frame(b"ccccc")]);
-    let cached = store_on_memory(body, tx, 8).await;
-    assert!(cached.is_none(), "over-limit object must not be cached");
-    assert_eq!(
-      forwarded_data(rx.collect::<Vec<_>>().await),
-      b"aaaaabbbbbccccc",
-      "all frames must be forwarded, not truncated"
-    );
-  }
-
-  #[tokio::test]
-  async fn boundary_exactly_max_is_cached() {
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
-    // 5 + 3 = 8 == limit (matches the original `size > max_each_size` boundary)
-    let body = body_from(vec![data_frame(b"aaaaa"), data_frame(b"bbb")]);
-    let cached = store_on_memory(body, tx, 8).await;
-    assert_eq!(cached.as_deref(), Some(&b"aaaaabbb"[..]));
-    assert_eq!(forwarded_data(rx.collect::<Vec<_>>().await), b"aaaaabbb");
-  }
-
-  /// This only pins down trailer *forwarding* and that `buf` holds data bytes only. It does
-  /// not assert that trailer-bearing responses are cacheable as a spec (a cache hit does not
-  /// reproduce trailers); that is pre-existing behaviour and out of scope (design doc 3/8).
-  #[tokio::test]
-  async fn forwards_trailers_without_buffering_them() {
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
-    let mut trailers = http::HeaderMap::new();
-    trailers.insert("x-trailer", http::HeaderValue::from_static("v"));
-    let body = body_from(vec![data_frame(b"data"), Ok(Frame::trailers(trailers))]);
-    let cached = store_on_memory(body, tx, 1024).await;
-    assert_eq!(cached.as_deref(), Some(&b"data"[..]));
-    let forwarded = rx.collect::<Vec<_>>().await;
-    assert_eq!(forwarded.len(), 2);
-    assert!(forwarded[1].as_ref().unwrap().is_trailers());
+/// Value of the request header in the normalized form compared for `Vary`: comma-separated elements
+/// of all fields joined by commas without surrounding whitespace, and lowercased for the headers
+/// whose values are case-insensitive
+fn normalized_header_value(headers: &HeaderMap, name: &HeaderName) -> String {
+  let value = headers
+    .get_all(name)
+    .iter()
+    .flat_map(|v| v.as_bytes().split(|b| *b == b','))
+    .map(|e| String::from_utf8_lossy(e.trim_ascii()))
+    .filter(|e| !e.is_empty())
+    .collect::<Vec<_>>()
+    .join(",");
+  if CASE_INSENSITIVE_VARY_HEADERS.contains(&name.as_str()) {
+    value.to_ascii_lowercase()
+  } else {
+    value
   }
+}
 
-  #[tokio::test]
-  async fn returns_none_when_downstream_dropped() {
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
-    drop(rx);
-    let body = body_from(vec![data_frame(b"a"), data_frame(b"b")]);
-    let cached = store_on_memory(body, tx, 1024).await;
-    assert!(cached.is_none());
+/// Replace the request headers listed in `Vary` with their normalized values
+fn normalize_headers(headers: &mut HeaderMap, vary: &[HeaderName]) {
+  for name in vary {
+    let value = normalized_header_value(headers, name);
+    headers.remove(name);
+    if let Ok(value) = HeaderValue::from_str(&value)
+      && !value.is_empty()
+    {
+      headers.insert(name.clone(), value);
+    }
   }
+}
 
-  /// Stand-in body error type. `hyper::Error` has no public constructor, so the error path is
-  /// exercised with a body whose `Error` is this type; `spool_and_store` is generic over the error.
-  #[derive(Debug)]
-  struct TestBodyError;
-
-  /// Regression test for the upstream-error path: an error frame must be propagated downstream
-  /// (not masked as a clean EOF), and the object must not be cached.
-  #[tokio::test]
-  async fn upstream_error_is_propagated_and_not_cached() {
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> =
-      vec![Ok(Frame::data(Bytes::from_static(b"partial"))), Err(TestBodyError)];
-    let body = StreamBody::new(stream::iter(frames));
-    let cached = store_on_memory(body, tx, 1024).await;
-    assert!(cached.is_none(), "an errored upstream body must not be cached");
-    let forwarded = rx.collect::<Vec<_>>().await;
-    assert_eq!(forwarded.len(), 2, "the data frame and the error frame are both forwarded");
-    assert!(forwarded[0].is_ok());
-    assert!(forwarded[1].is_err(), "the upstream error must be propagated downstream");
-  }
-
-  /// Unique temp path for a file-cache test object.
-  fn temp_cache_path(tag: &str) -> PathBuf {
-    let nanos = SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
-    std::env::temp_dir().join(format!("rpxy-cache-test-{tag}-{}-{nanos}", std::process::id()))
-  }
-
-  /// A cached file larger than `FILE_CACHE_READ_CHUNK` must be streamed back intact, exercising
-  /// the multi-chunk read path. Guards correct reassembly across chunk boundaries.
-  #[tokio::test]
-  async fn file_store_read_streams_object_across_chunks() {
-    let path = temp_cache_path("ok");
-    // ~200 KB so the read spans several FILE_CACHE_READ_CHUNK (64 KiB) iterations.
-    let content: Vec<u8> = (0..200_000usize).map(|i| (i % 251) as u8).collect();
-    fs::write(&path, &content).await.unwrap();
-    let hash = Bytes::copy_from_slice(Sha256::digest(&content).as_ref());
-
-    let file_store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(0)),
-      runtime_handle: tokio::runtime::Handle::current(),
-    };
-    let body = file_store.read(path.clone(), &hash).await.unwrap();
-    let got = BodyExt::collect(body).await.unwrap().to_bytes();
-    assert_eq!(got.as_ref(), content.as_slice());
-    // The happy path leaves the file in place.
-    assert!(fs::metadata(&path).await.is_ok());
-    let _ = fs::remove_file(&path).await;
+/// Normalize the request headers listed in `Vary` of the response, before the cache policy is built
+/// from the request, so that a variant is stored and looked up in the same normalized form
+pub(crate) fn normalize_vary_headers(req_headers: &mut HeaderMap, res_headers: &HeaderMap) {
+  if let Some(vary) = vary_names(res_headers) {
+    normalize_headers(req_headers, &vary);
   }
+}
 
-  /// On a hash mismatch the file is evicted. `read()` returns the stream immediately and the
-  /// integrity check + removal run at the end of the spawned task, so the stream is drained to
-  /// EOF before asserting the file is gone (draining to EOF implies the task finished, since it
-  /// awaits the removal before dropping the sender that closes the stream).
-  #[tokio::test]
-  async fn file_store_read_evicts_on_hash_mismatch() {
-    let path = temp_cache_path("bad");
-    let content = b"some cached bytes".to_vec();
-    fs::write(&path, &content).await.unwrap();
-    let wrong_hash = Bytes::from_static(&[0u8; 32]);
-
-    let file_store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(1)), // the entry for this file is still counted
-      runtime_handle: tokio::runtime::Handle::current(),
-    };
-    let body = file_store.read(path.clone(), &wrong_hash).await.unwrap();
-    let _ = BodyExt::collect(body).await; // drain to EOF; data frames are all Ok, the mismatch is internal
-    assert!(
-      fs::metadata(&path).await.is_err(),
-      "a corrupted cache file must be removed on hash mismatch"
-    );
-    // The mismatch path unlinks WITHOUT decrementing: the LRU entry still exists and the count
-    // is reconciled when that entry is evicted through the metadata path (no double decrement).
-    assert_eq!(
-      file_store.count().await,
-      1,
-      "the integrity-check removal must not touch the count"
-    );
-  }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    forwarder::cache::{
+      cache_file::{
+        CACHE_FILE_FIXED_HEADER_LEN, CACHE_FILE_MAGIC, CACHE_RESTORE_START,
+        tests::{
+          TEST_CHANNEL_CAPACITY, TestBodyError, cache_file_bytes, fresh_meta, fresh_policy, temp_cache_dir, test_file_store,
+          write_cache_file,
+        },
+        unique_cache_paths,
+      },
+      cache_rule::CacheRule,
+      cache_storage::CacheBody,
+    },
+    globals::CacheRuleConfig,
+  };
+  use async_trait::async_trait;
+  use futures::stream;
+  use http_body_util::Full;
+  use sha2::{Digest, Sha256};
+  use std::{convert::Infallible, path::Path, sync::LazyLock};
 
   /// An on-memory cache hit serves the stored object directly, without re-hashing it. The entry is
   /// inserted with an intentionally wrong `hash`: the previous per-hit re-hash would have detected
@@ -1035,15 +1561,20 @@ mod tests {
   #[tokio::test]
   async fn on_memory_hit_serves_object_without_rehash() {
     let cache = RpxyCache {
-      inner: LruCacheManager::new(10),
-      file_store: FileStore {
-        cnt: Arc::new(AtomicUsize::new(0)),
-        runtime_handle: tokio::runtime::Handle::current(),
-      },
+      storage: Storage::Tiered(TieredStorage {
+        index: LruCacheManager::new(10, 4, u64::MAX, u64::MAX),
+        file_store: test_file_store(),
+        cache_dir: std::env::temp_dir(),
+        max_each_size_on_memory: 4_096,
+      }),
       runtime_handle: tokio::runtime::Handle::current(),
       max_each_size: 65_535,
-      max_each_size_on_memory: 4_096,
-      cache_dir: std::env::temp_dir(),
+      stale_if_error_grace: Duration::ZERO,
+      revalidating: Default::default(),
+      coalescing_timeout: Duration::ZERO,
+      filling: Default::default(),
+      status_header: false,
+      status_key: false,
     };
 
     let uri: Uri = "http://example.com/onmem".parse().unwrap();
@@ -1066,10 +1597,17 @@ mod tests {
       Bytes::from_static(&[0u8; 32]),
     );
     let cache_key = derive_cache_key_from_effective_uri(&uri);
-    cache.inner.push(&cache_key, &cache_object).unwrap();
+    cache
+      .tiers()
+      .unwrap()
+      .index
+      .push(&cache_key, &cache_object, cache.purge_epoch())
+      .unwrap();
 
     let req = Request::builder().uri(uri.clone()).body(()).unwrap();
-    let response = cache.get(&req).await.expect("an on-memory hit must return a response");
+    let CacheLookup::Fresh(response) = cache.get(&req).await else {
+      panic!("an on-memory hit must return a response");
+    };
     let got = BodyExt::collect(response.into_body()).await.unwrap().to_bytes();
     assert_eq!(
       got, object,
@@ -1077,182 +1615,12 @@ mod tests {
     );
   }
 
-  /// A body larger than the on-memory threshold spills to a file; the committed file is renamed
-  /// into `cache_dir`, the whole body is forwarded downstream, and reading the file back with the
-  /// returned hash succeeds (proving the incremental hash matches a one-shot hash of the bytes).
-  #[tokio::test]
-  async fn store_spills_large_object_to_file_and_round_trips() {
-    let dir = temp_cache_dir("spill").await;
-    let uri: Uri = "http://example.com/big".parse().unwrap();
-    // 5000 + 5000 = 10000 bytes, well over the 4096 on-memory threshold and under max_each_size.
-    let chunk = vec![7u8; 5000];
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![
-      Ok(Frame::data(Bytes::from(chunk.clone()))),
-      Ok(Frame::data(Bytes::from(chunk.clone()))),
-    ];
-    let body = StreamBody::new(stream::iter(frames));
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
-
-    let (target, hash) = spool_and_store(body, tx, 1_000_000, 4096, &dir, &uri)
-      .await
-      .expect("a within-limit object must be cacheable");
-    let CacheFileOrOnMemory::File(path) = target else {
-      panic!("an over-threshold object must spill to a file target");
-    };
-    assert!(path.starts_with(&dir), "the committed file must live in cache_dir");
-    assert_eq!(
-      forwarded_len(rx.collect::<Vec<_>>().await),
-      10000,
-      "the whole body is forwarded"
-    );
-
-    // Read the committed file back, verifying integrity against the incrementally computed hash.
-    let file_store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(0)),
-      runtime_handle: tokio::runtime::Handle::current(),
-    };
-    let read_body = file_store.read(path.clone(), &hash).await.unwrap();
-    let got = BodyExt::collect(read_body).await.unwrap().to_bytes();
-    assert_eq!(got.len(), 10000);
-    assert!(got.iter().all(|&b| b == 7), "round-tripped bytes must match");
-    assert!(fs::metadata(&path).await.is_ok(), "a verified file is left in place");
-    let _ = fs::remove_dir_all(&dir).await;
-  }
-
-  /// A body within the on-memory threshold stays on memory and no file is created.
-  #[tokio::test]
-  async fn store_keeps_small_object_on_memory() {
-    let dir = temp_cache_dir("onmem-store").await;
-    let uri: Uri = "http://example.com/small".parse().unwrap();
-    let (tx, _rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
-    let body = body_from(vec![data_frame(b"tiny")]);
-
-    let (target, _hash) = spool_and_store(body, tx, 1_000_000, 4096, &dir, &uri)
-      .await
-      .expect("cacheable");
-    assert!(
-      matches!(target, CacheFileOrOnMemory::OnMemory(ref b) if b.as_ref() == b"tiny"),
-      "a sub-threshold object must stay on memory"
-    );
-    let mut entries = fs::read_dir(&dir).await.unwrap();
-    assert!(
-      entries.next_entry().await.unwrap().is_none(),
-      "no file must be created for an on-memory object"
-    );
-    let _ = fs::remove_dir_all(&dir).await;
-  }
-
-  /// A single frame larger than the on-memory threshold spills directly to a file (the buffer is
-  /// not first grown by the whole frame), forwards in full, and round-trips intact. Guards the
-  /// spill-first-on-threshold-crossing path that bounds store-path memory against a large frame.
-  #[tokio::test]
-  async fn store_single_large_frame_spills_directly() {
-    let dir = temp_cache_dir("single-large").await;
-    let uri: Uri = "http://example.com/onebig".parse().unwrap();
-    let data = vec![3u8; 50_000]; // one frame, well over the 4096 threshold
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![Ok(Frame::data(Bytes::from(data)))];
-    let body = StreamBody::new(stream::iter(frames));
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
-
-    let (target, hash) = spool_and_store(body, tx, 1_000_000, 4096, &dir, &uri)
-      .await
-      .expect("cacheable");
-    let CacheFileOrOnMemory::File(path) = target else {
-      panic!("a single frame over the threshold must spill to a file target");
-    };
-    assert_eq!(
-      forwarded_len(rx.collect::<Vec<_>>().await),
-      50_000,
-      "the whole frame is forwarded"
-    );
-
-    let file_store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(0)),
-      runtime_handle: tokio::runtime::Handle::current(),
-    };
-    let read_body = file_store.read(path.clone(), &hash).await.unwrap();
-    let got = BodyExt::collect(read_body).await.unwrap().to_bytes();
-    assert_eq!(got.len(), 50_000);
-    assert!(got.iter().all(|&b| b == 3), "round-tripped bytes must match");
-    let _ = fs::remove_dir_all(&dir).await;
-  }
-
-  /// Exceeding `max_each_size` *after* a spill keeps forwarding the full body, caches nothing, and
-  /// leaves no temp file behind.
-  #[tokio::test]
-  async fn store_too_large_after_spill_forwards_all_and_leaves_no_file() {
-    let dir = temp_cache_dir("toolarge").await;
-    let uri: Uri = "http://example.com/big".parse().unwrap();
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
-    // on-memory 4096, max_each_size 8000: 4000 (M) -> 8000 (spill) -> 12000 (too large).
-    let frame = |n: usize| Ok(Frame::data(Bytes::from(vec![1u8; n])));
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![frame(4000), frame(4000), frame(4000)];
-    let body = StreamBody::new(stream::iter(frames));
-
-    let out = spool_and_store(body, tx, 8000, 4096, &dir, &uri).await;
-    assert!(out.is_none(), "an over-limit object must not be cached");
-    assert_eq!(forwarded_len(rx.collect::<Vec<_>>().await), 12000, "all bytes are forwarded");
-    let mut entries = fs::read_dir(&dir).await.unwrap();
-    assert!(
-      entries.next_entry().await.unwrap().is_none(),
-      "the temp file must be cleaned up on abort"
-    );
-    let _ = fs::remove_dir_all(&dir).await;
-  }
-
-  /// An upstream error after a spill forwards the error, caches nothing, and cleans up the temp.
-  #[tokio::test]
-  async fn store_upstream_error_after_spill_forwards_and_cleans_temp() {
-    let dir = temp_cache_dir("err-spill").await;
-    let uri: Uri = "http://example.com/big".parse().unwrap();
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> =
-      vec![Ok(Frame::data(Bytes::from(vec![1u8; 5000]))), Err(TestBodyError)];
-    let body = StreamBody::new(stream::iter(frames));
-
-    let out = spool_and_store(body, tx, 1_000_000, 4096, &dir, &uri).await;
-    assert!(out.is_none(), "an errored body must not be cached");
-    let forwarded = rx.collect::<Vec<_>>().await;
-    assert!(
-      forwarded.iter().any(|f| f.is_err()),
-      "the upstream error is forwarded downstream"
-    );
-    let mut entries = fs::read_dir(&dir).await.unwrap();
-    assert!(
-      entries.next_entry().await.unwrap().is_none(),
-      "the temp file must be cleaned up after an upstream error"
-    );
-    let _ = fs::remove_dir_all(&dir).await;
-  }
-
-  /// A store-side I/O failure (here: a non-existent cache_dir so the spill cannot be created) must
-  /// never cut the downstream relay: the full body is still forwarded, and nothing is cached.
-  #[tokio::test]
-  async fn store_io_failure_keeps_forwarding_without_caching() {
-    let dir = temp_cache_path("missing-dir"); // intentionally NOT created
-    let uri: Uri = "http://example.com/big".parse().unwrap();
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![
-      Ok(Frame::data(Bytes::from(vec![9u8; 5000]))),
-      Ok(Frame::data(Bytes::from(vec![9u8; 1000]))),
-    ];
-    let body = StreamBody::new(stream::iter(frames));
-
-    let out = spool_and_store(body, tx, 1_000_000, 4096, &dir, &uri).await;
-    assert!(out.is_none(), "a failed store must not cache");
-    assert_eq!(
-      forwarded_len(rx.collect::<Vec<_>>().await),
-      6000,
-      "the full body must still reach downstream despite the store I/O failure"
-    );
-  }
-
   /// Re-storing the same key with a new file evicts the old generation's file (no orphan) and the
   /// file count stays at one.
   #[tokio::test]
   async fn publish_same_key_file_update_evicts_old_file() {
     let dir = temp_cache_dir("pub-ff").await;
-    let manager = LruCacheManager::new(10);
+    let manager = LruCacheManager::new(10, 4, u64::MAX, u64::MAX);
     let file_store = test_file_store();
     let uri: Uri = "http://example.com/x".parse().unwrap();
     let key = derive_cache_key_from_effective_uri(&uri);
@@ -1264,7 +1632,7 @@ mod tests {
       CacheFileOrOnMemory::File(path_a.clone()),
       Bytes::from_static(&[1u8; 32]),
     );
-    publish_cache_object(&manager, &file_store, &key, obj_a).await;
+    publish_cache_object(&manager, &file_store, &key, obj_a, manager.purge_epoch()).await;
     assert_eq!(file_store.count().await, 1);
 
     let path_b = dir.join("file-b");
@@ -1274,7 +1642,7 @@ mod tests {
       CacheFileOrOnMemory::File(path_b.clone()),
       Bytes::from_static(&[2u8; 32]),
     );
-    publish_cache_object(&manager, &file_store, &key, obj_b).await;
+    publish_cache_object(&manager, &file_store, &key, obj_b, manager.purge_epoch()).await;
 
     assert!(
       fs::metadata(&path_a).await.is_err(),
@@ -1290,7 +1658,7 @@ mod tests {
   #[tokio::test]
   async fn publish_file_then_on_memory_evicts_old_file() {
     let dir = temp_cache_dir("pub-fm").await;
-    let manager = LruCacheManager::new(10);
+    let manager = LruCacheManager::new(10, 4, u64::MAX, u64::MAX);
     let file_store = test_file_store();
     let uri: Uri = "http://example.com/x".parse().unwrap();
     let key = derive_cache_key_from_effective_uri(&uri);
@@ -1302,7 +1670,7 @@ mod tests {
       CacheFileOrOnMemory::File(path_a.clone()),
       Bytes::from_static(&[1u8; 32]),
     );
-    publish_cache_object(&manager, &file_store, &key, obj_a).await;
+    publish_cache_object(&manager, &file_store, &key, obj_a, manager.purge_epoch()).await;
     assert_eq!(file_store.count().await, 1);
 
     let obj_b = CacheObject::new(
@@ -1310,7 +1678,7 @@ mod tests {
       CacheFileOrOnMemory::OnMemory(Bytes::from_static(b"small")),
       Bytes::from_static(&[2u8; 32]),
     );
-    publish_cache_object(&manager, &file_store, &key, obj_b).await;
+    publish_cache_object(&manager, &file_store, &key, obj_b, manager.purge_epoch()).await;
 
     assert!(fs::metadata(&path_a).await.is_err(), "the displaced file must be evicted");
     assert_eq!(file_store.count().await, 0, "the file count drops back to zero");
@@ -1322,7 +1690,7 @@ mod tests {
   #[tokio::test]
   async fn publish_capacity_eviction_removes_displaced_file() {
     let dir = temp_cache_dir("pub-cap").await;
-    let manager = LruCacheManager::new(1); // capacity 1: the second push evicts the first
+    let manager = LruCacheManager::new(1, 4, u64::MAX, u64::MAX); // capacity 1: the second push evicts the first
     let file_store = test_file_store();
 
     let uri_x: Uri = "http://example.com/x".parse().unwrap();
@@ -1333,7 +1701,14 @@ mod tests {
       CacheFileOrOnMemory::File(path_a.clone()),
       Bytes::from_static(&[1u8; 32]),
     );
-    publish_cache_object(&manager, &file_store, &derive_cache_key_from_effective_uri(&uri_x), obj_a).await;
+    publish_cache_object(
+      &manager,
+      &file_store,
+      &derive_cache_key_from_effective_uri(&uri_x),
+      obj_a,
+      manager.purge_epoch(),
+    )
+    .await;
     assert_eq!(file_store.count().await, 1);
 
     let uri_y: Uri = "http://example.com/y".parse().unwrap();
@@ -1342,7 +1717,14 @@ mod tests {
       CacheFileOrOnMemory::OnMemory(Bytes::from_static(b"small")),
       Bytes::from_static(&[2u8; 32]),
     );
-    publish_cache_object(&manager, &file_store, &derive_cache_key_from_effective_uri(&uri_y), obj_b).await;
+    publish_cache_object(
+      &manager,
+      &file_store,
+      &derive_cache_key_from_effective_uri(&uri_y),
+      obj_b,
+      manager.purge_epoch(),
+    )
+    .await;
 
     assert!(
       fs::metadata(&path_a).await.is_err(),
@@ -1352,294 +1734,1416 @@ mod tests {
     let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// Removing a file when the count is already zero must saturate, not underflow/panic. This
-  /// defends the cross-lock count race: the file count and the LRU map are updated under separate
-  /// locks, so a pathological concurrent ordering could otherwise drive the `usize` count below
-  /// zero (panic in debug, wraparound in release).
+  /// A file object over the disk budget is not admitted over a more frequently requested one, and its
+  /// file is removed, until it is requested often enough to evict the other.
   #[tokio::test]
-  async fn file_store_remove_count_saturates_at_zero() {
-    let dir = temp_cache_dir("saturate").await;
-    let path = dir.join("f");
-    fs::write(&path, b"x").await.unwrap();
-    let store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(0)),
-      runtime_handle: tokio::runtime::Handle::current(),
+  async fn publish_over_disk_budget_admits_more_frequent_object() {
+    let dir = temp_cache_dir("pub-budget").await;
+    let manager = LruCacheManager::new(10, 4, u64::MAX, 10);
+    let file_store = test_file_store();
+    let publish = async |name: &str| {
+      let uri: Uri = format!("http://example.com/{name}").parse().unwrap();
+      let path = dir.join(name);
+      fs::write(&path, b"12345678").await.unwrap();
+      let object = CacheObject::new(
+        fresh_policy(&uri),
+        CacheFileOrOnMemory::File(path.clone()),
+        Bytes::from_static(&[1u8; 32]),
+      )
+      .with_size(8);
+      let key = derive_cache_key_from_effective_uri(&uri);
+      publish_cache_object(&manager, &file_store, &key, object, manager.purge_epoch()).await;
+      (key, path)
     };
-    store.remove(&path).await.unwrap();
-    assert_eq!(
-      store.count().await,
-      0,
-      "the file count must saturate at zero, not wrap around"
+
+    let (key_a, path_a) = publish("a").await;
+    manager.get(&key_a).unwrap();
+    let (key_b, path_b) = publish("b").await;
+    assert!(
+      manager.get(&key_b).unwrap().is_none(),
+      "the less frequent object is not admitted"
     );
+    assert!(fs::metadata(&path_b).await.is_err());
+    assert_eq!((file_store.count().await, manager.size()), (1, (0, 8)));
+
+    for _ in 0..3 {
+      manager.get(&key_b).unwrap();
+    }
+    publish("b").await;
+    assert!(manager.get(&key_b).unwrap().is_some());
+    assert!(manager.get(&key_a).unwrap().is_none());
+    assert!(fs::metadata(&path_a).await.is_err());
+    assert_eq!((file_store.count().await, manager.size()), (1, (0, 8)));
     let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// Evicting a counted file entry must restore the count even when the file is already gone (e.g.
-  /// removed externally, or by the integrity-check path on a hash mismatch). Otherwise the file
-  /// count leaks above the number of live entries once the metadata is popped.
+  /// Cache over a temp dir, for tests driving `RpxyCache` directly
+  fn test_cache(cache_dir: &Path) -> RpxyCache {
+    RpxyCache {
+      storage: Storage::Tiered(TieredStorage {
+        index: LruCacheManager::new(10, 4, u64::MAX, u64::MAX),
+        file_store: test_file_store(),
+        cache_dir: cache_dir.to_path_buf(),
+        max_each_size_on_memory: 4_096,
+      }),
+      runtime_handle: tokio::runtime::Handle::current(),
+      max_each_size: 65_535,
+      stale_if_error_grace: Duration::ZERO,
+      revalidating: Default::default(),
+      coalescing_timeout: Duration::ZERO,
+      filling: Default::default(),
+      status_header: false,
+      status_key: false,
+    }
+  }
+
+  /// Response stored for a request through `put`, fresh and without `Vary` by default
+  struct TestObject<'a> {
+    uri: &'a str,
+    request_headers: Vec<(&'a str, &'a str)>,
+    response_headers: Vec<(&'a str, &'a str)>,
+    /// Time elapsed since the response was received
+    age: Duration,
+    /// TTLs of the route bounding the lifetime
+    ttl: Option<CacheTtl>,
+    surrogate_keys: &'a [&'a str],
+    body: Bytes,
+  }
+
+  impl<'a> TestObject<'a> {
+    fn new(uri: &'a str) -> Self {
+      Self {
+        uri,
+        request_headers: Vec::new(),
+        response_headers: vec![("cache-control", "public, max-age=3600")],
+        age: Duration::ZERO,
+        ttl: None,
+        surrogate_keys: &[],
+        body: Bytes::from_static(b"body"),
+      }
+    }
+
+    /// Replace the response headers
+    fn headers(mut self, headers: &[(&'a str, &'a str)]) -> Self {
+      self.response_headers = headers.to_vec();
+      self
+    }
+
+    fn cache_control(self, cache_control: &'a str) -> Self {
+      self.headers(&[("cache-control", cache_control)])
+    }
+
+    fn vary(mut self, vary: &'a str) -> Self {
+      self.response_headers.push(("vary", vary));
+      self
+    }
+
+    fn request_header(mut self, name: &'a str, value: &'a str) -> Self {
+      self.request_headers.push((name, value));
+      self
+    }
+
+    fn age(mut self, age: Duration) -> Self {
+      self.age = age;
+      self
+    }
+
+    fn ttl(mut self, ttl: CacheTtl) -> Self {
+      self.ttl = Some(ttl);
+      self
+    }
+
+    fn surrogate_keys(mut self, surrogate_keys: &'a [&'a str]) -> Self {
+      self.surrogate_keys = surrogate_keys;
+      self
+    }
+
+    fn body(mut self, body: &'static [u8]) -> Self {
+      self.body = Bytes::from_static(body);
+      self
+    }
+
+    /// Store the object through `put` as the forwarder does, with the age and the surrogate keys given by `Age` and
+    /// `Surrogate-Key` of the response, and the TTLs by the cache rule of the route
+    async fn publish(self, cache: &RpxyCache) {
+      let mut req = self
+        .request_headers
+        .iter()
+        .fold(Request::builder().uri(self.uri), |builder, (name, value)| {
+          builder.header(*name, *value)
+        })
+        .body(())
+        .unwrap();
+      if let Some(ttl) = self.ttl {
+        req.extensions_mut().insert(CacheRule(Arc::new(CacheRuleConfig {
+          enabled: true,
+          default_ttl: ttl.default_ttl,
+          max_ttl: ttl.max_ttl,
+          ignore_query_params: Vec::new(),
+          sort_query: false,
+          key_headers: Vec::new(),
+          bypass_cookies: Vec::new(),
+        })));
+      }
+      let age = (!self.age.is_zero()).then(|| self.age.as_secs().to_string());
+      let surrogate_keys = (!self.surrogate_keys.is_empty()).then(|| self.surrogate_keys.join(" "));
+      let res = self
+        .response_headers
+        .iter()
+        .map(|(name, value)| (*name, *value))
+        .chain(age.as_deref().map(|age| ("age", age)))
+        .chain(surrogate_keys.as_deref().map(|keys| ("surrogate-key", keys)))
+        .fold(Response::builder(), |builder, (name, value)| builder.header(name, value))
+        .body(self.body)
+        .unwrap();
+      assert!(store_response(cache, req, res).await, "the test object must be cacheable");
+    }
+  }
+
+  /// Store the response to the request through `put` as the forwarder does, relaying its body, and wait until the
+  /// object is published. Returns whether the response was cacheable.
+  async fn store_response(cache: &RpxyCache, mut req: Request<()>, res: Response<Bytes>) -> bool {
+    normalize_vary_headers(req.headers_mut(), res.headers());
+    let Ok(Some(policy)) = get_policy_if_cacheable(Some(&req), Some(&res)) else {
+      return false;
+    };
+    let (parts, body) = res.into_parts();
+    let body = Full::new(body).map_err(|never: Infallible| -> hyper::Error { match never {} });
+    let (notifier, mut published) = watch::channel(FillHead::Pending);
+    let fill_guard = FillGuard {
+      filling: Default::default(),
+      cache_key: String::new(),
+      notifier,
+    };
+    let relayed = cache
+      .put(&req, &parts, body, &policy, cache.purge_epoch(), Some(fill_guard))
+      .await
+      .unwrap();
+    BodyExt::collect(relayed).await.unwrap();
+    // Resolved with an error when the fill guard is dropped after the object is published
+    while published.changed().await.is_ok() {}
+    true
+  }
+
+  fn is_cached(cache: &RpxyCache, uri: &str) -> bool {
+    let key = derive_cache_key_from_effective_uri(&uri.parse().unwrap());
+    cache.tiers().unwrap().index.get(&key).unwrap().is_some()
+  }
+
   #[tokio::test]
-  async fn evict_missing_file_still_restores_count() {
-    let file_store = test_file_store();
-    file_store.incr_count().await; // a counted file entry exists
-    assert_eq!(file_store.count().await, 1);
+  async fn purge_by_url_host_prefix_and_surrogate_key() {
+    let dir = temp_cache_dir("purge").await;
+    let cache = test_cache(&dir);
+    let uris = [
+      "https://a.example/img/1.png",
+      "https://a.example/img/2.png",
+      "https://a.example:8443/css/x.css",
+      "https://b.example/img/1.png",
+      "https://b.example/index.html",
+    ];
+    for uri in uris {
+      TestObject::new(uri).publish(&cache).await;
+    }
+    TestObject::new("https://b.example/api/list")
+      .surrogate_keys(&["products", "list"])
+      .publish(&cache)
+      .await;
+
+    let url = PurgeTarget::Url("https://a.example/img/1.png".parse().unwrap());
+    assert_eq!(cache.purge(&url).await.unwrap(), 1);
+    assert!(!is_cached(&cache, "https://a.example/img/1.png"));
+    assert_eq!(cache.purge(&url).await.unwrap(), 0);
+
+    let host_prefix = PurgeTarget::Host {
+      host: "A.example".to_string(),
+      path_prefix: Some("/css/".to_string()),
+    };
+    assert_eq!(cache.purge(&host_prefix).await.unwrap(), 1);
+    assert!(is_cached(&cache, "https://a.example/img/2.png"));
 
-    // The file is already gone (never created here); eviction must still correct the count.
-    let missing = std::env::temp_dir().join("rpxy-cache-test-never-created-file");
-    file_store.evict(&missing).await;
-    assert_eq!(
-      file_store.count().await,
-      0,
-      "the file count must be restored even when the file was already gone"
-    );
+    assert_eq!(cache.purge(&PurgeTarget::PathPrefix("/img/".to_string())).await.unwrap(), 2);
+    assert!(is_cached(&cache, "https://b.example/index.html"));
+
+    assert_eq!(cache.purge(&PurgeTarget::SurrogateKey("list".to_string())).await.unwrap(), 1);
+    assert!(!is_cached(&cache, "https://b.example/api/list"));
+
+    let host = PurgeTarget::Host {
+      host: "b.example".to_string(),
+      path_prefix: None,
+    };
+    assert_eq!(cache.purge(&host).await.unwrap(), 1);
+    assert_eq!(cache.count().await, (0, 0, 0));
+    let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// A stale snapshot must not evict a newer live entry of the same key. Models the race where a
-  /// `get()` cloned generation A, a concurrent re-store published generation B under the same key,
-  /// and the stale `get()` then attempts eviction: B must survive, and only B's own generation can
-  /// evict B.
+  /// A purge removes the spilled file of a file-backed entry, and a store that began before the
+  /// purge is not published after it, rolling back its committed file.
   #[tokio::test]
-  async fn evict_if_generation_spares_newer_entry() {
-    let manager = LruCacheManager::new(10);
-    let uri: Uri = "http://example.com/x".parse().unwrap();
+  async fn purge_removes_files_and_overtaken_stores() {
+    let dir = temp_cache_dir("purge-file").await;
+    let cache = test_cache(&dir);
+    let uri: Uri = "https://a.example/large".parse().unwrap();
     let key = derive_cache_key_from_effective_uri(&uri);
 
+    let path_a = dir.join("file-a");
+    fs::write(&path_a, b"AAAA").await.unwrap();
     let obj_a = CacheObject::new(
       fresh_policy(&uri),
-      CacheFileOrOnMemory::OnMemory(Bytes::from_static(b"A")),
+      CacheFileOrOnMemory::File(path_a.clone()),
       Bytes::from_static(&[1u8; 32]),
     );
-    let gen_a = obj_a.generation;
-    manager.push(&key, &obj_a).unwrap();
+    publish_cache_object(
+      &cache.tiers().unwrap().index,
+      &cache.tiers().unwrap().file_store,
+      &key,
+      obj_a,
+      cache.purge_epoch(),
+    )
+    .await;
+    assert_eq!(cache.count().await, (1, 0, 1));
+
+    let epoch_before_purge = cache.purge_epoch();
+    assert_eq!(cache.purge(&PurgeTarget::Url(uri.clone())).await.unwrap(), 1);
+    assert!(
+      fs::metadata(&path_a).await.is_err(),
+      "the purged entry's file must be removed"
+    );
+    assert_eq!(cache.count().await, (0, 0, 0));
 
-    // A concurrent re-store replaces the entry under the same key with a newer generation.
+    let path_b = dir.join("file-b");
+    fs::write(&path_b, b"BBBB").await.unwrap();
     let obj_b = CacheObject::new(
       fresh_policy(&uri),
-      CacheFileOrOnMemory::OnMemory(Bytes::from_static(b"B")),
+      CacheFileOrOnMemory::File(path_b.clone()),
       Bytes::from_static(&[2u8; 32]),
     );
-    let gen_b = obj_b.generation;
-    manager.push(&key, &obj_b).unwrap();
-
-    // The stale snapshot (generation A) must not evict the live entry B.
+    publish_cache_object(
+      &cache.tiers().unwrap().index,
+      &cache.tiers().unwrap().file_store,
+      &key,
+      obj_b,
+      epoch_before_purge,
+    )
+    .await;
     assert!(
-      manager.evict_if_generation(&key, gen_a).is_none(),
-      "a stale generation must not evict the newer entry"
+      cache.tiers().unwrap().index.get(&key).unwrap().is_none(),
+      "an overtaken store must not be published"
     );
-    let current = manager.get(&key).unwrap().expect("the newer entry must survive");
-    assert_eq!(current.generation, gen_b);
-
-    // The current generation can still be evicted.
-    assert!(manager.evict_if_generation(&key, gen_b).is_some());
     assert!(
-      manager.get(&key).unwrap().is_none(),
-      "evicting the current generation removes it"
+      fs::metadata(&path_b).await.is_err(),
+      "an overtaken store's file must be removed"
     );
+    assert_eq!(cache.count().await, (0, 0, 0));
+    let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// Body wrapper counting the frames pulled from it, to observe how far ahead of a stalled
-  /// consumer the spool producer runs.
-  struct CountingBody<B> {
-    inner: B,
-    pulled: Arc<AtomicUsize>,
+  #[test]
+  fn surrogate_keys_are_read_from_both_headers() {
+    let mut headers = HeaderMap::new();
+    headers.append("surrogate-key", "products  product/42".parse().unwrap());
+    headers.append("cache-tag", "products,list, top".parse().unwrap());
+    assert_eq!(surrogate_keys(&headers), vec!["list", "product/42", "products", "top"]);
+    assert!(surrogate_keys(&HeaderMap::new()).is_empty());
   }
 
-  impl<B> hyper::body::Body for CountingBody<B>
-  where
-    B: hyper::body::Body<Data = Bytes> + Unpin,
-  {
-    type Data = Bytes;
-    type Error = B::Error;
-
-    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
-      let this = self.get_mut();
-      let res = Pin::new(&mut this.inner).poll_frame(cx);
-      if let Poll::Ready(Some(_)) = &res {
-        this.pulled.fetch_add(1, Ordering::Relaxed);
-      }
-      res
+  /// A file committed by a store is restored into the index and served as before, while truncated,
+  /// stale, interrupted temp and foreign files in the cache dir are removed. Temp files of stores in
+  /// flight in this process are kept.
+  #[tokio::test]
+  async fn restore_rebuilds_index_and_discards_unusable_files() {
+    let dir = temp_cache_dir("restore").await;
+    let uri: Uri = "https://a.example/large".parse().unwrap();
+    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = vec![Ok(Frame::data(Bytes::from(vec![5u8; 10_000])))];
+    let (tx, _rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CHANNEL_CAPACITY);
+    let (target, _hash) = spool_and_store(
+      StreamBody::new(stream::iter(frames)),
+      tx,
+      1_000_000,
+      4096,
+      &dir,
+      &fresh_meta(&uri),
+    )
+    .await
+    .expect("cacheable");
+    let CacheFileOrOnMemory::File(stored) = target else {
+      panic!("an over-threshold object must spill to a file target");
+    };
+
+    let mut stale = fresh_meta(&"https://a.example/stale".parse().unwrap());
+    stale.response_time -= std::time::Duration::from_secs(7200);
+    let truncated = cache_file_bytes(&fresh_meta(&"https://a.example/truncated".parse().unwrap()), b"body");
+    let discarded = [
+      write_cache_file(&dir, &stale, b"body").await,
+      dir.join("truncated"),
+      dir.join("interrupted.tmp"),
+      dir.join("foreign"),
+    ];
+    fs::write(&discarded[1], &truncated[..CACHE_FILE_FIXED_HEADER_LEN + 4])
+      .await
+      .unwrap();
+    fs::write(&discarded[2], cache_file_bytes(&fresh_meta(&uri), b"body"))
+      .await
+      .unwrap();
+    fs::write(&discarded[3], b"not a cache file").await.unwrap();
+    LazyLock::force(&CACHE_RESTORE_START);
+    let (in_flight, _) = unique_cache_paths(&dir, "https://a.example/in-flight");
+    fs::write(&in_flight, b"partial").await.unwrap();
+
+    let cache = test_cache(&dir);
+    cache
+      .tiers()
+      .unwrap()
+      .restore(10, u64::MAX, cache.max_each_size, cache.stale_if_error_grace)
+      .await;
+    assert_eq!(cache.count().await, (1, 0, 1));
+    assert!(fs::metadata(&stored).await.is_ok());
+    assert!(
+      fs::metadata(&in_flight).await.is_ok(),
+      "temp files of this process must be kept"
+    );
+    for path in discarded {
+      assert!(fs::metadata(&path).await.is_err(), "{path:?} must be removed");
     }
+
+    let req = Request::builder().uri(uri).body(()).unwrap();
+    let CacheLookup::Fresh(response) = cache.get(&req).await else {
+      panic!("a restored entry must be served");
+    };
+    let got = BodyExt::collect(response.into_body()).await.unwrap().to_bytes();
+    assert_eq!(got.as_ref(), vec![5u8; 10_000].as_slice());
+    let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// The store/miss path must not race ahead of a stalled consumer: the producer parks once the
-  /// channel (capacity + the sender's guaranteed slot) is full instead of pulling the whole body.
-  /// Yielding cannot un-park it; only draining the receiver can.
+  /// Restoring keeps the newest response of each key and the newest ones up to the maximum number
+  /// of entries, with the newest as the most recently used. Files of the others are removed.
   #[tokio::test]
-  async fn store_backpressure_limits_producer_readahead() {
-    const TEST_CAPACITY: usize = 2;
-    const TOTAL_FRAMES: usize = 32;
-    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(TEST_CAPACITY);
-    let pulled = Arc::new(AtomicUsize::new(0));
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> = (0..TOTAL_FRAMES)
-      .map(|_| Ok(Frame::data(Bytes::from(vec![0u8; 1024]))))
-      .collect();
-    let body = CountingBody {
-      inner: StreamBody::new(stream::iter(frames)),
-      pulled: pulled.clone(),
-    };
-    let uri: Uri = "http://example.com/bp".parse().unwrap();
-    let dir = std::env::temp_dir();
-    let spool = tokio::spawn(async move {
-      // On-memory threshold usize::MAX: no disk involved, the only await point is the bounded send.
-      spool_and_store(body, tx, usize::MAX, usize::MAX, &dir, &uri).await
-    });
-
-    // Let the producer run until it parks on the full channel (single-threaded test runtime).
-    for _ in 0..50 {
-      tokio::task::yield_now().await;
+  async fn restore_keeps_newest_entries_up_to_max_entry() {
+    let dir = temp_cache_dir("restore-max").await;
+    let meta_of = |uri: &str, secs_ago: u64| {
+      let mut meta = fresh_meta(&uri.parse().unwrap());
+      meta.response_time -= std::time::Duration::from_secs(secs_ago);
+      meta
+    };
+    let older_duplicate = write_cache_file(&dir, &meta_of("https://a.example/1", 30), b"old").await;
+    let newest = write_cache_file(&dir, &meta_of("https://a.example/1", 10), b"new").await;
+    let second = write_cache_file(&dir, &meta_of("https://a.example/2", 20), b"2").await;
+    let oldest = write_cache_file(&dir, &meta_of("https://a.example/3", 40), b"3").await;
+
+    let cache = test_cache(&dir);
+    cache
+      .tiers()
+      .unwrap()
+      .restore(2, u64::MAX, cache.max_each_size, cache.stale_if_error_grace)
+      .await;
+    assert_eq!(cache.count().await, (2, 0, 2));
+    let least_recent = cache.tiers().unwrap().index.victim();
+    assert_eq!(least_recent.as_deref(), Some("https://a.example/2"));
+    assert!(is_cached(&cache, "https://a.example/1"));
+    assert!(!is_cached(&cache, "https://a.example/3"));
+    for path in [newest, second] {
+      assert!(fs::metadata(&path).await.is_ok());
     }
-    let ahead = pulled.load(Ordering::Relaxed);
-    assert!(
-      ahead < TOTAL_FRAMES,
-      "the producer must be parked by backpressure, not run to EOF (pulled {ahead})"
-    );
-    // Bound: TEST_CAPACITY buffered + the sender's guaranteed slot + the frame parked in the send.
-    assert!(
-      ahead <= TEST_CAPACITY + 2,
-      "the producer read-ahead must be bounded by the channel capacity (pulled {ahead})"
+    for path in [older_duplicate, oldest] {
+      assert!(fs::metadata(&path).await.is_err(), "{path:?} must be removed");
+    }
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// Body served from the cache for the request with `accept-encoding`
+  async fn cached_body(cache: &RpxyCache, uri: &str, accept_encoding: &str) -> Option<Bytes> {
+    let req = Request::builder()
+      .uri(uri)
+      .header("accept-encoding", accept_encoding)
+      .body(())
+      .unwrap();
+    let CacheLookup::Fresh(response) = cache.get(&req).await else {
+      return None;
+    };
+    Some(BodyExt::collect(response.into_body()).await.unwrap().to_bytes())
+  }
+
+  /// Responses with `Vary` are stored per variant and served to requests whose listed headers match in
+  /// the normalized form, while a purge of the URL removes every variant.
+  #[tokio::test]
+  async fn vary_variants_are_stored_and_served_per_normalized_headers() {
+    let dir = temp_cache_dir("vary").await;
+    let cache = test_cache(&dir);
+    let uri = "https://a.example/app.js";
+    TestObject::new(uri)
+      .request_header("accept-encoding", "gzip, br")
+      .vary("Accept-Encoding")
+      .body(b"compressed")
+      .publish(&cache)
+      .await;
+    TestObject::new(uri)
+      .request_header("accept-encoding", "identity")
+      .vary("Accept-Encoding")
+      .body(b"identity")
+      .publish(&cache)
+      .await;
+    assert_eq!(cache.count().await, (2, 2, 0));
+
+    assert_eq!(cached_body(&cache, uri, "GZIP,br").await.as_deref(), Some(&b"compressed"[..]));
+    assert_eq!(
+      cached_body(&cache, uri, " gzip ,  br ").await.as_deref(),
+      Some(&b"compressed"[..])
     );
+    assert_eq!(cached_body(&cache, uri, "identity").await.as_deref(), Some(&b"identity"[..]));
+    assert!(cached_body(&cache, uri, "br").await.is_none());
+    // A mismatched variant is not evicted.
+    assert_eq!(cache.count().await, (2, 2, 0));
+
+    let url = PurgeTarget::Url(uri.parse().unwrap());
+    assert_eq!(cache.purge(&url).await.unwrap(), 2);
+    assert!(cache.tiers().unwrap().index.vary(uri).unwrap().is_empty());
+    let _ = fs::remove_dir_all(&dir).await;
+  }
 
-    // Draining un-parks the producer; the spool completes and the full object is cached.
-    assert_eq!(forwarded_len(rx.collect::<Vec<_>>().await), TOTAL_FRAMES * 1024);
-    let stored = spool.await.unwrap();
-    assert!(
-      matches!(stored, Some((CacheFileOrOnMemory::OnMemory(ref b), _)) if b.len() == TOTAL_FRAMES * 1024),
-      "the drained object must be cached in full"
+  /// The oldest variant beyond the maximum per URI is evicted, and a response with another `Vary` or
+  /// without it supersedes every variant of the URI.
+  #[tokio::test]
+  async fn variants_are_capped_per_uri_and_superseded() {
+    let dir = temp_cache_dir("vary-cap").await;
+    let cache = test_cache(&dir);
+    let uri = "https://a.example/page";
+    for encoding in ["a", "b", "c", "d", "e"] {
+      TestObject::new(uri)
+        .request_header("accept-encoding", encoding)
+        .vary("accept-encoding")
+        .body(b"v")
+        .publish(&cache)
+        .await;
+    }
+    assert_eq!(cache.count().await, (4, 4, 0));
+    assert!(cached_body(&cache, uri, "a").await.is_none());
+    assert!(cached_body(&cache, uri, "e").await.is_some());
+    // Other URIs are not affected by the cap.
+    TestObject::new("https://a.example/other")
+      .request_header("accept-encoding", "a")
+      .vary("accept-encoding")
+      .body(b"v")
+      .publish(&cache)
+      .await;
+    assert_eq!(cache.count().await, (5, 5, 0));
+
+    TestObject::new(uri)
+      .request_header("accept-encoding", "a")
+      .vary("accept-encoding, accept-language")
+      .body(b"vary2")
+      .publish(&cache)
+      .await;
+    assert_eq!(cache.count().await, (2, 2, 0));
+    assert_eq!(cached_body(&cache, uri, "a").await.as_deref(), Some(&b"vary2"[..]));
+
+    TestObject::new(uri)
+      .request_header("accept-encoding", "a")
+      .body(b"plain")
+      .publish(&cache)
+      .await;
+    assert_eq!(cache.count().await, (2, 2, 0));
+    assert!(cache.tiers().unwrap().index.vary(uri).unwrap().is_empty());
+    assert_eq!(cached_body(&cache, uri, "b").await.as_deref(), Some(&b"plain"[..]));
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  #[test]
+  fn vary_names_are_normalized_and_star_is_not_cacheable() {
+    let mut headers = HeaderMap::new();
+    headers.append("vary", HeaderValue::from_static("Accept-Language, accept-encoding"));
+    headers.append("vary", HeaderValue::from_static("Accept-Encoding"));
+    let names = vary_names(&headers).unwrap();
+    assert_eq!(names, vec![header::ACCEPT_ENCODING, header::ACCEPT_LANGUAGE]);
+
+    let req = Request::builder().uri("https://a.example/").body(()).unwrap();
+    let res = Response::builder()
+      .header("cache-control", "public, max-age=3600")
+      .header("vary", "accept-encoding, *")
+      .body(())
+      .unwrap();
+    assert!(get_policy_if_cacheable(Some(&req), Some(&res)).unwrap().is_none());
+  }
+
+  /// Within `stale-while-revalidate`, a stale object is served with its age, and only the first request
+  /// gets the guard to refresh it until the guard is dropped.
+  #[tokio::test]
+  async fn stale_while_revalidate_serves_stale_and_refreshes_once() {
+    let cache = test_cache(&temp_cache_dir("swr").await);
+    let uri = "http://example.com/swr";
+    TestObject::new(uri)
+      .cache_control("max-age=10, stale-while-revalidate=60")
+      .age(Duration::from_secs(30))
+      .publish(&cache)
+      .await;
+    let req = Request::builder().uri(uri).body(()).unwrap();
+
+    let CacheLookup::Revalidate(res, Some((guard, _))) = cache.get(&req).await else {
+      panic!("the first request must refresh the stale object");
+    };
+    assert_eq!(res.headers().get(header::AGE).unwrap(), "30");
+    assert_eq!(
+      res.extensions().get::<CacheStatus>().unwrap().to_string(),
+      "rpxy; hit; ttl=-20; key=\"http://example.com/swr\""
     );
+    assert_eq!(BodyExt::collect(res.into_body()).await.unwrap().to_bytes(), "body");
+    assert!(matches!(cache.get(&req).await, CacheLookup::Revalidate(_, None)));
+    drop(guard);
+    assert!(matches!(cache.get(&req).await, CacheLookup::Revalidate(_, Some(_))));
+
+    // A request with `no-cache` does not take the stale object
+    let no_cache = Request::builder()
+      .uri(uri)
+      .header("cache-control", "no-cache")
+      .body(())
+      .unwrap();
+    assert!(matches!(cache.get(&no_cache).await, CacheLookup::Stale(_)));
+    assert!(is_cached(&cache, uri));
+
+    // Beyond the period, the entry is evicted
+    TestObject::new(uri)
+      .cache_control("max-age=10, stale-while-revalidate=60")
+      .age(Duration::from_secs(80))
+      .publish(&cache)
+      .await;
+    assert!(matches!(cache.get(&req).await, CacheLookup::Miss(CacheForward::Stale)));
+    assert!(!is_cached(&cache, uri));
   }
 
-  /// A slow consumer paces the file-read hit path. Mismatch eviction happens only at EOF, and a
-  /// full channel provably keeps the producer from reaching EOF, so with only one frame consumed
-  /// the wrong-hashed file must still exist; draining to EOF must then evict it.
+  /// Within `stale-if-error`, or the grace period unless revalidation is required, a stale entry is kept
+  /// to be served on upstream errors.
   #[tokio::test]
-  async fn file_read_backpressure_holds_eof_eviction_until_drained() {
-    let path = temp_cache_path("bp-read");
-    // Enough chunks that the producer cannot reach EOF while the channel is full.
-    let content = vec![5u8; FILE_CACHE_READ_CHUNK * (CACHE_STREAM_CHANNEL_CAPACITY + 4)];
-    fs::write(&path, &content).await.unwrap();
-    let wrong_hash = Bytes::from_static(&[0u8; 32]);
-    let file_store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(1)),
-      runtime_handle: tokio::runtime::Handle::current(),
+  async fn stale_if_error_keeps_entry_within_period_or_grace() {
+    let mut cache = test_cache(&temp_cache_dir("sie").await);
+    let uri = "http://example.com/sie";
+    let req = Request::builder().uri(uri).body(()).unwrap();
+
+    TestObject::new(uri)
+      .cache_control("max-age=10, stale-if-error=60")
+      .age(Duration::from_secs(30))
+      .publish(&cache)
+      .await;
+    let CacheLookup::Stale(entry) = cache.get(&req).await else {
+      panic!("the stale entry must be kept within stale-if-error");
     };
-    let mut body = file_store.read(path.clone(), &wrong_hash).await.unwrap();
+    let res = cache.get_stale_if_error(&entry, &req).await.unwrap();
+    assert_eq!(BodyExt::collect(res.into_body()).await.unwrap().to_bytes(), "body");
+
+    TestObject::new(uri)
+      .cache_control("max-age=10, stale-if-error=60")
+      .age(Duration::from_secs(80))
+      .publish(&cache)
+      .await;
+    assert!(matches!(cache.get(&req).await, CacheLookup::Miss(CacheForward::Stale)));
+    assert!(!is_cached(&cache, uri));
+
+    cache.stale_if_error_grace = Duration::from_secs(60);
+    TestObject::new(uri)
+      .cache_control("max-age=10")
+      .age(Duration::from_secs(30))
+      .publish(&cache)
+      .await;
+    assert!(matches!(cache.get(&req).await, CacheLookup::Stale(_)));
+    TestObject::new(uri)
+      .cache_control("max-age=10, must-revalidate")
+      .age(Duration::from_secs(30))
+      .publish(&cache)
+      .await;
+    assert!(matches!(cache.get(&req).await, CacheLookup::Miss(CacheForward::Stale)));
+    assert!(!is_cached(&cache, uri));
+  }
+
+  #[tokio::test]
+  async fn lookup_gives_cache_status() {
+    let cache = test_cache(&temp_cache_dir("status").await);
+    let uri = "http://example.com/status";
+    let req = Request::builder().uri(uri).body(()).unwrap();
+    assert!(matches!(cache.get(&req).await, CacheLookup::Miss(CacheForward::UriMiss)));
+
+    TestObject::new(uri)
+      .cache_control("max-age=100")
+      .age(Duration::from_secs(10))
+      .publish(&cache)
+      .await;
+    let CacheLookup::Fresh(res) = cache.get(&req).await else {
+      panic!("the entry must be fresh");
+    };
+    let status = res.extensions().get::<CacheStatus>().unwrap();
+    assert!(status.hit && status.fwd.is_none());
+    assert!(matches!(status.ttl, Some(89..=90)));
+    assert_eq!(status.key.as_deref(), Some(uri));
+    let no_cache = Request::builder()
+      .uri(uri)
+      .header("cache-control", "no-cache")
+      .body(())
+      .unwrap();
+    assert!(matches!(cache.get(&no_cache).await, CacheLookup::Miss(CacheForward::Request)));
+  }
 
-    // Consume a single frame, then stall.
-    assert!(body.frame().await.is_some(), "the first frame must arrive");
-    for _ in 0..50 {
-      tokio::task::yield_now().await;
+  /// Concurrent misses of an entry wait for the first request fetching it and are served from the stored
+  /// entry, or look up again on timeout. Requests other than `GET` are not collapsed.
+  #[tokio::test]
+  async fn concurrent_misses_wait_for_the_request_fetching_the_entry() {
+    let mut cache = test_cache(&temp_cache_dir("coalesce").await);
+    cache.coalescing_timeout = Duration::from_secs(5);
+    let uri = "http://example.com/coalesce";
+    let req = Request::builder().uri(uri).body(()).unwrap();
+
+    let (CacheLookup::Miss(_), Some(guard)) = cache.get_or_join(&req).await else {
+      panic!("the first miss must fetch the entry");
+    };
+    let post = Request::builder().method(Method::POST).uri(uri).body(()).unwrap();
+    assert!(matches!(cache.get_or_join(&post).await, (CacheLookup::Miss(_), None)));
+
+    let follower = {
+      let cache = cache.clone();
+      tokio::spawn(async move {
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        cache.get_or_join(&req).await
+      })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!follower.is_finished());
+    TestObject::new(uri).publish(&cache).await;
+    drop(guard);
+    let (CacheLookup::Fresh(res), None) = follower.await.unwrap() else {
+      panic!("the follower must be served from the stored entry");
+    };
+    assert_eq!(BodyExt::collect(res.into_body()).await.unwrap().to_bytes(), "body");
+
+    // A follower gives up waiting after the timeout
+    cache.coalescing_timeout = Duration::from_millis(50);
+    let other = Request::builder().uri("http://example.com/other").body(()).unwrap();
+    let (CacheLookup::Miss(_), Some(_guard)) = cache.get_or_join(&other).await else {
+      panic!("the first miss must fetch the entry");
+    };
+    assert!(matches!(cache.get_or_join(&other).await, (CacheLookup::Miss(_), None)));
+  }
+
+  /// Concurrent misses waiting for a cacheable response of a known length are relayed its body as it is received,
+  /// including those joining later, while those waiting for a response of an unknown length are woken up by its
+  /// head to go to the upstream by themselves.
+  #[tokio::test]
+  async fn waiting_misses_are_relayed_the_body_being_fetched() {
+    let mut cache = test_cache(&temp_cache_dir("coalesce-relay").await);
+    cache.coalescing_timeout = Duration::from_secs(5);
+    let follower = |cache: &RpxyCache, uri: &'static str, range: Option<&'static str>| {
+      let cache = cache.clone();
+      tokio::spawn(async move {
+        let req = range
+          .into_iter()
+          .fold(Request::builder().uri(uri), |builder, range| {
+            builder.header(header::RANGE, range)
+          })
+          .body(())
+          .unwrap();
+        cache.get_or_join(&req).await
+      })
+    };
+    let fetch = |cache: &RpxyCache, uri: &'static str, content_length: Option<&'static str>| {
+      let cache = cache.clone();
+      async move {
+        let req = Request::builder().uri(uri).body(()).unwrap();
+        let (CacheLookup::Miss(_), Some(guard)) = cache.get_or_join(&req).await else {
+          panic!("the first miss must fetch the entry");
+        };
+        let res = content_length
+          .into_iter()
+          .fold(Response::builder(), |builder, len| {
+            builder.header(header::CONTENT_LENGTH, len)
+          })
+          .header(header::CACHE_CONTROL, "public, max-age=3600")
+          .body(())
+          .unwrap();
+        let policy = get_policy_if_cacheable(Some(&req), Some(&res)).unwrap().unwrap();
+        let (parts, _) = res.into_parts();
+        let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(TEST_CHANNEL_CAPACITY);
+        (tx, rx, parts, policy, req, guard)
+      }
+    };
+
+    let uri = "http://example.com/relay";
+    let (mut tx, rx, parts, policy, req, guard) = fetch(&cache, uri, Some("10")).await;
+    let early = follower(&cache, uri, None);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!early.is_finished());
+    let relayed = cache
+      .put(&req, &parts, StreamBody::new(rx), &policy, cache.purge_epoch(), Some(guard))
+      .await
+      .unwrap();
+    tx.send(Ok(Frame::data(Bytes::from_static(b"hello")))).await.unwrap();
+    let (CacheLookup::Collapsed(early), None) = early.await.unwrap() else {
+      panic!("a waiting miss must be relayed the response");
+    };
+    let status = early.extensions().get::<CacheStatus>().unwrap();
+    assert!(status.collapsed && !status.hit);
+    let (CacheLookup::Collapsed(late), None) = follower(&cache, uri, None).await.unwrap() else {
+      panic!("a miss joining later must be relayed the response");
+    };
+    let ranged = follower(&cache, uri, Some("bytes=3-6"));
+    tx.send(Ok(Frame::data(Bytes::from_static(b"world")))).await.unwrap();
+    drop(tx);
+    for res in [early, late] {
+      assert_eq!(BodyExt::collect(res.into_body()).await.unwrap().to_bytes(), "helloworld");
     }
-    assert!(
-      fs::metadata(&path).await.is_ok(),
-      "the producer must be parked before EOF, so the mismatch eviction has not run yet"
-    );
+    let (CacheLookup::Collapsed(ranged), None) = ranged.await.unwrap() else {
+      panic!("a ranged miss must be relayed the range");
+    };
+    assert_eq!(ranged.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(BodyExt::collect(ranged.into_body()).await.unwrap().to_bytes(), "lowo");
+    assert_eq!(BodyExt::collect(relayed).await.unwrap().to_bytes(), "helloworld");
+
+    // Without `Content-Length`, the waiting misses are not held back until the whole body is received
+    let uri = "http://example.com/chunked";
+    let (mut tx, rx, parts, policy, req, guard) = fetch(&cache, uri, None).await;
+    let waiting = follower(&cache, uri, None);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let relayed = cache
+      .put(&req, &parts, StreamBody::new(rx), &policy, cache.purge_epoch(), Some(guard))
+      .await
+      .unwrap();
+    assert!(matches!(waiting.await.unwrap(), (CacheLookup::Miss(_), None)));
+    tx.send(Ok(Frame::data(Bytes::from_static(b"chunked")))).await.unwrap();
+    drop(tx);
+    assert_eq!(BodyExt::collect(relayed).await.unwrap().to_bytes(), "chunked");
+  }
 
-    // Drain to EOF: the integrity check finally runs and evicts the corrupted file.
-    while body.frame().await.is_some() {}
-    assert!(
-      fs::metadata(&path).await.is_err(),
-      "draining to EOF must evict the wrong-hashed file"
+  /// A response past its lifetime on arrival by `Age` has been stale since before it was received
+  #[test]
+  fn stale_response_counts_staleness_past_age() {
+    let now = SystemTime::now();
+    let stale_response = |age: &'static str| {
+      let res = Response::builder()
+        .header(header::CACHE_CONTROL, "max-age=10, stale-while-revalidate=60")
+        .header(header::AGE, age)
+        .body(())
+        .unwrap();
+      let policy = CachePolicy::new_options(&Request::new(()), &res, now, Default::default());
+      StaleResponse::new(&policy, res.status(), res.headers(), now, Duration::ZERO).unwrap()
+    };
+    assert_eq!(
+      stale_response("4").staleness(now + Duration::from_secs(10)),
+      Duration::from_secs(4)
     );
+    assert_eq!(stale_response("30").staleness(now), Duration::from_secs(20));
+    assert!(!stale_response("80").is_usable(now));
+  }
+
+  /// A stale entry with a validator is kept to be revalidated with a conditional request, unless the client
+  /// made the request conditional, and a `304 Not Modified` refreshes it without its body.
+  #[tokio::test]
+  async fn stale_entry_with_validator_is_revalidated_by_not_modified() {
+    let cache = test_cache(&temp_cache_dir("revalidate").await);
+    let uri = "http://example.com/revalidate";
+    let headers = [("cache-control", "max-age=10"), ("etag", "\"v1\""), ("x-version", "1")];
+    TestObject::new(uri)
+      .headers(&headers)
+      .age(Duration::from_secs(30))
+      .publish(&cache)
+      .await;
+    let req = Request::builder().uri(uri).body(()).unwrap();
+
+    let CacheLookup::Stale(entry) = cache.get(&req).await else {
+      panic!("the stale entry with a validator must be kept");
+    };
+    let mut upstream_req = Request::builder().uri(uri).body(()).unwrap();
+    assert!(entry.make_conditional(&mut upstream_req));
+    assert_eq!(upstream_req.headers().get(header::IF_NONE_MATCH).unwrap(), "\"v1\"");
+    let mut client_conditional = Request::builder()
+      .uri(uri)
+      .header(header::IF_MODIFIED_SINCE, "Thu, 01 Jan 2026 00:00:00 GMT")
+      .body(())
+      .unwrap();
+    assert!(!entry.make_conditional(&mut client_conditional));
+    assert!(!client_conditional.headers().contains_key(header::IF_NONE_MATCH));
+
+    let not_modified = Response::builder()
+      .status(StatusCode::NOT_MODIFIED)
+      .header("cache-control", "max-age=100")
+      .header("etag", "\"v1\"")
+      .header("x-version", "2")
+      .body(())
+      .unwrap();
+    let res = cache.refresh(&entry, &req, &not_modified).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("x-version").unwrap(), "2");
+    assert_eq!(BodyExt::collect(res.into_body()).await.unwrap().to_bytes(), "body");
+    let CacheLookup::Fresh(res) = cache.get(&req).await else {
+      panic!("the refreshed entry must be fresh");
+    };
+    assert_eq!(res.headers().get("x-version").unwrap(), "2");
+
+    // A `304` for another entity does not refresh it
+    TestObject::new(uri)
+      .headers(&headers)
+      .age(Duration::from_secs(30))
+      .publish(&cache)
+      .await;
+    let CacheLookup::Stale(entry) = cache.get(&req).await else {
+      panic!("the stale entry with a validator must be kept");
+    };
+    let other = Response::builder()
+      .status(StatusCode::NOT_MODIFIED)
+      .header("etag", "\"v2\"")
+      .body(())
+      .unwrap();
+    assert!(cache.refresh(&entry, &req, &other).await.is_none());
+    cache.discard(*entry).await;
+    assert!(!is_cached(&cache, uri));
   }
 
-  /// Dropping the receiver mid-stream after the store has spilled to a temp file must abort the
-  /// store, clean up the temp file, and cache nothing (complements
-  /// `returns_none_when_downstream_dropped`, which covers the receiver being gone from the start).
+  /// The head refreshed by a `304 Not Modified` is written to the cache file, from which the entry is restored
   #[tokio::test]
-  async fn store_dropped_receiver_after_spill_cleans_temp() {
-    let dir = temp_cache_dir("drop-spill").await;
-    let uri: Uri = "http://example.com/drop".parse().unwrap();
-    let (tx, mut rx) = mpsc::channel::<Result<Frame<Bytes>, TestBodyError>>(0);
-    // 6 x 2000 bytes with a 4096 on-memory threshold: the spill starts at the third frame.
-    let frames: Vec<Result<Frame<Bytes>, TestBodyError>> =
-      (0..6).map(|_| Ok(Frame::data(Bytes::from(vec![2u8; 2000])))).collect();
-    let body = StreamBody::new(stream::iter(frames));
-    let dir_clone = dir.clone();
-    let spool = tokio::spawn(async move { spool_and_store(body, tx, 1_000_000, 4096, &dir_clone, &uri).await });
-
-    // Consume enough frames for the spill to have happened (the third frame was processed once
-    // the fourth has been forwarded), then hang up.
-    for _ in 0..4 {
-      assert!(rx.next().await.is_some());
-    }
-    drop(rx);
-
-    assert!(spool.await.unwrap().is_none(), "an aborted store must not cache");
-    let mut entries = fs::read_dir(&dir).await.unwrap();
+  async fn refreshed_head_is_restored_from_cache_file() {
+    let dir = temp_cache_dir("refresh-restore").await;
+    let uri = "https://a.example/refresh";
+    let mut meta = fresh_meta(&uri.parse().unwrap());
+    meta.response_headers = vec![
+      ("cache-control".to_string(), b"max-age=10".to_vec()),
+      ("etag".to_string(), b"\"v1\"".to_vec()),
+      ("x-version".to_string(), b"1".to_vec()),
+    ];
+    meta.response_time -= Duration::from_secs(30);
+    let body = vec![7u8; 10_000];
+    let original = write_cache_file(&dir, &meta, &body).await;
+    let restore = |cache: RpxyCache| async move {
+      cache
+        .tiers()
+        .unwrap()
+        .restore(10, u64::MAX, cache.max_each_size, cache.stale_if_error_grace)
+        .await;
+      cache
+    };
+    let cache = restore(test_cache(&dir)).await;
+
+    let req = Request::builder().uri(uri).body(()).unwrap();
+    let CacheLookup::Stale(entry) = cache.get(&req).await else {
+      panic!("the stale entry with a validator must be kept");
+    };
+    let not_modified = Response::builder()
+      .status(StatusCode::NOT_MODIFIED)
+      .header("cache-control", "max-age=100")
+      .header("etag", "\"v1\"")
+      .header("x-version", "2")
+      .body(())
+      .unwrap();
+    let res = cache.refresh(&entry, &req, &not_modified).await.unwrap();
+    assert!(res.extensions().get::<CacheStatus>().unwrap().stored);
     assert!(
-      entries.next_entry().await.unwrap().is_none(),
-      "the temp file must be cleaned up when the receiver goes away mid-spill"
+      fs::metadata(&original).await.is_err(),
+      "the original cache file must be replaced"
+    );
+    assert_eq!(cache.count().await, (1, 0, 1));
+
+    let cache = restore(test_cache(&dir)).await;
+    assert_eq!(cache.count().await, (1, 0, 1));
+    let CacheLookup::Fresh(res) = cache.get(&req).await else {
+      panic!("the restored entry must be fresh with the refreshed head");
+    };
+    assert_eq!(res.headers().get("x-version").unwrap(), "2");
+    assert_eq!(
+      BodyExt::collect(res.into_body()).await.unwrap().to_bytes().as_ref(),
+      body.as_slice()
     );
     let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// Concurrent count updates converge without locks. Phase-separated for a deterministic
-  /// expectation (free interleaving with the saturating decrement would make the final count
-  /// scheduling-dependent): phase 1 increments concurrently, phase 2 evicts each task's own
-  /// pre-created file concurrently.
-  #[tokio::test]
-  async fn file_store_count_concurrent_storm_phased() {
-    const N: usize = 64;
-    let dir = temp_cache_dir("storm").await;
-    let store = test_file_store();
-
-    // Phase 1: N concurrent increments (publish-side bookkeeping).
-    let handles: Vec<_> = (0..N)
-      .map(|_| {
-        let s = store.clone();
-        tokio::spawn(async move { s.incr_count().await })
+  /// Look up the fresh entry for `uri` with the request headers
+  async fn fresh_hit(cache: &RpxyCache, uri: &str, headers: &[(&str, &str)]) -> (response::Parts, Bytes) {
+    let req = headers
+      .iter()
+      .fold(Request::builder().uri(uri), |builder, (name, value)| {
+        builder.header(*name, *value)
       })
-      .collect();
-    for h in handles {
-      h.await.unwrap();
+      .body(())
+      .unwrap();
+    let CacheLookup::Fresh(res) = cache.get(&req).await else {
+      panic!("the entry must be fresh");
+    };
+    let (parts, body) = res.into_parts();
+    (parts, BodyExt::collect(body).await.unwrap().to_bytes())
+  }
+
+  /// A fresh on-memory object answers a matching conditional request with `304 Not Modified`, and a `Range`
+  /// request with `206 Partial Content` in single and multipart ranges, or `416` if not satisfiable.
+  #[tokio::test]
+  async fn conditional_and_range_requests_are_served_from_on_memory_object() {
+    let cache = test_cache(&temp_cache_dir("range-memory").await);
+    let uri = "http://example.com/range";
+    let last_modified = "Thu, 01 Jan 2026 00:00:00 GMT";
+    let headers = [
+      ("cache-control", "max-age=100"),
+      ("etag", "\"v1\""),
+      ("last-modified", last_modified),
+      ("content-type", "text/plain"),
+    ];
+    TestObject::new(uri).headers(&headers).body(b"stale").publish(&cache).await;
+
+    for if_none_match in ["\"v1\"", "W/\"v1\"", "\"v0\", \"v1\"", "*"] {
+      let (parts, body) = fresh_hit(&cache, uri, &[("if-none-match", if_none_match)]).await;
+      assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+      assert_eq!(parts.headers.get(header::ETAG).unwrap(), "\"v1\"");
+      assert!(!parts.headers.contains_key(header::CONTENT_TYPE));
+      assert!(body.is_empty());
     }
-    assert_eq!(store.count().await, N, "all concurrent increments must be counted");
+    let (parts, _) = fresh_hit(&cache, uri, &[("if-modified-since", last_modified)]).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+    // `If-None-Match` takes precedence over `If-Modified-Since`
+    let not_matching = [("if-none-match", "\"v2\""), ("if-modified-since", last_modified)];
+    let (parts, body) = fresh_hit(&cache, uri, &not_matching).await;
+    assert_eq!((parts.status, body.as_ref()), (StatusCode::OK, b"stale".as_ref()));
+    let (parts, _) = fresh_hit(&cache, uri, &[("if-modified-since", "Wed, 31 Dec 2025 00:00:00 GMT")]).await;
+    assert_eq!(parts.status, StatusCode::OK);
+
+    let (parts, body) = fresh_hit(&cache, uri, &[("range", "bytes=1-2")]).await;
+    assert_eq!((parts.status, body.as_ref()), (StatusCode::PARTIAL_CONTENT, b"ta".as_ref()));
+    assert_eq!(parts.headers.get(header::CONTENT_RANGE).unwrap(), "bytes 1-2/5");
+    assert_eq!(parts.headers.get(header::CONTENT_LENGTH).unwrap(), "2");
+    let (_, body) = fresh_hit(&cache, uri, &[("range", "bytes=-2")]).await;
+    assert_eq!(body, "le");
+
+    let (parts, body) = fresh_hit(&cache, uri, &[("range", "bytes=0-0,3-")]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    let content_type = parts.headers.get(header::CONTENT_TYPE).unwrap().to_str().unwrap();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+    let expected = format!(
+      "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-0/5\r\n\r\ns\r\n\
+       --{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 3-4/5\r\n\r\nle\r\n--{boundary}--\r\n"
+    );
+    assert_eq!(body, expected);
+    assert_eq!(
+      parts.headers.get(header::CONTENT_LENGTH).unwrap(),
+      &expected.len().to_string()
+    );
 
-    // Phase 2: N concurrent evictions, each of its own counted file.
-    let mut handles = Vec::with_capacity(N);
-    for i in 0..N {
-      let p = dir.join(format!("f{i}"));
-      fs::write(&p, b"x").await.unwrap();
-      let s = store.clone();
-      handles.push(tokio::spawn(async move { s.evict(&p).await }));
+    let (parts, body) = fresh_hit(&cache, uri, &[("range", "bytes=5-")]).await;
+    assert_eq!(parts.status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(parts.headers.get(header::CONTENT_RANGE).unwrap(), "bytes */5");
+    assert!(body.is_empty());
+
+    // `Range` is ignored if invalid, or if `If-Range` does not match the strong validator
+    for headers in [
+      [("range", "bytes=2-1"), ("if-range", "\"v1\"")],
+      [("range", "bytes=1-2"), ("if-range", "\"v2\"")],
+      [("range", "bytes=1-2"), ("if-range", "W/\"v1\"")],
+      [("range", "bytes=1-2"), ("if-range", "Wed, 31 Dec 2025 00:00:00 GMT")],
+    ] {
+      let (parts, body) = fresh_hit(&cache, uri, &headers).await;
+      assert_eq!((parts.status, body.as_ref()), (StatusCode::OK, b"stale".as_ref()));
     }
-    for h in handles {
-      h.await.unwrap();
+    for if_range in ["\"v1\"", last_modified] {
+      let (parts, _) = fresh_hit(&cache, uri, &[("range", "bytes=1-2"), ("if-range", if_range)]).await;
+      assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
     }
-    assert_eq!(store.count().await, 0, "all concurrent evictions must be counted");
-    let mut entries = fs::read_dir(&dir).await.unwrap();
-    assert!(
-      entries.next_entry().await.unwrap().is_none(),
-      "every evicted file must be unlinked"
+  }
+
+  /// A file object serves byte ranges by seeking into the body after the header of the cache file.
+  /// A `Range` request missing the cache is forwarded without `Range`, so that the full response is stored for
+  /// later requests while the ranges are served from it. Partial content is never stored.
+  #[tokio::test]
+  async fn range_miss_stores_full_response() {
+    let cache = test_cache(&temp_cache_dir("range-miss").await);
+    let uri = "http://example.com/range-miss";
+    let upstream_response = |status: StatusCode, body: &'static [u8]| {
+      Response::builder()
+        .status(status)
+        .header("cache-control", "public, max-age=3600")
+        .header("content-length", body.len())
+        .body(Bytes::from_static(body))
+        .unwrap()
+    };
+    let partial = upstream_response(StatusCode::PARTIAL_CONTENT, b"23");
+    let req = Request::builder().uri(uri).header("range", "bytes=2-3").body(()).unwrap();
+    assert!(get_policy_if_cacheable(Some(&req), Some(&partial)).unwrap().is_none());
+    assert!(!store_response(&cache, req, partial).await);
+    assert!(!is_cached(&cache, uri));
+
+    // The range miss: the full response to the request without `Range` is stored, and sliced for the client
+    let req = Request::builder().uri(uri).body(()).unwrap();
+    assert!(store_response(&cache, req, upstream_response(StatusCode::OK, b"0123456789")).await);
+    let client_range = HeaderMap::from_iter([(header::RANGE, HeaderValue::from_static("bytes=2-3"))]);
+    let res = upstream_response(StatusCode::OK, b"0123456789").map(|body| ResponseBody::Boxed(BoxBody::new(full(body))));
+    let (parts, body) = cache.serve_removed_range(&client_range, res).await.unwrap().into_parts();
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(parts.headers.get(header::CONTENT_RANGE).unwrap(), "bytes 2-3/10");
+    assert_eq!(BodyExt::collect(body).await.unwrap().to_bytes(), "23");
+
+    // The full request that follows is served the whole body from the cache
+    let (parts, body) = fresh_hit(&cache, uri, &[]).await;
+    assert_eq!((parts.status, body.as_ref()), (StatusCode::OK, b"0123456789".as_ref()));
+    let (parts, body) = fresh_hit(&cache, uri, &[("range", "bytes=-2")]).await;
+    assert_eq!((parts.status, body.as_ref()), (StatusCode::PARTIAL_CONTENT, b"89".as_ref()));
+  }
+
+  #[tokio::test]
+  async fn range_requests_are_served_from_file_object() {
+    let dir = temp_cache_dir("range-file").await;
+    let cache = RpxyCache {
+      max_each_size: 1_000_000,
+      ..test_cache(&dir)
+    };
+    let uri = "http://example.com/video";
+    let body = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    TestObject {
+      body: Bytes::from(body.clone()),
+      ..TestObject::new(uri)
+    }
+    .publish(&cache)
+    .await;
+    assert_eq!(cache.count().await, (1, 0, 1));
+
+    let (parts, got) = fresh_hit(&cache, uri, &[("range", "bytes=70000-")]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(parts.headers.get(header::CONTENT_RANGE).unwrap(), "bytes 70000-99999/100000");
+    assert_eq!(got, body[70000..]);
+
+    let (parts, got) = fresh_hit(&cache, uri, &[("range", "bytes=10-19,-10")]).await;
+    let content_type = parts.headers.get(header::CONTENT_TYPE).unwrap().to_str().unwrap();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+    let mut expected = format!("--{boundary}\r\nContent-Range: bytes 10-19/100000\r\n\r\n").into_bytes();
+    expected.extend_from_slice(&body[10..20]);
+    expected.extend_from_slice(format!("\r\n--{boundary}\r\nContent-Range: bytes 99990-99999/100000\r\n\r\n").as_bytes());
+    expected.extend_from_slice(&body[99990..]);
+    expected.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    assert_eq!(got, expected);
+
+    let (parts, _) = fresh_hit(&cache, uri, &[("range", "bytes=100000-")]).await;
+    assert_eq!(parts.status, StatusCode::RANGE_NOT_SATISFIABLE);
+    let (parts, got) = fresh_hit(&cache, uri, &[]).await;
+    assert_eq!((parts.status, got.len()), (StatusCode::OK, body.len()));
+    let _ = fs::remove_dir_all(&dir).await;
+  }
+
+  /// `max_ttl` caps the lifetime of a response, and `default_ttl` replaces the heuristic one of a response
+  /// without explicit one. Hits are served with the `Cache-Control` of the upstream response.
+  #[tokio::test]
+  async fn route_ttl_bounds_freshness_lifetime() {
+    let cache = test_cache(&temp_cache_dir("route-ttl").await);
+    let secs = Duration::from_secs;
+    let is_fresh = async |uri: &str| {
+      let req = Request::builder().uri(uri).body(()).unwrap();
+      matches!(cache.get(&req).await, CacheLookup::Fresh(_))
+    };
+    let capped = CacheTtl {
+      default_ttl: None,
+      max_ttl: Some(secs(100)),
+    };
+    let explicit = [("cache-control", "public, max-age=3600")];
+    TestObject::new("http://example.com/capped")
+      .headers(&explicit)
+      .ttl(capped)
+      .age(secs(50))
+      .publish(&cache)
+      .await;
+    let (parts, _) = fresh_hit(&cache, "http://example.com/capped", &[]).await;
+    assert_eq!(parts.headers.get(header::CACHE_CONTROL).unwrap(), "public, max-age=3600");
+    TestObject::new("http://example.com/capped")
+      .headers(&explicit)
+      .ttl(capped)
+      .age(secs(150))
+      .publish(&cache)
+      .await;
+    assert!(!is_fresh("http://example.com/capped").await);
+    // Shorter lifetimes are kept as they are
+    let (policy, cache_control) = bound_ttl(
+      fresh_policy(&Uri::from_static("http://example.com/short")),
+      &Request::builder().uri("http://example.com/short").body(()).unwrap(),
+      StatusCode::OK,
+      &HeaderMap::from_iter([(header::CACHE_CONTROL, HeaderValue::from_static("max-age=10"))]),
+      SystemTime::now(),
+      &capped,
     );
+    assert!(cache_control.is_none());
+    assert!(policy.time_to_live(SystemTime::now()) <= secs(10));
+
+    let defaulted = CacheTtl {
+      default_ttl: Some(secs(100)),
+      max_ttl: None,
+    };
+    let heuristic = [("last-modified", "Thu, 01 Jan 2026 00:00:00 GMT")];
+    TestObject::new("http://example.com/default")
+      .headers(&[])
+      .ttl(defaulted)
+      .age(secs(50))
+      .publish(&cache)
+      .await;
+    let (parts, _) = fresh_hit(&cache, "http://example.com/default", &[]).await;
+    assert!(!parts.headers.contains_key(header::CACHE_CONTROL));
+    TestObject::new("http://example.com/heuristic")
+      .headers(&heuristic)
+      .ttl(CacheTtl::default())
+      .age(secs(150))
+      .publish(&cache)
+      .await;
+    assert!(is_fresh("http://example.com/heuristic").await);
+    TestObject::new("http://example.com/heuristic")
+      .headers(&heuristic)
+      .ttl(defaulted)
+      .age(secs(150))
+      .publish(&cache)
+      .await;
+    assert!(!is_fresh("http://example.com/heuristic").await);
+    // `default_ttl` does not apply to an explicit lifetime
+    TestObject::new("http://example.com/explicit")
+      .cache_control("max-age=10")
+      .ttl(defaulted)
+      .age(secs(50))
+      .publish(&cache)
+      .await;
+    assert!(!is_fresh("http://example.com/explicit").await);
+  }
+
+  /// The cache rule of the route is kept in cache files: the entry is restored as the variant selected by the
+  /// key headers, with the lifetime bounded by the TTLs.
+  #[tokio::test]
+  async fn restore_keeps_cache_rule_of_route() {
+    let uri = "https://a.example/rule";
+    let meta_of = |secs_ago: u64| {
+      let mut meta = fresh_meta(&uri.parse().unwrap());
+      meta.request_headers.push(("accept-language".to_string(), b"en".to_vec()));
+      meta.key_headers = vec!["accept-language".to_string()];
+      meta.ttl = CacheTtl {
+        default_ttl: None,
+        max_ttl: Some(Duration::from_secs(100)),
+      };
+      meta.response_time -= Duration::from_secs(secs_ago);
+      meta
+    };
+    let dir = temp_cache_dir("restore-rule").await;
+    write_cache_file(&dir, &meta_of(50), b"rule").await;
+    let cache = test_cache(&dir);
+    cache
+      .tiers()
+      .unwrap()
+      .restore(10, u64::MAX, cache.max_each_size, cache.stale_if_error_grace)
+      .await;
+    let (parts, body) = fresh_hit(&cache, uri, &[("accept-language", "en")]).await;
+    assert_eq!(body.as_ref(), b"rule");
+    assert_eq!(parts.headers.get(header::CACHE_CONTROL).unwrap(), "public, max-age=3600");
+    let req = Request::builder().uri(uri).header("accept-language", "fr").body(()).unwrap();
+    assert!(matches!(cache.get(&req).await, CacheLookup::Miss(CacheForward::VaryMiss)));
+    let _ = fs::remove_dir_all(&dir).await;
+
+    // Stale beyond `max_ttl`, the file is discarded
+    let dir = temp_cache_dir("restore-rule-stale").await;
+    write_cache_file(&dir, &meta_of(150), b"rule").await;
+    let cache = test_cache(&dir);
+    cache
+      .tiers()
+      .unwrap()
+      .restore(10, u64::MAX, cache.max_each_size, cache.stale_if_error_grace)
+      .await;
+    assert_eq!(cache.count().await.0, 0);
     let _ = fs::remove_dir_all(&dir).await;
   }
 
-  /// Dropping the receiver mid-read must not evict the file. The file has more chunks than the
-  /// channel can hold, so the producer cannot have reached EOF when the drop lands; the stored
-  /// hash is intentionally wrong, so the file surviving proves the aborted read exits without
-  /// acting on the (incomplete) integrity check - a run to EOF would have evicted it.
+  /// Stand-in for a storage shared among instances, keeping the entries only as encoded
+  #[derive(Default)]
+  struct EncodedStorage {
+    entries: Mutex<HashMap<String, (Bytes, Bytes)>>,
+    vary: Mutex<HashMap<String, Vec<HeaderName>>>,
+  }
+
+  #[async_trait]
+  impl CacheStorage for EncodedStorage {
+    async fn vary(&self, uri_key: &str) -> CacheResult<Vec<HeaderName>> {
+      Ok(self.vary.lock().unwrap().get(uri_key).cloned().unwrap_or_default())
+    }
+
+    async fn get(&self, cache_key: &str) -> CacheResult<Option<CacheEntry>> {
+      let entry = self.entries.lock().unwrap().get(cache_key).map(|(entry, _)| entry.clone());
+      entry.map(|entry| CacheEntry::from_bytes(&entry)).transpose()
+    }
+
+    async fn put(&self, cache_key: &str, entry: CacheEntry, body: Bytes) -> CacheResult<()> {
+      let vary = entry.vary().to_vec();
+      self
+        .vary
+        .lock()
+        .unwrap()
+        .insert(uri_of_cache_key(cache_key).to_string(), vary);
+      self
+        .entries
+        .lock()
+        .unwrap()
+        .insert(cache_key.to_string(), (entry.to_bytes()?, body));
+      Ok(())
+    }
+
+    async fn delete(&self, cache_key: &str) -> CacheResult<()> {
+      self.entries.lock().unwrap().remove(cache_key);
+      Ok(())
+    }
+
+    async fn stream_body(&self, cache_key: &str, _entry: &CacheEntry) -> CacheResult<CacheBody> {
+      let (_, body) = self
+        .entries
+        .lock()
+        .unwrap()
+        .get(cache_key)
+        .cloned()
+        .ok_or(CacheError::CacheBodyNotFound)?;
+      let (first, rest) = body.split_at(body.len() / 2);
+      let chunks = vec![Ok(Bytes::copy_from_slice(first)), Ok(Bytes::copy_from_slice(rest))];
+      Ok(CacheBody::stream(stream::iter(chunks)))
+    }
+
+    async fn purge(&self, target: &PurgeTarget) -> CacheResult<usize> {
+      let mut entries = self.entries.lock().unwrap();
+      let before = entries.len();
+      entries.retain(|key, (entry, _)| CacheEntry::from_bytes(entry).is_ok_and(|entry| !target.matches(key, &entry)));
+      Ok(before - entries.len())
+    }
+  }
+
+  /// Entry of a fresh response for a custom storage, tagged with the surrogate key
+  fn custom_entry(uri: &str, surrogate_key: &str, body: &[u8]) -> (String, CacheEntry) {
+    let mut meta = fresh_meta(&uri.parse().unwrap());
+    meta
+      .response_headers
+      .push(("surrogate-key".to_string(), surrogate_key.as_bytes().to_vec()));
+    meta.surrogate_keys = vec![surrogate_key.to_string()];
+    let hash = Bytes::copy_from_slice(Sha256::digest(body).as_slice());
+    let (cache_key, object) = meta
+      .cache_object(CacheFileOrOnMemory::External, hash, Duration::ZERO)
+      .unwrap();
+    (cache_key, CacheEntry::new(object, Some(Arc::new(meta))))
+  }
+
+  #[test]
+  fn cache_entry_round_trips_through_bytes() {
+    let (_, entry) = custom_entry("https://a.example/entry", "tag", b"body");
+    let bytes = entry.to_bytes().unwrap();
+    assert!(bytes.starts_with(CACHE_FILE_MAGIC));
+    let decoded = CacheEntry::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.surrogate_keys(), ["tag"]);
+    assert!(decoded.vary().is_empty());
+    assert_eq!(decoded.object.hash, entry.object.hash);
+    assert!(!decoded.object.policy.is_stale(SystemTime::now()));
+    assert_eq!(decoded.to_bytes().unwrap(), bytes);
+
+    assert!(CacheEntry::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(CacheEntry::from_bytes(b"RPXYCF00").is_err());
+    assert!(CacheEntry::new(entry.object, None).to_bytes().is_err());
+  }
+
+  /// Entries of a custom storage are served in full and by ranges from their encoded form, and purged through
+  /// the storage. A store overtaken by a purge is not put.
   #[tokio::test]
-  async fn file_read_dropped_receiver_does_not_evict() {
-    let path = temp_cache_path("drop-read");
-    let content = vec![6u8; FILE_CACHE_READ_CHUNK * (CACHE_STREAM_CHANNEL_CAPACITY + 4)];
-    fs::write(&path, &content).await.unwrap();
-    let wrong_hash = Bytes::from_static(&[0u8; 32]);
-    let file_store = FileStore {
-      cnt: Arc::new(AtomicUsize::new(1)),
-      runtime_handle: tokio::runtime::Handle::current(),
+  async fn custom_storage_serves_and_purges_entries() {
+    let storage = Arc::new(EncodedStorage::default());
+    let custom = CustomStorage::new(storage.clone(), Duration::ZERO);
+    let cache = RpxyCache {
+      storage: Storage::Custom(custom.clone()),
+      ..test_cache(Path::new(""))
+    };
+    let uri = "https://a.example/shared";
+    let (cache_key, entry) = custom_entry(uri, "news", b"hello shared cache");
+    custom
+      .put_if_not_purged(
+        &cache_key,
+        entry,
+        Bytes::from_static(b"hello shared cache"),
+        cache.purge_epoch(),
+      )
+      .await;
+
+    let (parts, body) = fresh_hit(&cache, uri, &[]).await;
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body.as_ref(), b"hello shared cache");
+    let (parts, body) = fresh_hit(&cache, uri, &[("range", "bytes=6-11")]).await;
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body.as_ref(), b"shared");
+    assert_eq!(cache.count().await, (0, 0, 0));
+
+    let epoch = cache.purge_epoch();
+    assert_eq!(cache.purge(&PurgeTarget::SurrogateKey("news".to_string())).await.unwrap(), 1);
+    let req = Request::builder().uri(uri).body(()).unwrap();
+    assert!(matches!(cache.get(&req).await, CacheLookup::Miss(CacheForward::UriMiss)));
+    let (cache_key, entry) = custom_entry(uri, "news", b"overtaken");
+    custom
+      .put_if_not_purged(&cache_key, entry, Bytes::from_static(b"overtaken"), epoch)
+      .await;
+    assert!(storage.entries.lock().unwrap().is_empty());
+  }
+
+  /// A `304 Not Modified` refreshes the entry of a custom storage with the merged head, keeping the body
+  #[tokio::test]
+  async fn custom_storage_entry_is_updated_by_not_modified() {
+    let storage = Arc::new(EncodedStorage::default());
+    let custom = CustomStorage::new(storage.clone(), Duration::ZERO);
+    let cache = RpxyCache {
+      storage: Storage::Custom(custom.clone()),
+      ..test_cache(Path::new(""))
     };
-    let mut body = file_store.read(path.clone(), &wrong_hash).await.unwrap();
-    assert!(body.frame().await.is_some(), "the first frame must arrive");
-    drop(body);
+    let uri = "https://a.example/revalidate";
+    let (_, entry) = custom_entry(uri, "news", b"revalidated body");
+    let mut meta = entry.meta.as_deref().unwrap().clone();
+    meta.response_headers = vec![
+      ("cache-control".to_string(), b"max-age=10".to_vec()),
+      ("etag".to_string(), b"\"v1\"".to_vec()),
+      ("x-version".to_string(), b"1".to_vec()),
+    ];
+    meta.response_time -= Duration::from_secs(30);
+    let (cache_key, object) = meta
+      .cache_object(CacheFileOrOnMemory::External, entry.object.hash.clone(), Duration::ZERO)
+      .unwrap();
+    let body = Bytes::from_static(b"revalidated body");
+    custom
+      .put_if_not_purged(
+        &cache_key,
+        CacheEntry::new(object, Some(Arc::new(meta))),
+        body,
+        cache.purge_epoch(),
+      )
+      .await;
+
+    let req = Request::builder().uri(uri).body(()).unwrap();
+    let CacheLookup::Stale(entry) = cache.get(&req).await else {
+      panic!("the stale entry with a validator must be kept");
+    };
+    let not_modified = Response::builder()
+      .status(StatusCode::NOT_MODIFIED)
+      .header("cache-control", "max-age=100")
+      .header("etag", "\"v1\"")
+      .header("x-version", "2")
+      .body(())
+      .unwrap();
+    let res = cache.refresh(&entry, &req, &not_modified).await.unwrap();
+    assert!(res.extensions().get::<CacheStatus>().unwrap().stored);
+
+    let (parts, body) = fresh_hit(&cache, uri, &[]).await;
+    assert_eq!(parts.headers.get("x-version").unwrap(), "2");
+    assert_eq!(body.as_ref(), b"revalidated body");
+  }
 
-    // Let the producer observe the disconnect and exit.
-    for _ in 0..50 {
-      tokio::task::yield_now().await;
+  /// The default storage keeps bodies put through the trait on memory or in cache files by size
+  #[tokio::test]
+  async fn tiered_storage_puts_bodies_on_memory_or_in_files() {
+    let dir = temp_cache_dir("tiered-put").await;
+    let cache = test_cache(&dir);
+    let tiers = cache.tiers().unwrap();
+    let large = Bytes::from(vec![b'x'; 5_000]);
+    for (uri, body) in [
+      ("https://a.example/small", Bytes::from_static(b"small")),
+      ("https://a.example/large", large.clone()),
+    ] {
+      let (cache_key, entry) = custom_entry(uri, "tag", &body);
+      tiers.put(&cache_key, entry, body).await.unwrap();
     }
-    assert!(
-      fs::metadata(&path).await.is_ok(),
-      "an aborted read must leave the file in place (no integrity verdict without EOF)"
-    );
-    let _ = fs::remove_file(&path).await;
+    assert_eq!(cache.count().await, (2, 1, 1));
+
+    let entry = tiers.get("https://a.example/large").await.unwrap().unwrap();
+    assert!(matches!(entry.object.target, CacheFileOrOnMemory::File(_)));
+    let body = tiers.stream_body("https://a.example/large", &entry).await.unwrap();
+    assert_eq!(body.collect().await.unwrap(), large);
+    let (_, body) = fresh_hit(&cache, "https://a.example/small", &[]).await;
+    assert_eq!(body.as_ref(), b"small");
+
+    tiers.delete("https://a.example/large").await.unwrap();
+    assert_eq!(cache.count().await, (1, 1, 0));
+    let CacheFileOrOnMemory::File(path) = &entry.object.target else {
+      unreachable!();
+    };
+    assert!(fs::metadata(path).await.is_err());
+    let _ = fs::remove_dir_all(&dir).await;
   }
 }
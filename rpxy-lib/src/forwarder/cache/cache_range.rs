@@ -0,0 +1,291 @@
+use super::cache_main::content_length;
+use crate::hyper_ext::body::{BoxBody, ResponseBody, empty, full};
+use bytes::{Bytes, BytesMut};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, header, response};
+use http_body_util::BodyExt;
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// Upper bound of the number of ranges in a `Range` request served from the cache, beyond which `Range` is
+/// ignored and the full response is served, not to amplify a small request into a huge multipart response
+const CACHE_MAX_RANGES: usize = 16;
+
+/// Headers of the cached response kept in `304 Not Modified` (RFC 9110 Section 15.4.5)
+const NOT_MODIFIED_HEADERS: [HeaderName; 8] = [
+  header::CACHE_CONTROL,
+  header::CONTENT_LOCATION,
+  header::DATE,
+  header::ETAG,
+  header::EXPIRES,
+  header::VARY,
+  header::AGE,
+  header::LAST_MODIFIED,
+];
+
+/// Byte range specified in `Range`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ByteRange {
+  /// From the first position to the last one inclusive, or to the end of the body
+  FromTo(u64, Option<u64>),
+  /// The last bytes of the length
+  Suffix(u64),
+}
+
+/// Part of the body of `206 Partial Content`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum BodySegment {
+  /// Delimiter and headers of a part in `multipart/byteranges`
+  Literal(Bytes),
+  /// Byte range of the cached body, from the first position to the last one inclusive
+  Range(u64, u64),
+}
+
+impl BodySegment {
+  /// Length of the segment in bytes
+  pub(super) fn len(&self) -> u64 {
+    match self {
+      BodySegment::Literal(bytes) => bytes.len() as u64,
+      BodySegment::Range(first, last) => last - first + 1,
+    }
+  }
+}
+
+/// Parse `Range` in bytes. Returns `None` if it is invalid or has too many ranges, in which case it is
+/// ignored (RFC 9110 Section 14.2).
+pub(super) fn parse_range(value: &HeaderValue) -> Option<Vec<ByteRange>> {
+  let (unit, specs) = value.to_str().ok()?.split_once('=')?;
+  if !unit.trim().eq_ignore_ascii_case("bytes") {
+    return None;
+  }
+  let position = |s: &str| (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())).then(|| s.parse::<u64>().ok())?;
+  let ranges = specs
+    .split(',')
+    .map(str::trim)
+    .filter(|spec| !spec.is_empty())
+    .map(|spec| {
+      let (first, last) = spec.split_once('-')?;
+      if first.is_empty() {
+        return position(last).map(ByteRange::Suffix);
+      }
+      let first = position(first)?;
+      let last = if last.is_empty() { None } else { Some(position(last)?) };
+      if last.is_some_and(|last| last < first) {
+        return None;
+      }
+      Some(ByteRange::FromTo(first, last))
+    })
+    .collect::<Option<Vec<_>>>()?;
+  (!ranges.is_empty() && ranges.len() <= CACHE_MAX_RANGES).then_some(ranges)
+}
+
+/// Resolve the ranges against the length of the body into the first and last positions of the satisfiable ones
+pub(super) fn resolve_ranges(ranges: &[ByteRange], len: u64) -> Vec<(u64, u64)> {
+  ranges
+    .iter()
+    .filter_map(|range| match *range {
+      ByteRange::FromTo(first, last) => (first < len).then(|| (first, last.map_or(len - 1, |last| last.min(len - 1)))),
+      ByteRange::Suffix(suffix) => (suffix > 0 && len > 0).then(|| (len - suffix.min(len), len - 1)),
+    })
+    .collect()
+}
+
+/// Turn the head into that of `206 Partial Content` for the ranges, returning the segments of the body.
+/// Multiple ranges are served as `multipart/byteranges` delimited by the boundary.
+pub(super) fn partial_content(
+  res_parts: &mut response::Parts,
+  ranges: &[(u64, u64)],
+  len: u64,
+  boundary: &str,
+) -> Vec<BodySegment> {
+  res_parts.status = StatusCode::PARTIAL_CONTENT;
+  let headers = &mut res_parts.headers;
+  if let [(first, last)] = ranges {
+    if let Ok(content_range) = HeaderValue::from_str(&format!("bytes {first}-{last}/{len}")) {
+      headers.insert(header::CONTENT_RANGE, content_range);
+    }
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(last - first + 1));
+    return vec![BodySegment::Range(*first, *last)];
+  }
+
+  let content_type = headers.remove(header::CONTENT_TYPE);
+  let content_type = content_type.as_ref().and_then(|v| v.to_str().ok());
+  let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+  for (i, (first, last)) in ranges.iter().enumerate() {
+    let delimiter = if i == 0 { "" } else { "\r\n" };
+    let mut part_head = format!("{delimiter}--{boundary}\r\n");
+    if let Some(content_type) = content_type {
+      part_head.push_str(&format!("Content-Type: {content_type}\r\n"));
+    }
+    part_head.push_str(&format!("Content-Range: bytes {first}-{last}/{len}\r\n\r\n"));
+    segments.push(BodySegment::Literal(Bytes::from(part_head)));
+    segments.push(BodySegment::Range(*first, *last));
+  }
+  segments.push(BodySegment::Literal(Bytes::from(format!("\r\n--{boundary}--\r\n"))));
+  if let Ok(multipart) = HeaderValue::from_str(&format!("multipart/byteranges; boundary={boundary}")) {
+    headers.insert(header::CONTENT_TYPE, multipart);
+  }
+  let content_length = segments.iter().map(BodySegment::len).sum::<u64>();
+  headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+  segments
+}
+
+/// Assemble the body of `206 Partial Content` from the segments over the whole object
+pub(super) fn slice_segments(object: &Bytes, segments: &[BodySegment]) -> Bytes {
+  if let [BodySegment::Range(first, last)] = segments {
+    return object.slice(*first as usize..=*last as usize);
+  }
+  let mut body = BytesMut::new();
+  for segment in segments {
+    match segment {
+      BodySegment::Literal(bytes) => body.extend_from_slice(bytes),
+      BodySegment::Range(first, last) => body.extend_from_slice(&object[*first as usize..=*last as usize]),
+    }
+  }
+  body.freeze()
+}
+
+/// Build `416 Range Not Satisfiable` from the head of the cached response
+pub(super) fn range_not_satisfiable(mut res_parts: response::Parts, len: u64) -> Response<ResponseBody> {
+  res_parts.status = StatusCode::RANGE_NOT_SATISFIABLE;
+  res_parts.headers.remove(header::CONTENT_TYPE);
+  if let Ok(content_range) = HeaderValue::from_str(&format!("bytes */{len}")) {
+    res_parts.headers.insert(header::CONTENT_RANGE, content_range);
+  }
+  res_parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(0));
+  Response::from_parts(res_parts, ResponseBody::Boxed(empty()))
+}
+
+/// Build `304 Not Modified` from the head of the cached response
+pub(super) fn not_modified(res_parts: response::Parts) -> Response<ResponseBody> {
+  let mut res = Response::new(ResponseBody::Boxed(empty()));
+  *res.status_mut() = StatusCode::NOT_MODIFIED;
+  *res.version_mut() = res_parts.version;
+  for name in NOT_MODIFIED_HEADERS {
+    for value in res_parts.headers.get_all(&name) {
+      res.headers_mut().append(name.clone(), value.clone());
+    }
+  }
+  res
+}
+
+/// Whether the cached response answers the conditional request with `304 Not Modified`, evaluating
+/// `If-None-Match`, or `If-Modified-Since` in its absence (RFC 9110 Section 13.2.2)
+pub(super) fn is_not_modified<R>(req: &Request<R>, res_headers: &HeaderMap) -> bool {
+  if req.method() != Method::GET && req.method() != Method::HEAD {
+    return false;
+  }
+  if let Some(if_none_match) = req.headers().get(header::IF_NONE_MATCH) {
+    let etag = res_headers.get(header::ETAG).and_then(|v| v.to_str().ok());
+    // Weak comparison, ignoring the `W/` prefix
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    return if_none_match.to_str().is_ok_and(|tags| {
+      tags
+        .split(',')
+        .any(|tag| tag.trim() == "*" || etag.is_some_and(|etag| opaque(tag) == opaque(etag)))
+    });
+  }
+  let Some(since) = http_date(req.headers(), header::IF_MODIFIED_SINCE) else {
+    return false;
+  };
+  http_date(res_headers, header::LAST_MODIFIED).is_some_and(|last_modified| last_modified <= since)
+}
+
+/// Whether `Range` applies under `If-Range`, which requires the cached response to have the same strong
+/// entity tag, or exactly the same last modification date (RFC 9110 Section 13.1.5)
+pub(super) fn if_range_matches(req_headers: &HeaderMap, res_headers: &HeaderMap) -> bool {
+  let Some(if_range) = req_headers.get(header::IF_RANGE) else {
+    return true;
+  };
+  let Ok(if_range) = if_range.to_str() else {
+    return false;
+  };
+  let if_range = if_range.trim();
+  if if_range.starts_with('"') || if_range.starts_with("W/") {
+    return !if_range.starts_with("W/")
+      && res_headers
+        .get(header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|etag| etag.trim() == if_range);
+  }
+  let last_modified = http_date(res_headers, header::LAST_MODIFIED);
+  last_modified.is_some() && last_modified == httpdate::parse_http_date(if_range).ok()
+}
+
+/// Parse the header in HTTP-date
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+  let value = headers.get(name)?.to_str().ok()?;
+  httpdate::parse_http_date(value).ok()
+}
+
+/// Serve `Range` of the client, removed from the request to fill the cache with the full response, from the
+/// response. The body is buffered to be sliced only if its length is known and within `max_len`. Otherwise, or if
+/// `If-Range` does not match, the full response is given since a server may ignore `Range`.
+pub(super) async fn serve_removed_range(
+  client_range: &HeaderMap,
+  res: Response<ResponseBody>,
+  max_len: usize,
+) -> crate::error::RpxyResult<Response<ResponseBody>> {
+  let len = content_length(res.headers());
+  let ranges = client_range.get(header::RANGE).and_then(parse_range);
+  let (Some(len), Some(ranges)) = (len, ranges) else {
+    return Ok(res);
+  };
+  if res.status() != StatusCode::OK || len > max_len as u64 || !if_range_matches(client_range, res.headers()) {
+    return Ok(res);
+  }
+  let (mut res_parts, body) = res.into_parts();
+  let object = body.collect().await?.to_bytes();
+  let len = object.len() as u64;
+  let ranges = resolve_ranges(&ranges, len);
+  if ranges.is_empty() {
+    return Ok(range_not_satisfiable(res_parts, len));
+  }
+  let boundary = Sha256::digest(&object)
+    .iter()
+    .take(12)
+    .map(|b| format!("{b:02x}"))
+    .collect::<String>();
+  let segments = partial_content(&mut res_parts, &ranges, len, &boundary);
+  let body = ResponseBody::Boxed(BoxBody::new(full(slice_segments(&object, &segments))));
+  Ok(Response::from_parts(res_parts, body))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn range_header_is_parsed_and_resolved() {
+    let ranges = |value: &'static str| parse_range(&HeaderValue::from_static(value));
+    assert_eq!(
+      ranges("bytes=0-9, 20-, -5"),
+      Some(vec![
+        ByteRange::FromTo(0, Some(9)),
+        ByteRange::FromTo(20, None),
+        ByteRange::Suffix(5)
+      ])
+    );
+    // Invalid or too many ranges are ignored
+    assert_eq!(ranges("bytes=9-0"), None);
+    assert_eq!(ranges("bytes=+1-2"), None);
+    assert_eq!(ranges("items=0-1"), None);
+    assert_eq!(ranges("bytes="), None);
+    let too_many = (0..=CACHE_MAX_RANGES)
+      .map(|i| format!("{i}-{i}"))
+      .collect::<Vec<_>>()
+      .join(",");
+    assert_eq!(
+      parse_range(&HeaderValue::from_str(&format!("bytes={too_many}")).unwrap()),
+      None
+    );
+
+    let all = [
+      ByteRange::FromTo(0, Some(9)),
+      ByteRange::FromTo(20, None),
+      ByteRange::Suffix(5),
+    ];
+    assert_eq!(resolve_ranges(&all, 30), vec![(0, 9), (20, 29), (25, 29)]);
+    assert_eq!(resolve_ranges(&all, 8), vec![(0, 7), (3, 7)]);
+    assert_eq!(resolve_ranges(&[ByteRange::Suffix(0), ByteRange::FromTo(8, None)], 8), vec![]);
+  }
+}
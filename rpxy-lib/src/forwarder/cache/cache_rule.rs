@@ -0,0 +1,151 @@
+use crate::globals::CacheRuleConfig;
+use http::{HeaderMap, Uri, header, uri::PathAndQuery};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+
+#[derive(Clone, Debug)]
+/// Cache rule of the upstream group chosen for a request, carried in request extensions from the handler
+/// to the forwarder, and on to the cache in the synthetic request the response is stored for.
+pub(crate) struct CacheRule(pub(crate) Arc<CacheRuleConfig>);
+
+impl CacheRule {
+  /// Whether the request bypasses the cache, i.e., the cache is disabled for the route or the request has
+  /// one of the bypass cookies
+  pub(crate) fn bypasses(&self, headers: &HeaderMap) -> bool {
+    if !self.0.enabled {
+      return true;
+    }
+    if self.0.bypass_cookies.is_empty() {
+      return false;
+    }
+    headers
+      .get_all(header::COOKIE)
+      .iter()
+      .filter_map(|v| v.to_str().ok())
+      .flat_map(|v| v.split(';'))
+      .filter_map(|pair| pair.split_once('=').map(|(name, _)| name.trim()))
+      .any(|name| self.0.bypass_cookies.iter().any(|cookie| cookie == name))
+  }
+
+  /// URI keying the cache for the effective URI, without the ignored query parameters and with the rest
+  /// sorted by name if configured. The order of the parameters of the same name is kept.
+  pub(crate) fn key_uri(&self, uri: &Uri) -> Uri {
+    let Some(query) = uri.query() else {
+      return uri.clone();
+    };
+    if self.0.ignore_query_params.is_empty() && !self.0.sort_query {
+      return uri.clone();
+    }
+    let mut params = query
+      .split('&')
+      .filter(|param| !param.is_empty())
+      .filter(|param| {
+        let name = param_name(param);
+        !self.0.ignore_query_params.iter().any(|pattern| matches_param(pattern, name))
+      })
+      .collect::<Vec<_>>();
+    if self.0.sort_query {
+      params.sort_by_key(|param| param_name(param));
+    }
+    let path_and_query = if params.is_empty() {
+      uri.path().to_string()
+    } else {
+      format!("{}?{}", uri.path(), params.join("&"))
+    };
+    let mut parts = uri.clone().into_parts();
+    let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) else {
+      return uri.clone();
+    };
+    parts.path_and_query = Some(path_and_query);
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+  }
+
+  /// Bounds of the freshness lifetime of the responses
+  pub(super) fn ttl(&self) -> CacheTtl {
+    CacheTtl {
+      default_ttl: self.0.default_ttl,
+      max_ttl: self.0.max_ttl,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Bounds of the freshness lifetime of a cached response given by the cache rule of its route
+pub(super) struct CacheTtl {
+  /// Lifetime of a response without explicit one
+  pub(super) default_ttl: Option<Duration>,
+  /// Upper bound of the lifetime
+  pub(super) max_ttl: Option<Duration>,
+}
+
+impl CacheTtl {
+  /// Whether the lifetime is given by the response as it is
+  pub(super) fn is_unbounded(&self) -> bool {
+    self.default_ttl.is_none() && self.max_ttl.is_none()
+  }
+}
+
+/// Name part of a query parameter
+fn param_name(param: &str) -> &str {
+  param.split_once('=').map_or(param, |(name, _)| name)
+}
+
+/// Whether the query parameter name matches the pattern, where a trailing `*` matches any suffix
+fn matches_param(pattern: &str, name: &str) -> bool {
+  match pattern.strip_suffix('*') {
+    Some(prefix) => name.starts_with(prefix),
+    None => name == pattern,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http::HeaderValue;
+
+  fn rule(ignore_query_params: &[&str], sort_query: bool, bypass_cookies: &[&str]) -> CacheRule {
+    CacheRule(Arc::new(CacheRuleConfig {
+      enabled: true,
+      default_ttl: None,
+      max_ttl: None,
+      ignore_query_params: ignore_query_params.iter().map(|p| p.to_string()).collect(),
+      sort_query,
+      key_headers: Vec::new(),
+      bypass_cookies: bypass_cookies.iter().map(|c| c.to_string()).collect(),
+    }))
+  }
+
+  #[test]
+  fn key_uri_drops_ignored_params_and_sorts_the_rest() {
+    let uri = Uri::from_static("https://example.com/a?utm_source=x&b=2&a=1&utm=y&a=0&fbclid=z");
+    assert_eq!(rule(&[], false, &[]).key_uri(&uri), uri);
+    assert_eq!(
+      rule(&["utm_*", "fbclid"], false, &[]).key_uri(&uri).to_string(),
+      "https://example.com/a?b=2&a=1&utm=y&a=0"
+    );
+    assert_eq!(
+      rule(&["utm_*", "fbclid"], true, &[]).key_uri(&uri).to_string(),
+      "https://example.com/a?a=1&a=0&b=2&utm=y"
+    );
+    let only_ignored = Uri::from_static("https://example.com/a?utm_source=x");
+    assert_eq!(
+      rule(&["utm_*"], true, &[]).key_uri(&only_ignored).to_string(),
+      "https://example.com/a"
+    );
+  }
+
+  #[test]
+  fn bypass_cookies_and_disabled_rule_bypass_the_cache() {
+    let mut headers = HeaderMap::new();
+    let rule = rule(&[], false, &["session"]);
+    assert!(!rule.bypasses(&headers));
+    headers.append(header::COOKIE, HeaderValue::from_static("theme=dark; session_hint=1"));
+    assert!(!rule.bypasses(&headers));
+    headers.append(header::COOKIE, HeaderValue::from_static("lang=en;session=abc"));
+    assert!(rule.bypasses(&headers));
+
+    let mut config = (*rule.0).clone();
+    config.enabled = false;
+    assert!(CacheRule(Arc::new(config)).bypasses(&HeaderMap::new()));
+  }
+}
@@ -0,0 +1,171 @@
+use http::{HeaderName, HeaderValue, StatusCode};
+use std::fmt::Write;
+
+/// `Cache-Status` response header (RFC 9211)
+pub(crate) const CACHE_STATUS: HeaderName = HeaderName::from_static("cache-status");
+
+/// Name identifying the cache of rpxy in `Cache-Status`
+const CACHE_STATUS_NAME: &str = "rpxy";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Reason why the request went forward to the upstream, given as `fwd` in `Cache-Status`
+pub(crate) enum CacheForward {
+  /// The cache was not consulted for the request, e.g., by the cache rule of the route
+  Bypass,
+  /// The request method is not served from the cache
+  Method,
+  /// No response was stored for the URI
+  UriMiss,
+  /// Responses were stored for the URI, but none of them for the request headers listed in `Vary`
+  VaryMiss,
+  /// No usable response was found for other reasons
+  Miss,
+  /// The request asked not to be served from the cache, e.g., with `Cache-Control: no-cache`
+  Request,
+  /// The stored response was stale
+  Stale,
+}
+
+impl CacheForward {
+  fn as_str(&self) -> &'static str {
+    match self {
+      CacheForward::Bypass => "bypass",
+      CacheForward::Method => "method",
+      CacheForward::UriMiss => "uri-miss",
+      CacheForward::VaryMiss => "vary-miss",
+      CacheForward::Miss => "miss",
+      CacheForward::Request => "request",
+      CacheForward::Stale => "stale",
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// How the cache handled a response, carried in response extensions from the forwarder to the handler for the
+/// access log, and given in the `Cache-Status` header if configured
+pub(crate) struct CacheStatus {
+  /// Whether the response was served from the cache without going forward to the upstream
+  pub(crate) hit: bool,
+  /// Reason why the request went forward to the upstream, if it did
+  pub(crate) fwd: Option<CacheForward>,
+  /// Status code of the upstream response, if any
+  pub(crate) fwd_status: Option<StatusCode>,
+  /// Remaining freshness lifetime of the response in seconds, negative if served stale
+  pub(crate) ttl: Option<i64>,
+  /// Whether the response was stored in the cache
+  pub(crate) stored: bool,
+  /// Whether the request was collapsed with a concurrent request for the same entry, whose response it was given
+  pub(crate) collapsed: bool,
+  /// Cache key of the response, i.e., the effective URI normalized by the cache rule of the route
+  pub(crate) key: Option<String>,
+}
+
+impl CacheStatus {
+  /// Status of a response served from the cache
+  pub(crate) fn hit(key: String, ttl: i64) -> Self {
+    Self {
+      hit: true,
+      ttl: Some(ttl),
+      key: Some(key),
+      ..Default::default()
+    }
+  }
+
+  /// Status of a response for which the request went forward to the upstream
+  pub(crate) fn forward(fwd: CacheForward, key: Option<String>) -> Self {
+    Self {
+      fwd: Some(fwd),
+      key,
+      ..Default::default()
+    }
+  }
+
+  /// Header value of the member of rpxy in `Cache-Status`. The key is given only if `with_key` is set, since it
+  /// reveals the URI normalized by the cache rule, query included, to clients.
+  pub(crate) fn to_header_value(&self, with_key: bool) -> Option<HeaderValue> {
+    let value = match with_key {
+      true => self.to_string(),
+      false => Self {
+        key: None,
+        ..self.clone()
+      }
+      .to_string(),
+    };
+    HeaderValue::from_str(&value).ok()
+  }
+}
+
+impl std::fmt::Display for CacheStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(CACHE_STATUS_NAME)?;
+    if self.hit {
+      f.write_str("; hit")?;
+    }
+    if let Some(fwd) = self.fwd {
+      write!(f, "; fwd={}", fwd.as_str())?;
+    }
+    if let Some(status) = self.fwd_status {
+      write!(f, "; fwd-status={}", status.as_u16())?;
+    }
+    if let Some(ttl) = self.ttl {
+      write!(f, "; ttl={ttl}")?;
+    }
+    if self.stored {
+      f.write_str("; stored")?;
+    }
+    if self.collapsed {
+      f.write_str("; collapsed")?;
+    }
+    if let Some(key) = &self.key {
+      // sf-string of RFC 8941, where only printable ASCII is allowed and `"` and `\` are escaped
+      f.write_str("; key=\"")?;
+      for c in key.chars().filter(|c| c.is_ascii() && !c.is_ascii_control()) {
+        if c == '"' || c == '\\' {
+          f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+      }
+      f.write_char('"')?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cache_status_renders_rfc9211_parameters() {
+    let hit = CacheStatus::hit("https://example.com/a?b=1".to_string(), 42);
+    assert_eq!(hit.to_string(), "rpxy; hit; ttl=42; key=\"https://example.com/a?b=1\"");
+
+    let stale = CacheStatus::hit("https://example.com/\"\\".to_string(), -3);
+    assert_eq!(
+      stale.to_header_value(true).unwrap(),
+      "rpxy; hit; ttl=-3; key=\"https://example.com/\\\"\\\\\""
+    );
+    // The key is left out of the header unless enabled
+    assert_eq!(stale.to_header_value(false).unwrap(), "rpxy; hit; ttl=-3");
+
+    let miss = CacheStatus {
+      fwd_status: Some(StatusCode::OK),
+      stored: true,
+      ..CacheStatus::forward(CacheForward::UriMiss, Some("https://example.com/".to_string()))
+    };
+    assert_eq!(
+      miss.to_string(),
+      "rpxy; fwd=uri-miss; fwd-status=200; stored; key=\"https://example.com/\""
+    );
+    let collapsed = CacheStatus {
+      fwd_status: Some(StatusCode::OK),
+      collapsed: true,
+      ..CacheStatus::forward(CacheForward::Miss, None)
+    };
+    assert_eq!(collapsed.to_string(), "rpxy; fwd=miss; fwd-status=200; collapsed");
+    assert_eq!(
+      CacheStatus::forward(CacheForward::Bypass, None).to_string(),
+      "rpxy; fwd=bypass"
+    );
+  }
+}
@@ -0,0 +1,582 @@
+use super::{
+  cache_error::*,
+  cache_file::{
+    CACHE_RESTORE_START, CacheFileMeta, FileStore, SpillFile, decode_cache_entry, is_temp_file_of_this_process,
+    remove_uncounted_file, restore_cache_file,
+  },
+  cache_index::LruCacheManager,
+  cache_main::{CACHE_STREAM_CHANNEL_CAPACITY, CacheFileOrOnMemory, CacheObject, PurgeTarget},
+};
+use crate::{
+  hyper_ext::body::{BoundedStreamBody, BoxBody, ResponseBody, full},
+  log::*,
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, Stream, StreamExt, channel::mpsc, stream::BoxStream};
+use http::HeaderName;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashSet,
+  path::PathBuf,
+  sync::{
+    Arc, LazyLock,
+    atomic::{AtomicU64, Ordering},
+  },
+  time::{Duration, SystemTime},
+};
+use tokio::fs;
+
+#[async_trait]
+/// Storage of the cache entries and their bodies behind `RpxyCache`, which decides what is stored and serves
+/// the stored responses to requests. The memory and file tiers of rpxy are the default storage, and an embedder
+/// of rpxy-lib may give another one, e.g., backed by Redis or memcached to share the cache among instances.
+///
+/// An entry is stored under the cache key of its URI, or of its variant if the response has `Vary`, where the
+/// key of a variant is the URI key followed by the normalized request headers listed in `Vary` on separate lines.
+/// A storage keeps the entries as encoded by `CacheEntry::to_bytes`, along with their bodies.
+pub trait CacheStorage: Send + Sync {
+  /// Request header names listed in `Vary` of the latest entry put for the URI key, empty if it does not vary
+  async fn vary(&self, uri_key: &str) -> Result<Vec<HeaderName>, CacheError>;
+
+  /// Get the entry of the cache key, if any
+  async fn get(&self, cache_key: &str) -> Result<Option<CacheEntry>, CacheError>;
+
+  /// Put the entry with its body under the cache key, replacing the existing one. The entries of the same URI
+  /// stored with another `Vary` may be dropped.
+  async fn put(&self, cache_key: &str, entry: CacheEntry, body: Bytes) -> Result<(), CacheError>;
+
+  /// Update the head of the entry of the cache key, refreshed by a `304 Not Modified` from the upstream, keeping its
+  /// body. Fails if the entry has been deleted or replaced with another body meanwhile. By default, the body is got
+  /// from the storage and put again with the entry.
+  async fn update_entry(&self, cache_key: &str, entry: CacheEntry) -> Result<(), CacheError> {
+    let Some(current) = self
+      .get(cache_key)
+      .await?
+      .filter(|current| current.object.hash == entry.object.hash)
+    else {
+      return Err(CacheError::CacheEntryReplaced);
+    };
+    let body = self.stream_body(cache_key, &current).await?.collect().await?;
+    self.put(cache_key, entry, body).await
+  }
+
+  /// Delete the entry of the cache key, if any
+  async fn delete(&self, cache_key: &str) -> Result<(), CacheError>;
+
+  /// Stream the body of the entry got from this storage under the cache key
+  async fn stream_body(&self, cache_key: &str, entry: &CacheEntry) -> Result<CacheBody, CacheError>;
+
+  /// Delete the entries matching the target, returning the number of them. Unsupported unless implemented.
+  async fn purge(&self, target: &PurgeTarget) -> Result<usize, CacheError> {
+    let _ = target;
+    Err(CacheError::UnsupportedByCacheStorage("purge"))
+  }
+}
+
+#[derive(Clone, Debug)]
+/// Cache entry of a stored response, i.e., its head and cache policy without the body
+pub struct CacheEntry {
+  pub(super) object: CacheObject,
+  /// Metadata from which the entry is encoded, absent for the entries of the default storage
+  pub(super) meta: Option<Arc<CacheFileMeta>>,
+}
+
+impl CacheEntry {
+  pub(super) fn new(object: CacheObject, meta: Option<Arc<CacheFileMeta>>) -> Self {
+    Self { object, meta }
+  }
+
+  /// Request header names listed in `Vary` of the response, empty if it does not vary
+  pub fn vary(&self) -> &[HeaderName] {
+    &self.object.vary
+  }
+
+  /// Surrogate keys tagging the response for purge
+  pub fn surrogate_keys(&self) -> &[String] {
+    &self.object.surrogate_keys
+  }
+
+  /// Encode the entry to be kept by a storage, in the format of the header of a cache file. The entries given to
+  /// `CacheStorage::put` are always encodable.
+  pub fn to_bytes(&self) -> Result<Bytes, CacheError> {
+    let Some(meta) = &self.meta else {
+      return Err(CacheError::UnusableCacheFile("entry without metadata".to_string()));
+    };
+    meta.to_header(&self.object.hash).map(Bytes::from)
+  }
+
+  /// Decode the entry encoded by `to_bytes`
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, CacheError> {
+    let (meta, object) = decode_cache_entry(bytes)?;
+    Ok(Self::new(object, Some(Arc::new(meta))))
+  }
+
+  /// Serve the entry stale within the grace period after the upstream fails, unless the response says otherwise
+  pub(super) fn with_stale_if_error_grace(self, stale_if_error_grace: Duration) -> Self {
+    let Some(meta) = &self.meta else {
+      return self;
+    };
+    let stale_response = meta.stale_response(&self.object.policy, stale_if_error_grace);
+    Self {
+      object: self.object.with_stale_response(stale_response),
+      meta: self.meta,
+    }
+  }
+}
+
+/// Body of a stored response streamed from a storage
+pub struct CacheBody(CacheBodyInner);
+
+enum CacheBodyInner {
+  /// Whole body at once
+  Full(Bytes),
+  /// Frames relayed from a cache file
+  Frames(BoundedStreamBody),
+  /// Chunks streamed by a storage
+  Stream(BoxStream<'static, Result<Bytes, CacheError>>),
+}
+
+impl CacheBody {
+  /// Body given at once
+  pub fn full(body: Bytes) -> Self {
+    Self(CacheBodyInner::Full(body))
+  }
+
+  /// Body given in chunks. The response is cut short when the stream yields an error.
+  pub fn stream<S>(stream: S) -> Self
+  where
+    S: Stream<Item = Result<Bytes, CacheError>> + Send + 'static,
+  {
+    Self(CacheBodyInner::Stream(stream.boxed()))
+  }
+
+  /// Body relayed from a cache file
+  pub(super) fn frames(body: BoundedStreamBody) -> Self {
+    Self(CacheBodyInner::Frames(body))
+  }
+
+  /// Collect the whole body
+  pub async fn collect(self) -> Result<Bytes, CacheError> {
+    match self.0 {
+      CacheBodyInner::Full(body) => Ok(body),
+      CacheBodyInner::Frames(body) => BodyExt::collect(body)
+        .await
+        .map(|collected| collected.to_bytes())
+        .map_err(|e| CacheError::FailedToReadCacheBody(e.to_string())),
+      CacheBodyInner::Stream(mut stream) => {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = stream.next().await {
+          body.extend_from_slice(&chunk?);
+        }
+        Ok(body.freeze())
+      }
+    }
+  }
+
+  /// Response body relaying the body, through a bounded channel if streamed by a storage
+  pub(super) fn into_response_body(self, runtime_handle: &tokio::runtime::Handle) -> ResponseBody {
+    let mut stream = match self.0 {
+      CacheBodyInner::Full(body) => return ResponseBody::Boxed(BoxBody::new(full(body))),
+      CacheBodyInner::Frames(body) => return ResponseBody::Streamed(body),
+      CacheBodyInner::Stream(stream) => stream,
+    };
+    let (mut body_tx, body_rx) = mpsc::channel::<Result<Frame<Bytes>, hyper::Error>>(CACHE_STREAM_CHANNEL_CAPACITY);
+    runtime_handle.spawn(async move {
+      while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+          Ok(chunk) => chunk,
+          Err(e) => {
+            // The head is already sent, so the body just ends short of its length
+            warn!("Failed to stream the body from the cache storage: {e}");
+            break;
+          }
+        };
+        if body_tx.send(Ok(Frame::data(chunk))).await.is_err() {
+          break;
+        }
+      }
+    });
+    ResponseBody::Streamed(StreamBody::new(body_rx))
+  }
+}
+
+/* ---------------------------------------------- */
+#[derive(Clone, Debug)]
+/// Default storage keeping small objects on memory and larger ones in cache files, within the byte budgets of the
+/// tiers. Responses from the upstream are streamed into the tiers by `RpxyCache::put` rather than `put` of the trait.
+pub(super) struct TieredStorage {
+  /// Inner lru cache manager storing http message caching policy
+  pub(super) index: LruCacheManager,
+  /// Managing committed cache file objects (lock-free count; files are immutable once committed)
+  pub(super) file_store: FileStore,
+  /// Cache directory path
+  pub(super) cache_dir: PathBuf,
+  /// Maximum size of cache object on memory
+  pub(super) max_each_size_on_memory: usize,
+}
+
+impl TieredStorage {
+  /// Rebuild the index from the headers of the cache files left in the cache directory by the previous run or the
+  /// previous config. Files with a broken header, too large, stale without a validator beyond the periods to serve
+  /// them stale, or of the oldest responses beyond `max_entry` or `max_disk_size` in total are removed, as well as
+  /// temp files of interrupted stores. Bodies are verified when served. On-memory objects are not persisted.
+  pub(super) async fn restore(&self, max_entry: usize, max_disk_size: u64, max_each_size: usize, stale_if_error_grace: Duration) {
+    // Temp files created before the first restore are never of stores in flight
+    LazyLock::force(&CACHE_RESTORE_START);
+    let mut dir = match fs::read_dir(&self.cache_dir).await {
+      Ok(dir) => dir,
+      Err(e) => {
+        warn!("Failed to read the cache dir: {e}");
+        return;
+      }
+    };
+    let now = SystemTime::now();
+    let mut restored = Vec::new();
+    let mut discarded = 0;
+    loop {
+      let entry = match dir.next_entry().await {
+        Ok(Some(entry)) => entry,
+        Ok(None) => break,
+        Err(e) => {
+          warn!("Failed to read the cache dir: {e}");
+          break;
+        }
+      };
+      if !entry.file_type().await.is_ok_and(|t| t.is_file()) {
+        continue;
+      }
+      let path = entry.path();
+      // Temp files of stores in flight in this process, e.g., of the previous config on reload, are not touched
+      if path.extension().is_some_and(|ext| ext == "tmp") && is_temp_file_of_this_process(&path) {
+        continue;
+      }
+      match restore_cache_file(&path, max_each_size, stale_if_error_grace, now).await {
+        Ok(file) => restored.push(file),
+        Err(e) => {
+          debug!("Discard cache file {path:?}: {e}");
+          remove_uncounted_file(&path).await;
+          discarded += 1;
+        }
+      }
+    }
+
+    // Keep the newest response of each key, and the newest ones up to the maximum number of entries
+    // and the disk budget.
+    restored.sort_by_key(|(_, _, response_time)| std::cmp::Reverse(*response_time));
+    let mut keys = HashSet::new();
+    let mut kept = Vec::new();
+    let mut disk_size = 0u64;
+    for (cache_key, cache_object, _) in restored {
+      if kept.len() < max_entry && disk_size.saturating_add(cache_object.size) <= max_disk_size && keys.insert(cache_key.clone())
+      {
+        disk_size += cache_object.size;
+        kept.push((cache_key, cache_object));
+        continue;
+      }
+      if let CacheFileOrOnMemory::File(path) = &cache_object.target {
+        remove_uncounted_file(path).await;
+      }
+      discarded += 1;
+    }
+
+    // Published from the oldest, so that the newest is the most recently used
+    let count = kept.len();
+    for (cache_key, cache_object) in kept.into_iter().rev() {
+      publish_cache_object(
+        &self.index,
+        &self.file_store,
+        &cache_key,
+        cache_object,
+        self.index.purge_epoch(),
+      )
+      .await;
+    }
+    if count > 0 || discarded > 0 {
+      info!(
+        "Restored {count} cache entries from {:?} ({discarded} files discarded)",
+        self.cache_dir
+      );
+    }
+  }
+}
+
+/// Publish a freshly stored cache object's metadata into the LRU, accounting for the file-store
+/// count and evicting any displaced entry's file.
+///
+/// Ordering matters for correctness: a file is counted (`incr_count`) BEFORE its metadata is
+/// published, so a `File` entry visible in the LRU is always already counted and a concurrent
+/// eviction can never decrement a not-yet-counted file (the count and the LRU use separate locks).
+/// If `push()` fails (poisoned mutex), the just-counted file is rolled back (unlink + decrement) via
+/// `evict`. The displaced entry's file is evicted after a successful `push()`. A store overtaken by a
+/// purge since `purge_epoch` is rolled back in the same way.
+pub(super) async fn publish_cache_object(
+  cache_manager: &LruCacheManager,
+  file_store: &FileStore,
+  cache_key: &str,
+  cache_object: CacheObject,
+  purge_epoch: u64,
+) {
+  let new_file_path = match &cache_object.target {
+    CacheFileOrOnMemory::File(path) => Some(path.clone()),
+    CacheFileOrOnMemory::OnMemory(_) | CacheFileOrOnMemory::External => None,
+  };
+  let generation = cache_object.generation;
+
+  // Count a file-backed object BEFORE publishing its metadata. The file count and the LRU map are
+  // guarded by different locks, so this ordering upholds the invariant "a File entry visible in the
+  // LRU has already been counted": any concurrent eviction that observes the published entry always
+  // finds a count it can safely decrement, instead of decrementing a not-yet-counted file (which
+  // would underflow). The transient `file > total` this opens (counted, not yet in the LRU) is
+  // tolerated by `count()` via `saturating_sub`.
+  if new_file_path.is_some() {
+    file_store.incr_count().await;
+  }
+
+  match cache_manager.push(cache_key, &cache_object, purge_epoch) {
+    Err(CacheError::PurgedWhileStoring) => {
+      debug!("Cache entry purged while being stored: {cache_key}");
+      if let Some(path) = &new_file_path {
+        file_store.evict(path).await;
+      }
+    }
+    Err(e) => {
+      // Metadata could not be published; roll back both the count and the committed-but-unpublished
+      // file so neither leaks (the file was already counted just above).
+      warn!("Failed to publish cache entry: {e}");
+      if let Some(path) = &new_file_path {
+        file_store.evict(path).await;
+      }
+    }
+    Ok(displaced) => {
+      // Evict the displaced entries' files (same-key update, capacity eviction, or variants
+      // superseded), unless it is the very file just published (only possible without
+      // generation-unique paths). The object just published is itself displaced if not admitted.
+      for (_, v) in displaced {
+        if let CacheFileOrOnMemory::File(old_path) = v.target
+          && (v.generation == generation || Some(&old_path) != new_file_path.as_ref())
+        {
+          info!("Evicting displaced cache file");
+          file_store.evict(&old_path).await;
+        }
+      }
+    }
+  }
+}
+
+#[async_trait]
+impl CacheStorage for TieredStorage {
+  async fn vary(&self, uri_key: &str) -> CacheResult<Vec<HeaderName>> {
+    self.index.vary(uri_key)
+  }
+
+  async fn get(&self, cache_key: &str) -> CacheResult<Option<CacheEntry>> {
+    Ok(self.index.get(cache_key)?.map(|object| CacheEntry::new(object, None)))
+  }
+
+  /// Keep the body on memory if small enough, otherwise write it to a cache file
+  async fn put(&self, cache_key: &str, entry: CacheEntry, body: Bytes) -> CacheResult<()> {
+    let hash = Bytes::copy_from_slice(Sha256::digest(&body).as_slice());
+    let (target, size) = if body.len() <= self.max_each_size_on_memory {
+      let size = body.len() as u64;
+      (CacheFileOrOnMemory::OnMemory(body), size)
+    } else {
+      let Some(meta) = entry.meta.as_ref() else {
+        return Err(CacheError::UnusableCacheFile("entry without metadata".to_string()));
+      };
+      let mut spill = SpillFile::create(&self.cache_dir, meta).await?;
+      if let Err(e) = spill.write(&body).await {
+        spill.abort().await;
+        return Err(e);
+      }
+      let path = spill.commit(&hash).await?;
+      let size = fs::metadata(&path).await.map(|m| m.len()).unwrap_or_default();
+      (CacheFileOrOnMemory::File(path), size)
+    };
+    let CacheObject {
+      policy,
+      surrogate_keys,
+      vary,
+      stale_response,
+      ttl,
+      cache_control,
+      ..
+    } = entry.object;
+    let cache_object = CacheObject {
+      stale_response,
+      ..CacheObject::new(policy, target, hash)
+        .with_size(size)
+        .with_surrogate_keys(surrogate_keys)
+        .with_vary(vary)
+        .with_ttl(ttl, cache_control)
+    };
+    publish_cache_object(
+      &self.index,
+      &self.file_store,
+      cache_key,
+      cache_object,
+      self.index.purge_epoch(),
+    )
+    .await;
+    Ok(())
+  }
+
+  /// Replace the object in the index, keeping the body on memory, or moving it to a cache file headed by the
+  /// refreshed metadata so that the entry is restored with the refreshed head
+  async fn update_entry(&self, cache_key: &str, entry: CacheEntry) -> CacheResult<()> {
+    let CacheEntry { object, meta } = entry;
+    let cached_target = object.target.clone();
+    let (object, rewritten) = match (&cached_target, meta) {
+      (CacheFileOrOnMemory::File(path), Some(meta)) => {
+        let (new_path, size) = self
+          .file_store
+          .rewrite_header(&self.cache_dir, path, &meta, &object.hash)
+          .await?;
+        let object = CacheObject {
+          target: CacheFileOrOnMemory::File(new_path.clone()),
+          ..object
+        };
+        (object.with_size(size), Some(new_path))
+      }
+      _ => (object, None),
+    };
+    if !self.index.replace_if_same_body(cache_key, &cached_target, object) {
+      if let Some(new_path) = &rewritten {
+        remove_uncounted_file(new_path).await;
+      }
+      return Err(CacheError::CacheEntryReplaced);
+    }
+    // The new cache file takes over the count of the original one
+    if let (Some(_), CacheFileOrOnMemory::File(path)) = (&rewritten, &cached_target) {
+      remove_uncounted_file(path).await;
+    }
+    Ok(())
+  }
+
+  async fn delete(&self, cache_key: &str) -> CacheResult<()> {
+    if let Some(CacheObject {
+      target: CacheFileOrOnMemory::File(path),
+      ..
+    }) = self.index.remove(cache_key)
+    {
+      self.file_store.remove(path).await?;
+    }
+    Ok(())
+  }
+
+  async fn stream_body(&self, cache_key: &str, entry: &CacheEntry) -> CacheResult<CacheBody> {
+    match &entry.object.target {
+      CacheFileOrOnMemory::File(path) => {
+        let stream_body = self.file_store.read(path.clone(), &entry.object.hash).await?;
+        debug!("Cache hit from file: {cache_key}");
+        Ok(CacheBody::frames(stream_body))
+      }
+      CacheFileOrOnMemory::OnMemory(object) => {
+        // No integrity re-check here, unlike the file target. A file-backed object lives on disk
+        // (an external, mutable resource that can be corrupted or overwritten independently), so
+        // `FileStore::read` re-verifies its hash on every read. An on-memory object is an
+        // immutable `Bytes` held inside the same `CacheObject` as its `hash` and is never mutated
+        // after insertion, with no external aliasing. Re-hashing it on every hit only guards
+        // against in-RAM corruption, which the stored `hash` itself equally suffers, so it is not
+        // worth a full SHA-256 per hit.
+        debug!("Cache hit from on memory: {cache_key}");
+        Ok(CacheBody::full(object.clone()))
+      }
+      CacheFileOrOnMemory::External => Err(CacheError::CacheBodyNotFound),
+    }
+  }
+
+  /// Purge the entries matching the target from both the LRU index and the file store
+  async fn purge(&self, target: &PurgeTarget) -> CacheResult<usize> {
+    let purged = self.index.purge(target)?;
+    for object in purged.iter() {
+      if let CacheFileOrOnMemory::File(path) = &object.target {
+        self.file_store.evict(path).await;
+      }
+    }
+    Ok(purged.len())
+  }
+}
+
+#[derive(Clone)]
+/// Storage given by the embedder, with the purge epoch and the grace period of `stale-if-error` of this instance
+pub(super) struct CustomStorage {
+  storage: Arc<dyn CacheStorage>,
+  /// Number of purges so far, checked before an entry is put so that a store overtaken by a purge is given up
+  purge_epoch: Arc<AtomicU64>,
+  /// Period a stale object is served when the upstream fails, unless given by `stale-if-error`
+  stale_if_error_grace: Duration,
+}
+
+impl std::fmt::Debug for CustomStorage {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("CustomStorage")
+      .field("purge_epoch", &self.purge_epoch)
+      .field("stale_if_error_grace", &self.stale_if_error_grace)
+      .finish_non_exhaustive()
+  }
+}
+
+impl CustomStorage {
+  pub(super) fn new(storage: Arc<dyn CacheStorage>, stale_if_error_grace: Duration) -> Self {
+    Self {
+      storage,
+      purge_epoch: Default::default(),
+      stale_if_error_grace,
+    }
+  }
+
+  /// Put the entry unless a purge has run since `purge_epoch`. Unlike the default storage, a store handed to the
+  /// storage just before a purge may outlive it.
+  /// Number of purges so far
+  pub(super) fn purge_epoch(&self) -> u64 {
+    self.purge_epoch.load(Ordering::Acquire)
+  }
+
+  pub(super) async fn put_if_not_purged(&self, cache_key: &str, entry: CacheEntry, body: Bytes, purge_epoch: u64) {
+    if self.purge_epoch.load(Ordering::Acquire) != purge_epoch {
+      debug!("Cache entry purged while being stored: {cache_key}");
+      return;
+    }
+    if let Err(e) = self.put(cache_key, entry, body).await {
+      warn!("Failed to put cache entry into the storage: {e}");
+    }
+  }
+}
+
+#[async_trait]
+impl CacheStorage for CustomStorage {
+  async fn vary(&self, uri_key: &str) -> CacheResult<Vec<HeaderName>> {
+    self.storage.vary(uri_key).await
+  }
+
+  async fn get(&self, cache_key: &str) -> CacheResult<Option<CacheEntry>> {
+    let entry = self.storage.get(cache_key).await?;
+    Ok(entry.map(|entry| entry.with_stale_if_error_grace(self.stale_if_error_grace)))
+  }
+
+  async fn put(&self, cache_key: &str, entry: CacheEntry, body: Bytes) -> CacheResult<()> {
+    self.storage.put(cache_key, entry, body).await
+  }
+
+  async fn update_entry(&self, cache_key: &str, entry: CacheEntry) -> CacheResult<()> {
+    self.storage.update_entry(cache_key, entry).await
+  }
+
+  async fn delete(&self, cache_key: &str) -> CacheResult<()> {
+    self.storage.delete(cache_key).await
+  }
+
+  async fn stream_body(&self, cache_key: &str, entry: &CacheEntry) -> CacheResult<CacheBody> {
+    let body = self.storage.stream_body(cache_key, entry).await?;
+    debug!("Cache hit from storage: {cache_key}");
+    Ok(body)
+  }
+
+  async fn purge(&self, target: &PurgeTarget) -> CacheResult<usize> {
+    self.purge_epoch.fetch_add(1, Ordering::AcqRel);
+    self.storage.purge(target).await
+  }
+}
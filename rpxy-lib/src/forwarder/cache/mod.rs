@@ -1,8 +1,19 @@
 mod cache_error;
+mod cache_file;
+mod cache_index;
 mod cache_main;
+mod cache_range;
+mod cache_rule;
+mod cache_status;
+mod cache_storage;
+mod tiny_lfu;
 
 pub use cache_error::CacheError;
-pub(crate) use cache_main::{RpxyCache, get_policy_if_cacheable};
+pub use cache_main::PurgeTarget;
+pub(crate) use cache_main::{CacheLookup, FillGuard, RpxyCache, StaleEntry, get_policy_if_cacheable, normalize_vary_headers};
+pub(crate) use cache_rule::CacheRule;
+pub(crate) use cache_status::{CACHE_STATUS, CacheForward, CacheStatus};
+pub use cache_storage::{CacheBody, CacheEntry, CacheStorage};
 
 /// Client-facing effective request URI (scheme + authority + path/query), captured by the
 /// handler before the upstream rewrite and carried to the forwarder via request extensions.
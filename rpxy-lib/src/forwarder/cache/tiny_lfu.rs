@@ -0,0 +1,328 @@
+use lru::LruCache;
+use std::hash::{BuildHasher, RandomState};
+
+/// Percentage of the entries and of the budget of each tier given to the admission window
+const WINDOW_PERCENT: u64 = 1;
+/// Percentage of the entries of the main space given to the protected segment
+const PROTECTED_PERCENT: usize = 80;
+/// Number of rows of the frequency sketch, each indexed by its own hash of the key
+const SKETCH_DEPTH: usize = 4;
+/// Saturating maximum of a counter in the frequency sketch
+const SKETCH_MAX_COUNT: u8 = 15;
+/// Number of recorded accesses per entry after which all counters of the sketch are halved
+const SKETCH_SAMPLE_FACTOR: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Storage tier of a cache object, each with its own byte budget
+pub(super) enum Tier {
+  Memory,
+  Disk,
+}
+
+impl Tier {
+  const ALL: [Tier; 2] = [Tier::Memory, Tier::Disk];
+
+  fn index(self) -> usize {
+    match self {
+      Tier::Memory => 0,
+      Tier::Disk => 1,
+    }
+  }
+}
+
+#[derive(Debug)]
+/// Size-aware W-TinyLFU eviction policy over the keys of the cache index.
+///
+/// A new entry enters the admission window, a small LRU. Entries pushed out of the window become
+/// candidates for the main space, an SLRU of the probation and the protected segments, and are admitted
+/// only if accessed more frequently than the least recently used entries of the same tier that they would
+/// evict. The frequency is estimated by a count-min sketch recording every lookup including misses, so
+/// that an object requested once does not flush the hot set however large it is, while a popular one is
+/// admitted even if it has just been fetched.
+pub(super) struct TinyLfu {
+  sketch: FrequencySketch,
+  /// Recency order of each segment with the tier and the size of each entry
+  window: LruCache<String, (Tier, u64)>,
+  probation: LruCache<String, (Tier, u64)>,
+  protected: LruCache<String, (Tier, u64)>,
+  /// Bytes of the entries per tier, in total and in the window
+  bytes: [u64; 2],
+  window_bytes: [u64; 2],
+  /// Byte budget per tier
+  budgets: [u64; 2],
+  max_entry: usize,
+  max_window_entry: usize,
+  max_protected_entry: usize,
+}
+
+impl TinyLfu {
+  pub(super) fn new(max_entry: usize, max_memory_size: u64, max_disk_size: u64) -> Self {
+    let max_entry = max_entry.max(1);
+    let max_window_entry = (max_entry * WINDOW_PERCENT as usize / 100).max(1);
+    Self {
+      sketch: FrequencySketch::new(max_entry),
+      window: LruCache::unbounded(),
+      probation: LruCache::unbounded(),
+      protected: LruCache::unbounded(),
+      bytes: [0; 2],
+      window_bytes: [0; 2],
+      budgets: [max_memory_size, max_disk_size],
+      max_entry,
+      max_window_entry,
+      max_protected_entry: max_entry.saturating_sub(max_window_entry) * PROTECTED_PERCENT / 100,
+    }
+  }
+
+  /// Number of entries
+  pub(super) fn len(&self) -> usize {
+    self.window.len() + self.probation.len() + self.protected.len()
+  }
+
+  /// Bytes of the entries in the tier
+  pub(super) fn size(&self, tier: Tier) -> u64 {
+    self.bytes[tier.index()]
+  }
+
+  /// Record a lookup of the key, promoting the entry if present
+  pub(super) fn access(&mut self, key: &str) {
+    self.sketch.increment(key);
+    if self.window.promote(key) || self.protected.promote(key) {
+      return;
+    }
+    let Some((key, value)) = self.probation.pop_entry(key) else {
+      return;
+    };
+    self.protected.put(key, value);
+    // Demote the overflow of the protected segment back to probation
+    while self.protected.len() > self.max_protected_entry {
+      let Some((key, value)) = self.protected.pop_lru() else {
+        break;
+      };
+      self.probation.put(key, value);
+    }
+  }
+
+  /// Insert the key of an entry not present, returning the keys to be evicted, which may include the
+  /// key itself if it is not admitted
+  pub(super) fn insert(&mut self, key: String, tier: Tier, size: u64) -> Vec<String> {
+    self.sketch.increment(&key);
+    if size > self.budgets[tier.index()] {
+      return vec![key];
+    }
+    self.bytes[tier.index()] += size;
+    self.window_bytes[tier.index()] += size;
+    self.window.put(key, (tier, size));
+
+    // Move the overflow of the window to probation, as candidates for the main space
+    let mut candidates = Vec::new();
+    while let Some(tier) = self.window_overflow() {
+      let Some(key) = least_recent(&self.window, tier, &[]) else {
+        break;
+      };
+      let Some((key, (tier, size))) = self.window.pop_entry(&key) else {
+        break;
+      };
+      self.window_bytes[tier.index()] -= size;
+      self.probation.put(key.clone(), (tier, size));
+      candidates.push(key);
+    }
+
+    // Evict the less frequent of the candidate and the victim until within the limits
+    let mut evicted = Vec::new();
+    while let Some(tier) = self.overflow() {
+      let candidate = candidates
+        .iter()
+        .position(|key| tier.is_none_or(|tier| self.probation.peek(key).is_some_and(|(t, _)| *t == tier)));
+      let victim = least_recent(&self.probation, tier, &candidates).or_else(|| least_recent(&self.protected, tier, &[]));
+      let key = match (candidate, victim) {
+        (Some(i), Some(victim)) => {
+          let candidate_freq = self.sketch.frequency(&candidates[i]);
+          if candidate_freq > self.sketch.frequency(&victim) {
+            victim
+          } else {
+            candidates.remove(i)
+          }
+        }
+        (Some(i), None) => candidates.remove(i),
+        (None, Some(victim)) => victim,
+        (None, None) => match least_recent(&self.window, tier, &[]) {
+          Some(key) => key,
+          None => break,
+        },
+      };
+      self.remove(&key);
+      evicted.push(key);
+    }
+    evicted
+  }
+
+  /// Remove the key if present
+  pub(super) fn remove(&mut self, key: &str) {
+    if let Some((tier, size)) = self.window.pop(key) {
+      self.window_bytes[tier.index()] -= size;
+      self.bytes[tier.index()] -= size;
+    } else if let Some((tier, size)) = self.probation.pop(key).or_else(|| self.protected.pop(key)) {
+      self.bytes[tier.index()] -= size;
+    }
+  }
+
+  #[cfg(test)]
+  /// Key to be evicted first regardless of frequency, i.e., the least recently used one in the main space
+  pub(super) fn victim(&self) -> Option<String> {
+    least_recent(&self.probation, None, &[])
+      .or_else(|| least_recent(&self.protected, None, &[]))
+      .or_else(|| least_recent(&self.window, None, &[]))
+  }
+
+  /// Whether the window exceeds its share, returning the tier of the entry to be moved out of it
+  fn window_overflow(&self) -> Option<Option<Tier>> {
+    if let Some(tier) = Tier::ALL
+      .into_iter()
+      .find(|tier| self.window_bytes[tier.index()] > self.budgets[tier.index()] / 100 * WINDOW_PERCENT)
+    {
+      return Some(Some(tier));
+    }
+    (self.window.len() > self.max_window_entry).then_some(None)
+  }
+
+  /// Whether the entries exceed the limits, returning the tier over its budget, or `None` if over the
+  /// maximum number of entries
+  fn overflow(&self) -> Option<Option<Tier>> {
+    if let Some(tier) = Tier::ALL
+      .into_iter()
+      .find(|tier| self.bytes[tier.index()] > self.budgets[tier.index()])
+    {
+      return Some(Some(tier));
+    }
+    (self.len() > self.max_entry).then_some(None)
+  }
+}
+
+/// Least recently used key in the segment of the tier if given, other than the excluded ones
+fn least_recent(segment: &LruCache<String, (Tier, u64)>, tier: Option<Tier>, excluded: &[String]) -> Option<String> {
+  segment
+    .iter()
+    .rev()
+    .find(|(key, (t, _))| tier.is_none_or(|tier| *t == tier) && !excluded.contains(key))
+    .map(|(key, _)| key.clone())
+}
+
+#[derive(Debug)]
+/// Count-min sketch estimating the access frequency of keys with small saturating counters, all of
+/// which are halved periodically so that the past popularity fades out
+struct FrequencySketch {
+  counters: Vec<u8>,
+  /// Mask of the index in a row, whose width is a power of two
+  mask: u64,
+  additions: usize,
+  sample_size: usize,
+  hasher: RandomState,
+}
+
+impl FrequencySketch {
+  fn new(max_entry: usize) -> Self {
+    let width = max_entry.max(16).next_power_of_two();
+    Self {
+      counters: vec![0; width * SKETCH_DEPTH],
+      mask: width as u64 - 1,
+      additions: 0,
+      sample_size: max_entry.max(16) * SKETCH_SAMPLE_FACTOR,
+      hasher: RandomState::new(),
+    }
+  }
+
+  /// Indexes of the counters of the key, one per row by double hashing
+  fn indexes(&self, key: &str) -> [usize; SKETCH_DEPTH] {
+    let hash = self.hasher.hash_one(key);
+    let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    let width = self.mask + 1;
+    std::array::from_fn(|row| (row as u64 * width + (h1.wrapping_add(row as u64 * h2) & self.mask)) as usize)
+  }
+
+  /// Estimated frequency of the key
+  fn frequency(&self, key: &str) -> u8 {
+    self.indexes(key).iter().map(|i| self.counters[*i]).min().unwrap_or_default()
+  }
+
+  /// Record an access of the key, halving all counters once the sample size is reached
+  fn increment(&mut self, key: &str) {
+    for i in self.indexes(key) {
+      self.counters[i] = (self.counters[i] + 1).min(SKETCH_MAX_COUNT);
+    }
+    self.additions += 1;
+    if self.additions >= self.sample_size {
+      self.counters.iter_mut().for_each(|c| *c /= 2);
+      self.additions /= 2;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sketch_counts_and_ages_frequencies() {
+    let mut sketch = FrequencySketch::new(16);
+    for _ in 0..3 {
+      sketch.increment("hot");
+    }
+    sketch.increment("cold");
+    assert!(sketch.frequency("hot") >= 3);
+    assert!(sketch.frequency("hot") > sketch.frequency("cold"));
+    for _ in 0..SKETCH_MAX_COUNT * 2 {
+      sketch.increment("hot");
+    }
+    assert_eq!(sketch.frequency("hot"), SKETCH_MAX_COUNT);
+
+    // Halved once the sample size is reached
+    for i in 0..sketch.sample_size {
+      sketch.increment(&format!("other-{i}"));
+    }
+    assert!(sketch.frequency("hot") < SKETCH_MAX_COUNT);
+  }
+
+  /// A large object requested once is not admitted over the hot entries of its tier, while a frequently
+  /// requested one is, evicting the least frequent ones. The memory tier is not touched.
+  #[test]
+  fn large_one_hit_wonder_does_not_flush_hot_entries() {
+    let mut policy = TinyLfu::new(1000, 1_000, 10_000);
+    for i in 0..10 {
+      let key = format!("hot-{i}");
+      assert!(policy.insert(key.clone(), Tier::Disk, 1_000).is_empty());
+      for _ in 0..3 {
+        policy.access(&key);
+      }
+    }
+    assert!(policy.insert("memory".to_string(), Tier::Memory, 500).is_empty());
+    assert_eq!((policy.size(Tier::Disk), policy.size(Tier::Memory)), (10_000, 500));
+
+    assert_eq!(policy.insert("large".to_string(), Tier::Disk, 5_000), vec!["large"]);
+    assert_eq!(policy.size(Tier::Disk), 10_000);
+
+    for _ in 0..5 {
+      policy.access("popular");
+    }
+    let evicted = policy.insert("popular".to_string(), Tier::Disk, 2_000);
+    assert_eq!(evicted.len(), 2);
+    assert!(evicted.iter().all(|key| key.starts_with("hot-")));
+    assert_eq!(policy.size(Tier::Disk), 10_000);
+    assert_eq!(policy.size(Tier::Memory), 500);
+
+    // Larger than the whole budget
+    assert_eq!(policy.insert("huge".to_string(), Tier::Memory, 1_001), vec!["huge"]);
+  }
+
+  #[test]
+  fn entries_are_bounded_by_count() {
+    let mut policy = TinyLfu::new(4, u64::MAX, u64::MAX);
+    let evicted = (0..10)
+      .flat_map(|i| policy.insert(format!("key-{i}"), Tier::Memory, 1))
+      .collect::<Vec<_>>();
+    assert_eq!(evicted.len(), 6);
+    assert_eq!(policy.len(), 4);
+    policy.remove("key-9");
+    policy.remove("missing");
+    assert_eq!((policy.len(), policy.size(Tier::Memory)), (3, 3));
+  }
+}
@@ -1,7 +1,6 @@
-#[allow(unused)]
 use crate::{
   error::{RpxyError, RpxyResult},
-  globals::Globals,
+  globals::{Globals, UpstreamTlsConfig},
   hyper_ext::{body::ResponseBody, rt::LocalExecutor},
   log::*,
 };
@@ -14,8 +13,37 @@ use hyper_util::client::legacy::{
 };
 use std::sync::Arc;
 
+#[cfg(feature = "rustls-backend")]
+use super::upstream_tls::{UpstreamTls, UpstreamTlsConnectorBuilder};
+
+#[cfg(unix)]
+use crate::hyper_ext::unix::{UnixConnector, is_unix_socket_uri};
+
+#[cfg(feature = "proxy-protocol")]
+use super::proxy_protocol::{ProxyProtocolClients, SendProxyProtocol};
+#[cfg(feature = "proxy-protocol")]
+use crate::hyper_ext::proxy_protocol::ProxyProtocolConnector;
+
+// Connector of the clients sending the PROXY protocol header, for each TLS backend variant
+#[cfg(all(feature = "proxy-protocol", feature = "rustls-backend"))]
+type ProxyProtocolHttpsConnector = hyper_rustls::HttpsConnector<ProxyProtocolConnector>;
+#[cfg(all(feature = "proxy-protocol", feature = "native-tls-backend", not(feature = "rustls-backend")))]
+type ProxyProtocolHttpsConnector = hyper_tls::HttpsConnector<ProxyProtocolConnector>;
+#[cfg(all(
+  feature = "proxy-protocol",
+  not(any(feature = "native-tls-backend", feature = "rustls-backend"))
+))]
+type ProxyProtocolHttpsConnector = ProxyProtocolConnector;
+
+#[cfg(feature = "cache")]
+use super::cache::{
+  CACHE_STATUS, CacheForward, CacheLookup, CacheRule, CacheStatus, ClientFacingEffectiveUri, FillGuard, RpxyCache, StaleEntry,
+  get_policy_if_cacheable, normalize_vary_headers,
+};
+#[cfg(feature = "cache")]
+use http::{HeaderMap, Method, StatusCode, header};
 #[cfg(feature = "cache")]
-use super::cache::{ClientFacingEffectiveUri, RpxyCache, get_policy_if_cacheable};
+use http_body_util::BodyExt;
 
 #[async_trait]
 /// Definition of the forwarder that simply forward requests from downstream client to upstream app servers.
@@ -30,10 +58,27 @@ pub struct Forwarder<C, B> {
   cache: Option<RpxyCache>,
   inner: Client<C, B>,
   inner_h2: Client<C, B>, // `h2c` or http/2-only client is defined separately
+  #[cfg(unix)]
+  inner_unix: Client<UnixConnector, B>, // clients for Unix domain socket upstreams, always cleartext
+  #[cfg(unix)]
+  inner_unix_h2: Client<UnixConnector, B>,
+  #[cfg(feature = "rustls-backend")]
+  inner_tls: Vec<UpstreamTlsClients<C, B>>, // clients for upstream groups with their own TLS settings
+  #[cfg(feature = "proxy-protocol")]
+  inner_pp: ProxyProtocolClients<ProxyProtocolHttpsConnector, B>, // per-client clients sending the PROXY protocol header
+}
+
+/// Clients built for the TLS settings of upstream groups, selected by the [`UpstreamTls`] request extension
+#[cfg(feature = "rustls-backend")]
+struct UpstreamTlsClients<C, B> {
+  config: Arc<UpstreamTlsConfig>,
+  inner: Client<C, B>,
+  inner_h2: Client<C, B>,
 }
 
 #[async_trait]
-impl<C, B1> ForwardRequest<B1, ResponseBody> for Forwarder<C, B1>
+/// Implemented on `Arc` so that a stale cache entry can be refreshed in background by the forwarder
+impl<C, B1> ForwardRequest<B1, ResponseBody> for Arc<Forwarder<C, B1>>
 where
   C: Send + Sync + Connect + Clone + 'static,
   B1: Body + Send + Sync + Unpin + 'static,
@@ -45,48 +90,18 @@ where
   async fn request(&self, req: Request<B1>) -> Result<Response<ResponseBody>, Self::Error> {
     #[cfg(feature = "cache")]
     {
-      let mut synth_req = None;
-      if let Some(cache) = self.cache.as_ref() {
-        // The cache is keyed on the client-facing effective URI captured by the handler before
-        // the upstream rewrite and carried in request extensions. When it is absent we fail
-        // closed: bypass the cache entirely rather than keying on the upstream-rewritten request
-        // URI (which would collide across client-facing vhosts sharing one upstream target).
-        if let Some(effective_uri) = cache_effective_uri(&req) {
-          // Synthetic request copy used just for caching (cannot clone request object...)
-          let sreq = build_synth_req_for_cache(&req, &effective_uri);
-          // try reading from cache
-          if let Some(cached_response) = cache.get(&sreq).await {
-            // if found, return it as response.
-            info!("Cache hit - Return from cache");
-            return Ok(cached_response);
-          };
-          synth_req = Some(sreq);
-        }
+      let mut res = self.request_with_cache(req).await;
+      // The member of rpxy is appended to `Cache-Status` after those of the caches closer to the upstream
+      if let (Some(cache), Ok(res)) = (self.cache.as_ref(), res.as_mut())
+        && cache.status_header()
+        && let Some(value) = res
+          .extensions()
+          .get::<CacheStatus>()
+          .and_then(|status| status.to_header_value(cache.status_key()))
+      {
+        res.headers_mut().append(CACHE_STATUS, value);
       }
-      let res = self.request_directly(req).await;
-
-      // No cache configured: return the upstream response uncached.
-      let Some(cache) = self.cache.as_ref() else {
-        return res.map(|inner| inner.map(ResponseBody::Incoming));
-      };
-
-      // check cacheability and store it if cacheable. `synth_req` is None when the cache was
-      // bypassed above (no client-facing effective URI); skip the store in that case too.
-      let Some(synth_req) = synth_req else {
-        return res.map(|inner| inner.map(ResponseBody::Incoming));
-      };
-      let Ok(Some(cache_policy)) = get_policy_if_cacheable(Some(&synth_req), res.as_ref().ok()) else {
-        return res.map(|inner| inner.map(ResponseBody::Incoming));
-      };
-      let (parts, body) = res.unwrap().into_parts();
-
-      // Get streamed body without waiting for the arrival of the body,
-      // which is done simultaneously with caching.
-      let stream_body = cache.put(synth_req.uri(), body, &cache_policy).await?;
-
-      // response with body being cached in background
-      let new_res = Response::from_parts(parts, ResponseBody::Streamed(stream_body));
-      Ok(new_res)
+      res
     }
 
     // No cache handling
@@ -100,6 +115,253 @@ where
   }
 }
 
+#[cfg(feature = "cache")]
+impl<C, B1> Forwarder<C, B1>
+where
+  C: Send + Sync + Connect + Clone + 'static,
+  B1: Body + Send + Sync + Unpin + 'static,
+  <B1 as Body>::Data: Send,
+  <B1 as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+  /// Forward the request through the cache if configured. The cache status of the response is given in its
+  /// extensions unless the cache is not configured.
+  async fn request_with_cache(self: &Arc<Self>, req: Request<B1>) -> RpxyResult<Response<ResponseBody>> {
+    let mut req = req;
+    let mut synth_req = None;
+    let mut stale_entry = None;
+    let mut fill_guard = None;
+    let mut status = None;
+    let mut client_range = None;
+    // Taken before requesting the upstream, so that a purge meanwhile keeps the response from being stored
+    let purge_epoch = self.cache.as_ref().map(|cache| cache.purge_epoch()).unwrap_or_default();
+    if let Some(cache) = self.cache.as_ref() {
+      // The cache is keyed on the client-facing effective URI captured by the handler before
+      // the upstream rewrite and carried in request extensions. When it is absent we fail
+      // closed: bypass the cache entirely rather than keying on the upstream-rewritten request
+      // URI (which would collide across client-facing vhosts sharing one upstream target).
+      // The cache rule of the route may also bypass the cache for the request.
+      let bypassed = req
+        .extensions()
+        .get::<CacheRule>()
+        .is_some_and(|rule| rule.bypasses(req.headers()));
+      if let Some(effective_uri) = cache_effective_uri(&req).filter(|_| !bypassed) {
+        // Synthetic request copy used just for caching (cannot clone request object...)
+        let mut sreq = build_synth_req_for_cache(&req, &effective_uri);
+        // try reading from cache, or wait for a concurrent request fetching the same entry
+        let (lookup, guard) = cache.get_or_join(&sreq).await;
+        fill_guard = guard;
+        let fwd = match lookup {
+          CacheLookup::Fresh(cached_response) => {
+            // if found, return it as response.
+            info!("Cache hit - Return from cache");
+            return Ok(cached_response);
+          }
+          CacheLookup::Revalidate(cached_response, revalidation) => {
+            // return the stale one, and refresh it in background by the request unless already refreshing
+            info!("Cache hit (stale) - Return from cache while revalidating");
+            if let Some((guard, entry)) = revalidation {
+              let forwarder = self.clone();
+              cache.revalidate(
+                guard,
+                async move { forwarder.revalidate(req, sreq, purge_epoch, entry).await },
+              );
+            }
+            return Ok(cached_response);
+          }
+          CacheLookup::Collapsed(collapsed_response) => {
+            info!("Cache miss collapsed - Return the response fetched by a concurrent request");
+            return Ok(collapsed_response);
+          }
+          CacheLookup::Stale(entry) => {
+            stale_entry = Some(entry);
+            CacheForward::Stale
+          }
+          CacheLookup::Miss(reason) => reason,
+        };
+        let fwd = if matches!(*sreq.method(), Method::GET | Method::HEAD) {
+          fwd
+        } else {
+          CacheForward::Method
+        };
+        // `Range` is removed so that the full response fills the cache, and the ranges are served from it
+        if *sreq.method() == Method::GET && req.headers().contains_key(header::RANGE) {
+          let mut range = HeaderMap::new();
+          for name in [header::RANGE, header::IF_RANGE] {
+            if let Some(value) = req.headers_mut().remove(&name) {
+              range.insert(name.clone(), value);
+            }
+            sreq.headers_mut().remove(&name);
+          }
+          client_range = Some(range);
+        }
+        status = Some(CacheStatus::forward(fwd, Some(sreq.uri().to_string())));
+        synth_req = Some(sreq);
+      } else {
+        status = Some(CacheStatus::forward(CacheForward::Bypass, None));
+      }
+    }
+    let conditional = stale_entry.as_ref().is_some_and(|entry| entry.make_conditional(&mut req));
+    let mut res = self.request_directly(req).await;
+
+    // No cache configured: return the upstream response uncached.
+    let Some(cache) = self.cache.as_ref() else {
+      return res.map(|inner| inner.map(ResponseBody::Incoming));
+    };
+    if let (Some(status), Ok(res)) = (status.as_mut(), res.as_ref()) {
+      status.fwd_status = Some(res.status());
+    }
+
+    if let Some(entry) = stale_entry {
+      // A `304 Not Modified` to the revalidation refreshes the stale entry, which is served instead
+      if conditional
+        && let (Some(synth_req), Ok(not_modified)) = (synth_req.as_ref(), res.as_ref())
+        && not_modified.status() == StatusCode::NOT_MODIFIED
+      {
+        if let Some(refreshed) = cache.refresh(&entry, synth_req, not_modified).await {
+          info!("Cache revalidated - Return from cache");
+          return serve_client_range(cache, client_range.as_ref(), refreshed).await;
+        }
+        // Never pass the `304` through to the client that did not make the request conditional
+        res = Err(RpxyError::FailedToFetchFromUpstream(
+          "Not modified response unusable for the cache revalidation".to_string(),
+        ));
+      }
+      // Serve the stale entry instead of the upstream error within `stale-if-error`
+      if res.as_ref().map_or(true, |res| res.status().is_server_error())
+        && let Some(synth_req) = synth_req.as_ref()
+        && let Some(mut stale_response) = cache.get_stale_if_error(&entry, synth_req).await
+      {
+        warn!("Upstream failed - Return stale response from cache");
+        if let Some(stale_status) = stale_response.extensions_mut().get_mut::<CacheStatus>() {
+          stale_status.fwd_status = status.and_then(|status| status.fwd_status);
+        }
+        return serve_client_range(cache, client_range.as_ref(), stale_response).await;
+      }
+      cache.discard(*entry).await;
+    }
+
+    // check cacheability and store it if cacheable. `synth_req` is None when the cache was
+    // bypassed above (no client-facing effective URI); skip the store in that case too.
+    let Some(synth_req) = synth_req else {
+      return res.map(|inner| with_status(inner.map(ResponseBody::Incoming), status));
+    };
+    let res = store_if_cacheable(cache, synth_req, res, purge_epoch, fill_guard, status).await?;
+    serve_client_range(cache, client_range.as_ref(), res).await
+  }
+}
+
+#[cfg(feature = "cache")]
+impl<C, B> Forwarder<C, B> {
+  /// Response cache shared by the forwarder, if enabled
+  pub(crate) fn cache(&self) -> Option<&RpxyCache> {
+    self.cache.as_ref()
+  }
+}
+
+#[cfg(feature = "cache")]
+impl<C, B1> Forwarder<C, B1>
+where
+  C: Send + Sync + Connect + Clone + 'static,
+  B1: Body + Send + Unpin + 'static,
+  <B1 as Body>::Data: Send,
+  <B1 as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+  /// Refresh a stale cache entry with the request that found it, conditionally if the entry has a validator.
+  /// A full response is read through so that it is stored. The conditionals and `Range` of the client are
+  /// removed since the response is not for the client.
+  async fn revalidate(&self, mut req: Request<B1>, mut synth_req: Request<()>, purge_epoch: u64, stale_entry: Box<StaleEntry>) {
+    let Some(cache) = self.cache.as_ref() else {
+      return;
+    };
+    for name in [
+      header::IF_MATCH,
+      header::IF_NONE_MATCH,
+      header::IF_MODIFIED_SINCE,
+      header::IF_UNMODIFIED_SINCE,
+      header::IF_RANGE,
+      header::RANGE,
+    ] {
+      req.headers_mut().remove(&name);
+      synth_req.headers_mut().remove(&name);
+    }
+    let conditional = stale_entry.make_conditional(&mut req);
+    let res = self.request_directly(req).await;
+    if conditional
+      && let Ok(not_modified) = res.as_ref()
+      && not_modified.status() == StatusCode::NOT_MODIFIED
+    {
+      if cache.refresh(&stale_entry, &synth_req, not_modified).await.is_none() {
+        warn!("Failed to revalidate stale cache entry: unusable not modified response");
+      }
+      return;
+    }
+    match store_if_cacheable(cache, synth_req, res, purge_epoch, None, None).await {
+      Ok(res) => {
+        let mut body = res.into_body();
+        while let Some(Ok(_)) = body.frame().await {}
+        debug!("Revalidated stale cache entry");
+      }
+      Err(e) => warn!("Failed to revalidate stale cache entry: {e}"),
+    }
+  }
+}
+
+#[cfg(feature = "cache")]
+/// Store the upstream response in the cache if cacheable, returning the response whose body is stored while relayed,
+/// with the cache status if any. The fill guard is released when the response is stored, or right away if it is not
+/// cacheable.
+async fn store_if_cacheable(
+  cache: &RpxyCache,
+  mut synth_req: Request<()>,
+  res: RpxyResult<Response<Incoming>>,
+  purge_epoch: u64,
+  fill_guard: Option<FillGuard>,
+  mut status: Option<CacheStatus>,
+) -> RpxyResult<Response<ResponseBody>> {
+  if let Ok(res) = res.as_ref() {
+    normalize_vary_headers(synth_req.headers_mut(), res.headers());
+  }
+  let Ok(Some(cache_policy)) = get_policy_if_cacheable(Some(&synth_req), res.as_ref().ok()) else {
+    return res.map(|inner| with_status(inner.map(ResponseBody::Incoming), status));
+  };
+  let (parts, body) = res?.into_parts();
+
+  // Get streamed body without waiting for the arrival of the body,
+  // which is done simultaneously with caching.
+  let stream_body = cache
+    .put(&synth_req, &parts, body, &cache_policy, purge_epoch, fill_guard)
+    .await?;
+
+  // response with body being cached in background
+  if let Some(status) = status.as_mut() {
+    status.stored = true;
+  }
+  let new_res = Response::from_parts(parts, ResponseBody::Streamed(stream_body));
+  Ok(with_status(new_res, status))
+}
+
+#[cfg(feature = "cache")]
+/// Serve the ranges of the client from the full response if `Range` was removed from the request to fill the cache
+async fn serve_client_range(
+  cache: &RpxyCache,
+  client_range: Option<&HeaderMap>,
+  res: Response<ResponseBody>,
+) -> RpxyResult<Response<ResponseBody>> {
+  match client_range {
+    Some(client_range) => cache.serve_removed_range(client_range, res).await,
+    None => Ok(res),
+  }
+}
+
+#[cfg(feature = "cache")]
+/// Put the cache status, if any, in the response extensions
+fn with_status(mut res: Response<ResponseBody>, status: Option<CacheStatus>) -> Response<ResponseBody> {
+  if let Some(status) = status {
+    res.extensions_mut().insert(status);
+  }
+  res
+}
+
 impl<C, B1> Forwarder<C, B1>
 where
   C: Send + Sync + Connect + Clone + 'static,
@@ -110,9 +372,45 @@ where
   async fn request_directly(&self, req: Request<B1>) -> RpxyResult<Response<Incoming>> {
     // TODO: Revisit this per-request HTTP version dispatch if hyper-util exposes
     // a setup-time h1/h2 client selection path. See https://github.com/hyperium/hyper/issues/2417.
+    #[cfg(unix)]
+    if is_unix_socket_uri(req.uri()) {
+      return match req.version() {
+        Version::HTTP_2 => self.inner_unix_h2.request(req).await,
+        _ => self.inner_unix.request(req).await,
+      }
+      .map_err(|e| RpxyError::FailedToFetchFromUpstream(e.to_string()));
+    }
+    #[cfg(feature = "proxy-protocol")]
+    if let Some(send_proxy_protocol) = req.extensions().get::<SendProxyProtocol>() {
+      #[cfg(feature = "rustls-backend")]
+      let upstream_tls = req.extensions().get::<UpstreamTls>().map(|UpstreamTls(config)| config);
+      #[cfg(not(feature = "rustls-backend"))]
+      let upstream_tls = None;
+      let (inner, inner_h2) = self.inner_pp.get(send_proxy_protocol, upstream_tls)?;
+      return match req.version() {
+        Version::HTTP_2 => inner_h2.request(req).await,
+        _ => inner.request(req).await,
+      }
+      .map_err(|e| RpxyError::FailedToFetchFromUpstream(e.to_string()));
+    }
+    #[cfg(feature = "rustls-backend")]
+    let (inner, inner_h2) = match req.extensions().get::<UpstreamTls>() {
+      Some(UpstreamTls(config)) => {
+        let clients = self
+          .inner_tls
+          .iter()
+          .find(|clients| clients.config == *config)
+          .ok_or_else(|| RpxyError::FailedToFetchFromUpstream("No client built for the upstream TLS settings".to_string()))?;
+        (&clients.inner, &clients.inner_h2)
+      }
+      None => (&self.inner, &self.inner_h2),
+    };
+    // Upstream TLS settings are rejected at build time without rustls, so the default clients serve everything.
+    #[cfg(not(feature = "rustls-backend"))]
+    let (inner, inner_h2) = (&self.inner, &self.inner_h2);
     match req.version() {
-      Version::HTTP_2 => self.inner_h2.request(req).await, // handles `h2c` requests
-      _ => self.inner.request(req).await,
+      Version::HTTP_2 => inner_h2.request(req).await, // handles `h2c` requests
+      _ => inner.request(req).await,
     }
     .map_err(|e| RpxyError::FailedToFetchFromUpstream(e.to_string()))
   }
@@ -126,7 +424,8 @@ where
   <B as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
 {
   /// Build inner client with http
-  pub async fn try_new(_globals: &Arc<Globals>) -> RpxyResult<Self> {
+  pub async fn try_new(_globals: &Arc<Globals>, upstream_tls_configs: &[Arc<UpstreamTlsConfig>]) -> RpxyResult<Self> {
+    reject_upstream_tls_configs(upstream_tls_configs)?;
     warn!(
       "
 --------------------------------------------------------------------------------------------------
@@ -142,12 +441,29 @@ Enable 'native-tls-backend' or 'rustls-backend' feature for TLS support.
     http.set_keepalive(Some(_globals.proxy_config.upstream_idle_timeout));
     // Disable Nagle's algorithm: rpxy relays many small request/response writes upstream.
     http.set_nodelay(true);
-    let inner = Client::builder(executor).build::<_, B>(http);
+    let inner = Client::builder(executor).build::<_, B>(http.clone());
     let inner_h2 = inner.clone();
 
+    #[cfg(feature = "proxy-protocol")]
+    let inner_pp = ProxyProtocolClients::new(
+      _globals.runtime_handle.clone(),
+      http,
+      Box::new(|connector, upstream_tls, _| upstream_tls.is_none().then_some(connector)),
+      _globals.proxy_config.max_clients,
+    );
+
+    #[cfg(unix)]
+    let (inner_unix, inner_unix_h2) = build_unix_clients(_globals);
+
     Ok(Self {
       inner,
       inner_h2,
+      #[cfg(unix)]
+      inner_unix,
+      #[cfg(unix)]
+      inner_unix_h2,
+      #[cfg(feature = "proxy-protocol")]
+      inner_pp,
       #[cfg(feature = "cache")]
       cache: RpxyCache::new(_globals).await,
     })
@@ -163,38 +479,61 @@ where
   <B1 as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
 {
   /// Build forwarder
-  pub async fn try_new(_globals: &Arc<Globals>) -> RpxyResult<Self> {
+  pub async fn try_new(_globals: &Arc<Globals>, upstream_tls_configs: &[Arc<UpstreamTlsConfig>]) -> RpxyResult<Self> {
+    reject_upstream_tls_configs(upstream_tls_configs)?;
     // build hyper client with hyper-tls
     info!("Native TLS support enabled for backend connections (native-tls)");
     let executor = LocalExecutor::new(_globals.runtime_handle.clone());
 
-    let try_build_connector = |alpns: &[&str]| {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_reuse_address(true);
+    http.set_keepalive(Some(_globals.proxy_config.upstream_idle_timeout));
+    // Disable Nagle's algorithm: rpxy relays many small request/response writes upstream.
+    http.set_nodelay(true);
+
+    let try_build_tls = |alpns: &[&str]| {
       hyper_tls::native_tls::TlsConnector::builder()
         .request_alpns(alpns)
         .build()
         .map_err(|e| RpxyError::FailedToBuildForwarder(e.to_string()))
-        .map(|tls| {
-          let mut http = HttpConnector::new();
-          http.enforce_http(false);
-          http.set_reuse_address(true);
-          http.set_keepalive(Some(_globals.proxy_config.upstream_idle_timeout));
-          // Disable Nagle's algorithm: rpxy relays many small request/response writes upstream.
-          http.set_nodelay(true);
-          hyper_tls::HttpsConnector::from((http, tls.into()))
-        })
     };
+    let tls = try_build_tls(&["h2", "http/1.1"])?;
+    let tls_h2 = try_build_tls(&["h2"])?;
 
-    let connector = try_build_connector(&["h2", "http/1.1"])?;
+    let connector = hyper_tls::HttpsConnector::from((http.clone(), tls.clone().into()));
     let inner = Client::builder(executor.clone()).build::<_, B1>(connector);
 
-    let connector_h2 = try_build_connector(&["h2"])?;
+    let connector_h2 = hyper_tls::HttpsConnector::from((http.clone(), tls_h2.clone().into()));
     let inner_h2 = Client::builder(executor.clone())
       .http2_only(true)
       .build::<_, B1>(connector_h2);
 
+    #[cfg(feature = "proxy-protocol")]
+    let inner_pp = ProxyProtocolClients::new(
+      _globals.runtime_handle.clone(),
+      http,
+      Box::new(move |connector, upstream_tls, http2_only| {
+        let tls = if http2_only { tls_h2.clone() } else { tls.clone() };
+        upstream_tls
+          .is_none()
+          .then(|| hyper_tls::HttpsConnector::from((connector, tls.into())))
+      }),
+      _globals.proxy_config.max_clients,
+    );
+
+    #[cfg(unix)]
+    let (inner_unix, inner_unix_h2) = build_unix_clients(_globals);
+
     Ok(Self {
       inner,
       inner_h2,
+      #[cfg(unix)]
+      inner_unix,
+      #[cfg(unix)]
+      inner_unix_h2,
+      #[cfg(feature = "proxy-protocol")]
+      inner_pp,
       #[cfg(feature = "cache")]
       cache: RpxyCache::new(_globals).await,
     })
@@ -210,7 +549,7 @@ where
   <B1 as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
 {
   /// Build forwarder
-  pub async fn try_new(_globals: &Arc<Globals>) -> RpxyResult<Self> {
+  pub async fn try_new(_globals: &Arc<Globals>, upstream_tls_configs: &[Arc<UpstreamTlsConfig>]) -> RpxyResult<Self> {
     // build hyper client with rustls and webpki, only https is allowed
     #[cfg(feature = "webpki-roots")]
     let builder = hyper_rustls::HttpsConnectorBuilder::new().with_webpki_roots();
@@ -234,21 +573,99 @@ where
     http.set_nodelay(true);
 
     let connector = builder.https_or_http().enable_all_versions().wrap_connector(http.clone());
-    let connector_h2 = builder_h2.https_or_http().enable_http2().wrap_connector(http);
+    let connector_h2 = builder_h2.https_or_http().enable_http2().wrap_connector(http.clone());
     let inner = Client::builder(LocalExecutor::new(_globals.runtime_handle.clone())).build::<_, B1>(connector);
     let inner_h2 = Client::builder(LocalExecutor::new(_globals.runtime_handle.clone()))
       .http2_only(true)
       .build::<_, B1>(connector_h2);
 
+    // Clients for upstream groups with their own TLS settings. Invalid settings, e.g., an unreadable
+    // CA bundle or client key, fail here so that they are reported at startup.
+    let tls_builders = upstream_tls_configs
+      .iter()
+      .map(|config| Ok((config.clone(), UpstreamTlsConnectorBuilder::try_new(config)?)))
+      .collect::<RpxyResult<Vec<_>>>()?;
+    let inner_tls = tls_builders
+      .iter()
+      .map(|(config, tls_builder)| UpstreamTlsClients {
+        config: config.clone(),
+        inner: Client::builder(LocalExecutor::new(_globals.runtime_handle.clone()))
+          .build::<_, B1>(tls_builder.build(http.clone(), false)),
+        inner_h2: Client::builder(LocalExecutor::new(_globals.runtime_handle.clone()))
+          .http2_only(true)
+          .build::<_, B1>(tls_builder.build(http.clone(), true)),
+      })
+      .collect::<Vec<_>>();
+    if !inner_tls.is_empty() {
+      info!(
+        "Rustls backend: {} custom TLS settings used for backend connections",
+        inner_tls.len()
+      );
+    }
+
+    // The default settings give the same verification as the default clients above.
+    #[cfg(feature = "proxy-protocol")]
+    let inner_pp = {
+      let default_builder = UpstreamTlsConnectorBuilder::try_new(&UpstreamTlsConfig::default())?;
+      ProxyProtocolClients::new(
+        _globals.runtime_handle.clone(),
+        http,
+        Box::new(move |connector, upstream_tls, http2_only| {
+          let tls_builder = match upstream_tls {
+            Some(upstream_tls) => &tls_builders.iter().find(|(config, _)| **config == *upstream_tls)?.1,
+            None => &default_builder,
+          };
+          Some(tls_builder.build(connector, http2_only))
+        }),
+        _globals.proxy_config.max_clients,
+      )
+    };
+
+    #[cfg(unix)]
+    let (inner_unix, inner_unix_h2) = build_unix_clients(_globals);
+
     Ok(Self {
       inner,
       inner_h2,
+      #[cfg(unix)]
+      inner_unix,
+      #[cfg(unix)]
+      inner_unix_h2,
+      inner_tls,
+      #[cfg(feature = "proxy-protocol")]
+      inner_pp,
       #[cfg(feature = "cache")]
       cache: RpxyCache::new(_globals).await,
     })
   }
 }
 
+#[cfg(not(feature = "rustls-backend"))]
+/// Per-upstream TLS settings are built on rustls, so they are rejected by the other backends.
+fn reject_upstream_tls_configs(upstream_tls_configs: &[Arc<UpstreamTlsConfig>]) -> RpxyResult<()> {
+  if upstream_tls_configs.is_empty() {
+    return Ok(());
+  }
+  Err(RpxyError::FailedToBuildForwarder(
+    "Upstream TLS settings (upstream_tls) require the 'rustls-backend' feature".to_string(),
+  ))
+}
+
+#[cfg(unix)]
+/// Build clients for Unix domain socket upstreams: the default one and the http/2-only (`h2c`) one.
+fn build_unix_clients<B>(globals: &Arc<Globals>) -> (Client<UnixConnector, B>, Client<UnixConnector, B>)
+where
+  B: Body + Send + Unpin + 'static,
+  <B as Body>::Data: Send,
+  <B as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+  let inner = Client::builder(LocalExecutor::new(globals.runtime_handle.clone())).build::<_, B>(UnixConnector);
+  let inner_h2 = Client::builder(LocalExecutor::new(globals.runtime_handle.clone()))
+    .http2_only(true)
+    .build::<_, B>(UnixConnector);
+  (inner, inner_h2)
+}
+
 #[cfg(feature = "cache")]
 /// Read the client-facing effective URI the handler placed in request extensions, if any.
 /// `None` means the forwarder must bypass the cache for this request (fail closed); the cache is
@@ -261,16 +678,19 @@ fn cache_effective_uri<B>(req: &Request<B>) -> Option<http::Uri> {
 /// Build synthetic request to cache, keyed on the client-facing effective URI (not the
 /// upstream-rewritten request URI). Method, version, and headers are copied from the live
 /// request; only the URI is overridden with the client-facing effective URI so the cache key
-/// and `CachePolicy` partition per client-facing vhost and scheme.
+/// and `CachePolicy` partition per client-facing vhost and scheme. The query of the URI is
+/// rewritten by the cache rule of the route if any, which is carried on to the cache as the only
+/// request extension.
 fn build_synth_req_for_cache<T>(req: &Request<T>, effective_uri: &http::Uri) -> Request<()> {
-  let mut builder = Request::builder()
-    .method(req.method())
-    .uri(effective_uri.clone())
-    .version(req.version());
-  // TODO: Include request extensions only if a future cache policy needs them.
+  let cache_rule = req.extensions().get::<CacheRule>();
+  let uri = cache_rule.map_or_else(|| effective_uri.clone(), |rule| rule.key_uri(effective_uri));
+  let mut builder = Request::builder().method(req.method()).uri(uri).version(req.version());
   for (header_key, header_value) in req.headers() {
     builder = builder.header(header_key, header_value);
   }
+  if let Some(cache_rule) = cache_rule {
+    builder = builder.extension(cache_rule.clone());
+  }
   builder.body(()).unwrap()
 }
 
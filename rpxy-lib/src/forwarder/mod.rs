@@ -1,13 +1,25 @@
 #[cfg(feature = "cache")]
 mod cache;
 mod client;
+#[cfg(feature = "proxy-protocol")]
+mod proxy_protocol;
+#[cfg(feature = "rustls-backend")]
+mod upstream_tls;
 
 use crate::hyper_ext::body::RequestBody;
 
 pub(crate) type Forwarder<C> = client::Forwarder<C, RequestBody>;
 pub(crate) use client::ForwardRequest;
+#[cfg(feature = "proxy-protocol")]
+pub(crate) use proxy_protocol::SendProxyProtocol;
+#[cfg(feature = "rustls-backend")]
+pub(crate) use upstream_tls::UpstreamTls;
+#[cfg(all(feature = "rustls-backend", feature = "health-check"))]
+pub(crate) use upstream_tls::build_https_connector;
 
 #[cfg(feature = "cache")]
-pub(crate) use cache::CacheError;
+pub(crate) use cache::RpxyCache;
 #[cfg(feature = "cache")]
-pub(crate) use cache::ClientFacingEffectiveUri;
+pub use cache::{CacheBody, CacheEntry, CacheError, CacheStorage, PurgeTarget};
+#[cfg(feature = "cache")]
+pub(crate) use cache::{CacheRule, CacheStatus, ClientFacingEffectiveUri};
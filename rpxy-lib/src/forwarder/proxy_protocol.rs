@@ -0,0 +1,233 @@
+use crate::{
+  constants::proxy_protocol::{UPSTREAM_CLIENT_IDLE_TIMEOUT_SEC, UPSTREAM_CLIENT_SWEEP_INTERVAL_SEC},
+  error::{RpxyError, RpxyResult},
+  globals::{ProxyProtocolVersion, UpstreamTlsConfig},
+  hyper_ext::{
+    proxy_protocol::{ProxyProtocolConnector, encode_proxy_header},
+    rt::LocalExecutor,
+  },
+  log::*,
+};
+use hyper::body::Body;
+use hyper_util::client::legacy::{
+  Client,
+  connect::{Connect, HttpConnector},
+};
+use lru::LruCache;
+use std::{
+  net::SocketAddr,
+  num::NonZeroUsize,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+/// PROXY protocol header to send for the client of a request, carried in request extensions from
+/// the handler to the forwarder.
+pub(crate) struct SendProxyProtocol {
+  pub(crate) version: ProxyProtocolVersion,
+  /// Address of the client
+  pub(crate) src: SocketAddr,
+  /// Address the client connected to
+  pub(crate) dst: SocketAddr,
+}
+
+/// Wrap a PROXY protocol connector into the connector of the TLS backend, applying the given upstream
+/// TLS settings, with ALPN for http/2 only if the flag is set. Returns None if the settings are unknown.
+pub(super) type WrapConnector<P> =
+  Box<dyn Fn(ProxyProtocolConnector, Option<&UpstreamTlsConfig>, bool) -> Option<P> + Send + Sync>;
+
+#[derive(PartialEq, Eq, Hash)]
+struct ClientKey {
+  version: ProxyProtocolVersion,
+  src: SocketAddr,
+  dst: SocketAddr,
+  upstream_tls: Option<Arc<UpstreamTlsConfig>>,
+}
+
+struct ClientEntry<P, B> {
+  inner: Client<P, B>,
+  inner_h2: Client<P, B>,
+  last_used: Instant,
+}
+
+/// Clients in the order of last use, so that idle ones are found from the least recently used end
+type ClientMap<P, B> = LruCache<ClientKey, ClientEntry<P, B>>;
+
+/// Upstream clients sending the PROXY protocol header. Clients are keyed by the source address of the
+/// downstream connection, port included, so pooling is per downstream connection rather than per client host:
+/// pooled upstream connections, which carry that address, are never reused by other connections.
+/// At most `max_clients` sets of clients are kept, as many as the downstream connections served at once,
+/// evicting the least recently used one, and idle ones are swept periodically in background.
+pub(super) struct ProxyProtocolClients<P, B> {
+  runtime_handle: tokio::runtime::Handle,
+  http: HttpConnector,
+  wrap: WrapConnector<P>,
+  clients: Arc<Mutex<ClientMap<P, B>>>,
+}
+
+impl<P, B> ProxyProtocolClients<P, B>
+where
+  P: Connect + Clone + Send + Sync + 'static,
+  B: Body + Send + Unpin + 'static,
+  <B as Body>::Data: Send,
+  <B as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync + 'static>>,
+{
+  /// Build an empty set of clients dialing with `http`, keeping clients for at most `max_clients` downstream
+  /// connections. The background sweep of idle clients ends when this is dropped.
+  pub(super) fn new(
+    runtime_handle: tokio::runtime::Handle,
+    http: HttpConnector,
+    wrap: WrapConnector<P>,
+    max_clients: usize,
+  ) -> Self {
+    let capacity = NonZeroUsize::new(max_clients).unwrap_or(NonZeroUsize::MIN);
+    let clients = Arc::new(Mutex::new(LruCache::new(capacity)));
+    let weak_clients = Arc::downgrade(&clients);
+    runtime_handle.spawn(async move {
+      let mut interval = tokio::time::interval(Duration::from_secs(UPSTREAM_CLIENT_SWEEP_INTERVAL_SEC));
+      interval.tick().await;
+      loop {
+        interval.tick().await;
+        let Some(clients) = weak_clients.upgrade() else {
+          break;
+        };
+        sweep_idle_clients(&clients, Instant::now());
+      }
+    });
+    Self {
+      runtime_handle,
+      http,
+      wrap,
+      clients,
+    }
+  }
+
+  /// Get the clients, the default one and the http/2-only one, dedicated to the client of the request.
+  pub(super) fn get(
+    &self,
+    send_proxy_protocol: &SendProxyProtocol,
+    upstream_tls: Option<&Arc<UpstreamTlsConfig>>,
+  ) -> RpxyResult<(Client<P, B>, Client<P, B>)> {
+    let key = ClientKey {
+      version: send_proxy_protocol.version,
+      src: send_proxy_protocol.src,
+      dst: send_proxy_protocol.dst,
+      upstream_tls: upstream_tls.cloned(),
+    };
+    let now = Instant::now();
+    // The lock only guards the map itself, so a poisoned lock still holds consistent entries.
+    let mut clients = self.clients.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(entry) = clients.get_mut(&key) {
+      entry.last_used = now;
+      return Ok((entry.inner.clone(), entry.inner_h2.clone()));
+    }
+
+    let header = encode_proxy_header(key.version, key.src, key.dst)
+      .map_err(|e| RpxyError::FailedToFetchFromUpstream(format!("Failed to encode PROXY protocol header: {e}")))?;
+    let connector = ProxyProtocolConnector::new(self.http.clone(), header);
+    let upstream_tls = key.upstream_tls.as_deref();
+    let (Some(connector_h1), Some(connector_h2)) = (
+      (self.wrap)(connector.clone(), upstream_tls, false),
+      (self.wrap)(connector, upstream_tls, true),
+    ) else {
+      return Err(RpxyError::FailedToFetchFromUpstream(
+        "No client built for the upstream TLS settings".to_string(),
+      ));
+    };
+    let inner = Client::builder(LocalExecutor::new(self.runtime_handle.clone())).build::<_, B>(connector_h1);
+    let inner_h2 = Client::builder(LocalExecutor::new(self.runtime_handle.clone()))
+      .http2_only(true)
+      .build::<_, B>(connector_h2);
+    trace!(
+      "New upstream clients sending PROXY protocol for {} ({} clients)",
+      key.src,
+      clients.len() + 1
+    );
+    // The least recently used clients are evicted once full. In-flight requests keep their own clone.
+    clients.push(
+      key,
+      ClientEntry {
+        inner: inner.clone(),
+        inner_h2: inner_h2.clone(),
+        last_used: now,
+      },
+    );
+    Ok((inner, inner_h2))
+  }
+}
+
+/// Drop clients idle for long, closing their pooled connections. In-flight requests keep their own clone.
+fn sweep_idle_clients<P, B>(clients: &Mutex<ClientMap<P, B>>, now: Instant) {
+  let idle_timeout = Duration::from_secs(UPSTREAM_CLIENT_IDLE_TIMEOUT_SEC);
+  let mut clients = clients.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  while clients
+    .peek_lru()
+    .is_some_and(|(_, entry)| now.duration_since(entry.last_used) >= idle_timeout)
+  {
+    clients.pop_lru();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::Empty;
+  use hyper::body::Bytes;
+
+  fn send_proxy_protocol(src: &str) -> SendProxyProtocol {
+    SendProxyProtocol {
+      version: ProxyProtocolVersion::V2,
+      src: src.parse().unwrap(),
+      dst: "198.51.100.1:443".parse().unwrap(),
+    }
+  }
+
+  fn proxy_protocol_clients(max_clients: usize) -> ProxyProtocolClients<ProxyProtocolConnector, Empty<Bytes>> {
+    ProxyProtocolClients::new(
+      tokio::runtime::Handle::current(),
+      HttpConnector::new(),
+      Box::new(|connector, upstream_tls, _| upstream_tls.is_none().then_some(connector)),
+      max_clients,
+    )
+  }
+
+  #[tokio::test]
+  async fn clients_are_dedicated_to_each_client() {
+    let clients = proxy_protocol_clients(16);
+    clients.get(&send_proxy_protocol("192.0.2.1:50000"), None).unwrap();
+    clients.get(&send_proxy_protocol("192.0.2.1:50000"), None).unwrap();
+    assert_eq!(clients.clients.lock().unwrap().len(), 1);
+    clients.get(&send_proxy_protocol("192.0.2.1:50001"), None).unwrap();
+    assert_eq!(clients.clients.lock().unwrap().len(), 2);
+
+    // Settings the wrapper does not know are rejected.
+    let upstream_tls = Arc::new(UpstreamTlsConfig::default());
+    assert!(
+      clients
+        .get(&send_proxy_protocol("192.0.2.1:50002"), Some(&upstream_tls))
+        .is_err()
+    );
+  }
+
+  #[tokio::test]
+  async fn clients_are_bounded_and_swept() {
+    let clients = proxy_protocol_clients(2);
+    clients.get(&send_proxy_protocol("192.0.2.1:50000"), None).unwrap();
+    clients.get(&send_proxy_protocol("192.0.2.1:50001"), None).unwrap();
+    clients.get(&send_proxy_protocol("192.0.2.1:50000"), None).unwrap();
+    // The least recently used one is evicted.
+    clients.get(&send_proxy_protocol("192.0.2.1:50002"), None).unwrap();
+    {
+      let map = clients.clients.lock().unwrap();
+      assert_eq!(map.len(), 2);
+      assert!(map.iter().all(|(key, _)| key.src.port() != 50001));
+    }
+
+    sweep_idle_clients(&clients.clients, Instant::now());
+    assert_eq!(clients.clients.lock().unwrap().len(), 2);
+    let later = Instant::now() + Duration::from_secs(UPSTREAM_CLIENT_IDLE_TIMEOUT_SEC);
+    sweep_idle_clients(&clients.clients, later);
+    assert!(clients.clients.lock().unwrap().is_empty());
+  }
+}
@@ -0,0 +1,253 @@
+use crate::globals::UpstreamTlsConfig;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+/// TLS settings of the upstream group chosen for a request, carried in request extensions from the
+/// handler to the forwarder so that the request is sent over the connections built for them.
+pub(crate) struct UpstreamTls(pub(crate) Arc<UpstreamTlsConfig>);
+
+pub(crate) use rustls_client::UpstreamTlsConnectorBuilder;
+#[cfg(any(feature = "health-check", test))]
+pub(crate) use rustls_client::build_https_connector;
+
+mod rustls_client {
+  use crate::{
+    error::{RpxyError, RpxyResult},
+    globals::{UpstreamTlsConfig, UpstreamTlsVersion},
+    log::*,
+  };
+  use hyper_rustls::{ConfigBuilderExt, FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+  #[cfg(any(feature = "health-check", test))]
+  use hyper_util::client::legacy::connect::HttpConnector;
+  use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+  };
+  use std::{path::Path, sync::Arc};
+
+  #[derive(Clone)]
+  /// Builder of https connectors applying upstream TLS settings. The settings, e.g., the CA bundle and
+  /// the client certificate, are read once and shared by every connector built from it.
+  pub(crate) struct UpstreamTlsConnectorBuilder {
+    tls_config: ClientConfig,
+    server_name: Option<ServerName<'static>>,
+  }
+
+  impl UpstreamTlsConnectorBuilder {
+    /// Read and validate the given upstream TLS settings
+    pub(crate) fn try_new(config: &UpstreamTlsConfig) -> RpxyResult<Self> {
+      let server_name = config
+        .sni
+        .as_ref()
+        .map(|sni| {
+          ServerName::try_from(sni.clone()).map_err(|e| RpxyError::InvalidUpstreamTlsConfig(format!("Invalid SNI {sni}: {e}")))
+        })
+        .transpose()?;
+      Ok(Self {
+        tls_config: build_client_config(config)?,
+        server_name,
+      })
+    }
+
+    /// Build an https connector over the given connector.
+    /// ALPN offers `h2` and `http/1.1`, or only `h2` if `http2_only` is set.
+    pub(crate) fn build<H>(&self, http: H, http2_only: bool) -> HttpsConnector<H> {
+      let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(self.tls_config.clone())
+        .https_or_http();
+      let builder = match &self.server_name {
+        Some(server_name) => builder.with_server_name_resolver(FixedServerNameResolver::new(server_name.clone())),
+        None => builder,
+      };
+      if http2_only {
+        builder.enable_http2().wrap_connector(http)
+      } else {
+        builder.enable_all_versions().wrap_connector(http)
+      }
+    }
+  }
+
+  #[cfg(any(feature = "health-check", test))]
+  /// Build an https connector applying the given upstream TLS settings.
+  /// ALPN offers `h2` and `http/1.1`, or only `h2` if `http2_only` is set.
+  pub(crate) fn build_https_connector(
+    config: &UpstreamTlsConfig,
+    http: HttpConnector,
+    http2_only: bool,
+  ) -> RpxyResult<HttpsConnector<HttpConnector>> {
+    Ok(UpstreamTlsConnectorBuilder::try_new(config)?.build(http, http2_only))
+  }
+
+  /// Build the rustls client config: protocol versions, server certificate verification and the
+  /// client certificate.
+  fn build_client_config(config: &UpstreamTlsConfig) -> RpxyResult<ClientConfig> {
+    let provider = CryptoProvider::get_default()
+      .cloned()
+      .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+    let versions: &[&'static rustls::SupportedProtocolVersion] = match config.min_version {
+      UpstreamTlsVersion::Tls12 => rustls::ALL_VERSIONS,
+      UpstreamTlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+      .with_protocol_versions(versions)
+      .map_err(|e| RpxyError::InvalidUpstreamTlsConfig(e.to_string()))?;
+
+    let builder = if config.insecure_skip_verify {
+      warn!("Upstream server certificates are NOT verified (insecure_skip_verify). Do not use this in production.");
+      builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(InsecureSkipVerify(provider)))
+    } else if let Some(ca_cert_path) = &config.ca_cert_path {
+      let mut roots = RootCertStore::empty();
+      for cert in read_certs(ca_cert_path)? {
+        roots.add(cert).map_err(|e| {
+          RpxyError::InvalidUpstreamTlsConfig(format!("Invalid CA certificate in {}: {e}", ca_cert_path.display()))
+        })?;
+      }
+      builder.with_root_certificates(roots)
+    } else {
+      #[cfg(feature = "webpki-roots")]
+      let builder = builder.with_webpki_roots();
+      #[cfg(not(feature = "webpki-roots"))]
+      let builder = builder
+        .try_with_platform_verifier()
+        .map_err(|e| RpxyError::InvalidUpstreamTlsConfig(e.to_string()))?;
+      builder
+    };
+
+    match (&config.client_cert_path, &config.client_key_path) {
+      (Some(cert_path), Some(key_path)) => {
+        let certs = read_certs(cert_path)?;
+        let key = PrivateKeyDer::from_pem_file(key_path)
+          .map_err(|e| RpxyError::InvalidUpstreamTlsConfig(format!("Failed to read client key {}: {e}", key_path.display())))?;
+        builder
+          .with_client_auth_cert(certs, key)
+          .map_err(|e| RpxyError::InvalidUpstreamTlsConfig(format!("Invalid client certificate: {e}")))
+      }
+      (None, None) => Ok(builder.with_no_client_auth()),
+      _ => Err(RpxyError::InvalidUpstreamTlsConfig(
+        "Both client certificate and key must be given".to_string(),
+      )),
+    }
+  }
+
+  /// Read all certificates in a PEM file, failing if there is none.
+  fn read_certs(path: &Path) -> RpxyResult<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+      .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+      .map_err(|e| RpxyError::InvalidUpstreamTlsConfig(format!("Failed to read certificates {}: {e}", path.display())))?;
+    if certs.is_empty() {
+      return Err(RpxyError::InvalidUpstreamTlsConfig(format!(
+        "No certificate found in {}",
+        path.display()
+      )));
+    }
+    Ok(certs)
+  }
+
+  #[derive(Debug)]
+  /// Server certificate verifier accepting any certificate. Handshake signatures are still checked,
+  /// so the peer must hold the key of the certificate it presents.
+  struct InsecureSkipVerify(Arc<CryptoProvider>);
+
+  impl ServerCertVerifier for InsecureSkipVerify {
+    fn verify_server_cert(
+      &self,
+      _end_entity: &CertificateDer<'_>,
+      _intermediates: &[CertificateDer<'_>],
+      _server_name: &ServerName<'_>,
+      _ocsp_response: &[u8],
+      _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+      Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+      &self,
+      message: &[u8],
+      cert: &CertificateDer<'_>,
+      dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+      verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+      &self,
+      message: &[u8],
+      cert: &CertificateDer<'_>,
+      dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+      verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+      self.0.signature_verification_algorithms.supported_schemes()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::globals::UpstreamTlsVersion;
+  use hyper_util::client::legacy::connect::HttpConnector;
+
+  fn connector(config: &UpstreamTlsConfig) -> crate::error::RpxyResult<()> {
+    build_https_connector(config, HttpConnector::new(), false).map(|_| ())
+  }
+
+  #[test]
+  fn build_connector_with_valid_settings() {
+    assert!(connector(&UpstreamTlsConfig::default()).is_ok());
+    assert!(
+      connector(&UpstreamTlsConfig {
+        sni: Some("backend.internal".to_string()),
+        min_version: UpstreamTlsVersion::Tls13,
+        insecure_skip_verify: true,
+        ..Default::default()
+      })
+      .is_ok()
+    );
+  }
+
+  #[test]
+  fn build_connector_rejects_invalid_settings() {
+    let missing = std::env::temp_dir().join(format!("rpxy-upstream-tls-missing-{}.pem", std::process::id()));
+    assert!(
+      connector(&UpstreamTlsConfig {
+        ca_cert_path: Some(missing.clone()),
+        ..Default::default()
+      })
+      .is_err()
+    );
+    // A client certificate needs its key.
+    assert!(
+      connector(&UpstreamTlsConfig {
+        client_cert_path: Some(missing),
+        ..Default::default()
+      })
+      .is_err()
+    );
+    assert!(
+      connector(&UpstreamTlsConfig {
+        sni: Some("bad name".to_string()),
+        ..Default::default()
+      })
+      .is_err()
+    );
+  }
+
+  #[test]
+  fn ca_bundle_without_certificate_is_rejected() {
+    let path = std::env::temp_dir().join(format!("rpxy-upstream-tls-empty-{}.pem", std::process::id()));
+    std::fs::write(&path, "no certificate here\n").unwrap();
+    let res = connector(&UpstreamTlsConfig {
+      ca_cert_path: Some(path.clone()),
+      ..Default::default()
+    });
+    assert!(res.is_err());
+    let _ = std::fs::remove_file(&path);
+  }
+}
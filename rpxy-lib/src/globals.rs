@@ -5,7 +5,7 @@ use crate::{
 use hot_reload::ReloaderReceiver;
 use ipnet::IpNet;
 use rpxy_certs::ServerCryptoBase;
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 #[cfg(feature = "sticky-cookie")]
 use aes_gcm::Aes256Gcm;
@@ -21,6 +21,16 @@ pub struct TcpRecvProxyProtocolConfig {
   pub timeout: Duration,
 }
 
+#[cfg(feature = "proxy-protocol")]
+/// PROXY protocol version sent to upstreams
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum ProxyProtocolVersion {
+  /// Human-readable header, e.g., `PROXY TCP4 192.0.2.1 198.51.100.1 51234 443\r\n`
+  V1,
+  /// Binary header
+  V2,
+}
+
 /// Global object containing proxy configurations and shared object like counters.
 /// The only lock-bearing shared state is the per-IP connection counter, which is touched
 /// solely on connection open/close (a cold path), not on the per-request path.
@@ -43,6 +53,9 @@ pub struct Globals {
   pub(crate) access_log_enabled: bool,
   #[cfg(feature = "sticky-cookie")]
   pub(crate) sticky_cookie_cipher: Option<std::sync::Arc<Aes256Gcm>>,
+  /// Storage of the cache given by the embedder in place of the memory and file tiers
+  #[cfg(feature = "cache")]
+  pub(crate) cache_storage: Option<std::sync::Arc<dyn crate::forwarder::CacheStorage>>,
 
   #[cfg(feature = "acme")]
   /// ServerConfig used for only ACME challenge for ACME domains
@@ -106,6 +119,9 @@ pub struct ProxyConfig {
   /// Default after config load is `Some(DEFAULTS::REQUEST_MAX_BODY_SIZE)`.
   pub request_max_body_size: Option<usize>,
 
+  /// Local admin API for runtime inspection. None means the admin API is disabled.
+  pub admin: Option<AdminConfig>,
+
   #[cfg(feature = "cache")]
   pub cache_enabled: bool,
   #[cfg(feature = "cache")]
@@ -116,6 +132,20 @@ pub struct ProxyConfig {
   pub cache_max_each_size: usize,
   #[cfg(feature = "cache")]
   pub cache_max_each_size_on_memory: usize,
+  #[cfg(feature = "cache")]
+  pub cache_max_memory_size: u64,
+  #[cfg(feature = "cache")]
+  pub cache_max_disk_size: u64,
+  #[cfg(feature = "cache")]
+  pub cache_max_variants: usize,
+  #[cfg(feature = "cache")]
+  pub cache_stale_if_error_grace: Duration,
+  #[cfg(feature = "cache")]
+  pub cache_coalescing_timeout: Duration,
+  #[cfg(feature = "cache")]
+  pub cache_status_header: bool,
+  #[cfg(feature = "cache")]
+  pub cache_status_key: bool,
 
   // All need to make packet acceptor
   #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
@@ -167,6 +197,8 @@ impl Default for ProxyConfig {
       connection_handling_timeout: None,
       request_max_body_size: Some(DEFAULTS::REQUEST_MAX_BODY_SIZE),
 
+      admin: None,
+
       #[cfg(feature = "proxy-protocol")]
       tcp_recv_proxy_protocol: None,
 
@@ -180,6 +212,20 @@ impl Default for ProxyConfig {
       cache_max_each_size: MAX_CACHE_EACH_SIZE,
       #[cfg(feature = "cache")]
       cache_max_each_size_on_memory: MAX_CACHE_EACH_SIZE_ON_MEMORY,
+      #[cfg(feature = "cache")]
+      cache_max_memory_size: MAX_CACHE_MEMORY_SIZE,
+      #[cfg(feature = "cache")]
+      cache_max_disk_size: MAX_CACHE_DISK_SIZE,
+      #[cfg(feature = "cache")]
+      cache_max_variants: MAX_CACHE_VARIANTS,
+      #[cfg(feature = "cache")]
+      cache_stale_if_error_grace: Duration::from_secs(CACHE_STALE_IF_ERROR_GRACE_SEC),
+      #[cfg(feature = "cache")]
+      cache_coalescing_timeout: Duration::from_secs(CACHE_COALESCING_TIMEOUT_SEC),
+      #[cfg(feature = "cache")]
+      cache_status_header: false,
+      #[cfg(feature = "cache")]
+      cache_status_key: false,
 
       #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
       http3: false,
@@ -199,11 +245,32 @@ impl Default for ProxyConfig {
   }
 }
 
+/// Configuration parameters for the local admin API
+#[derive(PartialEq, Eq, Clone)]
+pub struct AdminConfig {
+  /// Socket the admin API listens on, separated from the proxy listeners
+  pub listen: AdminListen,
+  /// Bearer token required in the `Authorization` header of every admin request
+  pub token: String,
+}
+
+/// Listen socket of the admin API
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum AdminListen {
+  /// TCP socket address, expected to be a loopback address
+  Tcp(SocketAddr),
+  #[cfg(unix)]
+  /// Path of a Unix domain socket, created with permissions only for the owner
+  Unix(PathBuf),
+}
+
 /// Configuration parameters for backend applications
 #[derive(PartialEq, Eq, Clone)]
 pub struct AppConfigList {
   pub inner: Vec<AppConfig>,
   pub default_app: Option<String>,
+  /// TCP and UDP stream proxies served alongside the applications
+  pub streams: Vec<StreamConfig>,
 }
 
 /// Configuration parameters for single backend application
@@ -222,9 +289,106 @@ pub struct ReverseProxyConfig {
   pub replace_path: Option<String>,
   pub upstream: Vec<UpstreamUri>,
   pub upstream_options: Option<Vec<String>>,
+  /// Origins allowed to send gRPC-Web requests to the route from browsers, or `*` for any origin without credentials.
+  /// None allows no cross-origin requests.
+  pub grpc_web_allowed_origins: Option<Vec<String>>,
   pub load_balance: Option<String>,
   #[cfg(feature = "health-check")]
   pub health_check: Option<HealthCheckConfig>,
+  /// Periodic re-resolution of upstream host names. None means upstreams are static.
+  pub resolve: Option<UpstreamResolveConfig>,
+  /// JSON file listing the upstreams, watched and applied on change. `upstream` is used until the
+  /// file is read successfully.
+  pub upstream_file: Option<PathBuf>,
+  /// TLS settings for connections to the upstreams. None means the default client of the forwarder.
+  pub upstream_tls: Option<UpstreamTlsConfig>,
+  #[cfg(feature = "proxy-protocol")]
+  /// PROXY protocol header prefixed to each new upstream connection. Connections are then not shared across clients.
+  pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+  #[cfg(feature = "cache")]
+  /// Cache rule of the route. None means responses are cached as per the global cache settings only.
+  pub cache: Option<CacheRuleConfig>,
+}
+
+/// Configuration parameters for a TCP or UDP stream proxy listening on its own socket
+#[derive(PartialEq, Eq, Clone)]
+pub struct StreamConfig {
+  /// Name of the stream, used in logs
+  pub name: String,
+  /// Listen socket address
+  pub listen: SocketAddr,
+  pub protocol: StreamProtocol,
+  pub upstream: Vec<UpstreamUri>,
+  pub load_balance: Option<String>,
+  #[cfg(feature = "health-check")]
+  pub health_check: Option<HealthCheckConfig>,
+  /// Connections (TCP) or sessions of a client address (UDP) are closed after no data is relayed for this duration
+  pub idle_timeout: Duration,
+  #[cfg(feature = "proxy-protocol")]
+  /// Inbound PROXY protocol receive configuration for the stream, only for TCP
+  pub recv_proxy_protocol: Option<std::sync::Arc<TcpRecvProxyProtocolConfig>>,
+  /// Server name of the certificate from the certificate reloader to terminate TLS with, only for TCP.
+  /// None means the stream is relayed as it is.
+  pub tls_server_name: Option<String>,
+}
+
+/// Transport protocol of a stream proxy
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum StreamProtocol {
+  Tcp,
+  Udp,
+}
+
+/// Upstream re-resolution configuration (internal, converted from TOML)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct UpstreamResolveConfig {
+  /// Interval between two resolutions of the upstream host names
+  pub interval: Duration,
+}
+
+/// TLS configuration for connections to upstreams (internal, converted from TOML)
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct UpstreamTlsConfig {
+  /// PEM bundle of CA certificates verifying upstream server certificates, in place of the default roots
+  pub ca_cert_path: Option<PathBuf>,
+  /// PEM certificate chain presented to upstreams as the client certificate
+  pub client_cert_path: Option<PathBuf>,
+  /// PEM private key of the client certificate
+  pub client_key_path: Option<PathBuf>,
+  /// Server name sent in SNI and verified against upstream certificates, in place of the upstream host
+  pub sni: Option<String>,
+  /// Minimum TLS version accepted from upstreams
+  pub min_version: UpstreamTlsVersion,
+  /// Skip verification of upstream server certificates. Only for lab environments.
+  pub insecure_skip_verify: bool,
+}
+
+#[cfg(feature = "cache")]
+/// Cache rule of a route, applied on top of the global cache settings (internal, converted from TOML)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CacheRuleConfig {
+  /// Whether responses of the route are cached. If false, requests are neither looked up nor stored.
+  pub enabled: bool,
+  /// Freshness lifetime of cacheable responses without explicit one, i.e., `max-age`, `s-maxage` or `Expires`
+  pub default_ttl: Option<Duration>,
+  /// Upper bound of the freshness lifetime of cached responses
+  pub max_ttl: Option<Duration>,
+  /// Names of the query parameters removed from the cache key, where a trailing `*` matches any suffix
+  pub ignore_query_params: Vec<String>,
+  /// Whether the query parameters in the cache key are sorted by name
+  pub sort_query: bool,
+  /// Lowercase names of the request headers keying the cache, as if listed in `Vary` of the responses
+  pub key_headers: Vec<String>,
+  /// Names of the cookies with which requests bypass the cache
+  pub bypass_cookies: Vec<String>,
+}
+
+/// Minimum TLS version for connections to upstreams
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub enum UpstreamTlsVersion {
+  #[default]
+  Tls12,
+  Tls13,
 }
 
 #[cfg(feature = "health-check")]
@@ -252,11 +416,61 @@ pub struct UpstreamUri {
   pub inner: http::Uri,
 }
 
+impl UpstreamUri {
+  /// Build an upstream destination from a `location` given in the config or an upstream file,
+  /// i.e., `host:port` reached over http(s), or `unix:<absolute path>` of a Unix domain socket.
+  pub fn from_location(location: &str, tls: bool) -> crate::error::RpxyResult<Self> {
+    if let Some(socket_path) = location.strip_prefix(UNIX_SOCKET_LOCATION_PREFIX) {
+      if tls {
+        return Err(crate::error::RpxyError::InvalidUpstreamUri(format!(
+          "TLS is not supported for Unix domain socket upstream: {location}"
+        )));
+      }
+      #[cfg(unix)]
+      return Self::from_unix_socket_path(socket_path);
+      #[cfg(not(unix))]
+      return Err(crate::error::RpxyError::InvalidUpstreamUri(format!(
+        "Unix domain socket upstream is not supported on this platform: {socket_path}"
+      )));
+    }
+    let scheme = if tls { "https" } else { "http" };
+    let inner = format!("{scheme}://{location}")
+      .parse::<http::Uri>()
+      .map_err(|e| crate::error::RpxyError::InvalidUpstreamUri(format!("{location}: {e}")))?;
+    Ok(Self { inner })
+  }
+
+  #[cfg(unix)]
+  /// Build an upstream destination reached over the Unix domain socket at `path`.
+  /// The path is carried in the uri authority, see [`crate::hyper_ext::unix`].
+  pub fn from_unix_socket_path(path: impl AsRef<std::path::Path>) -> crate::error::RpxyResult<Self> {
+    let path = path.as_ref();
+    if !path.is_absolute() {
+      return Err(crate::error::RpxyError::InvalidUpstreamUri(format!(
+        "Unix domain socket path must be absolute: {}",
+        path.display()
+      )));
+    }
+    let inner = crate::hyper_ext::unix::unix_socket_uri(path)
+      .map_err(|e| crate::error::RpxyError::InvalidUpstreamUri(format!("{}: {e}", path.display())))?;
+    Ok(Self { inner })
+  }
+
+  /// Whether the upstream is reached over a Unix domain socket
+  pub fn is_unix_socket(&self) -> bool {
+    crate::hyper_ext::unix::is_unix_socket_uri(&self.inner)
+  }
+}
+
 /// Configuration parameters on TLS for a single backend application
 #[derive(PartialEq, Eq, Clone)]
 pub struct TlsConfig {
   pub mutual_tls: bool,
+  /// Cipher suites or key exchange groups are restricted for the app, which is then not served over HTTP/3
+  pub restricted_crypto: bool,
   pub https_redirection: bool,
+  /// TLS is not terminated by rpxy: connections with the SNI of the app are piped to the upstream as they are
+  pub passthrough: bool,
   #[cfg(feature = "acme")]
   pub acme: bool,
 }
@@ -0,0 +1,264 @@
+use crate::error::RpxyError;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use bytes::{BufMut, Bytes, BytesMut};
+use http::HeaderMap;
+use hyper::body::{Body, Frame};
+use std::{
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+/// Flag of the gRPC-Web frame carrying trailers, in place of the compression flag of message frames
+const GRPC_WEB_TRAILER_FLAG: u8 = 0x80;
+
+/// Encode trailers into a gRPC-Web trailer frame, i.e., the flag, the 4-byte length and the `name:value\r\n` lines.
+pub(crate) fn encode_grpc_web_trailers(trailers: &HeaderMap) -> Bytes {
+  let mut block = BytesMut::new();
+  for (name, value) in trailers {
+    block.put_slice(name.as_str().as_bytes());
+    block.put_u8(b':');
+    block.put_slice(value.as_bytes());
+    block.put_slice(b"\r\n");
+  }
+  let mut frame = BytesMut::with_capacity(5 + block.len());
+  frame.put_u8(GRPC_WEB_TRAILER_FLAG);
+  frame.put_u32(block.len() as u32);
+  frame.put_slice(&block);
+  frame.freeze()
+}
+
+/* ------------------------------------ */
+/// Request body of `application/grpc-web-text` decoded from base64 into the native gRPC body.
+/// Chunks are not aligned to base64 quanta, and clients may concatenate separately padded segments.
+pub struct GrpcWebTextDecoder<B> {
+  inner: B,
+  /// Base64 characters received but not decoded yet, i.e., less than a quantum
+  pending: BytesMut,
+}
+
+impl<B> GrpcWebTextDecoder<B> {
+  pub fn new(inner: B) -> Self {
+    Self {
+      inner,
+      pending: BytesMut::new(),
+    }
+  }
+
+  /// Decode all complete quanta received so far
+  fn decode(&mut self, data: &[u8]) -> Result<Bytes, RpxyError> {
+    self.pending.extend(data.iter().filter(|c| !c.is_ascii_whitespace()).copied());
+    let quanta = self.pending.split_to(self.pending.len() / 4 * 4);
+    let mut decoded = Vec::with_capacity(quanta.len() / 4 * 3);
+    let mut rest = quanta.as_ref();
+    while !rest.is_empty() {
+      // A padded quantum ends a segment, which is decoded separately from the following ones.
+      let end = rest
+        .chunks_exact(4)
+        .position(|quantum| quantum.contains(&b'='))
+        .map_or(rest.len(), |i| (i + 1) * 4);
+      let (segment, tail) = rest.split_at(end);
+      STANDARD
+        .decode_vec(segment, &mut decoded)
+        .map_err(|e| RpxyError::InvalidGrpcWebBody(e.to_string()))?;
+      rest = tail;
+    }
+    Ok(decoded.into())
+  }
+}
+
+impl<B> Body for GrpcWebTextDecoder<B>
+where
+  B: Body<Data = Bytes, Error = RpxyError> + Unpin,
+{
+  type Data = Bytes;
+  type Error = RpxyError;
+
+  fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    loop {
+      match Pin::new(&mut this.inner).poll_frame(cx) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+        Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+          Ok(data) => {
+            let decoded = this.decode(&data)?;
+            if !decoded.is_empty() {
+              return Poll::Ready(Some(Ok(Frame::data(decoded))));
+            }
+          }
+          Err(frame) => return Poll::Ready(Some(Ok(frame))),
+        },
+        Poll::Ready(None) if this.pending.is_empty() => return Poll::Ready(None),
+        Poll::Ready(None) => {
+          return Poll::Ready(Some(Err(RpxyError::InvalidGrpcWebBody(
+            "Truncated base64 in grpc-web-text body".to_string(),
+          ))));
+        }
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.pending.is_empty() && self.inner.is_end_stream()
+  }
+}
+
+/* ------------------------------------ */
+/// Response body of native gRPC encoded for gRPC-Web: trailers become the trailer frame at the end of the body, and the
+/// whole body is encoded with base64 for `application/grpc-web-text`.
+pub struct GrpcWebEncoder<B> {
+  inner: B,
+  text: bool,
+  /// Bytes not encoded yet for `-text`, i.e., less than 3 bytes, so that only the end of the body is padded
+  pending: BytesMut,
+  finished: bool,
+}
+
+impl<B> GrpcWebEncoder<B> {
+  pub fn new(inner: B, text: bool) -> Self {
+    Self {
+      inner,
+      text,
+      pending: BytesMut::new(),
+      finished: false,
+    }
+  }
+
+  /// Encode the given bytes for the body, flushing the remainder with padding if `last` is set.
+  fn encode(&mut self, data: &[u8], last: bool) -> Bytes {
+    if !self.text {
+      return Bytes::copy_from_slice(data);
+    }
+    self.pending.extend_from_slice(data);
+    let len = if last {
+      self.pending.len()
+    } else {
+      self.pending.len() / 3 * 3
+    };
+    STANDARD.encode(self.pending.split_to(len)).into()
+  }
+}
+
+impl<B> Body for GrpcWebEncoder<B>
+where
+  B: Body<Data = Bytes, Error = RpxyError> + Unpin,
+{
+  type Data = Bytes;
+  type Error = RpxyError;
+
+  fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    if this.finished {
+      return Poll::Ready(None);
+    }
+    loop {
+      match Pin::new(&mut this.inner).poll_frame(cx) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+        Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+          Ok(data) if !this.text => return Poll::Ready(Some(Ok(Frame::data(data)))),
+          Ok(data) => {
+            let encoded = this.encode(&data, false);
+            if !encoded.is_empty() {
+              return Poll::Ready(Some(Ok(Frame::data(encoded))));
+            }
+          }
+          Err(frame) => {
+            let Ok(trailers) = frame.into_trailers() else {
+              continue;
+            };
+            this.finished = true;
+            let encoded = this.encode(&encode_grpc_web_trailers(&trailers), true);
+            return Poll::Ready(Some(Ok(Frame::data(encoded))));
+          }
+        },
+        Poll::Ready(None) => {
+          this.finished = true;
+          if this.pending.is_empty() {
+            return Poll::Ready(None);
+          }
+          let encoded = this.encode(&[], true);
+          return Poll::Ready(Some(Ok(Frame::data(encoded))));
+        }
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.finished
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http::HeaderValue;
+  use http_body_util::{BodyExt, StreamBody};
+
+  fn body(frames: Vec<Frame<Bytes>>) -> impl Body<Data = Bytes, Error = RpxyError> + Unpin {
+    StreamBody::new(futures::stream::iter(frames.into_iter().map(Ok::<_, RpxyError>)))
+  }
+
+  fn trailers() -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+    trailers.insert("grpc-message", HeaderValue::from_static("OK"));
+    trailers
+  }
+
+  #[test]
+  fn trailer_frame_layout() {
+    let frame = encode_grpc_web_trailers(&trailers());
+    let block = b"grpc-status:0\r\ngrpc-message:OK\r\n";
+    assert_eq!(frame[0], GRPC_WEB_TRAILER_FLAG);
+    assert_eq!(u32::from_be_bytes(frame[1..5].try_into().unwrap()) as usize, block.len());
+    assert_eq!(&frame[5..], block);
+  }
+
+  #[tokio::test]
+  async fn text_request_body_is_decoded_across_chunks_and_segments() {
+    let message = b"\x00\x00\x00\x00\x03abc";
+    let encoded = STANDARD.encode(message);
+    // Unaligned chunks of a segment followed by another padded segment
+    let frames = vec![
+      Frame::data(Bytes::copy_from_slice(&encoded.as_bytes()[..5])),
+      Frame::data(Bytes::copy_from_slice(&encoded.as_bytes()[5..])),
+      Frame::data(Bytes::from(STANDARD.encode(b"x"))),
+    ];
+    let decoded = GrpcWebTextDecoder::new(body(frames)).collect().await.unwrap().to_bytes();
+    assert_eq!(&decoded[..message.len()], message);
+    assert_eq!(&decoded[message.len()..], b"x");
+
+    let truncated = vec![Frame::data(Bytes::from_static(b"AAA"))];
+    assert!(GrpcWebTextDecoder::new(body(truncated)).collect().await.is_err());
+    let invalid = vec![Frame::data(Bytes::from_static(b"A*AA"))];
+    assert!(GrpcWebTextDecoder::new(body(invalid)).collect().await.is_err());
+  }
+
+  #[tokio::test]
+  async fn response_trailers_are_moved_into_body() {
+    let frames = vec![
+      Frame::data(Bytes::from_static(b"\x00\x00\x00\x00\x01z")),
+      Frame::trailers(trailers()),
+    ];
+    let collected = GrpcWebEncoder::new(body(frames), false).collect().await.unwrap();
+    assert!(collected.trailers().is_none());
+    let bytes = collected.to_bytes();
+    assert_eq!(&bytes[..6], b"\x00\x00\x00\x00\x01z");
+    assert_eq!(&bytes[6..], &encode_grpc_web_trailers(&trailers())[..]);
+  }
+
+  #[tokio::test]
+  async fn text_response_is_encoded_with_padding_only_at_the_end() {
+    let message = b"\x00\x00\x00\x00\x01z".to_vec();
+    let frames = vec![
+      Frame::data(Bytes::copy_from_slice(&message[..4])),
+      Frame::data(Bytes::copy_from_slice(&message[4..])),
+      Frame::trailers(trailers()),
+    ];
+    let encoded = GrpcWebEncoder::new(body(frames), true).collect().await.unwrap().to_bytes();
+    let mut expected = message;
+    expected.extend_from_slice(&encode_grpc_web_trailers(&trailers()));
+    assert_eq!(encoded, STANDARD.encode(expected).as_bytes());
+  }
+}
@@ -1,4 +1,4 @@
-use super::body::IncomingLike;
+use super::body::{GrpcWebEncoder, GrpcWebTextDecoder, IncomingLike};
 use crate::{error::RpxyError, log::*};
 use futures::channel::mpsc::Receiver;
 use http_body_util::{BodyExt, Empty, Full, StreamBody, combinators};
@@ -124,9 +124,11 @@ pub type LimitedIncoming = LimitedBody<Incoming>;
 ///   `Option<usize>` limit of `None` disables the check.
 /// - IncomingLike: a Incoming-like type in which channel is used (h3 path; the h3 body
 ///   forwarder enforces the limit before the channel is fed)
+/// - GrpcWebText: a body of `application/grpc-web-text` decoded into native gRPC
 pub enum RequestBody {
   Incoming(LimitedIncoming),
   IncomingLike(IncomingLike),
+  GrpcWebText(Box<GrpcWebTextDecoder<RequestBody>>),
 }
 
 impl Body for RequestBody {
@@ -140,6 +142,7 @@ impl Body for RequestBody {
     match self.get_mut() {
       RequestBody::Incoming(limited) => Pin::new(limited).poll_frame(cx),
       RequestBody::IncomingLike(incoming_like) => Pin::new(incoming_like).poll_frame(cx),
+      RequestBody::GrpcWebText(decoder) => Pin::new(decoder.as_mut()).poll_frame(cx),
     }
   }
 }
@@ -155,10 +158,12 @@ pub type BoundedStreamBody = StreamBody<Receiver<Result<Frame<bytes::Bytes>, hyp
 /// - Incoming: just a type that only forwards the upstream response body to downstream.
 /// - Boxed: a type that is generated from cache or synthetic response body, e.g.,, small byte object.
 /// - Streamed: another type that is generated from stream, e.g., large byte object.
+/// - GrpcWeb: a native gRPC response body encoded for gRPC-Web clients.
 pub enum ResponseBody {
   Incoming(Incoming),
   Boxed(BoxBody),
   Streamed(BoundedStreamBody),
+  GrpcWeb(Box<GrpcWebEncoder<ResponseBody>>),
 }
 
 impl Body for ResponseBody {
@@ -173,6 +178,7 @@ impl Body for ResponseBody {
       ResponseBody::Incoming(incoming) => Pin::new(incoming).poll_frame(cx),
       ResponseBody::Boxed(boxed) => Pin::new(boxed).poll_frame(cx),
       ResponseBody::Streamed(streamed) => Pin::new(streamed).poll_frame(cx),
+      ResponseBody::GrpcWeb(encoder) => return Pin::new(encoder.as_mut()).poll_frame(cx),
     }
     .map_err(RpxyError::HyperBodyError)
   }
@@ -1,7 +1,11 @@
+mod body_grpc_web;
 mod body_incoming_like;
 mod body_type;
 mod executor;
+#[cfg(feature = "proxy-protocol")]
+mod send_proxy_protocol;
 mod tokio_timer;
+mod unix_socket;
 mod watch;
 
 #[allow(unused)]
@@ -11,8 +15,19 @@ pub(crate) mod rt {
 }
 #[allow(unused)]
 pub(crate) mod body {
+  pub(crate) use super::body_grpc_web::{GrpcWebEncoder, GrpcWebTextDecoder};
   pub(crate) use super::body_incoming_like::IncomingLike;
   pub(crate) use super::body_type::{
     BoundedStreamBody, BoxBody, LimitedBody, LimitedIncoming, RequestBody, ResponseBody, empty, full,
   };
 }
+#[allow(unused)]
+pub(crate) mod unix {
+  pub(crate) use super::unix_socket::{UNIX_SCHEME, UNIX_SOCKET_HOST, is_unix_socket_uri};
+  #[cfg(unix)]
+  pub(crate) use super::unix_socket::{UnixConnector, unix_socket_path, unix_socket_uri};
+}
+#[cfg(feature = "proxy-protocol")]
+pub(crate) mod proxy_protocol {
+  pub(crate) use super::send_proxy_protocol::{ProxyProtocolConnector, encode_proxy_header};
+}
@@ -0,0 +1,143 @@
+//! PROXY protocol for connections to upstreams.
+//!
+//! The header is written right after the TCP connection is established, before TLS if any, so the
+//! connector sits under the TLS connector of the forwarder. A connection carries the address of one
+//! client for its whole lifetime, so clients built with this connector must never be shared across
+//! clients.
+
+use crate::globals::ProxyProtocolVersion;
+use hyper::Uri;
+use hyper_util::{client::legacy::connect::HttpConnector, rt::TokioIo};
+use std::{
+  future::Future,
+  net::{IpAddr, SocketAddr},
+  pin::Pin,
+  sync::Arc,
+  task::{Context, Poll},
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tower_service::Service;
+
+/// Encode the PROXY protocol header carrying the client address `src` and the address `dst` the
+/// client connected to. If the families differ, both are sent as IPv6 with the IPv4 one mapped.
+pub(crate) fn encode_proxy_header(version: ProxyProtocolVersion, src: SocketAddr, dst: SocketAddr) -> std::io::Result<Vec<u8>> {
+  let (src_ip, dst_ip) = match (src.ip().to_canonical(), dst.ip().to_canonical()) {
+    (IpAddr::V4(s), IpAddr::V4(d)) => (IpAddr::V4(s), IpAddr::V4(d)),
+    (s, d) => (IpAddr::V6(to_ipv6(s)), IpAddr::V6(to_ipv6(d))),
+  };
+  match (version, src_ip, dst_ip) {
+    (ProxyProtocolVersion::V1, IpAddr::V4(s), IpAddr::V4(d)) => {
+      Ok(format!("PROXY TCP4 {s} {d} {} {}\r\n", src.port(), dst.port()).into_bytes())
+    }
+    (ProxyProtocolVersion::V1, s, d) => Ok(format!("PROXY TCP6 {s} {d} {} {}\r\n", src.port(), dst.port()).into_bytes()),
+    (ProxyProtocolVersion::V2, IpAddr::V4(s), IpAddr::V4(d)) => {
+      let addresses: ppp::v2::Addresses = ppp::v2::IPv4::new(s, d, src.port(), dst.port()).into();
+      build_v2(addresses)
+    }
+    (ProxyProtocolVersion::V2, s, d) => {
+      let addresses: ppp::v2::Addresses = ppp::v2::IPv6::new(to_ipv6(s), to_ipv6(d), src.port(), dst.port()).into();
+      build_v2(addresses)
+    }
+  }
+}
+
+fn build_v2(addresses: ppp::v2::Addresses) -> std::io::Result<Vec<u8>> {
+  let version_command = ppp::v2::Version::Two | ppp::v2::Command::Proxy;
+  ppp::v2::Builder::with_addresses(version_command, ppp::v2::Protocol::Stream, addresses).build()
+}
+
+fn to_ipv6(addr: IpAddr) -> std::net::Ipv6Addr {
+  match addr {
+    IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+    IpAddr::V6(v6) => v6,
+  }
+}
+
+#[derive(Clone, Debug)]
+/// Connector writing a fixed PROXY protocol header on every new TCP connection
+pub(crate) struct ProxyProtocolConnector {
+  http: HttpConnector,
+  header: Arc<[u8]>,
+}
+
+impl ProxyProtocolConnector {
+  /// Build a connector dialing with `http` and prefixing each connection with `header`
+  pub(crate) fn new(http: HttpConnector, header: Vec<u8>) -> Self {
+    Self {
+      http,
+      header: header.into(),
+    }
+  }
+}
+
+impl Service<Uri> for ProxyProtocolConnector {
+  type Response = TokioIo<TcpStream>;
+  type Error = Box<dyn std::error::Error + Send + Sync>;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.http.poll_ready(cx).map_err(Into::into)
+  }
+
+  fn call(&mut self, uri: Uri) -> Self::Future {
+    let connecting = self.http.call(uri);
+    let header = self.header.clone();
+    Box::pin(async move {
+      let mut io = connecting.await?;
+      io.inner_mut().write_all(&header).await?;
+      Ok(io)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::io::AsyncReadExt;
+
+  #[test]
+  fn encode_v1_header() {
+    let src: SocketAddr = "192.0.2.1:51234".parse().unwrap();
+    let dst: SocketAddr = "198.51.100.1:443".parse().unwrap();
+    let header = encode_proxy_header(ProxyProtocolVersion::V1, src, dst).unwrap();
+    assert_eq!(header, b"PROXY TCP4 192.0.2.1 198.51.100.1 51234 443\r\n");
+
+    // Mixed families are sent as IPv6.
+    let dst: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+    let header = encode_proxy_header(ProxyProtocolVersion::V1, src, dst).unwrap();
+    assert_eq!(header, b"PROXY TCP6 ::ffff:192.0.2.1 2001:db8::1 51234 443\r\n");
+  }
+
+  #[test]
+  fn encode_v2_header_roundtrip() {
+    // An IPv4-mapped client address is sent as plain IPv4.
+    let src: SocketAddr = "[::ffff:192.0.2.1]:51234".parse().unwrap();
+    let dst: SocketAddr = "198.51.100.1:443".parse().unwrap();
+    let header = encode_proxy_header(ProxyProtocolVersion::V2, src, dst).unwrap();
+    let parsed = ppp::v2::Header::try_from(header.as_slice()).unwrap();
+    assert_eq!(parsed.command, ppp::v2::Command::Proxy);
+    let ppp::v2::Addresses::IPv4(addrs) = parsed.addresses else {
+      panic!("expected IPv4 addresses");
+    };
+    assert_eq!(addrs.source_address, "192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap());
+    assert_eq!(addrs.source_port, 51234);
+    assert_eq!(addrs.destination_port, 443);
+  }
+
+  #[tokio::test]
+  async fn connector_writes_header_first() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 51234 443\r\n".to_vec();
+
+    let mut connector = ProxyProtocolConnector::new(HttpConnector::new(), header.clone());
+    let uri: Uri = format!("http://{addr}/").parse().unwrap();
+    let (connected, accepted) = tokio::join!(connector.call(uri), listener.accept());
+    let _io = connected.unwrap();
+    let (mut stream, _) = accepted.unwrap();
+
+    let mut buf = vec![0u8; header.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, header);
+  }
+}
@@ -0,0 +1,173 @@
+//! Unix domain socket upstreams for the hyper-util legacy client.
+//!
+//! A socket path cannot be carried in the authority of an `http::Uri` as is, so a socket upstream
+//! is represented as `unix://<hex-encoded socket path>/...`. The scheme tells the forwarder and the
+//! health checker to dial the socket instead of TCP, and the connector decodes the authority back
+//! into the filesystem path. Connections over a socket are always cleartext.
+
+use hyper::Uri;
+
+/// Uri scheme marking an upstream reached over a Unix domain socket
+pub(crate) const UNIX_SCHEME: &str = "unix";
+
+/// Host value sent to a Unix domain socket upstream when the `Host` header is rewritten to the
+/// upstream (e.g. `set_upstream_host`) or built for a health check probe.
+pub(crate) const UNIX_SOCKET_HOST: &str = "localhost";
+
+/// Returns whether the uri points at a Unix domain socket upstream.
+pub(crate) fn is_unix_socket_uri(uri: &Uri) -> bool {
+  uri.scheme_str() == Some(UNIX_SCHEME)
+}
+
+#[cfg(unix)]
+pub(crate) use inner::*;
+
+#[cfg(unix)]
+mod inner {
+  use super::*;
+  use hyper_util::rt::TokioIo;
+  use std::{
+    ffi::OsStr,
+    future::Future,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+  };
+  use tokio::net::UnixStream;
+
+  /// Build the base uri `unix://<hex(path)>/` of a Unix domain socket upstream.
+  pub(crate) fn unix_socket_uri(path: &Path) -> Result<Uri, http::Error> {
+    let encoded = path
+      .as_os_str()
+      .as_bytes()
+      .iter()
+      .map(|b| format!("{b:02x}"))
+      .collect::<String>();
+    Uri::builder()
+      .scheme(UNIX_SCHEME)
+      .authority(encoded.as_str())
+      .path_and_query("/")
+      .build()
+  }
+
+  /// Decode the socket path from a uri built by [`unix_socket_uri`].
+  /// Returns `None` if the uri is not a Unix domain socket uri or its authority is malformed.
+  pub(crate) fn unix_socket_path(uri: &Uri) -> Option<PathBuf> {
+    if !is_unix_socket_uri(uri) {
+      return None;
+    }
+    let encoded = uri.host()?.as_bytes();
+    if encoded.is_empty() || encoded.len() % 2 != 0 {
+      return None;
+    }
+    let decoded = encoded
+      .chunks(2)
+      .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+      .collect::<Option<Vec<u8>>>()?;
+    Some(PathBuf::from(OsStr::from_bytes(&decoded)))
+  }
+
+  #[derive(Clone, Debug, Default)]
+  /// Connector dialing the Unix domain socket encoded in the request uri
+  pub(crate) struct UnixConnector;
+
+  impl tower_service::Service<Uri> for UnixConnector {
+    type Response = TokioIo<UnixStream>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+      Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+      Box::pin(async move {
+        let path = unix_socket_path(&uri).ok_or_else(|| {
+          std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Not a Unix domain socket upstream: {uri}"),
+          )
+        })?;
+        let stream = UnixStream::connect(path).await?;
+        Ok(TokioIo::new(stream))
+      })
+    }
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+  use crate::hyper_ext::rt::LocalExecutor;
+  use http_body_util::{BodyExt, Empty};
+  use hyper::body::Bytes;
+  use hyper_util::client::legacy::Client;
+  use std::path::{Path, PathBuf};
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+  fn temp_socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rpxy-{}-{}.sock", name, std::process::id()))
+  }
+
+  #[test]
+  fn unix_socket_uri_roundtrip() {
+    let path = Path::new("/run/app/app.sock");
+    let uri = unix_socket_uri(path).unwrap();
+    assert!(is_unix_socket_uri(&uri));
+    assert_eq!(uri.scheme_str(), Some("unix"));
+    assert_eq!(unix_socket_path(&uri).unwrap(), path);
+
+    // The path survives the rebuild of the request uri done by the handler.
+    let rebuilt: Uri = format!("{}://{}/some/path?q=1", uri.scheme_str().unwrap(), uri.authority().unwrap())
+      .parse()
+      .unwrap();
+    assert_eq!(unix_socket_path(&rebuilt).unwrap(), path);
+  }
+
+  #[test]
+  fn unix_socket_path_rejects_other_uris() {
+    assert!(unix_socket_path(&"http://backend.local:8080/".parse().unwrap()).is_none());
+    assert!(unix_socket_path(&"unix://abc/".parse().unwrap()).is_none());
+    assert!(unix_socket_path(&"unix://zz/".parse().unwrap()).is_none());
+  }
+
+  #[tokio::test]
+  async fn unix_connector_forwards_request() {
+    let path = temp_socket_path("unix-connector");
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+    let server = tokio::spawn(async move {
+      let (mut stream, _) = listener.accept().await.unwrap();
+      let mut buf = vec![0u8; 1024];
+      let n = stream.read(&mut buf).await.unwrap();
+      let request = String::from_utf8_lossy(&buf[..n]).to_string();
+      stream
+        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok")
+        .await
+        .unwrap();
+      request
+    });
+
+    let client = Client::builder(LocalExecutor::new(tokio::runtime::Handle::current())).build::<_, Empty<Bytes>>(UnixConnector);
+    let base = unix_socket_uri(&path).unwrap();
+    let uri: Uri = format!("unix://{}/healthz", base.authority().unwrap()).parse().unwrap();
+    let req = http::Request::builder()
+      .uri(uri)
+      .header(http::header::HOST, UNIX_SOCKET_HOST)
+      .body(Empty::<Bytes>::new())
+      .unwrap();
+    let res = client.request(req).await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    let body = res.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), b"ok");
+
+    // The request line is sent in origin-form, so the encoded socket path never reaches the app.
+    let request = server.await.unwrap();
+    assert!(request.starts_with("GET /healthz HTTP/1.1\r\n"), "{request}");
+    assert!(request.to_ascii_lowercase().contains("host: localhost\r\n"), "{request}");
+
+    let _ = std::fs::remove_file(&path);
+  }
+}
@@ -1,3 +1,4 @@
+mod admin;
 mod backend;
 mod constants;
 mod count;
@@ -28,8 +29,12 @@ use tokio_util::sync::CancellationToken;
 
 /* ------------------------------------------------ */
 pub use crate::{
-  constants::log_event_names,
-  globals::{AppConfig, AppConfigList, ProxyConfig, ReverseProxyConfig, TlsConfig, UpstreamUri},
+  backend::UpstreamOverrides,
+  constants::{log_event_names, stream as stream_defaults, upstream_resolve as upstream_resolve_defaults},
+  globals::{
+    AdminConfig, AdminListen, AppConfig, AppConfigList, ProxyConfig, ReverseProxyConfig, StreamConfig, StreamProtocol, TlsConfig,
+    UpstreamResolveConfig, UpstreamTlsConfig, UpstreamTlsVersion, UpstreamUri,
+  },
 };
 
 #[cfg(feature = "health-check")]
@@ -47,7 +52,15 @@ pub use crate::{
   globals::{HealthCheckConfig, HealthCheckType},
 };
 #[cfg(feature = "proxy-protocol")]
-pub use crate::{constants::proxy_protocol as proxy_protocol_defaults, globals::TcpRecvProxyProtocolConfig};
+pub use crate::{
+  constants::proxy_protocol as proxy_protocol_defaults,
+  globals::{ProxyProtocolVersion, TcpRecvProxyProtocolConfig},
+};
+#[cfg(feature = "cache")]
+pub use crate::{
+  forwarder::{CacheBody, CacheEntry, CacheError, CacheStorage, PurgeTarget},
+  globals::CacheRuleConfig,
+};
 
 pub mod reexports {
   pub use hyper::Uri;
@@ -74,9 +87,18 @@ pub struct RpxyOptions {
   /// true so library consumers that never set it keep full access logging.
   #[builder(default = "true")]
   pub access_log_enabled: bool,
+  /// Upstream overrides set via the admin API. Pass the same registry each time the service is
+  /// rebuilt to keep the overrides across config reloads.
+  #[builder(default)]
+  pub upstream_overrides: UpstreamOverrides,
   #[cfg(feature = "sticky-cookie")]
   #[builder(default)]
   pub sticky_cookie_secret: Option<Arc<StickyCookieSecret>>,
+  /// Storage of the cache in place of the default memory and file tiers, e.g., one shared among instances.
+  /// `cache_dir` is not used when given.
+  #[cfg(feature = "cache")]
+  #[builder(default)]
+  pub cache_storage: Option<Arc<dyn CacheStorage>>,
 
   #[cfg(feature = "acme")]
   /// ServerConfig used for only ACME challenge for ACME domains
@@ -92,8 +114,11 @@ pub async fn entrypoint(
     runtime_handle,
     unsafe_debug_headers,
     access_log_enabled,
+    upstream_overrides,
     #[cfg(feature = "sticky-cookie")]
     sticky_cookie_secret,
+    #[cfg(feature = "cache")]
+    cache_storage,
     #[cfg(feature = "acme")]
     server_configs_acme_challenge,
   }: &RpxyOptions,
@@ -138,7 +163,9 @@ pub async fn entrypoint(
     debug!("Trusted forwarded proxies: {:?}", proxy_config.trusted_forwarded_proxies);
   }
   #[cfg(feature = "cache")]
-  if proxy_config.cache_enabled {
+  if proxy_config.cache_enabled && cache_storage.is_some() {
+    info!("Cache is enabled with a custom storage");
+  } else if proxy_config.cache_enabled {
     info!("Cache is enabled: cache dir = {:?}", proxy_config.cache_dir.as_ref().unwrap());
   } else {
     info!("Cache is disabled")
@@ -175,6 +202,7 @@ pub async fn entrypoint(
 
   // 1. build backends, and make it contained in Arc
   let app_manager = Arc::new(backend::BackendAppManager::try_from(app_config_list)?);
+  upstream_overrides.bind(&app_manager);
 
   // 2. build global shared context
   let globals = Arc::new(Globals {
@@ -187,13 +215,23 @@ pub async fn entrypoint(
     access_log_enabled: *access_log_enabled,
     #[cfg(feature = "sticky-cookie")]
     sticky_cookie_cipher,
+    #[cfg(feature = "cache")]
+    cache_storage: cache_storage.clone(),
 
     #[cfg(feature = "acme")]
     server_configs_acme_challenge: server_configs_acme_challenge.clone(),
   });
 
   // 3. build forwarder
-  let forwarder = Arc::new(Forwarder::try_new(&globals).await?);
+  let forwarder = Arc::new(Forwarder::try_new(&globals, &app_manager.upstream_tls_configs()).await?);
+  let admin_service = globals.proxy_config.admin.as_ref().map(|admin_config| admin::AdminService {
+    config: Arc::new(admin_config.clone()),
+    globals: globals.clone(),
+    app_manager: app_manager.clone(),
+    upstream_overrides: upstream_overrides.clone(),
+    #[cfg(feature = "cache")]
+    cache: forwarder.cache().cloned(),
+  });
 
   // 4. build message handler containing Arc-ed http_client and backends, and make it contained in Arc as well
   let message_handler = Arc::new(
@@ -246,9 +284,18 @@ pub async fn entrypoint(
         .listener_spec(listener_spec)
         .connection_builder(connection_builder.clone())
         .message_handler(message_handler.clone())
+        .app_manager(app_manager.clone())
         .build()
     })
     .collect::<Result<Vec<_>, _>>()?;
+  let stream_proxies = app_manager
+    .streams
+    .iter()
+    .map(|stream| proxy::StreamProxy {
+      globals: globals.clone(),
+      stream: Arc::new(stream.clone()),
+    })
+    .collect::<Vec<_>>();
 
   // 6. spawn health checker tasks before proxy tasks so that HTTP client
   //    construction errors are caught before any proxy listener is started.
@@ -257,6 +304,8 @@ pub async fn entrypoint(
   #[cfg(feature = "health-check")]
   let health_checker_handles =
     backend::health_check::spawn_health_checkers(&app_manager, cancel_token.clone(), &globals.runtime_handle)?;
+  let resolver_handles = backend::spawn_upstream_resolvers(&app_manager, cancel_token.clone(), &globals.runtime_handle);
+  let upstream_file_handles = backend::spawn_upstream_file_watchers(&app_manager, cancel_token.clone(), &globals.runtime_handle);
 
   let proxy_handles: Vec<_> = proxies
     .into_iter()
@@ -280,11 +329,62 @@ pub async fn entrypoint(
       })
     })
     .collect();
+  let stream_proxy_handles: Vec<_> = stream_proxies
+    .into_iter()
+    .map(|stream_proxy| {
+      let cancel_token = cancel_token.clone();
+      globals.runtime_handle.spawn(async move {
+        info!("rpxy stream proxy service {} started", stream_proxy.name());
+
+        tokio::select! {
+          _ = cancel_token.cancelled() => {
+            debug!("rpxy stream proxy service {} terminated", stream_proxy.name());
+            Ok(())
+          },
+          proxy_res = stream_proxy.start() => {
+            info!("rpxy stream proxy service {} exited", stream_proxy.name());
+            // cancel other proxy tasks
+            cancel_token.cancel();
+            proxy_res
+          }
+        }
+      })
+    })
+    .collect();
+
+  // The admin API is served apart from the proxy listeners, so its failure does not stop them.
+  let admin_handle = admin_service.map(|admin_service| {
+    let cancel_token = cancel_token.clone();
+    globals.runtime_handle.spawn(async move {
+      tokio::select! {
+        _ = cancel_token.cancelled() => {
+          debug!("rpxy admin service terminated");
+        },
+        admin_res = admin_service.start() => {
+          if let Err(e) = admin_res {
+            error!("rpxy admin service exited on error: {e}");
+          }
+        }
+      }
+      Ok(())
+    })
+  });
 
   #[cfg(feature = "health-check")]
-  let handles = health_checker_handles.into_iter().chain(proxy_handles.into_iter());
+  let handles = health_checker_handles
+    .into_iter()
+    .chain(resolver_handles)
+    .chain(upstream_file_handles)
+    .chain(proxy_handles.into_iter())
+    .chain(stream_proxy_handles.into_iter())
+    .chain(admin_handle);
   #[cfg(not(feature = "health-check"))]
-  let handles = proxy_handles.into_iter();
+  let handles = resolver_handles
+    .into_iter()
+    .chain(upstream_file_handles)
+    .chain(proxy_handles.into_iter())
+    .chain(stream_proxy_handles.into_iter())
+    .chain(admin_handle);
 
   // 7. wait for tasks — fail fast on first error, then cancel remaining tasks
   let mut futures: FuturesUnordered<_> = handles.into_iter().collect();
@@ -0,0 +1,300 @@
+use crate::{
+  constants::GRPC_WEB_CORS_MAX_AGE_SEC,
+  hyper_ext::body::{GrpcWebEncoder, GrpcWebTextDecoder, RequestBody, ResponseBody, empty},
+  log::*,
+};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, header};
+
+const GRPC: &str = "application/grpc";
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+/// Request headers allowed in CORS preflight responses unless the client asks for specific ones
+const GRPC_WEB_ALLOWED_HEADERS: &str = "content-type,x-grpc-web,x-user-agent,grpc-timeout";
+/// Response headers exposed to scripts, which carry the status of trailers-only responses
+const GRPC_WEB_EXPOSED_HEADERS: &str = "grpc-status,grpc-message,grpc-status-details-bin";
+
+#[derive(Debug, Clone)]
+/// gRPC-Web request translated into native gRPC, kept to encode the response for the client
+pub(super) struct GrpcWebContext {
+  /// Body is base64-encoded, i.e., `application/grpc-web-text`
+  text: bool,
+  /// Content type of the translated request
+  grpc_content_type: HeaderValue,
+  /// `Access-Control-Allow-Origin` of the response, None if the origin of the request is not allowed
+  origin: Option<HeaderValue>,
+}
+
+/// Split a content type starting with the given media type into the suffix like `+proto`, if any
+fn strip_media_type<'a>(content_type: &'a str, media_type: &str) -> Option<&'a str> {
+  let prefix = content_type.get(..media_type.len())?;
+  if !prefix.eq_ignore_ascii_case(media_type) {
+    return None;
+  }
+  let suffix = &content_type[media_type.len()..];
+  (suffix.is_empty() || suffix.starts_with(['+', ';'])).then_some(suffix)
+}
+
+/// Check if the request is a CORS preflight request
+pub(super) fn is_cors_preflight<B>(req: &Request<B>) -> bool {
+  req.method() == Method::OPTIONS
+    && req.headers().contains_key(header::ORIGIN)
+    && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Value of `Access-Control-Allow-Origin` for the origin of the request: the origin itself if listed in the allowed
+/// origins, or `*` if any origin is allowed. None if the origin is not allowed.
+fn allowed_origin(origin: &HeaderValue, allowed_origins: &[String]) -> Option<HeaderValue> {
+  if allowed_origins.iter().any(|allowed| allowed == "*") {
+    return Some(HeaderValue::from_static("*"));
+  }
+  let origin_str = origin.to_str().ok()?;
+  allowed_origins
+    .iter()
+    .any(|allowed| allowed.eq_ignore_ascii_case(origin_str))
+    .then(|| origin.clone())
+}
+
+/// Answer a CORS preflight request for a gRPC-Web route, allowing the origin to send gRPC-Web requests only if it is
+/// one of the allowed origins. Credentials are never allowed.
+pub(super) fn cors_preflight_response<B>(req: &Request<B>, allowed_origins: &[String]) -> Response<ResponseBody> {
+  let mut res = Response::new(ResponseBody::Boxed(empty()));
+  let allow_origin = req
+    .headers()
+    .get(header::ORIGIN)
+    .and_then(|origin| allowed_origin(origin, allowed_origins));
+  res.headers_mut().insert(
+    header::VARY,
+    HeaderValue::from_static("origin, access-control-request-method, access-control-request-headers"),
+  );
+  let Some(allow_origin) = allow_origin else {
+    debug!("CORS preflight for gRPC-Web from an origin not allowed");
+    *res.status_mut() = StatusCode::FORBIDDEN;
+    return res;
+  };
+  *res.status_mut() = StatusCode::NO_CONTENT;
+  let headers = res.headers_mut();
+  headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+  headers.insert(
+    header::ACCESS_CONTROL_ALLOW_METHODS,
+    HeaderValue::from_static("POST, OPTIONS"),
+  );
+  // Custom metadata of gRPC calls is sent in request headers
+  let allowed_headers = req
+    .headers()
+    .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+    .cloned()
+    .unwrap_or(HeaderValue::from_static(GRPC_WEB_ALLOWED_HEADERS));
+  headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers);
+  headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(GRPC_WEB_CORS_MAX_AGE_SEC));
+  res
+}
+
+/// Detect a gRPC-Web request by its content type, keeping its origin if allowed to read the response
+pub(super) fn grpc_web_context<B>(req: &Request<B>, allowed_origins: &[String]) -> Option<GrpcWebContext> {
+  let content_type = req.headers().get(header::CONTENT_TYPE)?.to_str().ok()?;
+  let (text, suffix) = match strip_media_type(content_type, GRPC_WEB_TEXT) {
+    Some(suffix) => (true, suffix),
+    None => (false, strip_media_type(content_type, GRPC_WEB)?),
+  };
+  Some(GrpcWebContext {
+    text,
+    grpc_content_type: HeaderValue::from_str(&format!("{GRPC}{suffix}")).ok()?,
+    origin: req
+      .headers()
+      .get(header::ORIGIN)
+      .and_then(|origin| allowed_origin(origin, allowed_origins)),
+  })
+}
+
+/// Translate a gRPC-Web request into native gRPC: the content type is rewritten, `-text` bodies are decoded, and
+/// `TE: trailers` is set. HTTP/2 is then chosen for the upstream by the gRPC content type.
+pub(super) fn translate_grpc_web_request(req: Request<RequestBody>, context: &GrpcWebContext) -> Request<RequestBody> {
+  debug!("Translate gRPC-Web request into gRPC (text: {})", context.text);
+  let (mut parts, body) = req.into_parts();
+  parts.headers.insert(header::CONTENT_TYPE, context.grpc_content_type.clone());
+  parts.headers.insert(header::TE, HeaderValue::from_static("trailers"));
+  let body = if context.text {
+    parts.headers.remove(header::CONTENT_LENGTH);
+    RequestBody::GrpcWebText(Box::new(GrpcWebTextDecoder::new(body)))
+  } else {
+    body
+  };
+  Request::from_parts(parts, body)
+}
+
+/// Encode a native gRPC response for the gRPC-Web client: the content type is rewritten and trailers are moved into
+/// the body. Non-gRPC responses like errors of intermediaries are passed through, with CORS headers only.
+pub(super) fn encode_grpc_web_response(res: Response<ResponseBody>, context: &GrpcWebContext) -> Response<ResponseBody> {
+  let (mut parts, body) = res.into_parts();
+  let suffix = parts
+    .headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| strip_media_type(v, GRPC))
+    .map(ToOwned::to_owned);
+  let body = match suffix {
+    Some(suffix) => {
+      let media_type = if context.text { GRPC_WEB_TEXT } else { GRPC_WEB };
+      if let Ok(content_type) = HeaderValue::from_str(&format!("{media_type}{suffix}")) {
+        parts.headers.insert(header::CONTENT_TYPE, content_type);
+      }
+      parts.headers.remove(header::CONTENT_LENGTH);
+      ResponseBody::GrpcWeb(Box::new(GrpcWebEncoder::new(body, context.text)))
+    }
+    None => body,
+  };
+  add_cors_headers(&mut parts.headers, context);
+  Response::from_parts(parts, body)
+}
+
+/// Allow the origin of the gRPC-Web request to read the response if allowed
+fn add_cors_headers(headers: &mut HeaderMap, context: &GrpcWebContext) {
+  let Some(origin) = &context.origin else {
+    return;
+  };
+  headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+  headers.insert(
+    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+    HeaderValue::from_static(GRPC_WEB_EXPOSED_HEADERS),
+  );
+  headers.append(header::VARY, HeaderValue::from_static("origin"));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hyper_ext::body::{IncomingLike, full};
+  use bytes::Bytes;
+
+  fn grpc_web_request(content_type: &str) -> Request<RequestBody> {
+    let (_sender, body) = IncomingLike::channel();
+    Request::builder()
+      .method(Method::POST)
+      .header(header::CONTENT_TYPE, content_type)
+      .header(header::CONTENT_LENGTH, "8")
+      .header(header::ORIGIN, "https://web.example.com")
+      .body(RequestBody::IncomingLike(body))
+      .unwrap()
+  }
+
+  #[test]
+  fn request_is_translated_by_content_type() {
+    let req = grpc_web_request("application/grpc-web+proto");
+    let context = grpc_web_context(&req, &[]).unwrap();
+    assert!(!context.text);
+    let req = translate_grpc_web_request(req, &context);
+    assert_eq!(req.headers().get(header::CONTENT_TYPE).unwrap(), "application/grpc+proto");
+    assert_eq!(req.headers().get(header::TE).unwrap(), "trailers");
+    assert!(req.headers().contains_key(header::CONTENT_LENGTH));
+
+    let req = grpc_web_request("application/grpc-web-text");
+    let context = grpc_web_context(&req, &[]).unwrap();
+    assert!(context.text);
+    let req = translate_grpc_web_request(req, &context);
+    assert_eq!(req.headers().get(header::CONTENT_TYPE).unwrap(), "application/grpc");
+    assert!(!req.headers().contains_key(header::CONTENT_LENGTH));
+    assert!(matches!(req.body(), RequestBody::GrpcWebText(_)));
+
+    for content_type in ["application/grpc", "application/grpc-webx", "text/plain"] {
+      assert!(grpc_web_context(&grpc_web_request(content_type), &[]).is_none());
+    }
+  }
+
+  #[test]
+  fn response_is_encoded_for_client() {
+    let context = GrpcWebContext {
+      text: true,
+      grpc_content_type: HeaderValue::from_static("application/grpc+proto"),
+      origin: Some(HeaderValue::from_static("https://web.example.com")),
+    };
+    let res = Response::builder()
+      .header(header::CONTENT_TYPE, "application/grpc+proto")
+      .header(header::CONTENT_LENGTH, "6")
+      .body(ResponseBody::Boxed(full(Bytes::from_static(b"\x00\x00\x00\x00\x01z"))))
+      .unwrap();
+    let res = encode_grpc_web_response(res, &context);
+    assert_eq!(
+      res.headers().get(header::CONTENT_TYPE).unwrap(),
+      "application/grpc-web-text+proto"
+    );
+    assert!(!res.headers().contains_key(header::CONTENT_LENGTH));
+    assert_eq!(
+      res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+      "https://web.example.com"
+    );
+    assert!(matches!(res.body(), ResponseBody::GrpcWeb(_)));
+
+    let res = Response::builder()
+      .status(StatusCode::BAD_GATEWAY)
+      .header(header::CONTENT_TYPE, "text/html")
+      .body(ResponseBody::Boxed(empty()))
+      .unwrap();
+    let res = encode_grpc_web_response(res, &context);
+    assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/html");
+    assert!(matches!(res.body(), ResponseBody::Boxed(_)));
+  }
+
+  #[test]
+  fn cors_preflight() {
+    let preflight = |origin: &str| {
+      Request::builder()
+        .method(Method::OPTIONS)
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .body(())
+        .unwrap()
+    };
+    let req = preflight("https://web.example.com");
+    assert!(is_cors_preflight(&req));
+    let allowed_origins = vec!["https://web.example.com".to_string()];
+    let res = cors_preflight_response(&req, &allowed_origins);
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+      res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+      "https://web.example.com"
+    );
+    assert_eq!(
+      res.headers().get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+      GRPC_WEB_ALLOWED_HEADERS
+    );
+    assert!(!res.headers().contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    assert!(!is_cors_preflight(
+      &Request::builder().method(Method::OPTIONS).body(()).unwrap()
+    ));
+
+    // Origins not listed are not allowed, nor any origin by default
+    for allowed_origins in [allowed_origins.clone(), vec![]] {
+      let res = cors_preflight_response(&preflight("https://evil.example.com"), &allowed_origins);
+      assert_eq!(res.status(), StatusCode::FORBIDDEN);
+      assert!(!res.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+      assert!(!res.headers().contains_key(header::ACCESS_CONTROL_ALLOW_HEADERS));
+    }
+
+    // The wildcard allows any origin without reflecting it
+    let res = cors_preflight_response(&preflight("https://any.example.com"), &["*".to_string()]);
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+  }
+
+  #[test]
+  fn cors_headers_only_for_allowed_origins() {
+    let req = grpc_web_request("application/grpc-web");
+    assert_eq!(
+      grpc_web_context(&req, &["HTTPS://web.example.com".to_ascii_lowercase()])
+        .unwrap()
+        .origin
+        .unwrap(),
+      "https://web.example.com"
+    );
+    let context = grpc_web_context(&req, &["https://other.example.com".to_string()]).unwrap();
+    assert!(context.origin.is_none());
+    let res = Response::builder()
+      .header(header::CONTENT_TYPE, "application/grpc")
+      .body(ResponseBody::Boxed(empty()))
+      .unwrap();
+    let res = encode_grpc_web_response(res, &context);
+    assert!(!res.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert!(!res.headers().contains_key(header::ACCESS_CONTROL_EXPOSE_HEADERS));
+  }
+}
@@ -1,14 +1,18 @@
 use super::{
+  grpc_web::{
+    cors_preflight_response, encode_grpc_web_response, grpc_web_context, is_cors_preflight, translate_grpc_web_request,
+  },
   header_ops::*,
   http_log::HttpMessageLog,
   http_result::{HttpError, HttpResult},
   request_ops::InspectParseHost,
   synthetic_response::{secure_redirection_response, synthetic_error_response, synthetic_error_response_with_close},
+  upgrade::{UpgradeRequest, convert_switching_response_for_extended_connect, extract_upgrade_request, is_upgrade_accepted},
 };
 #[cfg(feature = "sticky-cookie")]
 use crate::backend::StickyCookieConfig;
 use crate::{
-  backend::{BackendAppManager, LoadBalanceContext},
+  backend::{BackendAppManager, LoadBalanceContext, UpstreamOption},
   error::*,
   forwarder::{ForwardRequest, Forwarder},
   globals::Globals,
@@ -18,6 +22,7 @@ use crate::{
 };
 use derive_builder::Builder;
 use http::{Request, Response, StatusCode};
+use hyper::ext::Protocol;
 use hyper_util::{client::legacy::connect::Connect, rt::TokioIo};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::io::copy_bidirectional;
@@ -169,20 +174,32 @@ where
       debug!("Redirect to secure connection: {}", backend_app.server_name);
       return secure_redirection_response(&backend_app.server_name, self.globals.proxy_config.public_https_port, &req);
     }
+    // Apps passing TLS through are served only by their upstreams over TLS
+    if backend_app.tls_passthrough {
+      debug!("No HTTP serving for TLS passthrough app: {}", backend_app.server_name);
+      return Err(HttpError::NoMatchingBackendApp);
+    }
 
     // Find reverse proxy for given path and choose one of upstream host
     // Longest prefix match
     let path = req.uri().path();
     let upstream_candidates = backend_app.path_manager.get(path).ok_or(HttpError::NoUpstreamCandidates)?;
 
-    // Upgrade in request header
-    let upgrade_in_request = extract_upgrade(req.headers());
-    if upgrade_in_request.is_some() && req.version() != http::Version::HTTP_11 {
-      return Err(HttpError::FailedToUpgrade(format!(
-        "Unsupported HTTP version: {:?}",
-        req.version()
-      )));
+    // gRPC-Web: answer CORS preflight requests, and translate gRPC-Web requests into native gRPC
+    let mut grpc_web = None;
+    if upstream_candidates.options.contains(&UpstreamOption::GrpcWeb) {
+      if is_cors_preflight(&req) {
+        debug!("CORS preflight for gRPC-Web");
+        return Ok(cors_preflight_response(&req, &upstream_candidates.grpc_web_allowed_origins));
+      }
+      grpc_web = grpc_web_context(&req, &upstream_candidates.grpc_web_allowed_origins);
+      if let Some(context) = grpc_web.as_ref() {
+        req = translate_grpc_web_request(req, context);
+      }
     }
+
+    // Upgrade in request header, or extended CONNECT over HTTP/2
+    let upgrade_in_request = extract_upgrade_request(&req)?;
     // let request_upgraded = req.extensions_mut().remove::<hyper::upgrade::OnUpgrade>();
     let req_on_upgrade = hyper::upgrade::on(&mut req);
 
@@ -197,7 +214,7 @@ where
         &client_addr,
         &listen_addr,
         &mut req,
-        &upgrade_in_request,
+        upgrade_in_request.as_ref(),
         upstream_candidates,
         tls_enabled,
         fallback_host,
@@ -215,6 +232,8 @@ where
       l.xff(&req.headers().get(header_defs::X_FORWARDED_FOR));
       l.upstream(req.uri());
     }
+    // Extended CONNECT is kept only for HTTP/2 upstreams, which answer it with 2xx instead of 101.
+    let upstream_extended_connect = req.method() == http::Method::CONNECT && req.extensions().get::<Protocol>().is_some();
     //////
 
     //////////////
@@ -224,6 +243,12 @@ where
       .request(req)
       .await
       .map_err(|e| HttpError::FailedToGetResponseFromBackend(e.to_string()))?;
+    #[cfg(feature = "cache")]
+    if let Some(l) = log_data.as_mut()
+      && let Some(cache_status) = res_backend.extensions().get::<crate::forwarder::CacheStatus>()
+    {
+      l.cache_status(cache_status);
+    }
 
     //////////////
     // Process reverse proxy context generated during the forwarding request generation.
@@ -250,27 +275,27 @@ where
       }
     }
 
-    if res_backend.status() != StatusCode::SWITCHING_PROTOCOLS {
+    let upgrade_accepted = match upgrade_in_request.as_ref() {
+      Some(upgrade) => is_upgrade_accepted(&res_backend, upgrade, upstream_extended_connect),
+      None => false,
+    };
+    if !upgrade_accepted {
+      if res_backend.status() == StatusCode::SWITCHING_PROTOCOLS {
+        return Err(HttpError::FailedToUpgrade(format!(
+          "Backend tried to switch to protocol {:?} when {:?} was requested",
+          extract_upgrade(res_backend.headers()),
+          upgrade_in_request.as_ref().map(|u| u.protocol())
+        )));
+      }
+      if let Some(context) = grpc_web.as_ref() {
+        res_backend = encode_grpc_web_response(res_backend, context);
+      }
       // Generate response to client
       self
         .generate_response_forwarded(&mut res_backend, backend_app, tls_enabled)
         .map_err(|e| HttpError::FailedToGenerateDownstreamResponse(e.to_string()))?;
       return Ok(res_backend);
     }
-
-    // Handle StatusCode::SWITCHING_PROTOCOLS in response
-    let upgrade_in_response = extract_upgrade(res_backend.headers());
-    let should_upgrade = match (upgrade_in_request.as_ref(), upgrade_in_response.as_ref()) {
-      (Some(u_req), Some(u_res)) => u_req.eq_ignore_ascii_case(u_res),
-      _ => false,
-    };
-
-    if !should_upgrade {
-      return Err(HttpError::FailedToUpgrade(format!(
-        "Backend tried to switch to protocol {:?} when {:?} was requested",
-        upgrade_in_response, upgrade_in_request
-      )));
-    }
     // let Some(request_upgraded) = request_upgraded else {
     //   return Err(HttpError::NoUpgradeExtensionInRequest);
     // };
@@ -298,6 +323,14 @@ where
       Ok(()) as RpxyResult<()>
     });
 
+    // The tunnel of extended CONNECT is opened by a 2xx response, whatever the upstream answered with.
+    if upgrade_in_request == Some(UpgradeRequest::ExtendedConnect) {
+      convert_switching_response_for_extended_connect(&mut res_backend);
+      self
+        .generate_response_forwarded(&mut res_backend, backend_app, tls_enabled)
+        .map_err(|e| HttpError::FailedToGenerateDownstreamResponse(e.to_string()))?;
+    }
+
     Ok(res_backend)
   }
 }
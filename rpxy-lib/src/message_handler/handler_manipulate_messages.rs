@@ -1,4 +1,10 @@
-use super::{HttpMessageHandler, handler_main::HandlerContext, header_ops::*, request_ops::update_request_line};
+use super::{
+  HttpMessageHandler,
+  handler_main::HandlerContext,
+  header_ops::*,
+  request_ops::update_request_line,
+  upgrade::{UpgradeRequest, apply_upgrade_to_request},
+};
 use crate::{
   backend::{BackendApp, UpstreamCandidates},
   constants::RESPONSE_HEADER_SERVER,
@@ -33,9 +39,15 @@ where
 
     #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
     {
-      // Manipulate ALT_SVC allowing h3 in response message only when mutual TLS is not enabled
+      // Manipulate ALT_SVC allowing h3 in response message only when mutual TLS is not enabled and the crypto is not
+      // restricted, i.e., when the app is served by the HTTP/3 listener
       // TODO: Support per-vhost HTTP/3 client authentication so mTLS domains can advertise Alt-Svc safely.
-      if let Some(port) = h3_alt_svc_port(&self.globals.proxy_config, backend_app.mutual_tls, is_secure_transport) {
+      if let Some(port) = h3_alt_svc_port(
+        &self.globals.proxy_config,
+        backend_app.mutual_tls,
+        backend_app.tls_restricted_crypto,
+        is_secure_transport,
+      ) {
         add_header_entry_overwrite_if_exist(
           headers,
           header::ALT_SVC,
@@ -68,7 +80,7 @@ where
     client_addr: &SocketAddr,
     listen_addr: &SocketAddr,
     req: &mut Request<B>,
-    upgrade: &Option<String>,
+    upgrade: Option<&UpgradeRequest>,
     upstream_candidates: &UpstreamCandidates,
     tls_enabled: bool,
     fallback_host: Option<&ServerName>,
@@ -133,10 +145,13 @@ where
 
     /////////////////////////////////////////////
     // Fix unique upstream destination since there could be multiple ones.
+    // A snapshot of the upstream set is taken so that the load balancer state and the chosen
+    // upstream stay consistent even if the set is replaced by re-resolution meanwhile.
     #[cfg(feature = "sticky-cookie")]
     let (upstream_chosen_opt, context_from_lb, sticky_cookie_config) = {
+      let upstream_set = upstream_candidates.pool.load();
       let mut sticky_cookie_config = None;
-      let context_to_lb = if let crate::backend::LoadBalance::StickyRoundRobin(lb) = &upstream_candidates.load_balance {
+      let context_to_lb = if let crate::backend::LoadBalance::StickyRoundRobin(lb) = &upstream_set.load_balance {
         let cipher = self
           .globals
           .sticky_cookie_cipher
@@ -147,11 +162,11 @@ where
       } else {
         None
       };
-      let (upstream_chosen_opt, context_from_lb) = upstream_candidates.get(&context_to_lb);
+      let (upstream_chosen_opt, context_from_lb) = upstream_set.get(&context_to_lb);
       (upstream_chosen_opt, context_from_lb, sticky_cookie_config)
     };
     #[cfg(not(feature = "sticky-cookie"))]
-    let (upstream_chosen_opt, _) = upstream_candidates.get(&None);
+    let (upstream_chosen_opt, _) = upstream_candidates.pool.load().get(&None);
 
     let upstream_chosen = upstream_chosen_opt.ok_or_else(|| anyhow!("Failed to get upstream"))?;
     let context = HandlerContext {
@@ -172,7 +187,7 @@ where
     apply_upstream_options_to_header(
       headers,
       authoritative_host.as_deref(),
-      upstream_chosen,
+      &upstream_chosen,
       upstream_candidates,
       &self.globals.proxy_config.trusted_forwarded_proxies,
     )?;
@@ -206,15 +221,32 @@ where
     *req.uri_mut() = new_uri.path_and_query(new_pq).build()?;
 
     // upgrade
-    if let Some(v) = upgrade {
-      req.headers_mut().insert(header::UPGRADE, v.parse()?);
+    match upgrade {
+      Some(upgrade) => apply_upgrade_to_request(req, upgrade, &upstream_candidates.options)?,
+      // can update request line i.e., http version, only if not upgrade
+      None => update_request_line(req, &upstream_chosen, upstream_candidates)?,
+    }
+
+    // Tell the forwarder to connect with the TLS settings of the upstream group, if any.
+    #[cfg(feature = "rustls-backend")]
+    if let Some(upstream_tls) = &upstream_candidates.upstream_tls {
       req
-        .headers_mut()
-        .insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        .extensions_mut()
+        .insert(crate::forwarder::UpstreamTls(upstream_tls.clone()));
+    }
+    // Tell the forwarder to announce the client address with the PROXY protocol on new connections.
+    #[cfg(feature = "proxy-protocol")]
+    if let Some(version) = upstream_candidates.send_proxy_protocol {
+      req.extensions_mut().insert(crate::forwarder::SendProxyProtocol {
+        version,
+        src: *client_addr,
+        dst: *listen_addr,
+      });
     }
-    if upgrade.is_none() {
-      // can update request line i.e., http version, only if not upgrade (http 1.1)
-      update_request_line(req, upstream_chosen, upstream_candidates)?;
+    // Tell the forwarder to cache the response as per the cache rule of the upstream group, if any.
+    #[cfg(feature = "cache")]
+    if let Some(cache_rule) = &upstream_candidates.cache_rule {
+      req.extensions_mut().insert(crate::forwarder::CacheRule(cache_rule.clone()));
     }
 
     // Carry the client-facing effective URI to the forwarder/cache boundary via request
@@ -264,9 +296,10 @@ fn te_contains_trailers(te: &HeaderValue) -> bool {
 fn h3_alt_svc_port(
   proxy_config: &crate::globals::ProxyConfig,
   backend_mutual_tls: Option<bool>,
+  backend_restricted_crypto: bool,
   is_secure_transport: bool,
 ) -> Option<u16> {
-  if proxy_config.http3 && is_secure_transport && backend_mutual_tls == Some(false) {
+  if proxy_config.http3 && is_secure_transport && backend_mutual_tls == Some(false) && !backend_restricted_crypto {
     proxy_config.public_https_port
   } else {
     None
@@ -347,32 +380,39 @@ mod tests {
   #[test]
   fn h3_alt_svc_port_advertises_on_secure_non_mtls_transport() {
     let proxy_config = proxy_config_for_h3_alt_svc(true, Some(443));
-    assert_eq!(h3_alt_svc_port(&proxy_config, Some(false), true), Some(443));
+    assert_eq!(h3_alt_svc_port(&proxy_config, Some(false), false, true), Some(443));
   }
 
   #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
   #[test]
   fn h3_alt_svc_port_does_not_advertise_on_plain_http() {
     let proxy_config = proxy_config_for_h3_alt_svc(true, Some(443));
-    assert_eq!(h3_alt_svc_port(&proxy_config, Some(false), false), None);
+    assert_eq!(h3_alt_svc_port(&proxy_config, Some(false), false, false), None);
   }
 
   #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
   #[test]
   fn h3_alt_svc_port_does_not_advertise_for_mtls_or_plaintext_app() {
     let proxy_config = proxy_config_for_h3_alt_svc(true, Some(443));
-    assert_eq!(h3_alt_svc_port(&proxy_config, Some(true), true), None);
-    assert_eq!(h3_alt_svc_port(&proxy_config, None, true), None);
+    assert_eq!(h3_alt_svc_port(&proxy_config, Some(true), false, true), None);
+    assert_eq!(h3_alt_svc_port(&proxy_config, None, false, true), None);
+  }
+
+  #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
+  #[test]
+  fn h3_alt_svc_port_does_not_advertise_for_app_with_restricted_crypto() {
+    let proxy_config = proxy_config_for_h3_alt_svc(true, Some(443));
+    assert_eq!(h3_alt_svc_port(&proxy_config, Some(false), true, true), None);
   }
 
   #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
   #[test]
   fn h3_alt_svc_port_requires_h3_enabled_and_public_port() {
     let h3_disabled = proxy_config_for_h3_alt_svc(false, Some(443));
-    assert_eq!(h3_alt_svc_port(&h3_disabled, Some(false), true), None);
+    assert_eq!(h3_alt_svc_port(&h3_disabled, Some(false), false, true), None);
 
     let no_public_port = proxy_config_for_h3_alt_svc(true, None);
-    assert_eq!(h3_alt_svc_port(&no_public_port, Some(false), true), None);
+    assert_eq!(h3_alt_svc_port(&no_public_port, Some(false), false, true), None);
   }
 
   /// Lowercase `trailers` is the unchanged baseline.
@@ -100,20 +100,32 @@ pub(in crate::message_handler) fn apply_upstream_options_to_header(
 mod tests {
   use super::*;
   use crate::{
-    backend::{LoadBalance, Upstream},
+    backend::{LoadBalance, Upstream, UpstreamPool, UpstreamSet},
     globals::UpstreamUri,
   };
   use ahash::HashSet;
 
   fn candidates_with_options(uri: &str, options: HashSet<UpstreamOption>) -> UpstreamCandidates {
     UpstreamCandidates {
-      inner: vec![Upstream::from(&UpstreamUri { inner: uri.parse().unwrap() })],
+      pool: UpstreamPool::new(UpstreamSet {
+        inner: vec![Upstream::from(&UpstreamUri {
+          inner: uri.parse().unwrap(),
+        })],
+        load_balance: LoadBalance::default(),
+      }),
       path: "/".into(),
       replace_path: None,
-      load_balance: LoadBalance::default(),
       options,
+      grpc_web_allowed_origins: vec![],
       #[cfg(feature = "health-check")]
       health_check_config: None,
+      resolve: None,
+      upstream_file: None,
+      upstream_tls: None,
+      #[cfg(feature = "proxy-protocol")]
+      send_proxy_protocol: None,
+      #[cfg(feature = "cache")]
+      cache_rule: None,
     }
   }
 
@@ -124,7 +136,14 @@ mod tests {
       "http://backend.internal",
       HashSet::from_iter([UpstreamOption::UpgradeInsecureRequests]),
     );
-    apply_upstream_options_to_header(&mut headers, Some("app.example"), &candidates.inner[0], &candidates, &[]).unwrap();
+    apply_upstream_options_to_header(
+      &mut headers,
+      Some("app.example"),
+      &candidates.pool.load().inner[0],
+      &candidates,
+      &[],
+    )
+    .unwrap();
     assert_eq!(headers.get(header::UPGRADE_INSECURE_REQUESTS).unwrap(), "1");
   }
 
@@ -132,7 +151,14 @@ mod tests {
   fn upgrade_insecure_requests_absent_when_option_unset() {
     let mut headers = HeaderMap::new();
     let candidates = candidates_with_options("http://backend.internal", HashSet::default());
-    apply_upstream_options_to_header(&mut headers, Some("app.example"), &candidates.inner[0], &candidates, &[]).unwrap();
+    apply_upstream_options_to_header(
+      &mut headers,
+      Some("app.example"),
+      &candidates.pool.load().inner[0],
+      &candidates,
+      &[],
+    )
+    .unwrap();
     assert!(headers.get(header::UPGRADE_INSECURE_REQUESTS).is_none());
   }
 
@@ -144,7 +170,14 @@ mod tests {
       "http://backend.internal",
       HashSet::from_iter([UpstreamOption::UpgradeInsecureRequests]),
     );
-    apply_upstream_options_to_header(&mut headers, Some("app.example"), &candidates.inner[0], &candidates, &[]).unwrap();
+    apply_upstream_options_to_header(
+      &mut headers,
+      Some("app.example"),
+      &candidates.pool.load().inner[0],
+      &candidates,
+      &[],
+    )
+    .unwrap();
     // or_insert leaves a pre-existing value untouched
     assert_eq!(headers.get(header::UPGRADE_INSECURE_REQUESTS).unwrap(), "0");
   }
@@ -166,19 +199,29 @@ mod tests {
       inner: "http://backend.internal".parse().unwrap(),
     });
     let upstream_candidates = UpstreamCandidates {
-      inner: vec![upstream],
+      pool: UpstreamPool::new(UpstreamSet {
+        inner: vec![upstream],
+        load_balance: LoadBalance::default(),
+      }),
       path: "/".into(),
       replace_path: None,
-      load_balance: LoadBalance::default(),
       options: HashSet::from_iter([UpstreamOption::ForwardedHeader]),
+      grpc_web_allowed_origins: vec![],
       #[cfg(feature = "health-check")]
       health_check_config: None,
+      resolve: None,
+      upstream_file: None,
+      upstream_tls: None,
+      #[cfg(feature = "proxy-protocol")]
+      send_proxy_protocol: None,
+      #[cfg(feature = "cache")]
+      cache_rule: None,
     };
 
     apply_upstream_options_to_header(
       &mut headers,
       Some("app.example:8443"),
-      &upstream_candidates.inner[0],
+      &upstream_candidates.pool.load().inner[0],
       &upstream_candidates,
       &[],
     )
@@ -227,19 +270,29 @@ mod tests {
       inner: "http://backend.internal:8080".parse().unwrap(),
     });
     let upstream_candidates = UpstreamCandidates {
-      inner: vec![upstream],
+      pool: UpstreamPool::new(UpstreamSet {
+        inner: vec![upstream],
+        load_balance: LoadBalance::default(),
+      }),
       path: "/".into(),
       replace_path: None,
-      load_balance: LoadBalance::default(),
       options: HashSet::from_iter([UpstreamOption::SetUpstreamHost]),
+      grpc_web_allowed_origins: vec![],
       #[cfg(feature = "health-check")]
       health_check_config: None,
+      resolve: None,
+      upstream_file: None,
+      upstream_tls: None,
+      #[cfg(feature = "proxy-protocol")]
+      send_proxy_protocol: None,
+      #[cfg(feature = "cache")]
+      cache_rule: None,
     };
 
     apply_upstream_options_to_header(
       &mut headers,
       Some("app.example"),
-      &upstream_candidates.inner[0],
+      &upstream_candidates.pool.load().inner[0],
       &upstream_candidates,
       &[],
     )
@@ -257,19 +310,29 @@ mod tests {
       inner: "http://backend.internal:8080".parse().unwrap(),
     });
     let upstream_candidates = UpstreamCandidates {
-      inner: vec![upstream],
+      pool: UpstreamPool::new(UpstreamSet {
+        inner: vec![upstream],
+        load_balance: LoadBalance::default(),
+      }),
       path: "/".into(),
       replace_path: None,
-      load_balance: LoadBalance::default(),
       options: HashSet::from_iter([UpstreamOption::SetUpstreamHost, UpstreamOption::KeepOriginalHost]),
+      grpc_web_allowed_origins: vec![],
       #[cfg(feature = "health-check")]
       health_check_config: None,
+      resolve: None,
+      upstream_file: None,
+      upstream_tls: None,
+      #[cfg(feature = "proxy-protocol")]
+      send_proxy_protocol: None,
+      #[cfg(feature = "cache")]
+      cache_rule: None,
     };
 
     apply_upstream_options_to_header(
       &mut headers,
       Some("app.example"),
-      &upstream_candidates.inner[0],
+      &upstream_candidates.pool.load().inner[0],
       &upstream_candidates,
       &[],
     )
@@ -290,19 +353,29 @@ mod tests {
     });
     assert!(upstream.host_header().is_none());
     let upstream_candidates = UpstreamCandidates {
-      inner: vec![upstream],
+      pool: UpstreamPool::new(UpstreamSet {
+        inner: vec![upstream],
+        load_balance: LoadBalance::default(),
+      }),
       path: "/".into(),
       replace_path: None,
-      load_balance: LoadBalance::default(),
       options: HashSet::from_iter([UpstreamOption::SetUpstreamHost]),
+      grpc_web_allowed_origins: vec![],
       #[cfg(feature = "health-check")]
       health_check_config: None,
+      resolve: None,
+      upstream_file: None,
+      upstream_tls: None,
+      #[cfg(feature = "proxy-protocol")]
+      send_proxy_protocol: None,
+      #[cfg(feature = "cache")]
+      cache_rule: None,
     };
 
     let err = apply_upstream_options_to_header(
       &mut headers,
       Some("app.example"),
-      &upstream_candidates.inner[0],
+      &upstream_candidates.pool.load().inner[0],
       &upstream_candidates,
       &[],
     )
@@ -3,6 +3,9 @@ use crate::{log::*, message_handler::header_ops};
 use http::header;
 use std::{borrow::Cow, net::SocketAddr};
 
+#[cfg(feature = "cache")]
+use crate::forwarder::CacheStatus;
+
 /// Placeholder substituted for redacted query-string values in the access log.
 const REDACTED: &str = "<redacted>";
 
@@ -102,6 +105,9 @@ pub struct HttpMessageLog {
   status: Option<http::StatusCode>,
   uri: LoggedUri,
   upstream: Option<LoggedUpstream>,
+  /// How the cache handled the response, with the key masked like the request URI under redaction
+  #[cfg(feature = "cache")]
+  cache_status: Option<CacheStatus>,
   /// Whether query-string values are masked. Set at construction; consulted by the `upstream`
   /// setter (which runs after `new()`).
   redact_query: bool,
@@ -142,6 +148,8 @@ impl HttpMessageLog {
       status: None,
       uri,
       upstream: None,
+      #[cfg(feature = "cache")]
+      cache_status: None,
       redact_query,
     }
   }
@@ -194,7 +202,12 @@ impl std::fmt::Display for HttpMessageLog {
       f,
       "{} <- {} -- {} {} {:?} -- {} -- {} \"{}\", \"{}\"{} \"{}\"",
       host, client_addr, self.method, p_and_q, self.version, status, target, ua, xff, forwarded_part, upstream
-    )
+    )?;
+    #[cfg(feature = "cache")]
+    if let Some(cache_status) = &self.cache_status {
+      write!(f, " \"{cache_status}\"")?;
+    }
+    Ok(())
   }
 }
 
@@ -220,6 +233,16 @@ impl HttpMessageLog {
     self
   }
 
+  #[cfg(feature = "cache")]
+  pub(crate) fn cache_status(&mut self, cache_status: &CacheStatus) -> &mut Self {
+    let mut cache_status = cache_status.clone();
+    if self.redact_query {
+      cache_status.key = cache_status.key.map(|key| redact_query_values(&key).into_owned());
+    }
+    self.cache_status = Some(cache_status);
+    self
+  }
+
   pub fn output(&self) {
     info!(
       name: crate::constants::log_event_names::ACCESS_LOG,
@@ -249,6 +272,8 @@ mod tests {
       // Production upstreams always carry a path; the explicit path also avoids `Uri::to_string`
       // normalizing an authority-only URI to a trailing "/".
       upstream: Some(LoggedUpstream::Verbatim("https://backend.example.com/api".parse().unwrap())),
+      #[cfg(feature = "cache")]
+      cache_status: None,
       redact_query: false,
     }
   }
@@ -337,6 +362,25 @@ mod tests {
     assert_eq!(out, "/p?&a=<redacted>");
   }
 
+  #[cfg(feature = "cache")]
+  #[test]
+  fn cache_status_is_appended_with_key_redacted() {
+    let req = http::Request::builder()
+      .uri("https://example.com/a?token=abc123")
+      .body(())
+      .unwrap();
+    let status = CacheStatus::hit("https://example.com/a?token=abc123".to_string(), 10);
+    let mut log = HttpMessageLog::new(&req, false);
+    log.cache_status(&status);
+    assert!(format!("{log}").ends_with(" \"rpxy; hit; ttl=10; key=\"https://example.com/a?token=abc123\"\""));
+
+    let mut log = HttpMessageLog::new(&req, true);
+    log.cache_status(&status);
+    let formatted = format!("{log}");
+    assert!(formatted.ends_with(" \"rpxy; hit; ttl=10; key=\"https://example.com/a?token=<redacted>\"\""));
+    assert!(!formatted.contains("abc123"), "token value leaked: {formatted}");
+  }
+
   #[test]
   fn new_redacts_query_when_enabled() {
     let req = http::Request::builder()
@@ -1,4 +1,5 @@
 mod canonical_address;
+mod grpc_web;
 mod handler_main;
 mod handler_manipulate_messages;
 mod header_ops;
@@ -6,6 +7,7 @@ mod http_log;
 mod http_result;
 mod request_ops;
 mod synthetic_response;
+mod upgrade;
 
 pub use handler_main::HttpMessageHandlerBuilderError;
 pub(crate) use handler_main::{HttpMessageHandler, HttpMessageHandlerBuilder};
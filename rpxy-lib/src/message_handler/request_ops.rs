@@ -1,5 +1,6 @@
 use crate::{
   backend::{Upstream, UpstreamCandidates, UpstreamOption},
+  hyper_ext::unix::is_unix_socket_uri,
   log::*,
 };
 use anyhow::{Result, anyhow, ensure};
@@ -79,8 +80,8 @@ pub(super) fn update_request_line<B>(
   }
 
   // If not specified (force_httpXX_upstream) and https, version is preserved except for http/3
-  if upstream_chosen.uri.scheme() == Some(&Scheme::HTTP) {
-    // Change version to http/1.1 when destination scheme is http
+  if upstream_chosen.uri.scheme() == Some(&Scheme::HTTP) || is_unix_socket_uri(&upstream_chosen.uri) {
+    // Change version to http/1.1 when destination scheme is http (or a cleartext Unix domain socket)
     debug!("Change version to http/1.1 when destination scheme is http unless upstream option enabled.");
     *req.version_mut() = Version::HTTP_11;
   } else if req.version() == Version::HTTP_3 {
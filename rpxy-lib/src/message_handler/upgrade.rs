@@ -0,0 +1,197 @@
+use super::{
+  header_ops::extract_upgrade,
+  http_result::{HttpError, HttpResult},
+};
+use crate::{backend::UpstreamOption, log::*};
+use ahash::HashSet;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use http::{HeaderValue, Method, Request, Response, StatusCode, Version, header};
+use hyper::ext::Protocol;
+
+/// Protocol name of WebSocket given in `Upgrade` and `:protocol`
+const WEBSOCKET: &str = "websocket";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Protocol switch requested by a client
+pub(super) enum UpgradeRequest {
+  /// HTTP/1.1 `Upgrade` mechanism with the requested protocol
+  Http11(String),
+  /// WebSocket over HTTP/2 extended CONNECT (RFC 8441)
+  ExtendedConnect,
+}
+
+impl UpgradeRequest {
+  /// Protocol to be switched to
+  pub(super) fn protocol(&self) -> &str {
+    match self {
+      Self::Http11(protocol) => protocol,
+      Self::ExtendedConnect => WEBSOCKET,
+    }
+  }
+}
+
+/// Extract the protocol switch requested in the request, if any.
+/// Extended CONNECT is accepted only for WebSocket, and `Upgrade` only for HTTP/1.1.
+pub(super) fn extract_upgrade_request<B>(req: &Request<B>) -> HttpResult<Option<UpgradeRequest>> {
+  if req.method() == Method::CONNECT
+    && let Some(protocol) = req.extensions().get::<Protocol>()
+  {
+    if !protocol.as_str().eq_ignore_ascii_case(WEBSOCKET) {
+      return Err(HttpError::FailedToUpgrade(format!(
+        "Unsupported protocol in extended CONNECT: {}",
+        protocol.as_str()
+      )));
+    }
+    debug!("Extended CONNECT for {:?}", req.version());
+    return Ok(Some(UpgradeRequest::ExtendedConnect));
+  }
+
+  let Some(upgrade) = extract_upgrade(req.headers()) else {
+    return Ok(None);
+  };
+  if req.version() != Version::HTTP_11 {
+    return Err(HttpError::FailedToUpgrade(format!(
+      "Unsupported HTTP version: {:?}",
+      req.version()
+    )));
+  }
+  Ok(Some(UpgradeRequest::Http11(upgrade)))
+}
+
+/// Rewrite the request line and headers of an upgrade request to be forwarded upstream, after hop headers are removed.
+///
+/// HTTP/1.1 upgrades are forwarded as they are. Extended CONNECT is forwarded as is over HTTP/2 if `force_http2_upstream`
+/// is set for the upstream group, and otherwise translated into an HTTP/1.1 WebSocket handshake.
+pub(super) fn apply_upgrade_to_request<B>(
+  req: &mut Request<B>,
+  upgrade: &UpgradeRequest,
+  upstream_options: &HashSet<UpstreamOption>,
+) -> anyhow::Result<()> {
+  if matches!(upgrade, UpgradeRequest::ExtendedConnect) && upstream_options.contains(&UpstreamOption::ForceHttp2Upstream) {
+    // `:protocol` is carried in the request extension as it is.
+    *req.version_mut() = Version::HTTP_2;
+    return Ok(());
+  }
+
+  if matches!(upgrade, UpgradeRequest::ExtendedConnect) {
+    *req.method_mut() = Method::GET;
+    req.extensions_mut().remove::<Protocol>();
+    // RFC 8441 omits the key since the stream already proves the handshake, so a fresh one is needed for HTTP/1.1.
+    let key: [u8; 16] = rand::random();
+    req
+      .headers_mut()
+      .insert(header::SEC_WEBSOCKET_KEY, HeaderValue::from_str(&STANDARD.encode(key))?);
+  }
+  *req.version_mut() = Version::HTTP_11;
+  req.headers_mut().insert(header::UPGRADE, upgrade.protocol().parse()?);
+  req
+    .headers_mut()
+    .insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+  Ok(())
+}
+
+/// Check if the upstream accepted the protocol switch, with `101 Switching Protocols` to the requested protocol
+/// for HTTP/1.1, and with any successful status for extended CONNECT.
+pub(super) fn is_upgrade_accepted<B>(res: &Response<B>, upgrade: &UpgradeRequest, upstream_extended_connect: bool) -> bool {
+  if upstream_extended_connect {
+    return res.status().is_success();
+  }
+  res.status() == StatusCode::SWITCHING_PROTOCOLS
+    && extract_upgrade(res.headers()).is_some_and(|u| u.eq_ignore_ascii_case(upgrade.protocol()))
+}
+
+/// Turn the `101 Switching Protocols` response from an HTTP/1.1 upstream into the `200 OK` response opening the
+/// extended CONNECT tunnel. Hop headers are removed afterwards with the other ones.
+pub(super) fn convert_switching_response_for_extended_connect<B>(res: &mut Response<B>) {
+  if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+    *res.status_mut() = StatusCode::OK;
+    res.headers_mut().remove(header::SEC_WEBSOCKET_ACCEPT);
+  }
+  // A successful response to CONNECT must not have any content.
+  res.headers_mut().remove(header::CONTENT_LENGTH);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn extended_connect(protocol: &'static str) -> Request<()> {
+    let mut req = Request::builder()
+      .method(Method::CONNECT)
+      .version(Version::HTTP_2)
+      .uri("https://example.com/chat")
+      .header(header::SEC_WEBSOCKET_VERSION, "13")
+      .body(())
+      .unwrap();
+    req.extensions_mut().insert(Protocol::from_static(protocol));
+    req
+  }
+
+  #[test]
+  fn extract_upgrade_request_kinds() {
+    let req = Request::builder()
+      .header(header::CONNECTION, "Upgrade")
+      .header(header::UPGRADE, "websocket")
+      .body(())
+      .unwrap();
+    assert_eq!(
+      extract_upgrade_request(&req).unwrap(),
+      Some(UpgradeRequest::Http11("websocket".to_string()))
+    );
+    assert_eq!(
+      extract_upgrade_request(&extended_connect("websocket")).unwrap(),
+      Some(UpgradeRequest::ExtendedConnect)
+    );
+    assert!(extract_upgrade_request(&extended_connect("connect-udp")).is_err());
+
+    let mut req = req;
+    *req.version_mut() = Version::HTTP_2;
+    assert!(extract_upgrade_request(&req).is_err());
+    assert_eq!(extract_upgrade_request(&Request::new(())).unwrap(), None);
+  }
+
+  #[test]
+  fn extended_connect_is_translated_to_http11_handshake() {
+    let mut req = extended_connect("websocket");
+    apply_upgrade_to_request(&mut req, &UpgradeRequest::ExtendedConnect, &HashSet::default()).unwrap();
+    assert_eq!(req.method(), Method::GET);
+    assert_eq!(req.version(), Version::HTTP_11);
+    assert!(req.extensions().get::<Protocol>().is_none());
+    assert_eq!(req.headers().get(header::UPGRADE).unwrap(), "websocket");
+    assert_eq!(req.headers().get(header::CONNECTION).unwrap(), "upgrade");
+    assert_eq!(req.headers().get(header::SEC_WEBSOCKET_VERSION).unwrap(), "13");
+    let key = req.headers().get(header::SEC_WEBSOCKET_KEY).unwrap();
+    assert_eq!(STANDARD.decode(key.as_bytes()).unwrap().len(), 16);
+  }
+
+  #[test]
+  fn extended_connect_is_kept_for_http2_upstream() {
+    let mut req = extended_connect("websocket");
+    let options = HashSet::from_iter([UpstreamOption::ForceHttp2Upstream]);
+    apply_upgrade_to_request(&mut req, &UpgradeRequest::ExtendedConnect, &options).unwrap();
+    assert_eq!(req.method(), Method::CONNECT);
+    assert_eq!(req.version(), Version::HTTP_2);
+    assert_eq!(req.extensions().get::<Protocol>().unwrap().as_str(), "websocket");
+    assert!(req.headers().get(header::UPGRADE).is_none());
+    assert!(req.headers().get(header::SEC_WEBSOCKET_KEY).is_none());
+  }
+
+  #[test]
+  fn upgrade_acceptance_and_response_conversion() {
+    let mut res = Response::builder()
+      .status(StatusCode::SWITCHING_PROTOCOLS)
+      .header(header::CONNECTION, "upgrade")
+      .header(header::UPGRADE, "WebSocket")
+      .header(header::SEC_WEBSOCKET_ACCEPT, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+      .body(())
+      .unwrap();
+    assert!(is_upgrade_accepted(&res, &UpgradeRequest::ExtendedConnect, false));
+    assert!(!is_upgrade_accepted(&res, &UpgradeRequest::Http11("h2c".to_string()), false));
+    assert!(!is_upgrade_accepted(&res, &UpgradeRequest::ExtendedConnect, true));
+
+    convert_switching_response_for_extended_connect(&mut res);
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get(header::SEC_WEBSOCKET_ACCEPT).is_none());
+    assert!(is_upgrade_accepted(&res, &UpgradeRequest::ExtendedConnect, true));
+  }
+}
@@ -1,7 +1,10 @@
 mod proxy_main;
 #[cfg(feature = "proxy-protocol")]
 mod proxy_protocol;
+mod proxy_stream;
+mod proxy_tls_passthrough;
 mod socket;
+mod stream_relay;
 
 #[cfg(any(feature = "http3-quinn", feature = "http3-s2n"))]
 mod proxy_h3;
@@ -23,6 +26,8 @@ use std::sync::Arc;
 pub type SniServerCryptoMap = std::collections::HashMap<ServerName, ServerCryptoForSni, ahash::RandomState>;
 
 pub use proxy_main::{ListenerKind, ListenerSpecBuilder, ListenerSpecBuilderError, ProxyBuilder, ProxyBuilderError};
+pub(crate) use proxy_stream::StreamProxy;
+pub(crate) use socket::bind_tcp_socket;
 
 /// build connection builder shared with proxy instances
 pub(crate) fn connection_builder(globals: &Arc<Globals>) -> Arc<ConnectionBuilder<LocalExecutor>> {
@@ -39,9 +44,11 @@ pub(crate) fn connection_builder(globals: &Arc<Globals>) -> Arc<ConnectionBuilde
     .keep_alive(globals.proxy_config.keepalive)
     .header_read_timeout(globals.proxy_config.proxy_idle_timeout)
     .timer(TokioTimer);
+  // Advertise SETTINGS_ENABLE_CONNECT_PROTOCOL to accept WebSocket over extended CONNECT (RFC 8441).
   http_server
     .http2()
-    .max_concurrent_streams(globals.proxy_config.max_concurrent_streams);
+    .max_concurrent_streams(globals.proxy_config.max_concurrent_streams)
+    .enable_connect_protocol();
 
   if globals.proxy_config.keepalive {
     http_server
@@ -38,6 +38,8 @@ where
       return Ok(());
     };
 
+    // TODO: Advertise extended CONNECT (RFC 9220) for WebSocket once the h3 crate accepts `:protocol = websocket`
+    // (see TODO.md). Until then, WebSocket clients use HTTP/2 or HTTP/1.1 instead.
     let mut h3_conn = h3::server::Connection::<_, Bytes>::new(quic_connection).await?;
     debug!("QUIC/HTTP3 connection established from {:?} {}", client_addr, tls_server_name);
 
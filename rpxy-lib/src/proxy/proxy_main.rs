@@ -1,5 +1,9 @@
-use super::socket::bind_tcp_socket;
+use super::{
+  proxy_tls_passthrough::{peek_client_hello_sni, serve_tls_passthrough},
+  socket::bind_tcp_socket,
+};
 use crate::{
+  backend::{BackendApp, BackendAppManager},
   constants::TLS_HANDSHAKE_TIMEOUT_SEC,
   count::PerIpConnectionGuard,
   error::*,
@@ -174,7 +178,7 @@ const PROXY_PROTOCOL_FALLBACK_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[cfg(feature = "proxy-protocol")]
 /// Extracts and parses the PROXY protocol header from the given TCP stream, returning the real client address.
-async fn extract_parse_result_from_proxy_protocol_header(
+pub(super) async fn extract_parse_result_from_proxy_protocol_header(
   stream: &mut TcpStream,
   peer_addr: SocketAddr,
   pp_config: &TcpRecvProxyProtocolConfig,
@@ -271,6 +275,8 @@ where
   pub(crate) connection_builder: Arc<ConnectionBuilder<E>>,
   /// message handler serving incoming http request
   pub(crate) message_handler: Arc<HttpMessageHandler<T>>,
+  /// backend apps, looked up by SNI for TLS passthrough
+  pub(crate) app_manager: Arc<BackendAppManager>,
   /// listener spec
   pub(crate) listener_spec: ListenerSpec,
 }
@@ -334,6 +340,28 @@ where
     });
   }
 
+  /// Pipes a connection passed through to the upstream without TLS termination.
+  /// It is counted as a client, and closed when idle instead of by the connection handling timeout since
+  /// passed-through connections are as long-lived as the application protocol on top of TLS.
+  async fn serve_tls_passthrough_connection(
+    &self,
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    backend_app: &BackendApp,
+    per_ip_guard: PerIpConnectionGuard,
+  ) {
+    let _per_ip_guard = per_ip_guard;
+    let request_count = self.globals.request_count.clone();
+    if request_count.increment() >= self.globals.proxy_config.max_clients {
+      request_count.decrement();
+      return;
+    }
+    if let Err(e) = serve_tls_passthrough(stream, peer_addr, self.listener_spec.listening_on, backend_app).await {
+      warn!("TLS passthrough for {peer_addr} failed: {e}");
+    }
+    request_count.decrement();
+  }
+
   /// Start without TLS (HTTP cleartext)
   async fn start_without_tls(&self) -> RpxyResult<()> {
     let listener_service = async {
@@ -481,10 +509,12 @@ where
             warn!("No valid certificates loaded yet, waiting for next reload cycle");
             continue;
           };
+          // Certificates only for TLS-terminating streams are not served at the HTTPS listener.
           let map = server_config.individual_config_map.clone().iter().map(|(k,v)| {
             let server_name = ServerName::from(k.as_slice());
             (server_name, v.clone())
-          }).collect::<std::collections::HashMap<_,_,ahash::RandomState>>();
+          }).filter(|(server_name, _)| self.app_manager.apps.contains_key(server_name))
+          .collect::<std::collections::HashMap<_,_,ahash::RandomState>>();
           server_crypto_map = Some(Arc::new(map));
           info!("TLS certificates updated successfully");
         }
@@ -493,14 +523,16 @@ where
     Ok(())
   }
 
-  /// Handle TCP connection at TLS listener, including PROXY protocol parsing if enabled, TLS handshake, and then serve the connection for HTTP/1.1 and HTTP/2
+  /// Handle TCP connection at TLS listener, including PROXY protocol parsing if enabled, TLS handshake, and then serve the connection for HTTP/1.1 and HTTP/2.
+  /// Connections to apps passing TLS through are piped to the upstream instead, found by SNI peeked before the handshake.
   fn serve_tls_tcp_connection(
     &self,
     tcp_cnx: Result<(TcpStream, SocketAddr), std::io::Error>,
     server_crypto_map: &Option<Arc<super::SniServerCryptoMap>>,
     #[cfg(feature = "proxy-protocol")] pp_semaphore: &Arc<tokio::sync::Semaphore>,
   ) {
-    if tcp_cnx.is_err() || server_crypto_map.is_none() {
+    let tls_passthrough = self.app_manager.tls_passthrough;
+    if tcp_cnx.is_err() || (server_crypto_map.is_none() && !tls_passthrough) {
       return;
     }
 
@@ -527,8 +559,8 @@ where
       None
     };
 
-    // Clone necessary variables for async task, server_crypto_map was confirmed to be `Some` above, so unwrap is safe here.
-    let server_crypto_map = server_crypto_map.clone().unwrap();
+    // Clone necessary variables for async task. server_crypto_map may be `None` only if some apps pass TLS through.
+    let server_crypto_map = server_crypto_map.clone();
     let self_inner = self.clone();
     #[cfg(feature = "acme")]
     let server_configs_acme_challenge = self.globals.server_configs_acme_challenge.clone();
@@ -562,6 +594,26 @@ where
         return;
       };
 
+      // [TLS-PASSTHROUGH] Peek SNI in ClientHello, and pipe the connection as it is if the app passes TLS through
+      if tls_passthrough {
+        let peek_fut = peek_client_hello_sni(&raw_stream);
+        let Ok(Ok(sni)) = timeout(Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SEC), peek_fut).await else {
+          warn!(peer = %client_addr, sni = "-", failure = "client_hello", "TLS handshake failed");
+          return;
+        };
+        let app_manager = self_inner.app_manager.clone();
+        if let Some(backend_app) = sni.and_then(|sni| app_manager.tls_passthrough_app(&ServerName::from(sni.as_str()))) {
+          self_inner
+            .serve_tls_passthrough_connection(raw_stream, client_addr, backend_app, per_ip_guard)
+            .await;
+          return;
+        }
+      }
+      let Some(server_crypto_map) = server_crypto_map else {
+        debug!("No valid certificates loaded yet, dropping connection from {client_addr}");
+        return;
+      };
+
       #[cfg(feature = "acme")]
       let tls_handshake_fut = serve_tls_handshake(raw_stream, client_addr, server_configs_acme_challenge, server_crypto_map);
       #[cfg(not(feature = "acme"))]
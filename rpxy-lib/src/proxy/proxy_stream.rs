@@ -0,0 +1,392 @@
+use super::{
+  socket::{bind_tcp_socket, bind_udp_socket},
+  stream_relay::{Activity, connect_upstream, relay, upstream_tcp_addr},
+};
+use crate::{
+  backend::StreamBackend,
+  constants::{
+    TLS_HANDSHAKE_TIMEOUT_SEC,
+    stream::{CONNECT_TIMEOUT_SEC, UDP_MAX_DATAGRAM_SIZE, UDP_MAX_QUEUED_DATAGRAMS},
+  },
+  count::PerIpConnectionGuard,
+  error::*,
+  globals::{Globals, StreamProtocol},
+  hyper_ext::unix::is_unix_socket_uri,
+  log::*,
+};
+use ahash::HashMap;
+use futures::{FutureExt, select};
+use hot_reload::ReloaderReceiver;
+use rpxy_certs::ServerCryptoBase;
+use std::{
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite},
+  net::{TcpStream, UdpSocket},
+  time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+
+/// Client address to UDP session map
+type UdpSessionMap = HashMap<SocketAddr, UdpSessionEntry>;
+
+/// Stream proxy relaying TCP connections or UDP datagrams received at its listen socket to the upstream server(s)
+#[derive(Clone)]
+pub(crate) struct StreamProxy {
+  /// global context shared among async tasks
+  pub globals: Arc<Globals>,
+  /// stream configuration and its upstream server(s)
+  pub stream: Arc<StreamBackend>,
+}
+
+impl StreamProxy {
+  /// Name of the stream, used in logs
+  pub(crate) fn name(&self) -> &str {
+    &self.stream.config.name
+  }
+
+  /// Entrypoint for the stream proxy
+  pub(crate) async fn start(&self) -> RpxyResult<()> {
+    match self.stream.config.protocol {
+      StreamProtocol::Tcp => self.tcp_listener_service().await,
+      StreamProtocol::Udp => self.udp_listener_service().await,
+    }
+  }
+
+  /* -------------------------------------------------------------------------------------------------------- */
+  /// TCP listener service, terminating TLS before relaying if configured
+  async fn tcp_listener_service(&self) -> RpxyResult<()> {
+    let config = &self.stream.config;
+    let mut server_crypto_rx = match config.tls_server_name {
+      Some(_) => Some(
+        self
+          .globals
+          .cert_reloader_rx
+          .clone()
+          .ok_or(RpxyError::NoCertificateReloader)?,
+      ),
+      None => None,
+    };
+    let tcp_socket = bind_tcp_socket(&config.listen)?;
+    let tcp_listener = tcp_socket.listen(self.globals.proxy_config.tcp_listen_backlog)?;
+    info!("Start TCP stream proxy {} on {}", config.name, config.listen);
+
+    let mut tls_acceptor: Option<TlsAcceptor> = None;
+    loop {
+      select! {
+        tcp_cnx = tcp_listener.accept().fuse() => {
+          let Ok((stream, client_addr)) = tcp_cnx else {
+            continue;
+          };
+          trace!("Accepted TCP connection from {client_addr} at stream {}", config.name);
+          if config.tls_server_name.is_some() && tls_acceptor.is_none() {
+            debug!("No valid certificate loaded yet for stream {}, dropping connection from {client_addr}", config.name);
+            continue;
+          }
+          self.serve_tcp_connection(stream, client_addr, tls_acceptor.clone());
+        }
+        // Listen for certificate updates from the reloader, only if TLS is terminated
+        updated = certificate_updated(&mut server_crypto_rx).fuse() => {
+          let Some(server_crypto_base) = updated else {
+            error!("Reloader is broken");
+            break;
+          };
+          let server_name = config.tls_server_name.as_deref().unwrap_or_default();
+          match server_crypto_base.build_stream_server_config(server_name) {
+            Ok(Some(server_config)) => {
+              tls_acceptor = Some(TlsAcceptor::from(server_config));
+              info!("TLS certificate for stream {} updated successfully", config.name);
+            }
+            // Don't break the loop - certs might become available later (e.g., ACME provisioning)
+            Ok(None) => warn!("No certificate for {server_name} loaded yet for stream {}, waiting for next reload cycle", config.name),
+            Err(e) => warn!("Failed to build TLS config for stream {}: {e}", config.name),
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Handle a TCP connection, including PROXY protocol parsing and TLS handshake if enabled, and relay it to an upstream.
+  /// It is counted as a client as with the connections of HTTP listeners.
+  fn serve_tcp_connection(&self, stream: TcpStream, client_addr: SocketAddr, tls_acceptor: Option<TlsAcceptor>) {
+    let request_count = self.globals.request_count.clone();
+    if request_count.increment() >= self.globals.proxy_config.max_clients {
+      request_count.decrement();
+      return;
+    }
+    if let Err(e) = stream.set_nodelay(true) {
+      debug!("Failed to set TCP_NODELAY for {client_addr}: {e}");
+    }
+
+    let self_inner = self.clone();
+    self.globals.runtime_handle.spawn(async move {
+      self_inner.handle_tcp_connection(stream, client_addr, tls_acceptor).await;
+      request_count.decrement();
+    });
+  }
+
+  async fn handle_tcp_connection(&self, stream: TcpStream, client_addr: SocketAddr, tls_acceptor: Option<TlsAcceptor>) {
+    let name = self.name();
+
+    // [PROXY-PROTOCOL] Parse PROXY header before TLS handshake, and obtain the real client address
+    #[cfg(feature = "proxy-protocol")]
+    let mut stream = stream;
+    #[cfg(feature = "proxy-protocol")]
+    let client_addr = match self.stream.config.recv_proxy_protocol.as_ref() {
+      Some(pp_config) => {
+        match super::proxy_main::extract_parse_result_from_proxy_protocol_header(&mut stream, client_addr, pp_config).await {
+          Ok(addr) => addr,
+          Err(e) => {
+            warn!("Failed to parse PROXY header: {e}. Closing connection from {client_addr} at stream {name}");
+            return;
+          }
+        }
+      }
+      None => client_addr,
+    };
+
+    let Some(per_ip_guard) = self.globals.per_ip_connection_count.try_acquire(client_addr.ip()) else {
+      debug!("Per-IP connection limit reached for {client_addr}, dropping connection at stream {name}");
+      return;
+    };
+
+    let res = match tls_acceptor {
+      Some(tls_acceptor) => {
+        let handshake = timeout(Duration::from_secs(TLS_HANDSHAKE_TIMEOUT_SEC), tls_acceptor.accept(stream)).await;
+        match handshake {
+          Ok(Ok(tls_stream)) => self.relay_to_upstream(tls_stream, client_addr, per_ip_guard).await,
+          Ok(Err(e)) => {
+            warn!(peer = %client_addr, stream = name, failure = "handshake", reason = %e, "TLS handshake failed");
+            return;
+          }
+          Err(_) => {
+            warn!(peer = %client_addr, stream = name, failure = "timeout", "TLS handshake failed");
+            return;
+          }
+        }
+      }
+      None => self.relay_to_upstream(stream, client_addr, per_ip_guard).await,
+    };
+    if let Err(e) = res {
+      warn!("Stream {name} for {client_addr} failed: {e}");
+    }
+  }
+
+  /// Connect to an upstream chosen by the load balancer, and relay the client stream until either side closes or it goes idle
+  async fn relay_to_upstream<S>(
+    &self,
+    mut client: S,
+    client_addr: SocketAddr,
+    per_ip_guard: PerIpConnectionGuard,
+  ) -> RpxyResult<()>
+  where
+    S: AsyncRead + AsyncWrite + Unpin,
+  {
+    let _per_ip_guard = per_ip_guard;
+    let name = self.name();
+    let (upstream, _) = self.stream.upstream_candidates.pool.load().get(&None);
+    let upstream = upstream.ok_or_else(|| RpxyError::NoStreamUpstream(name.to_string()))?;
+    debug!("Stream {name} from {client_addr} to {}", upstream.uri);
+
+    // Ports of TCP upstreams are mandatory for streams, so the default port is never used.
+    let mut upstream_stream = connect_upstream(&upstream.uri, 0, Duration::from_secs(CONNECT_TIMEOUT_SEC)).await?;
+    relay(
+      &format!("Stream {name}"),
+      &mut client,
+      &mut upstream_stream,
+      Some(self.stream.config.idle_timeout),
+    )
+    .await
+  }
+
+  /* -------------------------------------------------------------------------------------------------------- */
+  /// UDP listener service. Datagrams from each client address are relayed over a session with its own upstream
+  /// socket, which is closed once no datagram is relayed in either direction for the idle timeout.
+  async fn udp_listener_service(&self) -> RpxyResult<()> {
+    let config = &self.stream.config;
+    let socket = Arc::new(UdpSocket::from_std(bind_udp_socket(&config.listen)?)?);
+    info!("Start UDP stream proxy {} on {}", config.name, config.listen);
+
+    let sessions: Arc<Mutex<UdpSessionMap>> = Default::default();
+    let mut buf = vec![0u8; UDP_MAX_DATAGRAM_SIZE];
+    loop {
+      let (len, client_addr) = match socket.recv_from(&mut buf).await {
+        Ok(received) => received,
+        Err(e) => {
+          // e.g., ICMP port unreachable reported for a previous datagram sent to a client
+          debug!("Failed to receive datagram at stream {}: {e}", config.name);
+          continue;
+        }
+      };
+      let datagram = &buf[..len];
+      let session = {
+        let mut sessions_map = lock_sessions(&sessions);
+        match sessions_map.get_mut(&client_addr) {
+          Some(UdpSessionEntry::Open(session)) => session.clone(),
+          Some(UdpSessionEntry::Opening(queued)) => {
+            if queued.len() < UDP_MAX_QUEUED_DATAGRAMS {
+              queued.push(datagram.to_vec());
+            }
+            continue;
+          }
+          None => {
+            // The upstream is resolved and connected in the session task, not to block datagrams of other clients
+            if let Some(per_ip_guard) = self.admit_udp_client(client_addr) {
+              sessions_map.insert(client_addr, UdpSessionEntry::Opening(vec![datagram.to_vec()]));
+              self.spawn_udp_session(&socket, &sessions, client_addr, per_ip_guard);
+            }
+            continue;
+          }
+        }
+      };
+      session.activity.touch();
+      if let Err(e) = session.upstream.send(datagram).await {
+        debug!("Failed to send datagram to upstream at stream {}: {e}", config.name);
+      }
+    }
+  }
+
+  /// Admit a new client address within the limits on the number of clients, counting it as a client.
+  /// Returns None if the client is not accepted.
+  fn admit_udp_client(&self, client_addr: SocketAddr) -> Option<PerIpConnectionGuard> {
+    let name = self.name();
+    let Some(per_ip_guard) = self.globals.per_ip_connection_count.try_acquire(client_addr.ip()) else {
+      debug!("Per-IP connection limit reached for {client_addr}, dropping datagram at stream {name}");
+      return None;
+    };
+    let request_count = &self.globals.request_count;
+    if request_count.increment() >= self.globals.proxy_config.max_clients {
+      request_count.decrement();
+      return None;
+    }
+    Some(per_ip_guard)
+  }
+
+  /// Spawn the session of an admitted client address, which connects to the upstream, relays the datagrams queued
+  /// meanwhile, and then relays datagrams from the upstream back to the client until it goes idle.
+  fn spawn_udp_session(
+    &self,
+    socket: &Arc<UdpSocket>,
+    sessions: &Arc<Mutex<UdpSessionMap>>,
+    client_addr: SocketAddr,
+    per_ip_guard: PerIpConnectionGuard,
+  ) {
+    let proxy = self.clone();
+    let socket = socket.clone();
+    let sessions = sessions.clone();
+    self.globals.runtime_handle.spawn(async move {
+      let _per_ip_guard = per_ip_guard;
+      let name = proxy.name();
+      let request_count = proxy.globals.request_count.clone();
+      let upstream_socket = match proxy.connect_udp_upstream().await {
+        Ok(upstream_socket) => upstream_socket,
+        Err(e) => {
+          warn!("Stream {name} for {client_addr} failed: {e}");
+          lock_sessions(&sessions).remove(&client_addr);
+          request_count.decrement();
+          return;
+        }
+      };
+      if let Ok(upstream_addr) = upstream_socket.peer_addr() {
+        debug!("Stream {name} from {client_addr} to {upstream_addr}");
+      }
+
+      let session = Arc::new(UdpSession {
+        upstream: upstream_socket,
+        activity: Activity::new(),
+      });
+      let queued = match lock_sessions(&sessions).insert(client_addr, UdpSessionEntry::Open(session.clone())) {
+        Some(UdpSessionEntry::Opening(queued)) => queued,
+        _ => Vec::new(),
+      };
+      for datagram in queued.iter() {
+        if let Err(e) = session.upstream.send(datagram).await {
+          debug!("Failed to send datagram to upstream at stream {name}: {e}");
+        }
+      }
+
+      let idle_timeout = proxy.stream.config.idle_timeout;
+      let mut buf = vec![0u8; UDP_MAX_DATAGRAM_SIZE];
+      loop {
+        select! {
+          received = session.upstream.recv(&mut buf).fuse() => {
+            let Ok(len) = received else {
+              // e.g., ICMP port unreachable from the upstream; keep the session until it goes idle
+              continue;
+            };
+            session.activity.touch();
+            if let Err(e) = socket.send_to(&buf[..len], client_addr).await {
+              debug!("Failed to send datagram to {client_addr} at stream {name}: {e}");
+            }
+          }
+          _ = session.activity.idle(idle_timeout).fuse() => {
+            debug!("UDP session of {client_addr} at stream {name} closed after idle for {idle_timeout:?}");
+            break;
+          }
+        }
+      }
+      lock_sessions(&sessions).remove(&client_addr);
+      request_count.decrement();
+    });
+  }
+
+  /// Bind a UDP socket connected to an upstream chosen by the load balancer
+  async fn connect_udp_upstream(&self) -> RpxyResult<UdpSocket> {
+    let (upstream, _) = self.stream.upstream_candidates.pool.load().get(&None);
+    let upstream = upstream.ok_or_else(|| RpxyError::NoStreamUpstream(self.name().to_string()))?;
+    if is_unix_socket_uri(&upstream.uri) {
+      return Err(RpxyError::InvalidUpstreamUri(upstream.uri.to_string()));
+    }
+    let addr = upstream_tcp_addr(&upstream.uri, 0).ok_or_else(|| RpxyError::InvalidUpstreamUri(upstream.uri.to_string()))?;
+    let upstream_addr = timeout(Duration::from_secs(CONNECT_TIMEOUT_SEC), tokio::net::lookup_host(&addr))
+      .await
+      .map_err(|_| RpxyError::FailedToFetchFromUpstream(format!("Resolution of {addr} timed out")))??
+      .next()
+      .ok_or_else(|| RpxyError::FailedToFetchFromUpstream(format!("No address found for {addr}")))?;
+    let bind_addr: SocketAddr = if upstream_addr.is_ipv4() {
+      (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+      (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let upstream_socket = UdpSocket::bind(bind_addr).await?;
+    upstream_socket.connect(upstream_addr).await?;
+    Ok(upstream_socket)
+  }
+}
+
+/// Entry of a client address in the UDP session map
+enum UdpSessionEntry {
+  /// The upstream is being connected, with datagrams received meanwhile queued up to `UDP_MAX_QUEUED_DATAGRAMS`
+  Opening(Vec<Vec<u8>>),
+  /// Datagrams are relayed to the upstream
+  Open(Arc<UdpSession>),
+}
+
+/// UDP session of a client address
+struct UdpSession {
+  /// Socket connected to the upstream chosen for the client address
+  upstream: UdpSocket,
+  /// Last datagram relayed in either direction
+  activity: Activity,
+}
+
+/// Lock the session map, recovering from poisoning since the map stays consistent on every single operation
+fn lock_sessions(sessions: &Mutex<UdpSessionMap>) -> std::sync::MutexGuard<'_, UdpSessionMap> {
+  sessions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Wait for the next certificate update from the reloader. It never completes if TLS is not terminated.
+/// Returns None if the reloader is broken.
+async fn certificate_updated(server_crypto_rx: &mut Option<ReloaderReceiver<ServerCryptoBase>>) -> Option<ServerCryptoBase> {
+  let Some(rx) = server_crypto_rx else {
+    return futures::future::pending().await;
+  };
+  if rx.changed().await.is_err() || rx.borrow().is_none() {
+    return None;
+  }
+  rx.get()
+}
@@ -0,0 +1,286 @@
+use super::stream_relay::{connect_upstream, relay};
+use crate::{
+  backend::BackendApp,
+  constants::{TLS_CLIENT_HELLO_PEEK_INTERVAL_MSEC, TLS_PASSTHROUGH_CONNECT_TIMEOUT_SEC, TLS_PASSTHROUGH_IDLE_TIMEOUT_SEC},
+  error::*,
+  log::*,
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+/// TLS record header: content type, legacy version and length
+const TLS_RECORD_HEADER_LEN: usize = 5;
+/// Maximum length of a TLS plaintext record carrying the ClientHello
+const TLS_MAX_RECORD_LEN: usize = 16_384;
+/// Content type of TLS handshake records
+const TLS_CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+/// Handshake type of ClientHello
+const TLS_HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+/// Extension type of server_name (RFC 6066)
+const TLS_EXTENSION_SERVER_NAME: u16 = 0x0000;
+/// Name type of host_name in server_name
+const TLS_SERVER_NAME_HOST_NAME: u8 = 0x00;
+
+#[derive(Debug, PartialEq, Eq)]
+/// Result of parsing the first TLS record received from a client
+enum ClientHelloSni {
+  /// More bytes are needed to read the ClientHello
+  Incomplete,
+  /// Server name given in SNI
+  Found(String),
+  /// Not a ClientHello, or no server name in it. The connection is left to the TLS acceptor.
+  NotFound,
+}
+
+/// Cursor reading big-endian integers and length-prefixed vectors from the ClientHello
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+    if self.0.len() < len {
+      return None;
+    }
+    let (head, tail) = self.0.split_at(len);
+    self.0 = tail;
+    Some(head)
+  }
+  fn u8(&mut self) -> Option<u8> {
+    self.take(1).map(|b| b[0])
+  }
+  fn u16(&mut self) -> Option<u16> {
+    self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+  }
+  fn u24(&mut self) -> Option<usize> {
+    self.take(3).map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+  }
+  fn vec_u8(&mut self) -> Option<&'a [u8]> {
+    let len = self.u8()? as usize;
+    self.take(len)
+  }
+  fn vec_u16(&mut self) -> Option<&'a [u8]> {
+    let len = self.u16()? as usize;
+    self.take(len)
+  }
+}
+
+/// Parse the server name from the ClientHello in the first TLS record.
+/// A ClientHello fragmented over multiple records is not parsed, and is left to the TLS acceptor.
+fn parse_client_hello_sni(buf: &[u8]) -> ClientHelloSni {
+  let mut record = Reader(buf);
+  let (Some(content_type), Some(_version), Some(record_len)) = (record.u8(), record.u16(), record.u16()) else {
+    return ClientHelloSni::Incomplete;
+  };
+  if content_type != TLS_CONTENT_TYPE_HANDSHAKE || record_len as usize > TLS_MAX_RECORD_LEN {
+    return ClientHelloSni::NotFound;
+  }
+  let Some(fragment) = record.take(record_len as usize) else {
+    return ClientHelloSni::Incomplete;
+  };
+  let mut handshake = Reader(fragment);
+  let (Some(TLS_HANDSHAKE_CLIENT_HELLO), Some(len)) = (handshake.u8(), handshake.u24()) else {
+    return ClientHelloSni::NotFound;
+  };
+  let Some(client_hello) = handshake.take(len) else {
+    return ClientHelloSni::NotFound;
+  };
+  find_server_name(client_hello).map_or(ClientHelloSni::NotFound, ClientHelloSni::Found)
+}
+
+/// Find the host_name in the server_name extension of the ClientHello body
+fn find_server_name(client_hello: &[u8]) -> Option<String> {
+  let mut body = Reader(client_hello);
+  body.take(2 + 32)?; // legacy_version, random
+  body.vec_u8()?; // legacy_session_id
+  body.vec_u16()?; // cipher_suites
+  body.vec_u8()?; // legacy_compression_methods
+  let mut extensions = Reader(body.vec_u16()?);
+  while !extensions.0.is_empty() {
+    let extension_type = extensions.u16()?;
+    let data = extensions.vec_u16()?;
+    if extension_type != TLS_EXTENSION_SERVER_NAME {
+      continue;
+    }
+    let mut server_names = Reader(Reader(data).vec_u16()?);
+    while !server_names.0.is_empty() {
+      let name_type = server_names.u8()?;
+      let name = server_names.vec_u16()?;
+      if name_type == TLS_SERVER_NAME_HOST_NAME {
+        return std::str::from_utf8(name).ok().map(str::to_ascii_lowercase);
+      }
+    }
+    return None;
+  }
+  None
+}
+
+/// Peek the ClientHello without consuming it, and return the server name in SNI if any.
+/// The stream is then either handed to the TLS acceptor or piped to the upstream from the first byte.
+/// Peek never tells the end of stream once any byte is buffered, so the deadline is enforced by the caller.
+pub(super) async fn peek_client_hello_sni(stream: &TcpStream) -> std::io::Result<Option<String>> {
+  let mut buf = vec![0u8; TLS_RECORD_HEADER_LEN + TLS_MAX_RECORD_LEN];
+  loop {
+    let n = stream.peek(&mut buf).await?;
+    if n == 0 {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "Connection closed before ClientHello could be read",
+      ));
+    }
+    match parse_client_hello_sni(&buf[..n]) {
+      ClientHelloSni::Found(server_name) => return Ok(Some(server_name)),
+      ClientHelloSni::NotFound => return Ok(None),
+      // Peek returns immediately while any byte is buffered, so back off until the rest arrives.
+      ClientHelloSni::Incomplete => tokio::time::sleep(Duration::from_millis(TLS_CLIENT_HELLO_PEEK_INTERVAL_MSEC)).await,
+    }
+  }
+}
+
+/// Pipe the TLS connection of a client to one of the upstreams of the app chosen by the load balancer,
+/// prefixed with the PROXY protocol header if configured for the upstreams.
+pub(super) async fn serve_tls_passthrough(
+  mut client: TcpStream,
+  client_addr: SocketAddr,
+  listen_addr: SocketAddr,
+  backend_app: &BackendApp,
+) -> RpxyResult<()> {
+  let server_name = backend_app.server_name.to_string();
+  let upstream_candidates = backend_app
+    .path_manager
+    .get("/")
+    .ok_or_else(|| RpxyError::NoTlsPassthroughUpstream(server_name.clone()))?;
+  let (upstream, _) = upstream_candidates.pool.load().get(&None);
+  let upstream = upstream.ok_or_else(|| RpxyError::NoTlsPassthroughUpstream(server_name.clone()))?;
+  debug!("TLS passthrough for {server_name} from {client_addr} to {}", upstream.uri);
+
+  #[cfg(feature = "proxy-protocol")]
+  let header = upstream_candidates
+    .send_proxy_protocol
+    .map(|version| crate::hyper_ext::proxy_protocol::encode_proxy_header(version, client_addr, listen_addr))
+    .transpose()?;
+  #[cfg(not(feature = "proxy-protocol"))]
+  let header: Option<Vec<u8>> = {
+    let _ = listen_addr;
+    None
+  };
+
+  let connect_timeout = Duration::from_secs(TLS_PASSTHROUGH_CONNECT_TIMEOUT_SEC);
+  // The port defaults to 443 since the upstream terminates TLS.
+  let mut upstream_stream = connect_upstream(&upstream.uri, 443, connect_timeout).await?;
+  if let Some(header) = header {
+    upstream_stream.write_all(&header).await?;
+  }
+  let idle_timeout = Duration::from_secs(TLS_PASSTHROUGH_IDLE_TIMEOUT_SEC);
+  relay("TLS passthrough", &mut client, &mut upstream_stream, Some(idle_timeout)).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::proxy::stream_relay::upstream_tcp_addr;
+  use tokio::{io::AsyncReadExt, net::TcpListener};
+
+  /// Build a minimal ClientHello record with the given server name and an extension before it
+  fn client_hello(server_name: Option<&str>) -> Vec<u8> {
+    let mut extensions = vec![0x00, 0x17, 0x00, 0x00]; // extended_master_secret
+    if let Some(name) = server_name {
+      let name = name.as_bytes();
+      let list_len = 3 + name.len();
+      extensions.extend_from_slice(&TLS_EXTENSION_SERVER_NAME.to_be_bytes());
+      extensions.extend_from_slice(&((list_len + 2) as u16).to_be_bytes());
+      extensions.extend_from_slice(&(list_len as u16).to_be_bytes());
+      extensions.push(TLS_SERVER_NAME_HOST_NAME);
+      extensions.extend_from_slice(&(name.len() as u16).to_be_bytes());
+      extensions.extend_from_slice(name);
+    }
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&[0u8; 32]);
+    body.push(0); // session id
+    body.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // cipher suites
+    body.extend_from_slice(&[0x01, 0x00]); // compression methods
+    body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    body.extend_from_slice(&extensions);
+
+    let mut handshake = vec![TLS_HANDSHAKE_CLIENT_HELLO];
+    handshake.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&body);
+    let mut record = vec![TLS_CONTENT_TYPE_HANDSHAKE, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+  }
+
+  #[test]
+  fn sni_is_parsed_from_client_hello() {
+    let record = client_hello(Some("Pass.Example.com"));
+    assert_eq!(
+      parse_client_hello_sni(&record),
+      ClientHelloSni::Found("pass.example.com".to_string())
+    );
+    assert_eq!(parse_client_hello_sni(&record[..3]), ClientHelloSni::Incomplete);
+    assert_eq!(
+      parse_client_hello_sni(&record[..record.len() - 1]),
+      ClientHelloSni::Incomplete
+    );
+    assert_eq!(parse_client_hello_sni(&client_hello(None)), ClientHelloSni::NotFound);
+    assert_eq!(parse_client_hello_sni(b"GET / HTTP/1.1\r\n"), ClientHelloSni::NotFound);
+  }
+
+  #[tokio::test]
+  async fn client_hello_is_peeked_without_consuming() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let record = client_hello(Some("pass.example.com"));
+    let (head, tail) = (record[..10].to_vec(), record[10..].to_vec());
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    client.write_all(&head).await.unwrap();
+    let peek = tokio::spawn(async move {
+      let sni = peek_client_hello_sni(&server).await.unwrap();
+      let mut received = vec![0u8; record.len()];
+      server.read_exact(&mut received).await.unwrap();
+      (sni, received == record)
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    client.write_all(&tail).await.unwrap();
+    assert_eq!(peek.await.unwrap(), (Some("pass.example.com".to_string()), true));
+  }
+
+  #[tokio::test]
+  async fn client_hello_peek_is_bounded_by_deadline() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let record = client_hello(Some("pass.example.com"));
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    client.write_all(&record[..10]).await.unwrap();
+    let peek =
+      tokio::spawn(async move { tokio::time::timeout(Duration::from_millis(100), peek_client_hello_sni(&server)).await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    client.shutdown().await.unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(1), peek).await.unwrap().unwrap();
+    assert!(result.is_err());
+
+    // A client closing without sending anything ends the peek at once
+    let client = TcpStream::connect(addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    drop(client);
+    let result = tokio::time::timeout(Duration::from_secs(1), peek_client_hello_sni(&server))
+      .await
+      .unwrap();
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn upstream_port_defaults_to_https() {
+    assert_eq!(
+      upstream_tcp_addr(&"http://backend.local".parse().unwrap(), 443).as_deref(),
+      Some("backend.local:443")
+    );
+    assert_eq!(
+      upstream_tcp_addr(&"http://[::1]:8443".parse().unwrap(), 443).as_deref(),
+      Some("[::1]:8443")
+    );
+  }
+}
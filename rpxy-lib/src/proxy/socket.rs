@@ -1,14 +1,12 @@
 use crate::{error::*, log::*};
 use socket2::{Domain, Protocol, Socket, Type};
-use std::net::SocketAddr;
-#[cfg(feature = "http3-quinn")]
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
 use tokio::net::TcpSocket;
 
 /// Bind TCP socket to the given `SocketAddr`, and returns the TCP socket with `SO_REUSEADDR` and `SO_REUSEPORT` options.
 /// This option is required to re-bind the socket address when the proxy instance is reconstructed.
 /// Mostly imported from tokio::net::tcp::socket::TcpSocket
-pub(super) fn bind_tcp_socket(listening_on: &SocketAddr) -> RpxyResult<TcpSocket> {
+pub(crate) fn bind_tcp_socket(listening_on: &SocketAddr) -> RpxyResult<TcpSocket> {
   let domain = listening_on.is_ipv6().then(|| Domain::IPV6).unwrap_or(Domain::IPV4);
   let ty = Type::STREAM;
   #[cfg(any(
@@ -53,7 +51,6 @@ pub(super) fn bind_tcp_socket(listening_on: &SocketAddr) -> RpxyResult<TcpSocket
   Ok(tcp_socket)
 }
 
-#[cfg(feature = "http3-quinn")]
 /// Bind UDP socket to the given `SocketAddr`, and returns the UDP socket with `SO_REUSEADDR` and `SO_REUSEPORT` options.
 /// This option is required to re-bind the socket address when the proxy instance is reconstructed.
 pub(super) fn bind_udp_socket(listening_on: &SocketAddr) -> RpxyResult<UdpSocket> {
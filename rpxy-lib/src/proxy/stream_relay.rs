@@ -0,0 +1,227 @@
+use crate::{error::*, hyper_ext::unix::is_unix_socket_uri, log::*};
+use hyper::Uri;
+use std::{
+  io,
+  pin::Pin,
+  sync::atomic::{AtomicU64, Ordering},
+  task::{Context, Poll},
+  time::Duration,
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf, copy_bidirectional},
+  net::TcpStream,
+  time::{Instant, timeout},
+};
+
+#[cfg(unix)]
+use crate::hyper_ext::unix::unix_socket_path;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+/// Byte stream connected to an upstream server over TCP or a Unix domain socket
+pub(super) enum UpstreamStream {
+  Tcp(TcpStream),
+  #[cfg(unix)]
+  Unix(UnixStream),
+}
+
+/// Connect to the upstream server given by the uri. The port of a TCP upstream defaults to `default_port`.
+pub(super) async fn connect_upstream(uri: &Uri, default_port: u16, connect_timeout: Duration) -> RpxyResult<UpstreamStream> {
+  #[cfg(unix)]
+  if is_unix_socket_uri(uri) {
+    let path = unix_socket_path(uri).ok_or_else(|| RpxyError::InvalidUpstreamUri(uri.to_string()))?;
+    let stream = timeout(connect_timeout, UnixStream::connect(&path))
+      .await
+      .map_err(|_| RpxyError::FailedToFetchFromUpstream(format!("Connection to {} timed out", path.display())))??;
+    return Ok(UpstreamStream::Unix(stream));
+  }
+  #[cfg(not(unix))]
+  if is_unix_socket_uri(uri) {
+    return Err(RpxyError::InvalidUpstreamUri(uri.to_string()));
+  }
+
+  let addr = upstream_tcp_addr(uri, default_port).ok_or_else(|| RpxyError::InvalidUpstreamUri(uri.to_string()))?;
+  let stream = timeout(connect_timeout, TcpStream::connect(&addr))
+    .await
+    .map_err(|_| RpxyError::FailedToFetchFromUpstream(format!("Connection to {addr} timed out")))??;
+  stream.set_nodelay(true).ok();
+  Ok(UpstreamStream::Tcp(stream))
+}
+
+/// Address of a TCP upstream, i.e., host:port
+pub(super) fn upstream_tcp_addr(uri: &Uri, default_port: u16) -> Option<String> {
+  let authority = uri.authority()?;
+  Some(format!(
+    "{}:{}",
+    authority.host(),
+    authority.port_u16().unwrap_or(default_port)
+  ))
+}
+
+impl AsyncRead for UpstreamStream {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+      #[cfg(unix)]
+      Self::Unix(s) => Pin::new(s).poll_read(cx, buf),
+    }
+  }
+}
+
+impl AsyncWrite for UpstreamStream {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    match self.get_mut() {
+      Self::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+      #[cfg(unix)]
+      Self::Unix(s) => Pin::new(s).poll_write(cx, buf),
+    }
+  }
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Tcp(s) => Pin::new(s).poll_flush(cx),
+      #[cfg(unix)]
+      Self::Unix(s) => Pin::new(s).poll_flush(cx),
+    }
+  }
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+      #[cfg(unix)]
+      Self::Unix(s) => Pin::new(s).poll_shutdown(cx),
+    }
+  }
+}
+
+/* -------------------------------------------------------------------------------------------------------- */
+/// Copy bytes in both directions until both sides close. If `idle_timeout` is given, the relay is also closed
+/// once no byte is relayed in either direction for the duration.
+pub(super) async fn relay<A, B>(label: &str, client: &mut A, upstream: &mut B, idle_timeout: Option<Duration>) -> RpxyResult<()>
+where
+  A: AsyncRead + AsyncWrite + Unpin,
+  B: AsyncRead + AsyncWrite + Unpin,
+{
+  let Some(idle_timeout) = idle_timeout else {
+    let (from_client, from_upstream) = copy_bidirectional(client, upstream)
+      .await
+      .map_err(|e| RpxyError::FailedToCopyBidirectional(e.to_string()))?;
+    trace!("{label} closed: {from_client} bytes from client, {from_upstream} bytes from upstream");
+    return Ok(());
+  };
+
+  let activity = Activity::new();
+  let mut client = ActivityTracked {
+    inner: client,
+    activity: &activity,
+  };
+  let mut upstream = ActivityTracked {
+    inner: upstream,
+    activity: &activity,
+  };
+  tokio::select! {
+    res = copy_bidirectional(&mut client, &mut upstream) => {
+      let (from_client, from_upstream) = res.map_err(|e| RpxyError::FailedToCopyBidirectional(e.to_string()))?;
+      trace!("{label} closed: {from_client} bytes from client, {from_upstream} bytes from upstream");
+    }
+    _ = activity.idle(idle_timeout) => {
+      debug!("{label} closed after idle for {idle_timeout:?}");
+    }
+  }
+  Ok(())
+}
+
+/// Time of the last byte relayed in either direction, in milliseconds since the relay started
+pub(super) struct Activity {
+  started: Instant,
+  last_millis: AtomicU64,
+}
+
+impl Activity {
+  pub(super) fn new() -> Self {
+    Self {
+      started: Instant::now(),
+      last_millis: AtomicU64::new(0),
+    }
+  }
+  /// Record that data is relayed now
+  pub(super) fn touch(&self) {
+    let now = self.started.elapsed().as_millis() as u64;
+    self.last_millis.fetch_max(now, Ordering::Relaxed);
+  }
+  /// Completes once no data is relayed for `idle_timeout`
+  pub(super) async fn idle(&self, idle_timeout: Duration) {
+    loop {
+      let last = self.started + Duration::from_millis(self.last_millis.load(Ordering::Relaxed));
+      let deadline = last + idle_timeout;
+      if Instant::now() >= deadline {
+        return;
+      }
+      tokio::time::sleep_until(deadline).await;
+    }
+  }
+}
+
+/// Stream recording the activity on every read and write making progress
+struct ActivityTracked<'a, S> {
+  inner: &'a mut S,
+  activity: &'a Activity,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ActivityTracked<'_, S> {
+  fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    let filled = buf.filled().len();
+    let res = Pin::new(&mut *this.inner).poll_read(cx, buf);
+    if matches!(res, Poll::Ready(Ok(()))) && buf.filled().len() > filled {
+      this.activity.touch();
+    }
+    res
+  }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ActivityTracked<'_, S> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    let res = Pin::new(&mut *this.inner).poll_write(cx, buf);
+    if matches!(res, Poll::Ready(Ok(n)) if n > 0) {
+      this.activity.touch();
+    }
+    res
+  }
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+  }
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+  #[tokio::test(start_paused = true)]
+  async fn relay_closes_after_idle_timeout() {
+    let (mut client, mut client_peer) = duplex(64);
+    let (mut upstream, mut upstream_peer) = duplex(64);
+    let relay_task =
+      tokio::spawn(async move { relay("test", &mut client_peer, &mut upstream_peer, Some(Duration::from_secs(10))).await });
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    upstream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    // Activity keeps the relay open
+    tokio::time::sleep(Duration::from_secs(8)).await;
+    upstream.write_all(b"pong").await.unwrap();
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+    tokio::time::sleep(Duration::from_secs(8)).await;
+    assert!(!relay_task.is_finished());
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    relay_task.await.unwrap().unwrap();
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+  }
+}